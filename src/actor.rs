@@ -1,39 +1,287 @@
 use std::borrow::Borrow;
 use std::cell::RefCell;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 
-use crate::config::Config;
-use crate::db::{Db, DbCache, DbStore};
+use crate::config::{Config, Layout, Timeout};
+use crate::db::{self, Db, DbCache, DbStore, Kind, BUCKET_KEYS, MANIFEST_KEY};
+use crate::lease::{self, Lease};
 use crate::path::Path;
-use crate::planner::{Act, Op};
+use crate::planner::{Act, Change, Op};
+use crate::store::{Rev, Size};
+
+// How an act went for the actor that dispatched it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Ok,
+    // One of its writes failed its CAS, and the actor stopped.
+    Conflict,
+    // The actor stopped for another reason, such as a failed guard.
+    Halted,
+    // A timeout left the actor not knowing whether a write landed.
+    Unconfirmed,
+    // The actor had already stopped, so the act did nothing.
+    Skipped,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Ok => write!(f, "ok"),
+            Outcome::Conflict => write!(f, "conflict"),
+            Outcome::Halted => write!(f, "halted"),
+            Outcome::Unconfirmed => write!(f, "unconfirmed"),
+            Outcome::Skipped => write!(f, "skipped"),
+        }
+    }
+}
+
+// An entry in the actor's trace: an act it dispatched, the rev of the act's
+// key it saw, and how the act went. The rev is the one the actor held going
+// into the act, which a write is conditional on, or failing that the one
+// the act read. It is None if the key was missing or the act never read it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trace {
+    pub act: String,
+    pub key: Path,
+    pub rev: Option<Rev>,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.rev {
+            Some(rev) => write!(f, "{}, saw '{}' at rev {}", self.outcome, self.key, rev),
+            None => write!(f, "{}, saw no rev of '{}'", self.outcome, self.key),
+        }
+    }
+}
 
 pub struct Actor<'a, T> {
     cache: DbCache<'a, T>,
     config: Config,
     crashed: bool,
-    unlinks: BTreeSet<String>,
+    conflicted: bool,
+    partitioned: bool,
+    timeout: Option<bool>,
+    timed_out: bool,
+    faulted: bool,
+    unconfirmed: bool,
+    unlinks: BTreeMap<String, BTreeSet<String>>,
+    leases: BTreeMap<Path, Lease>,
+    observations: Vec<(Path, Option<T>)>,
+    listings: Vec<(Path, BTreeSet<String>)>,
+    trace: Option<Vec<Trace>>,
 }
 
 impl<T> Actor<'_, T>
 where
-    T: Clone,
+    T: Clone + Size,
 {
     pub fn new(store: &RefCell<DbStore<T>>, config: Config) -> Actor<'_, T> {
         Actor {
             cache: DbCache::new(store),
             config,
             crashed: false,
-            unlinks: BTreeSet::new(),
+            conflicted: false,
+            partitioned: false,
+            timeout: None,
+            timed_out: false,
+            faulted: false,
+            unconfirmed: false,
+            unlinks: BTreeMap::new(),
+            leases: BTreeMap::new(),
+            observations: Vec::new(),
+            listings: Vec::new(),
+            trace: None,
+        }
+    }
+
+    // Returns the actor to the state it was created in, so that a worker can
+    // reuse it for the next execution once the store has been reset.
+    pub fn reset(&mut self) {
+        self.cache.reset();
+        self.crashed = false;
+        self.conflicted = false;
+        self.partitioned = false;
+        self.timeout = None;
+        self.timed_out = false;
+        self.faulted = false;
+        self.unconfirmed = false;
+        self.unlinks.clear();
+        self.leases.clear();
+        self.observations.clear();
+        self.listings.clear();
+        if let Some(trace) = &mut self.trace {
+            trace.clear();
+        }
+    }
+
+    // Models the client's process restarting: the acts that follow run as a
+    // fresh actor would run them, with an empty cache and no memory of the
+    // unlinks, leases or transaction its earlier acts had under way. A
+    // client that has already stopped stays stopped.
+    pub fn restart(&mut self) {
+        if self.crashed {
+            return;
         }
+        self.faulted = true;
+        self.cache.reset();
+        self.conflicted = false;
+        self.timeout = None;
+        self.timed_out = false;
+        self.unlinks.clear();
+        self.leases.clear();
+    }
+
+    pub fn partition(&mut self) {
+        self.partitioned = true;
+    }
+
+    pub fn is_partitioned(&self) -> bool {
+        self.partitioned
+    }
+
+    pub fn time_out(&mut self, applied: bool) {
+        self.timeout = Some(applied);
+    }
+
+    // Whether an injected fault has had any effect: a partition that cut
+    // off an actor that was still running, or a timeout that struck a write.
+    // A fault the actor never reaches, or reaches only once it has crashed,
+    // leaves it unset.
+    pub fn is_faulted(&self) -> bool {
+        self.faulted
+    }
+
+    pub fn trace_reads(&mut self, on: bool) {
+        self.cache.trace_reads(on);
+    }
+
+    // The keys the actor has read from the store since this was last called,
+    // while its reads are traced.
+    pub fn take_reads(&mut self) -> Vec<Path> {
+        self.cache.take_reads()
+    }
+
+    // While tracing, the actor notes each act it dispatches along with what
+    // it saw of the act's key and whether the act went through.
+    pub fn trace_acts(&mut self, on: bool) {
+        self.trace = on.then(Vec::new);
+    }
+
+    // The acts the actor has dispatched since this was last called, while
+    // its acts are traced.
+    pub fn take_trace(&mut self) -> Vec<Trace> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // The actor's view of a key: the record it last read or wrote, or None
+    // if it has no record of the key.
+    pub fn cached(&self, key: &str) -> Option<Option<(Rev, Option<Db<T>>)>> {
+        self.cache.cached(key).cloned()
+    }
+
+    // A client's operations are acknowledged if every one of its writes was
+    // confirmed by the store: it never crashed, and never carried on past a
+    // write whose outcome it did not learn.
+    pub fn is_acknowledged(&self) -> bool {
+        !self.crashed && !self.unconfirmed
     }
 
     pub fn dispatch(&mut self, act: &Act<T>) {
+        let Some(mut trace) = self.trace.take() else {
+            self.run(act);
+            return;
+        };
+        let (stopped, unconfirmed) = (self.crashed || self.partitioned, self.unconfirmed);
+        let before = self.seen_rev(&act.path);
+        self.run(act);
+
+        let outcome = if stopped {
+            Outcome::Skipped
+        } else if self.crashed && self.conflicted {
+            Outcome::Conflict
+        } else if self.crashed {
+            Outcome::Halted
+        } else if self.unconfirmed && !unconfirmed {
+            Outcome::Unconfirmed
+        } else {
+            Outcome::Ok
+        };
+        trace.push(Trace {
+            act: format!("{:?}", act),
+            key: act.path.clone(),
+            rev: before.or_else(|| self.seen_rev(&act.path)).flatten(),
+            outcome,
+        });
+        self.trace = Some(trace);
+    }
+
+    // The rev of the key in the actor's cache: None if it has no record of
+    // the key, or Some(None) if it found the key missing.
+    fn seen_rev(&self, key: &Path) -> Option<Option<Rev>> {
+        let record = self.cache.cached(key.full())?;
+        Some(record.as_ref().map(|(rev, _)| *rev))
+    }
+
+    fn run(&mut self, act: &Act<T>) {
+        self.cache.tick();
+
+        if self.partitioned {
+            self.faulted |= !self.crashed;
+            self.crashed = true;
+            return;
+        }
+        self.attempt(act);
+        self.timeout = None;
+
+        if self.timed_out {
+            self.timed_out = false;
+
+            match self.config.timeout {
+                Timeout::Abort => self.crashed = true,
+                Timeout::Continue => self.unconfirmed = true,
+                Timeout::Retry => self.attempt(act),
+            }
+        }
+    }
+
+    // Performs the act, and performs it again each time one of its writes
+    // fails its CAS, up to the number of retries the config allows, before
+    // giving up as a client without retries does. A failed write forgets
+    // the key, so each attempt reads it afresh and reapplies its update to
+    // whatever the other client wrote.
+    fn attempt(&mut self, act: &Act<T>) {
+        for _ in 0..self.config.retries {
+            self.conflicted = false;
+            self.perform(act);
+
+            if !self.conflicted {
+                return;
+            }
+            self.crashed = false;
+        }
+        self.conflicted = false;
+        self.perform(act);
+    }
+
+    fn perform(&mut self, act: &Act<T>) {
         match &act.op {
             Op::Get => {
                 self.get(&act.path);
             }
+            Op::Observe => {
+                self.observe(&act.path);
+            }
+            Op::ExpectList(_) => {
+                self.observe_list(&act.path);
+            }
+            Op::Guard(check) => {
+                self.guard(&act.path, check);
+            }
             Op::Put(update) => {
-                self.put(&act.path, update);
+                self.put(&act.path, &act.client_id, update);
             }
             Op::Rm => {
                 self.rm(&act.path);
@@ -41,12 +289,41 @@ where
             Op::List => {
                 self.list(&act.path);
             }
-            Op::Link(name) => {
-                self.link(&act.path, name);
+            Op::ReadMany(dirs) => {
+                self.read_many(dirs, &act.path);
+            }
+            Op::Link(name, kind) => {
+                self.link(&act.path, name, *kind);
             }
             Op::Unlink(name) => {
                 self.unlink(&act.path, name);
             }
+            Op::Flush(changes) => {
+                self.flush(&act.path, changes);
+            }
+            Op::Sync => {
+                self.sync(&act.path);
+            }
+            Op::Repair(name, kind) => {
+                self.repair(&act.path, name, *kind);
+            }
+            Op::Acquire(ttl) => {
+                self.acquire(&act.path, &act.client_id, *ttl);
+            }
+            Op::Release => {
+                self.release(&act.path);
+            }
+            Op::Begin => {
+                self.begin();
+            }
+            Op::Commit => {
+                self.commit();
+            }
+            Op::Custom(op) => {
+                if !self.crashed {
+                    op.execute(self, &act.path);
+                }
+            }
         }
     }
 
@@ -54,20 +331,107 @@ where
         if self.crashed {
             return None;
         }
-        if let Some(Db::Doc(value)) = self.cache.read(path) {
+        if let (Layout::Packed { .. }, Some((dir, name))) =
+            (&self.config.layout, path.links().last())
+        {
+            if let Some(Some(value)) = self.read_packed(dir).remove(name) {
+                return Some(value);
+            }
+        }
+        if let Some(Db::Doc(value, _)) = self.cache.read(path) {
             Some(value)
         } else {
             None
         }
     }
 
-    pub fn put<F>(&mut self, path: &Path, update: F)
+    // Gets the doc and records what it saw. A crashed actor sees nothing and
+    // records nothing.
+    pub fn observe(&mut self, path: &Path) {
+        if self.crashed {
+            return;
+        }
+        let value = self.get(path);
+        self.observations.push((path.clone(), value));
+    }
+
+    // What each of the actor's observations saw, in the order it made them.
+    pub fn observations(&self) -> &[(Path, Option<T>)] {
+        &self.observations
+    }
+
+    // Lists the dir and records the entries it saw, taking a missing dir to
+    // have none. As with `observe()`, a crashed actor records nothing.
+    pub fn observe_list(&mut self, path: &Path) {
+        if self.crashed {
+            return;
+        }
+        let entries = self.list(path).unwrap_or_default();
+        self.listings.push((path.clone(), entries));
+    }
+
+    // The entries each of the actor's listings saw, in the order it made them.
+    pub fn listings(&self) -> &[(Path, BTreeSet<String>)] {
+        &self.listings
+    }
+
+    pub fn guard<F>(&mut self, path: &Path, check: F)
+    where
+        F: Fn(Option<&T>) -> bool,
+    {
+        if !self.crashed && !check(self.get(path).as_ref()) {
+            self.crashed = true;
+        }
+    }
+
+    pub fn put<F>(&mut self, path: &Path, writer: &str, update: F)
     where
         F: Fn(Option<T>) -> Option<T>,
     {
-        if !self.crashed {
-            if let Some(value) = update(self.get(path)) {
-                self.write(path, Db::Doc(value));
+        if self.crashed {
+            return;
+        }
+        if self.updates_on_server() {
+            let mtime = self.cache.clock().now();
+            self.update(path, |old| {
+                let doc = match old {
+                    Some(Db::Doc(value, _)) => Some(value.clone()),
+                    _ => None,
+                };
+                update(doc).map(|value| Db::doc_from(value, mtime, writer))
+            });
+        } else if let Some(value) = update(self.get(path)) {
+            if let Layout::Packed { threshold } = self.config.layout {
+                self.put_packed(path, writer, value, threshold);
+            } else {
+                let doc = Db::doc_from(value, self.cache.clock().now(), writer);
+                self.write(path, doc);
+            }
+        }
+    }
+
+    fn put_packed(&mut self, path: &Path, writer: &str, value: T, threshold: usize) {
+        let (dir, name) = match path.links().last() {
+            Some((dir, name)) => (Path::from(dir), name),
+            None => return,
+        };
+        let mut entries = self.read_packed(dir.full());
+        let spilled = self.cache.read(path).is_some();
+
+        if value.size() <= threshold {
+            entries.insert(name.to_string(), Some(value));
+            self.write(&dir, Db::Packed(entries));
+
+            if spilled && !self.crashed {
+                self.remove(path);
+            }
+        } else {
+            let doc = Db::doc_from(value, self.cache.clock().now(), writer);
+            self.write(path, doc);
+
+            if !self.crashed && matches!(entries.get(name), Some(Some(_))) {
+                entries.insert(name.to_string(), None);
+                self.write(&dir, Db::Packed(entries));
             }
         }
     }
@@ -77,22 +441,44 @@ where
             return;
         }
 
-        if !self.cache.remove(path) {
-            self.crashed = true;
+        if let Layout::Packed { .. } = self.config.layout {
+            self.rm_packed(path);
+        } else {
+            self.remove(path);
+        }
+        if self.crashed {
             return;
         }
 
-        self.unlinks = BTreeSet::new();
+        self.unlinks = BTreeMap::new();
 
-        for (dir, name) in path.links().rev() {
-            self.unlinks.insert(dir.to_string());
+        for (dir, name, _) in db::links(&self.config.layout, path).into_iter().rev() {
+            let entries = self.list(dir).unwrap_or_default();
+            let only = entries.len() == 1 && entries.contains(name);
+            self.unlinks.insert(dir.to_string(), entries);
 
-            if self.list(dir) != Some(BTreeSet::from([name.to_string()])) {
+            if !only {
                 break;
             }
         }
     }
 
+    fn rm_packed(&mut self, path: &Path) {
+        let (dir, name) = match path.links().last() {
+            Some((dir, name)) => (Path::from(dir), name),
+            None => return,
+        };
+        let mut entries = self.read_packed(dir.full());
+
+        if matches!(entries.get(name), Some(Some(_))) {
+            entries.insert(name.to_string(), None);
+            self.write(&dir, Db::Packed(entries));
+        }
+        if !self.crashed && self.cache.read(path).is_some() {
+            self.remove(path);
+        }
+    }
+
     pub fn list<'a, P>(&mut self, path: &'a P) -> Option<BTreeSet<String>>
     where
         Path: Borrow<P>,
@@ -102,154 +488,1311 @@ where
         if self.crashed {
             return None;
         }
-        if let Some(Db::Dir(value)) = self.cache.read(path) {
-            Some(value)
+        match self.config.layout {
+            Layout::DirDocs | Layout::Packed { .. } => self.read_dir(path),
+            Layout::Flat => {
+                let dir = path.into();
+                let keys = self.cache.scan(dir.full());
+                db::child_entries(dir.full(), keys.iter().map(Path::full))
+            }
+            Layout::Manifest => {
+                let dir = path.into();
+                let manifest = self.read_dir::<str>(MANIFEST_KEY)?;
+                db::child_entries(dir.full(), manifest.iter().map(String::as_str))
+            }
+            Layout::Buckets { count } => {
+                let dir = path.into();
+                let mut keys = BTreeSet::new();
+
+                for bucket in &BUCKET_KEYS[..count] {
+                    keys.extend(self.read_dir::<str>(bucket).unwrap_or_default());
+                }
+                db::child_entries(dir.full(), keys.iter().map(String::as_str))
+            }
+        }
+    }
+
+    // Lists each dir and gets the doc at `path`, fetching every key this
+    // reads from the store in a single batch first.
+    pub fn read_many(&mut self, dirs: &[Path], path: &Path) {
+        if self.crashed {
+            return;
+        }
+        let mut keys: Vec<_> = dirs.iter().flat_map(|dir| self.dir_keys(dir)).collect();
+        keys.push(path.clone());
+        self.cache.read_many(&keys);
+
+        for dir in dirs {
+            self.list(dir);
+        }
+        self.get(path);
+    }
+
+    // Re-reads every key under `dir`, and the keys that hold its listing, so
+    // that the client drops records the store no longer has and adopts the
+    // current rev of everything else.
+    pub fn sync(&mut self, dir: &Path) {
+        if self.crashed {
+            return;
+        }
+        let keys = self.dir_keys(dir);
+        self.cache.sync(dir.full(), &keys);
+    }
+
+    // The keys that listing `dir` reads. A flat layout lists dirs by a scan,
+    // which reads no keys in particular.
+    fn dir_keys(&self, dir: &Path) -> Vec<Path> {
+        match self.config.layout {
+            Layout::DirDocs | Layout::Packed { .. } => vec![dir.clone()],
+            Layout::Flat => Vec::new(),
+            Layout::Manifest => vec![MANIFEST_KEY.into()],
+            Layout::Buckets { count } => {
+                BUCKET_KEYS[..count].iter().map(|&key| key.into()).collect()
+            }
+        }
+    }
+
+    fn read_dir<'a, P>(&mut self, path: &'a P) -> Option<BTreeSet<String>>
+    where
+        Path: Borrow<P>,
+        P: Ord + ?Sized,
+        &'a P: Into<Path>,
+    {
+        match self.cache.read(path) {
+            Some(Db::Dir(value)) => Some(value.into_keys().collect()),
+            Some(Db::Packed(value)) => Some(value.into_keys().collect()),
+            _ => None,
+        }
+    }
+
+    fn read_entries(&mut self, path: &Path) -> BTreeMap<String, Kind> {
+        if let Some(Db::Dir(entries)) = self.cache.read(path) {
+            entries
         } else {
-            None
+            BTreeMap::new()
         }
     }
 
-    pub fn link(&mut self, path: &Path, entry: &str) {
-        if !self.crashed {
-            let mut entries = self.list(path).unwrap_or_default();
+    fn read_packed(&mut self, path: &str) -> BTreeMap<String, Option<T>> {
+        if let Some(Db::Packed(entries)) = self.cache.read(path) {
+            entries
+        } else {
+            BTreeMap::new()
+        }
+    }
 
-            if !self.config.skip_links || !entries.contains(entry) {
-                entries.insert(entry.to_string());
+    pub fn link(&mut self, path: &Path, entry: &str, kind: Kind) {
+        if let (false, Layout::Packed { .. }) = (self.crashed, &self.config.layout) {
+            let mut entries = self.read_packed(path.full());
+
+            if !self.config.skip_links || !entries.contains_key(entry) {
+                entries.entry(entry.to_string()).or_insert(None);
+                self.write(path, Db::Packed(entries));
+            }
+        } else if !self.crashed && self.updates_on_server() {
+            let skip_links = self.config.skip_links;
+
+            self.update(path, |old| {
+                let mut entries = dir_entries(old);
+                if skip_links && entries.contains_key(entry) {
+                    return None;
+                }
+                entries.entry(entry.to_string()).or_insert(kind);
+                Some(Db::Dir(entries))
+            });
+        } else if !self.crashed {
+            let mut entries = self.read_entries(path);
+
+            if !self.config.skip_links || !entries.contains_key(entry) {
+                entries.entry(entry.to_string()).or_insert(kind);
                 self.write(path, Db::Dir(entries));
             }
         }
     }
 
     pub fn unlink(&mut self, path: &Path, entry: &str) {
-        if !self.crashed && self.unlinks.contains(path.full()) {
-            let mut entries = self.list(path).unwrap_or_default();
+        if self.crashed || !self.unlinks.contains_key(path.full()) {
+            return;
+        }
+        if self.gained_entries(path, entry) {
+            self.unlinks.clear();
+        } else {
+            self.remove_entry(path, entry);
+        }
+    }
+
+    // With `relist_before_unlink` set, lists the dir again from the store
+    // just before unlinking `entry` from it, and tells whether the dir, or
+    // the dir that `entry` names, has gained entries since `rm()` listed it.
+    // If so, another client is creating something under it, and the remove
+    // gives up on this unlink and those above it. The cache only peeks at
+    // the store, so that the unlink is still written conditional on the rev
+    // that `rm()` saw, while the reads show up in traces like any other.
+    fn gained_entries(&mut self, dir: &Path, entry: &str) -> bool {
+        if !self.config.relist_before_unlink {
+            return false;
+        }
+        self.cache.peek(true);
+        let mut gained = false;
+
+        for dir in [dir.clone(), db::child_path(dir.full(), entry)] {
+            if !self.unlinks.contains_key(dir.full()) {
+                continue;
+            }
+            let entries = self.list(&dir).unwrap_or_default();
+            if !entries.is_subset(&self.unlinks[dir.full()]) {
+                gained = true;
+                break;
+            }
+        }
+        self.cache.peek(false);
+        gained
+    }
+
+    // Writes all of an operation's changes to a dir at once, on top of the
+    // listing the operation read. As with `unlink()`, unlinks only apply to
+    // dirs that `rm()` found to hold nothing else.
+    pub fn flush(&mut self, path: &Path, changes: &[Change]) {
+        if self.crashed {
+            return;
+        }
+        let mut unlinked = self.unlinks.contains_key(path.full());
+        let gained = changes.iter().any(|change| match change {
+            Change::Unlink(name) => unlinked && self.gained_entries(path, name),
+            Change::Link(..) => false,
+        });
+        if gained {
+            self.unlinks.clear();
+            unlinked = false;
+        }
+        let changes: Vec<_> = changes
+            .iter()
+            .filter(|change| unlinked || matches!(change, Change::Link(..)))
+            .collect();
+
+        if changes.is_empty() {
+            return;
+        }
+        let skip_links = self.config.skip_links;
+
+        if let Layout::Packed { .. } = self.config.layout {
+            let mut entries = self.read_packed(path.full());
+
+            if apply_changes(&mut entries, &changes, skip_links, |_| None) {
+                self.write(path, Db::Packed(entries));
+            }
+        } else if self.updates_on_server() {
+            self.update(path, |old| {
+                let mut entries = dir_entries(old);
+                apply_changes(&mut entries, &changes, skip_links, |kind| kind)
+                    .then_some(Db::Dir(entries))
+            });
+        } else {
+            let mut entries = self.read_entries(path);
+
+            if apply_changes(&mut entries, &changes, skip_links, |kind| kind) {
+                self.write(path, Db::Dir(entries));
+            }
+        }
+    }
+
+    pub fn repair(&mut self, path: &Path, entry: &str, kind: Kind) {
+        if self.crashed {
+            return;
+        }
+        let child = db::child_path(path.full(), entry);
+
+        let exists = match kind {
+            Kind::Dir => self.list(&child).is_some(),
+            Kind::Doc => self.get(&child).is_some(),
+        };
+        let linked = self.read_dir(path).is_some_and(|e| e.contains(entry));
+
+        if exists && !linked {
+            self.link(path, entry, kind);
+        } else if linked && !exists {
+            self.remove_entry(path, entry);
+        }
+    }
+
+    // Scans the store and repairs every link it finds broken, as an fsck tool
+    // run at this point would. Each doc and each path that a dir entry names
+    // has the links along its path repaired as `repair()` does, deepest
+    // first, under CAS like any other write.
+    pub fn recover(&mut self) {
+        if self.crashed {
+            return;
+        }
+        let mut paths = BTreeSet::new();
+
+        for key in self.cache.scan("/") {
+            let value = self.cache.read(&key);
+            paths.extend(db::repair_keys_of(&key, value.as_ref()));
+        }
+        for path in paths {
+            for (dir, name, kind) in db::links(&self.config.layout, &path).into_iter().rev() {
+                self.repair(&dir.into(), name, kind);
+            }
+        }
+    }
+
+    pub fn acquire(&mut self, path: &Path, owner: &str, ttl: usize) {
+        if self.crashed {
+            return;
+        }
+        let key = lease::lock_key(path);
+        let clock = self.cache.clock();
+
+        if let Some(Db::Lease(held)) = self.cache.read(&key) {
+            if held.is_live(&clock) {
+                self.crashed = true;
+                return;
+            }
+        }
+
+        let lease = Lease::new(owner, clock.after(ttl));
+        self.write(&key, Db::Lease(lease.clone()));
+
+        if !self.crashed {
+            self.leases.insert(path.clone(), lease);
+        }
+    }
+
+    pub fn release(&mut self, path: &Path) {
+        if self.crashed || self.leases.remove(path).is_none() {
+            return;
+        }
+        self.remove(&lease::lock_key(path));
+    }
+
+    pub fn begin(&mut self) {
+        if !self.crashed && self.config.write_batch {
+            self.cache.begin();
+        }
+    }
+
+    pub fn commit(&mut self) {
+        if !self.cache.is_batching() {
+            return;
+        }
+        if self.crashed {
+            self.cache.abort();
+            return;
+        }
+        if let Some(applied) = self.timeout.take() {
+            self.timed_out = true;
+            self.faulted = true;
+
+            // A retry resends the batch, so it stays open.
+            if !applied {
+                if self.config.timeout != Timeout::Retry {
+                    self.cache.abort();
+                }
+                return;
+            }
+            let keys = match self.cache.commit() {
+                Ok(keys) => {
+                    keys.iter().for_each(|key| self.check_leases(key));
+                    keys
+                }
+                Err(keys) => keys,
+            };
+            for key in &keys {
+                self.cache.forget(key);
+            }
+        } else {
+            match self.cache.commit() {
+                Ok(keys) => keys.iter().for_each(|key| self.check_leases(key)),
+                Err(_) => self.crashed = true,
+            }
+        }
+    }
+
+    // The key's record as the actor's cache reads it, for custom ops that
+    // work on the store's records directly. A crashed actor reads nothing.
+    pub fn read_raw(&mut self, key: &Path) -> Option<Db<T>> {
+        if self.crashed {
+            return None;
+        }
+        self.cache.read(key)
+    }
+
+    // Writes or removes the key's record, conditional on the rev the actor
+    // last saw, and crashes the actor if the store has moved on.
+    pub fn write_raw(&mut self, key: &Path, value: Option<Db<T>>) {
+        if self.crashed {
+            return;
+        }
+        match value {
+            Some(value) => self.write(key, value),
+            None => self.remove(key),
+        }
+    }
+
+    fn check_leases(&mut self, key: &Path) {
+        let clock = self.cache.clock();
+
+        let expired: Vec<_> = self
+            .leases
+            .iter()
+            .filter(|(dir, lease)| lease::covers(dir, key) && !lease.is_live(&clock))
+            .map(|(dir, lease)| {
+                format!(
+                    "client '{}' wrote '{}' at time {} after its lease on '{}' expired at time {}",
+                    lease.owner,
+                    key,
+                    clock.now(),
+                    dir,
+                    lease.expires
+                )
+            })
+            .collect();
+
+        for violation in expired {
+            self.cache.report(violation);
+        }
+    }
+
+    fn remove_entry(&mut self, path: &Path, entry: &str) {
+        if let Layout::Packed { .. } = self.config.layout {
+            let mut entries = self.read_packed(path.full());
+            entries.remove(entry);
+            self.write(path, Db::Packed(entries));
+        } else if self.updates_on_server() {
+            self.update(path, |old| {
+                let mut entries = dir_entries(old);
+                entries.remove(entry);
+                Some(Db::Dir(entries))
+            });
+        } else {
+            let mut entries = self.read_entries(path);
             entries.remove(entry);
             self.write(path, Db::Dir(entries));
         }
     }
 
     fn write(&mut self, key: &Path, value: Db<T>) {
-        if !self.cache.write(key, value) {
+        if self.cache.is_batching() {
+            self.cache.write(key, value);
+            return;
+        }
+        if self.timed_out {
+            return;
+        }
+        if let Some(applied) = self.timeout.take() {
+            if applied && self.cache.write(key, value) {
+                self.check_leases(key);
+            }
+            self.cache.forget(key);
+            self.timed_out = true;
+            self.faulted = true;
+        } else if self.cache.write(key, value) {
+            self.check_leases(key);
+        } else {
             self.crashed = true;
+            self.conflicted = true;
+        }
+    }
+
+    // With server-side updates, docs are written and dirs are linked and
+    // unlinked by having the store apply the change, rather than by a read
+    // followed by a write conditional on its rev. Packed dirs hold docs as
+    // well as links, and a put may spill a doc out of one, which takes more
+    // than one key, so they keep their client-side updates, as do
+    // transactions.
+    fn updates_on_server(&self) -> bool {
+        self.config.server_update
+            && !matches!(self.config.layout, Layout::Packed { .. })
+            && !self.cache.is_batching()
+    }
+
+    fn update<F>(&mut self, key: &Path, update: F)
+    where
+        F: FnOnce(Option<&Db<T>>) -> Option<Db<T>>,
+    {
+        if self.timed_out {
+            return;
+        }
+        if let Some(applied) = self.timeout.take() {
+            if applied && self.cache.update(key, update) {
+                self.check_leases(key);
+            }
+            self.timed_out = true;
+            self.faulted = true;
+        } else if self.cache.update(key, update) {
+            self.check_leases(key);
+        } else {
+            self.crashed = true;
+        }
+    }
+
+    fn remove(&mut self, key: &Path) {
+        if self.cache.is_batching() {
+            self.cache.remove(key);
+            return;
+        }
+        if self.timed_out {
+            return;
+        }
+        if let Some(applied) = self.timeout.take() {
+            if applied && self.cache.remove(key) {
+                self.check_leases(key);
+            }
+            self.cache.forget(key);
+            self.timed_out = true;
+            self.faulted = true;
+        } else if self.cache.remove(key) {
+            self.check_leases(key);
+        } else {
+            self.crashed = true;
+            self.conflicted = true;
         }
     }
 }
 
+fn dir_entries<T>(value: Option<&Db<T>>) -> BTreeMap<String, Kind> {
+    match value {
+        Some(Db::Dir(entries)) => entries.clone(),
+        _ => BTreeMap::new(),
+    }
+}
+
+// Applies changes to a dir's entries in order, and returns whether they call
+// for a write. Like `link()`, a link only skips the write if `skip_links` is
+// set and the entry is already there. `link` gives the value a new entry
+// holds for its kind: the tag in a dir, or no doc in a packed one.
+fn apply_changes<V, F>(
+    entries: &mut BTreeMap<String, V>,
+    changes: &[&Change],
+    skip_links: bool,
+    link: F,
+) -> bool
+where
+    F: Fn(Kind) -> V,
+{
+    let mut needed = false;
+
+    for change in changes {
+        match change {
+            Change::Link(name, kind) => {
+                needed |= !skip_links || !entries.contains_key(name);
+                entries.entry(name.clone()).or_insert_with(|| link(*kind));
+            }
+            Change::Unlink(name) => {
+                needed = true;
+                entries.remove(name);
+            }
+        }
+    }
+    needed
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn x_path() -> Path {
-        Path::from("/path/x.json")
+    use crate::clock::Time;
+    use crate::planner::{CustomOp, Planner};
+
+    fn x_path() -> Path {
+        Path::from("/path/x.json")
+    }
+
+    fn y_path() -> Path {
+        Path::from("/path/to/y.json")
+    }
+
+    fn make_store() -> RefCell<DbStore<Vec<char>>> {
+        let mut store = DbStore::new(Config::new());
+
+        store.write("/".into(), None, Db::dir_from(&["path/"]));
+        store.write("/path/".into(), None, Db::dir_from(&["to/", "x.json"]));
+        store.write("/path/to/".into(), None, Db::dir_from(&["y.json"]));
+
+        store.write(x_path(), None, Db::doc(vec!['a', 'b']));
+        store.write(y_path(), None, Db::doc(vec!['c', 'd', 'e']));
+
+        RefCell::new(store)
+    }
+
+    #[test]
+    fn gets_an_existing_document() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        let doc = actor.get(&x_path());
+        assert_eq!(doc, Some(vec!['a', 'b']));
+    }
+
+    #[test]
+    fn returns_none_for_a_missing_document() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        let doc = actor.get(&"/y.json".into());
+        assert_eq!(doc, None);
+    }
+
+    #[test]
+    fn records_what_each_observation_saw() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        actor.observe(&x_path());
+        actor.observe(&"/y.json".into());
+        assert_eq!(
+            actor.observations(),
+            [(x_path(), Some(vec!['a', 'b'])), ("/y.json".into(), None)]
+        );
+
+        actor.reset();
+        assert_eq!(actor.observations(), []);
+    }
+
+    #[test]
+    fn records_what_each_listing_saw() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        actor.observe_list(&"/path/".into());
+        actor.observe_list(&"/nope/".into());
+        assert_eq!(
+            actor.listings(),
+            [
+                (
+                    "/path/".into(),
+                    BTreeSet::from(["to/".to_string(), "x.json".to_string()])
+                ),
+                ("/nope/".into(), BTreeSet::new())
+            ]
+        );
+
+        actor.reset();
+        assert_eq!(actor.listings(), []);
+    }
+
+    #[test]
+    fn updates_a_document() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        actor.get(&x_path());
+        actor.put(&x_path(), "A", |doc| {
+            Some(doc?.iter().rev().cloned().collect())
+        });
+
+        let rec = store.borrow().read(&x_path());
+        assert_eq!(rec, Some((2, Some(Db::doc_from(vec!['b', 'a'], 0, "A")))));
+
+        let doc = actor.get(&x_path());
+        assert_eq!(doc, Some(vec!['b', 'a']));
+    }
+
+    #[test]
+    fn updates_a_document_multiple_times() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        actor.get(&x_path());
+        actor.put(&x_path(), "A", |doc| {
+            Some(doc?.iter().rev().cloned().collect())
+        });
+
+        actor.put(&x_path(), "A", |doc| {
+            doc.map(|mut d| {
+                d.push('z');
+                d
+            })
+        });
+
+        let rec = store.borrow().read(&x_path());
+        assert_eq!(
+            rec,
+            Some((3, Some(Db::doc_from(vec!['b', 'a', 'z'], 0, "A"))))
+        );
+
+        let doc = actor.get(&x_path());
+        assert_eq!(doc, Some(vec!['b', 'a', 'z']));
+    }
+
+    #[test]
+    fn fails_to_write_a_conflicting_update() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        actor.get(&x_path());
+
+        store
+            .borrow_mut()
+            .write(x_path(), Some(1), Db::doc(vec!['z']));
+
+        actor.put(&x_path(), "A", |_| Some(vec!['p', 'q']));
+
+        let rec = store.borrow().read(&x_path());
+        assert_eq!(rec, Some((2, Some(Db::doc(vec!['z'])))));
+    }
+
+    #[test]
+    fn retries_a_conflicting_update_on_the_value_that_beat_it() {
+        let store = make_store();
+        let config = Config::new().retries(1);
+        let mut actor = Actor::new(&store, config.clone());
+
+        let mut planner = Planner::new(config);
+        planner
+            .client("A")
+            .update("/path/x.json", |doc| doc.map(|d| [d, vec!['q']].concat()));
+        let plan = planner.orderings().next().unwrap();
+        let (put, rest) = plan.split_last().unwrap();
+
+        for act in rest {
+            actor.dispatch(act);
+        }
+        store
+            .borrow_mut()
+            .write(x_path(), Some(1), Db::doc(vec!['z']));
+
+        actor.dispatch(put);
+        assert!(actor.is_acknowledged());
+        assert_eq!(
+            db::doc_value(&store.borrow(), "/path/x.json"),
+            Some(vec!['z', 'q'])
+        );
+    }
+
+    #[test]
+    fn traces_what_each_act_saw_and_how_it_went() {
+        let store = make_store();
+        let config = Config::new();
+        let mut actor = Actor::new(&store, config.clone());
+        actor.trace_acts(true);
+
+        let mut planner = Planner::new(config);
+        planner
+            .client("A")
+            .update("/path/x.json", |doc| doc.map(|d| [d, vec!['q']].concat()));
+        let plan = planner.orderings().next().unwrap();
+        let (put, rest) = plan.split_last().unwrap();
+
+        for act in rest {
+            actor.dispatch(act);
+        }
+        store
+            .borrow_mut()
+            .write(x_path(), Some(1), Db::doc(vec!['z']));
+        actor.dispatch(put);
+        actor.dispatch(put);
+
+        let trace = actor.take_trace();
+        let outcomes: Vec<_> = trace.iter().map(|entry| entry.outcome).collect();
+        assert_eq!(outcomes[..rest.len()], vec![Outcome::Ok; rest.len()]);
+        assert_eq!(
+            trace[rest.len()..],
+            [
+                Trace {
+                    act: String::from("Act<A: put('/path/x.json')>"),
+                    key: x_path(),
+                    rev: Some(1),
+                    outcome: Outcome::Conflict,
+                },
+                Trace {
+                    act: String::from("Act<A: put('/path/x.json')>"),
+                    key: x_path(),
+                    rev: None,
+                    outcome: Outcome::Skipped,
+                },
+            ]
+        );
+        assert_eq!(
+            trace[rest.len()].to_string(),
+            "conflict, saw '/path/x.json' at rev 1"
+        );
+        assert_eq!(actor.take_trace(), []);
+    }
+
+    #[test]
+    fn does_not_perform_more_actions_after_a_failed_write() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        actor.get(&x_path());
+
+        store
+            .borrow_mut()
+            .write(x_path(), Some(1), Db::doc(vec!['z']));
+
+        actor.put(&x_path(), "A", |_| Some(vec!['p', 'q']));
+
+        assert_eq!(actor.get(&x_path()), None);
+        actor.put(&x_path(), "A", |_| Some(vec!['x', 'y']));
+
+        let rec = store.borrow().read(&x_path());
+        assert_eq!(rec, Some((2, Some(Db::doc(vec!['z'])))));
+    }
+
+    #[test]
+    fn has_the_store_apply_an_update_to_a_value_changed_since_it_was_read() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new().server_update(true));
+
+        actor.get(&x_path());
+
+        store
+            .borrow_mut()
+            .write(x_path(), Some(1), Db::doc(vec!['z']));
+
+        actor.put(&x_path(), "A", |doc| Some([doc?, vec!['q']].concat()));
+
+        let rec = store.borrow().read(&x_path());
+        assert_eq!(rec, Some((3, Some(Db::doc_from(vec!['z', 'q'], 0, "A")))));
+
+        assert_eq!(actor.get(&x_path()), Some(vec!['z', 'q']));
+    }
+
+    #[test]
+    fn has_the_store_link_and_unlink_entries() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new().server_update(true));
+
+        actor.list("/path/");
+
+        store.borrow_mut().write(
+            "/path/".into(),
+            Some(1),
+            Db::dir_from(&["a.txt", "to/", "x.json"]),
+        );
+
+        actor.link(&"/path/".into(), "b.txt", Kind::Doc);
+        actor.rm(&x_path());
+        actor.unlink(&"/path/".into(), "x.json");
+
+        let rec = store.borrow().read("/path/");
+        assert_eq!(
+            rec,
+            Some((4, Some(Db::dir_from(&["a.txt", "b.txt", "to/"]))))
+        );
+    }
+
+    #[test]
+    fn continues_after_a_passing_guard() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        actor.guard(&y_path(), |doc| doc == Some(&vec!['c', 'd', 'e']));
+        actor.put(&x_path(), "A", |_| Some(vec!['z']));
+
+        let rec = store.borrow().read(&x_path());
+        assert_eq!(rec, Some((2, Some(Db::doc_from(vec!['z'], 0, "A")))));
+    }
+
+    #[test]
+    fn halts_after_a_failing_guard() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        actor.guard(&y_path(), |doc| doc.is_none());
+        actor.put(&x_path(), "A", |_| Some(vec!['z']));
+
+        let rec = store.borrow().read(&x_path());
+        assert_eq!(rec, Some((1, Some(Db::doc(vec!['a', 'b'])))));
+    }
+
+    // Copies the doc at its path to another key, as a store primitive
+    // prototyped outside the planner.
+    struct Copy(Path);
+
+    impl CustomOp<Vec<char>> for Copy {
+        fn name(&self) -> &str {
+            "copy"
+        }
+
+        fn execute(&self, actor: &mut Actor<'_, Vec<char>>, path: &Path) {
+            let value = actor.read_raw(path);
+            actor.read_raw(&self.0);
+            actor.write_raw(&self.0, value);
+        }
+    }
+
+    #[test]
+    fn runs_custom_ops_through_the_actors_cache() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").custom("/path/x.json", Copy(y_path()));
+        planner.client("A").custom("/path/x.json", Copy(y_path()));
+        let plan = planner.orderings().next().unwrap();
+
+        actor.dispatch(plan[0]);
+        assert_eq!(actor.get(&y_path()), Some(vec!['a', 'b']));
+
+        store
+            .borrow_mut()
+            .write(y_path(), Some(2), Db::doc(vec!['z']));
+        actor.dispatch(plan[1]);
+        assert!(!actor.is_acknowledged());
+        assert_eq!(store.borrow().read(&y_path()).unwrap().0, 3);
+    }
+
+    #[test]
+    fn recovers_every_broken_link_in_the_store() {
+        let store = make_store();
+        store
+            .borrow_mut()
+            .write("/z.json".into(), None, Db::doc(vec!['z']));
+        store.borrow_mut().write(
+            "/path/to/".into(),
+            Some(1),
+            Db::dir_from(&["y.json", "w.json"]),
+        );
+
+        let mut actor = Actor::new(&store, Config::new());
+        actor.recover();
+
+        assert_eq!(
+            actor.list("/"),
+            Some(BTreeSet::from(["path/".to_string(), "z.json".to_string()]))
+        );
+        assert_eq!(
+            actor.list("/path/to/"),
+            Some(BTreeSet::from(["y.json".to_string()]))
+        );
+        assert_eq!(db::Checker::new(&store).check(), Ok(()));
+    }
+
+    #[test]
+    fn does_not_reach_the_store_while_partitioned() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").put_unlinked("/path/x.json", |_| None);
+        let plan = planner.orderings().next().unwrap();
+
+        actor.dispatch(plan[0]);
+        actor.partition();
+        actor.dispatch(plan[1]);
+
+        assert!(actor.is_partitioned());
+        assert_eq!(store.borrow().clock.now(), 2);
+
+        let rec = store.borrow().read(&x_path());
+        assert_eq!(rec, Some((1, Some(Db::doc(vec!['a', 'b'])))));
+    }
+
+    #[test]
+    fn is_faulted_only_by_faults_that_strike() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        let mut planner = Planner::new(Config::new());
+        let mut client = planner.client("A");
+        client.put_unlinked("/path/x.json", |_| None);
+        client.put_unlinked("/path/x.json", |_| Some(vec!['z']));
+        let plan = planner.orderings().next().unwrap();
+
+        actor.dispatch(plan[0]);
+        actor.time_out(false);
+        actor.dispatch(plan[1]);
+        assert!(!actor.is_faulted());
+
+        actor.dispatch(plan[2]);
+        actor.time_out(false);
+        actor.dispatch(plan[3]);
+        assert!(actor.is_faulted());
+
+        actor.reset();
+        actor.guard(&y_path(), |doc| doc.is_none());
+        actor.partition();
+        actor.dispatch(plan[3]);
+        assert!(!actor.is_faulted());
+    }
+
+    fn transaction_plan(config: Config) -> Planner<Vec<char>> {
+        let mut planner = Planner::new(config);
+        planner.client("A").transaction(|tx| {
+            tx.put_unlinked("/path/x.json", |_| Some(vec!['x']));
+            tx.put_unlinked("/path/to/y.json", |_| Some(vec!['y']));
+        });
+        planner
+    }
+
+    #[test]
+    fn writes_a_transaction_in_one_batch() {
+        let store = make_store();
+        let config = Config::new().write_batch(true);
+        let mut actor = Actor::new(&store, config.clone());
+
+        let planner = transaction_plan(config);
+        let plan = planner.orderings().next().unwrap();
+        let (commit, rest) = plan.split_last().unwrap();
+
+        for act in rest {
+            actor.dispatch(act);
+        }
+        assert_eq!(
+            store.borrow().get(&x_path()),
+            Some(&Db::doc(vec!['a', 'b']))
+        );
+
+        actor.dispatch(commit);
+        assert!(actor.is_acknowledged());
+        assert_eq!(
+            db::doc_value(&store.borrow(), "/path/x.json"),
+            Some(vec!['x'])
+        );
+        assert_eq!(
+            db::doc_value(&store.borrow(), "/path/to/y.json"),
+            Some(vec!['y'])
+        );
+    }
+
+    #[test]
+    fn writes_nothing_if_a_transaction_conflicts() {
+        let store = make_store();
+        let config = Config::new().write_batch(true);
+        let mut actor = Actor::new(&store, config.clone());
+
+        let planner = transaction_plan(config);
+        let plan = planner.orderings().next().unwrap();
+        let (commit, rest) = plan.split_last().unwrap();
+
+        for act in rest {
+            actor.dispatch(act);
+        }
+        store
+            .borrow_mut()
+            .write(y_path(), Some(1), Db::doc(vec!['z']));
+        actor.dispatch(commit);
+
+        assert!(!actor.is_acknowledged());
+        assert_eq!(
+            db::doc_value(&store.borrow(), "/path/x.json"),
+            Some(vec!['a', 'b'])
+        );
+        assert_eq!(
+            db::doc_value(&store.borrow(), "/path/to/y.json"),
+            Some(vec!['z'])
+        );
+    }
+
+    #[test]
+    fn writes_a_transaction_key_by_key_without_write_batches() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        let planner = transaction_plan(Config::new());
+        let plan = planner.orderings().next().unwrap();
+        let (_, rest) = plan.split_last().unwrap();
+
+        for act in rest {
+            actor.dispatch(act);
+        }
+        assert_eq!(
+            db::doc_value(&store.borrow(), "/path/x.json"),
+            Some(vec!['x'])
+        );
+    }
+
+    #[test]
+    fn forgets_cached_reads_and_crashes_when_reset() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        actor.get(&x_path());
+        store
+            .borrow_mut()
+            .write(x_path(), Some(1), Db::doc(vec!['z']));
+        actor.put(&x_path(), "A", |_| Some(vec!['p']));
+        assert!(!actor.is_acknowledged());
+
+        actor.reset();
+        assert!(actor.is_acknowledged());
+
+        actor.put(&x_path(), "A", |doc| Some([doc?, vec!['q']].concat()));
+        assert_eq!(
+            store.borrow().read(&x_path()),
+            Some((3, Some(Db::doc_from(vec!['z', 'q'], 0, "A"))))
+        );
+    }
+
+    #[test]
+    fn forgets_what_it_had_under_way_when_restarted() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        actor.rm(&y_path());
+        actor.restart();
+        actor.unlink(&"/path/to/".into(), "y.json");
+
+        assert!(actor.is_faulted());
+        assert_eq!(actor.cached("/path/to/"), None);
+        assert_eq!(
+            store.borrow().read("/path/to/"),
+            Some((1, Some(Db::dir_from(&["y.json"]))))
+        );
+    }
+
+    #[test]
+    fn stays_stopped_when_restarted_after_a_failed_write() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        actor.get(&x_path());
+        store
+            .borrow_mut()
+            .write(x_path(), Some(1), Db::doc(vec!['z']));
+        actor.put(&x_path(), "A", |_| Some(vec!['p']));
+        actor.restart();
+        actor.put(&x_path(), "A", |_| Some(vec!['q']));
+
+        assert!(!actor.is_faulted());
+        assert_eq!(
+            store.borrow().read(&x_path()),
+            Some((2, Some(Db::doc(vec!['z']))))
+        );
+    }
+
+    #[test]
+    fn aborts_after_a_timed_out_write_by_default() {
+        let store = make_store();
+        let mut planner = Planner::new(Config::new());
+        planner
+            .client("A")
+            .update("/path/x.json", |_| Some(vec!['z']));
+        let plan = planner.orderings().next().unwrap();
+
+        let mut actor = Actor::new(&store, Config::new());
+        let link = plan.iter().position(|act| act.op.may_write()).unwrap();
+
+        for (i, act) in plan.iter().enumerate() {
+            if i == link {
+                actor.time_out(false);
+            }
+            actor.dispatch(act);
+        }
+
+        assert_eq!(
+            store.borrow().get(&x_path()),
+            Some(&Db::doc(vec!['a', 'b']))
+        );
     }
 
-    fn y_path() -> Path {
-        Path::from("/path/to/y.json")
+    #[test]
+    fn continues_after_a_timed_out_write_that_was_applied() {
+        let store = make_store();
+        let config = Config::new().timeout(Timeout::Continue);
+        let mut planner = Planner::new(config.clone());
+        let mut client = planner.client("A");
+        client.put_unlinked("/path/x.json", |_| Some(vec!['y']));
+        client.put_unlinked("/path/x.json", |doc| Some([doc?, vec!['z']].concat()));
+        let plan = planner.orderings().next().unwrap();
+
+        let mut actor = Actor::new(&store, config);
+        let put = plan.iter().position(|act| act.op.may_write()).unwrap();
+
+        for (i, act) in plan.iter().enumerate() {
+            if i == put {
+                actor.time_out(true);
+            }
+            actor.dispatch(act);
+        }
+
+        let rec = store.borrow().read(&x_path());
+        assert_eq!(rec, Some((3, Some(Db::doc_from(vec!['y', 'z'], 4, "A")))));
     }
 
-    fn make_store() -> RefCell<DbStore<Vec<char>>> {
-        let mut store = DbStore::new(Config::new());
+    #[test]
+    fn retries_a_timed_out_update_against_the_current_value() {
+        let store = make_store();
+        let config = Config::new().timeout(Timeout::Retry);
 
-        store.write("/".into(), None, Db::dir_from(&["path/"]));
-        store.write("/path/".into(), None, Db::dir_from(&["to/", "x.json"]));
-        store.write("/path/to/".into(), None, Db::dir_from(&["y.json"]));
+        let mut planner = Planner::new(config.clone());
+        planner
+            .client("A")
+            .put_unlinked("/path/x.json", |doc| Some([doc?, vec!['z']].concat()));
+        let plan = planner.orderings().next().unwrap();
 
-        store.write(x_path(), None, Db::Doc(vec!['a', 'b']));
-        store.write(y_path(), None, Db::Doc(vec!['c', 'd', 'e']));
+        let mut actor = Actor::new(&store, config);
+        actor.dispatch(plan[0]);
+        actor.time_out(true);
+        actor.dispatch(plan[1]);
 
-        RefCell::new(store)
+        let rec = store.borrow().read(&x_path());
+        assert_eq!(
+            rec,
+            Some((3, Some(Db::doc_from(vec!['a', 'b', 'z', 'z'], 2, "A"))))
+        );
     }
 
     #[test]
-    fn gets_an_existing_document() {
+    fn reads_a_doc_and_its_dirs_in_one_batch() {
         let store = make_store();
         let mut actor = Actor::new(&store, Config::new());
 
-        let doc = actor.get(&x_path());
-        assert_eq!(doc, Some(vec!['a', 'b']));
+        actor.read_many(&["/".into(), "/path/".into()], &x_path());
+
+        store
+            .borrow_mut()
+            .write("/path/".into(), Some(2), Db::dir_from(&["to/"]));
+        store
+            .borrow_mut()
+            .write(x_path(), Some(4), Db::doc(vec!['z']));
+
+        assert_eq!(
+            actor.list("/path/"),
+            Some(BTreeSet::from(["to/".into(), "x.json".into()]))
+        );
+        assert_eq!(actor.get(&x_path()), Some(vec!['a', 'b']));
     }
 
     #[test]
-    fn returns_none_for_a_missing_document() {
+    fn lists_directories_by_prefix_scan_with_a_flat_layout() {
         let store = make_store();
-        let mut actor = Actor::new(&store, Config::new());
+        let mut actor = Actor::new(&store, Config::new().layout(Layout::Flat));
 
-        let doc = actor.get(&"/y.json".into());
-        assert_eq!(doc, None);
+        assert_eq!(
+            actor.list("/path/"),
+            Some(BTreeSet::from(["to/".into(), "x.json".into()]))
+        );
+        assert_eq!(
+            actor.list("/path/to/"),
+            Some(BTreeSet::from(["y.json".into()]))
+        );
+        assert_eq!(actor.list("/other/"), None);
     }
 
     #[test]
-    fn updates_a_document() {
-        let store = make_store();
-        let mut actor = Actor::new(&store, Config::new());
+    fn maintains_a_manifest_with_a_manifest_layout() {
+        let config = Config::new().layout(Layout::Manifest);
+        let store = RefCell::new(DbStore::new(config.clone()));
+        let mut actor = Actor::new(&store, config);
 
-        actor.get(&x_path());
-        actor.put(&x_path(), |doc| Some(doc?.iter().rev().cloned().collect()));
+        actor.list("/");
+        actor.link(&"/".into(), "/path/to/x.json", Kind::Doc);
+        actor.link(&"/".into(), "/path/y.json", Kind::Doc);
 
-        let rec = store.borrow().read(&x_path());
-        assert_eq!(rec, Some((2, Some(Db::Doc(vec!['b', 'a'])))));
+        assert_eq!(
+            actor.list("/path/"),
+            Some(BTreeSet::from(["to/".into(), "y.json".into()]))
+        );
 
-        let doc = actor.get(&x_path());
-        assert_eq!(doc, Some(vec!['b', 'a']));
+        actor.put(&"/path/y.json".into(), "A", |_| Some(vec!['y']));
+        actor.rm(&"/path/y.json".into());
+        actor.unlink(&"/".into(), "/path/y.json");
+
+        assert_eq!(
+            store.borrow().read("/"),
+            Some((3, Some(Db::dir_from(&["/path/to/x.json"]))))
+        );
     }
 
     #[test]
-    fn updates_a_document_multiple_times() {
-        let store = make_store();
-        let mut actor = Actor::new(&store, Config::new());
+    fn aggregates_listings_across_buckets_with_a_bucket_layout() {
+        let config = Config::new().layout(Layout::Buckets { count: 2 });
+        let store: RefCell<DbStore<char>> = RefCell::new(DbStore::new(config.clone()));
+        let mut actor = Actor::new(&store, config);
 
-        actor.get(&x_path());
-        actor.put(&x_path(), |doc| Some(doc?.iter().rev().cloned().collect()));
+        let x = Path::from("/path/x.json");
+        let y = Path::from("/path/to/y.json");
+        let (bx, by) = (db::bucket_key(2, &x), db::bucket_key(2, &y));
+        assert_ne!(bx, by);
 
-        actor.put(&x_path(), |doc| {
-            doc.map(|mut d| {
-                d.push('z');
-                d
-            })
-        });
+        actor.link(&bx.into(), x.full(), Kind::Doc);
+        actor.link(&by.into(), y.full(), Kind::Doc);
 
-        let rec = store.borrow().read(&x_path());
-        assert_eq!(rec, Some((3, Some(Db::Doc(vec!['b', 'a', 'z'])))));
+        assert_eq!(
+            actor.list("/path/"),
+            Some(BTreeSet::from(["to/".into(), "x.json".into()]))
+        );
+        assert_eq!(
+            store.borrow().read(bx),
+            Some((1, Some(Db::dir_from(&["/path/x.json"]))))
+        );
+    }
 
-        let doc = actor.get(&x_path());
-        assert_eq!(doc, Some(vec!['b', 'a', 'z']));
+    fn make_packed_store() -> (Config, RefCell<DbStore<Vec<char>>>) {
+        let config = Config::new().layout(Layout::Packed { threshold: 2 });
+        let mut store = DbStore::new(config.clone());
+
+        store.write("/".into(), None, Db::packed_from(&[("path/", None)]));
+        store.write(
+            "/path/".into(),
+            None,
+            Db::packed_from(&[("x.json", Some(vec!['a'])), ("y.json", None)]),
+        );
+        store.write("/path/y.json".into(), None, Db::doc(vec!['b', 'c', 'd']));
+
+        (config, RefCell::new(store))
     }
 
     #[test]
-    fn fails_to_write_a_conflicting_update() {
-        let store = make_store();
-        let mut actor = Actor::new(&store, Config::new());
+    fn reads_inline_and_spilled_docs_with_a_packed_layout() {
+        let (config, store) = make_packed_store();
+        let mut actor = Actor::new(&store, config);
 
-        actor.get(&x_path());
+        assert_eq!(actor.get(&x_path()), Some(vec!['a']));
+        assert_eq!(actor.get(&"/path/y.json".into()), Some(vec!['b', 'c', 'd']));
+        assert_eq!(
+            actor.list("/path/"),
+            Some(BTreeSet::from(["x.json".into(), "y.json".into()]))
+        );
+    }
 
-        store
-            .borrow_mut()
-            .write(x_path(), Some(1), Db::Doc(vec!['z']));
+    #[test]
+    fn spills_a_doc_that_grows_over_the_threshold() {
+        let (config, store) = make_packed_store();
+        let mut actor = Actor::new(&store, config);
 
-        actor.put(&x_path(), |_| Some(vec!['p', 'q']));
+        actor.put(&x_path(), "A", |_| Some(vec!['a', 'b', 'c']));
 
-        let rec = store.borrow().read(&x_path());
-        assert_eq!(rec, Some((2, Some(Db::Doc(vec!['z'])))));
+        assert_eq!(
+            store.borrow().read("/path/"),
+            Some((
+                2,
+                Some(Db::packed_from(&[("x.json", None), ("y.json", None)]))
+            ))
+        );
+        assert_eq!(
+            store.borrow().read(&x_path()),
+            Some((1, Some(Db::doc_from(vec!['a', 'b', 'c'], 0, "A"))))
+        );
+        assert_eq!(actor.get(&x_path()), Some(vec!['a', 'b', 'c']));
     }
 
     #[test]
-    fn does_not_perform_more_actions_after_a_failed_write() {
-        let store = make_store();
-        let mut actor = Actor::new(&store, Config::new());
+    fn packs_a_doc_that_shrinks_under_the_threshold() {
+        let (config, store) = make_packed_store();
+        let mut actor = Actor::new(&store, config);
 
-        actor.get(&x_path());
+        actor.put(&"/path/y.json".into(), "A", |_| Some(vec!['z']));
 
-        store
-            .borrow_mut()
-            .write(x_path(), Some(1), Db::Doc(vec!['z']));
+        assert_eq!(
+            store.borrow().read("/path/"),
+            Some((
+                2,
+                Some(Db::packed_from(&[
+                    ("x.json", Some(vec!['a'])),
+                    ("y.json", Some(vec!['z']))
+                ]))
+            ))
+        );
+        assert_eq!(store.borrow().read("/path/y.json"), Some((2, None)));
+    }
 
-        actor.put(&x_path(), |_| Some(vec!['p', 'q']));
+    #[test]
+    fn removes_an_inline_doc_with_a_packed_layout() {
+        let (config, store) = make_packed_store();
+        let mut actor = Actor::new(&store, config);
 
-        assert_eq!(actor.get(&x_path()), None);
-        actor.put(&x_path(), |_| Some(vec!['x', 'y']));
+        actor.rm(&x_path());
+        actor.unlink(&"/path/".into(), "x.json");
 
-        let rec = store.borrow().read(&x_path());
-        assert_eq!(rec, Some((2, Some(Db::Doc(vec!['z'])))));
+        assert_eq!(
+            store.borrow().read("/path/"),
+            Some((3, Some(Db::packed_from(&[("y.json", None)]))))
+        );
+        assert_eq!(store.borrow().read(&x_path()), None);
     }
 
     #[test]
@@ -257,8 +1800,8 @@ mod tests {
         let store = make_store();
         let mut actor = Actor::new(&store, Config::new());
 
-        actor.link(&"/path/".into(), "a.txt");
-        actor.link(&"/path/".into(), "z.txt");
+        actor.link(&"/path/".into(), "a.txt", Kind::Doc);
+        actor.link(&"/path/".into(), "z.txt", Kind::Doc);
 
         let rec = store.borrow().read("/path/");
         assert_eq!(
@@ -272,7 +1815,7 @@ mod tests {
         let store = make_store();
         let mut actor = Actor::new(&store, Config::new());
 
-        actor.link(&"/path/".into(), "x.json");
+        actor.link(&"/path/".into(), "x.json", Kind::Doc);
 
         let rec = store.borrow().read("/path/");
         assert_eq!(rec, Some((2, Some(Db::dir_from(&["to/", "x.json"])))));
@@ -283,7 +1826,7 @@ mod tests {
         let store = make_store();
         let mut skipper = Actor::new(&store, Config::new().skip_links(true));
 
-        skipper.link(&"/path/".into(), "x.json");
+        skipper.link(&"/path/".into(), "x.json", Kind::Doc);
 
         let rec = store.borrow().read("/path/");
         assert_eq!(rec, Some((1, Some(Db::dir_from(&["to/", "x.json"])))));
@@ -294,7 +1837,7 @@ mod tests {
         let store = make_store();
         let mut skipper = Actor::new(&store, Config::new().skip_links(true));
 
-        skipper.link(&"/path/".into(), "a.json");
+        skipper.link(&"/path/".into(), "a.json", Kind::Doc);
 
         let rec = store.borrow().read("/path/");
         assert_eq!(
@@ -359,6 +1902,67 @@ mod tests {
         assert_eq!(store.borrow().read("/path/x.json"), Some((2, None)));
     }
 
+    fn remove_while_creating(config: Config) -> RefCell<DbStore<Vec<char>>> {
+        let store = make_store();
+        let mut a = Actor::new(&store, config.clone());
+        let mut b = Actor::new(&store, config);
+
+        a.rm(&"/path/to/y.json".into());
+        a.unlink(&"/path/to/".into(), "y.json");
+        b.link(&"/path/to/".into(), "z.json", Kind::Doc);
+        a.unlink(&"/path/".into(), "to/");
+
+        store
+    }
+
+    #[test]
+    fn removes_a_dir_that_gains_entries_after_it_was_listed_by_default() {
+        let store = remove_while_creating(Config::new());
+
+        assert_eq!(
+            store.borrow().read("/path/"),
+            Some((2, Some(Db::dir_from(&["x.json"]))))
+        );
+        assert_eq!(
+            store.borrow().read("/path/to/"),
+            Some((3, Some(Db::dir_from(&["z.json"]))))
+        );
+    }
+
+    #[test]
+    fn keeps_a_dir_that_gains_entries_if_relisting_before_unlink() {
+        let store = remove_while_creating(Config::new().relist_before_unlink(true));
+
+        assert_eq!(
+            store.borrow().read("/path/"),
+            Some((1, Some(Db::dir_from(&["to/", "x.json"]))))
+        );
+        assert_eq!(
+            store.borrow().read("/path/to/"),
+            Some((3, Some(Db::dir_from(&["z.json"]))))
+        );
+    }
+
+    #[test]
+    fn traces_the_reads_that_relist_before_unlink() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new().relist_before_unlink(true));
+
+        actor.rm(&"/path/to/y.json".into());
+        actor.unlink(&"/path/to/".into(), "y.json");
+        actor.trace_reads(true);
+        actor.unlink(&"/path/".into(), "to/");
+
+        assert_eq!(
+            actor.take_reads(),
+            [Path::from("/path/"), "/path/to/".into()]
+        );
+        assert_eq!(
+            store.borrow().read("/path/"),
+            Some((2, Some(Db::dir_from(&["x.json"]))))
+        );
+    }
+
     #[test]
     fn does_not_decide_to_remove_directories_by_default() {
         let store = make_store();
@@ -381,4 +1985,182 @@ mod tests {
             Some((1, Some(Db::dir_from(&["y.json"]))))
         );
     }
+
+    #[test]
+    fn flushes_a_rename_within_a_dir_with_one_write() {
+        let store = make_store();
+        let config = Config::new().write_back(true);
+        let mut actor = Actor::new(&store, config.clone());
+
+        let mut planner = Planner::new(config);
+        planner
+            .client("A")
+            .rename("/path/to/y.json", "/path/to/z.json", |doc| doc);
+
+        for act in planner.orderings().next().unwrap() {
+            actor.dispatch(act);
+        }
+
+        assert_eq!(
+            store.borrow().read("/"),
+            Some((2, Some(Db::dir_from(&["path/"]))))
+        );
+        assert_eq!(
+            store.borrow().read("/path/"),
+            Some((2, Some(Db::dir_from(&["to/", "x.json"]))))
+        );
+        assert_eq!(
+            store.borrow().read("/path/to/"),
+            Some((2, Some(Db::dir_from(&["z.json"]))))
+        );
+        assert_eq!(store.borrow().read("/path/to/y.json"), Some((2, None)));
+    }
+
+    #[test]
+    fn writes_to_a_dir_another_client_changed_after_syncing() {
+        let store = make_store();
+        let mut a = Actor::new(&store, Config::new());
+        let mut b = Actor::new(&store, Config::new());
+
+        a.list("/path/");
+        b.link(&"/path/".into(), "z.json", Kind::Doc);
+        a.sync(&"/".into());
+        a.link(&"/path/".into(), "w.json", Kind::Doc);
+
+        assert_eq!(
+            store.borrow().read("/path/"),
+            Some((
+                3,
+                Some(Db::dir_from(&["to/", "w.json", "x.json", "z.json"]))
+            ))
+        );
+    }
+
+    #[test]
+    fn repairs_a_link_to_an_unlinked_document() {
+        let store = make_store();
+        store
+            .borrow_mut()
+            .write("/path/".into(), Some(1), Db::dir_from(&["to/"]));
+
+        let mut actor = Actor::new(&store, Config::new());
+        actor.repair(&"/path/".into(), "x.json", Kind::Doc);
+
+        assert_eq!(
+            store.borrow().read("/path/"),
+            Some((3, Some(Db::dir_from(&["to/", "x.json"]))))
+        );
+    }
+
+    #[test]
+    fn repairs_a_link_to_a_missing_document() {
+        let store = make_store();
+        store.borrow_mut().remove(x_path(), Some(1));
+
+        let mut actor = Actor::new(&store, Config::new());
+        actor.repair(&"/path/".into(), "x.json", Kind::Doc);
+
+        assert_eq!(
+            store.borrow().read("/path/"),
+            Some((2, Some(Db::dir_from(&["to/"]))))
+        );
+    }
+
+    #[test]
+    fn does_not_change_consistent_links_during_repair() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+
+        actor.repair(&"/path/".into(), "x.json", Kind::Doc);
+        actor.repair(&"/path/".into(), "to/", Kind::Dir);
+
+        assert_eq!(
+            store.borrow().read("/path/"),
+            Some((1, Some(Db::dir_from(&["to/", "x.json"]))))
+        );
+    }
+
+    fn advance_to(store: &RefCell<DbStore<Vec<char>>>, time: Time) {
+        while !store.borrow().clock.has_reached(time) {
+            store.borrow_mut().clock.tick();
+        }
+    }
+
+    #[test]
+    fn does_not_acquire_a_live_lease_held_by_another_client() {
+        let store = make_store();
+        let mut a = Actor::new(&store, Config::new());
+        let mut b = Actor::new(&store, Config::new());
+        let dir = Path::from("/path/");
+
+        a.acquire(&dir, "A", 3);
+        advance_to(&store, 2);
+        b.acquire(&dir, "B", 3);
+        b.put(&x_path(), "B", |_| Some(vec!['z']));
+
+        assert_eq!(
+            store.borrow().get("#lock/path/"),
+            Some(&Db::Lease(Lease::new("A", 3)))
+        );
+        assert_eq!(
+            store.borrow().get(&x_path()),
+            Some(&Db::doc(vec!['a', 'b']))
+        );
+    }
+
+    #[test]
+    fn takes_over_an_expired_lease() {
+        let store = make_store();
+        let mut a = Actor::new(&store, Config::new());
+        let mut b = Actor::new(&store, Config::new());
+        let dir = Path::from("/path/");
+
+        a.acquire(&dir, "A", 3);
+        advance_to(&store, 3);
+        b.acquire(&dir, "B", 3);
+
+        assert_eq!(
+            store.borrow().get("#lock/path/"),
+            Some(&Db::Lease(Lease::new("B", 6)))
+        );
+
+        a.release(&dir);
+        assert!(store.borrow().get("#lock/path/").is_some());
+    }
+
+    #[test]
+    fn reports_writes_made_after_a_lease_expires() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+        let dir = Path::from("/path/");
+
+        actor.acquire(&dir, "A", 2);
+        actor.get(&x_path());
+        actor.get(&y_path());
+
+        advance_to(&store, 1);
+        actor.put(&x_path(), "A", |_| Some(vec!['z']));
+        assert!(store.borrow().violations.is_empty());
+
+        advance_to(&store, 2);
+        actor.put(&y_path(), "A", |_| Some(vec!['z']));
+        assert_eq!(
+            store.borrow().violations,
+            [
+                "client 'A' wrote '/path/to/y.json' at time 2 after its lease on '/path/' expired at time 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn releases_a_held_lease() {
+        let store = make_store();
+        let mut actor = Actor::new(&store, Config::new());
+        let dir = Path::from("/path/");
+
+        actor.acquire(&dir, "A", 2);
+        actor.release(&dir);
+
+        assert_eq!(store.borrow().get("#lock/path/"), None);
+    }
 }
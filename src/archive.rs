@@ -0,0 +1,379 @@
+use crate::config::Config;
+use crate::db::Violation;
+use crate::runner::Record;
+
+use self::sqlite::{Connection, Value};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS executions (
+        scenario  TEXT NOT NULL,
+        config    TEXT NOT NULL,
+        ordering  INTEGER NOT NULL,
+        fault     TEXT,
+        outcome   TEXT NOT NULL,
+        violation TEXT,
+        error     TEXT
+    )
+";
+
+const INSERT: &str = "INSERT INTO executions VALUES (?, ?, ?, ?, ?, ?, ?)";
+
+pub struct Archive {
+    db: Connection,
+}
+
+impl Archive {
+    pub fn open(path: &str) -> Result<Archive, String> {
+        let db = Connection::open(path)?;
+        db.prepare(SCHEMA)?.execute(&[])?;
+        Ok(Archive { db })
+    }
+
+    pub fn write(
+        &mut self,
+        scenario: &str,
+        config: &Config,
+        records: &[Record],
+    ) -> Result<(), String> {
+        let config = format!("{:?}", config);
+
+        self.db.prepare("BEGIN")?.execute(&[])?;
+        let written = self.insert(scenario, &config, records);
+        let end = if written.is_ok() {
+            "COMMIT"
+        } else {
+            "ROLLBACK"
+        };
+        self.db.prepare(end)?.execute(&[])?;
+
+        written
+    }
+
+    pub fn query(&self, sql: &str) -> Result<Vec<Vec<Option<String>>>, String> {
+        self.db.prepare(sql)?.query()
+    }
+
+    fn insert(&self, scenario: &str, config: &str, records: &[Record]) -> Result<(), String> {
+        let mut insert = self.db.prepare(INSERT)?;
+
+        for record in records {
+            let outcome = if record.error.is_some() {
+                "fail"
+            } else {
+                "pass"
+            };
+            insert.execute(&[
+                Value::Text(scenario),
+                Value::Text(config),
+                Value::Int(record.ordering as i64),
+                Value::from(record.fault.as_deref()),
+                Value::Text(outcome),
+                Value::from(record.violation.map(violation_kind)),
+                Value::from(record.error.as_deref()),
+            ])?;
+        }
+        Ok(())
+    }
+}
+
+pub fn violation_kind(violation: Violation) -> &'static str {
+    match violation {
+        Violation::Unlinked => "unlinked",
+        Violation::MissingDir => "missing dir",
+        Violation::LeaseExpired => "lease expired",
+        Violation::FinalCheck => "final check",
+    }
+}
+
+// The crate's only unsafe code: just enough of the system SQLite library to
+// run prepared statements. Values are always bound as parameters, never
+// pasted into SQL, and each binding is cleared before `execute` returns, so
+// SQLite never holds a pointer into a Rust value past the call it was lent to.
+mod sqlite {
+    use std::ffi::{c_char, c_int, c_void, CStr, CString};
+    use std::ptr;
+
+    #[repr(C)]
+    struct Sqlite3 {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    struct Stmt {
+        _private: [u8; 0],
+    }
+
+    #[link(name = "sqlite3")]
+    extern "C" {
+        fn sqlite3_open(filename: *const c_char, db: *mut *mut Sqlite3) -> c_int;
+        fn sqlite3_close(db: *mut Sqlite3) -> c_int;
+        fn sqlite3_errmsg(db: *mut Sqlite3) -> *const c_char;
+        fn sqlite3_prepare_v2(
+            db: *mut Sqlite3,
+            sql: *const c_char,
+            bytes: c_int,
+            stmt: *mut *mut Stmt,
+            tail: *mut *const c_char,
+        ) -> c_int;
+        fn sqlite3_bind_int64(stmt: *mut Stmt, index: c_int, value: i64) -> c_int;
+        fn sqlite3_bind_null(stmt: *mut Stmt, index: c_int) -> c_int;
+        // The destructor is always null, SQLITE_STATIC, so it is declared as
+        // a plain pointer.
+        fn sqlite3_bind_text(
+            stmt: *mut Stmt,
+            index: c_int,
+            value: *const c_char,
+            bytes: c_int,
+            destructor: *const c_void,
+        ) -> c_int;
+        fn sqlite3_step(stmt: *mut Stmt) -> c_int;
+        fn sqlite3_column_count(stmt: *mut Stmt) -> c_int;
+        fn sqlite3_column_type(stmt: *mut Stmt, column: c_int) -> c_int;
+        fn sqlite3_column_text(stmt: *mut Stmt, column: c_int) -> *const c_char;
+        fn sqlite3_reset(stmt: *mut Stmt) -> c_int;
+        fn sqlite3_clear_bindings(stmt: *mut Stmt) -> c_int;
+        fn sqlite3_finalize(stmt: *mut Stmt) -> c_int;
+    }
+
+    const SQLITE_OK: c_int = 0;
+    const SQLITE_ROW: c_int = 100;
+    const SQLITE_DONE: c_int = 101;
+    const SQLITE_NULL: c_int = 5;
+
+    pub struct Connection {
+        db: *mut Sqlite3,
+    }
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            unsafe {
+                sqlite3_close(self.db);
+            }
+        }
+    }
+
+    impl Connection {
+        pub fn open(path: &str) -> Result<Connection, String> {
+            let filename = CString::new(path).map_err(|e| e.to_string())?;
+            let mut db = ptr::null_mut();
+
+            let rc = unsafe { sqlite3_open(filename.as_ptr(), &mut db) };
+            let connection = Connection { db };
+
+            if rc != SQLITE_OK {
+                return Err(format!("could not open '{}' (code {})", path, rc));
+            }
+            Ok(connection)
+        }
+
+        pub fn prepare(&self, sql: &str) -> Result<Statement<'_>, String> {
+            let bytes = c_int::try_from(sql.len()).map_err(|e| e.to_string())?;
+            let mut stmt = ptr::null_mut();
+
+            let rc = unsafe {
+                sqlite3_prepare_v2(
+                    self.db,
+                    sql.as_ptr().cast(),
+                    bytes,
+                    &mut stmt,
+                    ptr::null_mut(),
+                )
+            };
+            let statement = Statement { db: self, stmt };
+
+            if rc != SQLITE_OK {
+                return Err(self.error());
+            }
+            Ok(statement)
+        }
+
+        fn error(&self) -> String {
+            let message = unsafe { CStr::from_ptr(sqlite3_errmsg(self.db)) };
+            message.to_string_lossy().into_owned()
+        }
+    }
+
+    pub enum Value<'v> {
+        Int(i64),
+        Text(&'v str),
+        Null,
+    }
+
+    impl<'v> From<Option<&'v str>> for Value<'v> {
+        fn from(value: Option<&'v str>) -> Value<'v> {
+            value.map_or(Value::Null, Value::Text)
+        }
+    }
+
+    pub struct Statement<'c> {
+        db: &'c Connection,
+        stmt: *mut Stmt,
+    }
+
+    impl Drop for Statement<'_> {
+        fn drop(&mut self) {
+            unsafe {
+                sqlite3_finalize(self.stmt);
+            }
+        }
+    }
+
+    impl Statement<'_> {
+        // Runs the statement once with `params` bound in order, ignoring any
+        // rows it returns.
+        pub fn execute(&mut self, params: &[Value<'_>]) -> Result<(), String> {
+            let stepped = self.bind(params).and_then(|()| loop {
+                match unsafe { sqlite3_step(self.stmt) } {
+                    SQLITE_ROW => continue,
+                    SQLITE_DONE => break Ok(()),
+                    _ => break Err(self.db.error()),
+                }
+            });
+            unsafe {
+                sqlite3_reset(self.stmt);
+                sqlite3_clear_bindings(self.stmt);
+            }
+            stepped
+        }
+
+        pub fn query(&mut self) -> Result<Vec<Vec<Option<String>>>, String> {
+            let mut rows = Vec::new();
+
+            loop {
+                match unsafe { sqlite3_step(self.stmt) } {
+                    SQLITE_ROW => rows.push(self.row()),
+                    SQLITE_DONE => break,
+                    _ => return Err(self.db.error()),
+                }
+            }
+            unsafe {
+                sqlite3_reset(self.stmt);
+            }
+            Ok(rows)
+        }
+
+        fn bind(&mut self, params: &[Value<'_>]) -> Result<(), String> {
+            for (i, param) in params.iter().enumerate() {
+                let index = c_int::try_from(i + 1).map_err(|e| e.to_string())?;
+                let rc = match param {
+                    Value::Int(value) => unsafe { sqlite3_bind_int64(self.stmt, index, *value) },
+                    Value::Text(value) => {
+                        let bytes = c_int::try_from(value.len()).map_err(|e| e.to_string())?;
+                        unsafe {
+                            sqlite3_bind_text(
+                                self.stmt,
+                                index,
+                                value.as_ptr().cast(),
+                                bytes,
+                                ptr::null(),
+                            )
+                        }
+                    }
+                    Value::Null => unsafe { sqlite3_bind_null(self.stmt, index) },
+                };
+                if rc != SQLITE_OK {
+                    return Err(self.db.error());
+                }
+            }
+            Ok(())
+        }
+
+        fn row(&self) -> Vec<Option<String>> {
+            let count = unsafe { sqlite3_column_count(self.stmt) };
+
+            (0..count)
+                .map(|column| unsafe {
+                    if sqlite3_column_type(self.stmt, column) == SQLITE_NULL {
+                        return None;
+                    }
+                    let value = CStr::from_ptr(sqlite3_column_text(self.stmt, column));
+                    Some(value.to_string_lossy().into_owned())
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record {
+                ordering: 1,
+                fault: None,
+                violation: None,
+                error: None,
+            },
+            Record {
+                ordering: 2,
+                fault: Some(String::from("client 'A' loses access from its act #1")),
+                violation: Some(Violation::Unlinked),
+                error: Some(String::from(
+                    "dir '/' does not include name 'x', required by doc '/x'",
+                )),
+            },
+        ]
+    }
+
+    #[test]
+    fn stores_a_row_for_each_execution() {
+        let mut archive = Archive::open(":memory:").unwrap();
+        archive
+            .write("it's a test", &Config::new(), &records())
+            .unwrap();
+
+        let rows = archive
+            .query("SELECT scenario, ordering, fault, outcome, violation FROM executions")
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            [
+                vec![
+                    Some(String::from("it's a test")),
+                    Some(String::from("1")),
+                    None,
+                    Some(String::from("pass")),
+                    None
+                ],
+                vec![
+                    Some(String::from("it's a test")),
+                    Some(String::from("2")),
+                    Some(String::from("client 'A' loses access from its act #1")),
+                    Some(String::from("fail")),
+                    Some(String::from("unlinked"))
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn stores_values_that_look_like_sql() {
+        let mut archive = Archive::open(":memory:").unwrap();
+        let scenario = "x'); DROP TABLE executions; --";
+        archive.write(scenario, &Config::new(), &records()).unwrap();
+
+        let rows = archive
+            .query("SELECT DISTINCT scenario FROM executions")
+            .unwrap();
+
+        assert_eq!(rows, [vec![Some(String::from(scenario))]]);
+    }
+
+    #[test]
+    fn names_each_kind_of_violation() {
+        assert_eq!(violation_kind(Violation::MissingDir), "missing dir");
+        assert_eq!(violation_kind(Violation::LeaseExpired), "lease expired");
+        assert_eq!(violation_kind(Violation::FinalCheck), "final check");
+    }
+
+    #[test]
+    fn reports_sql_errors() {
+        let archive = Archive::open(":memory:").unwrap();
+        let error = archive.query("SELECT * FROM missing").unwrap_err();
+
+        assert_eq!(error, "no such table: missing");
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt;
+
+use crate::runner::Accept;
+use crate::store::Size;
+
+#[derive(Clone, Eq, Hash, PartialEq)]
+pub struct Blob(Vec<u8>);
+
+impl fmt::Debug for Blob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Blob({} bytes, #{:08x})", self.len(), self.checksum())
+    }
+}
+
+impl Size for Blob {
+    fn size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl Accept for Blob {}
+
+impl From<&[u8]> for Blob {
+    fn from(bytes: &[u8]) -> Blob {
+        Blob(bytes.to_vec())
+    }
+}
+
+impl Blob {
+    pub fn new(len: usize, byte: u8) -> Blob {
+        Blob(vec![byte; len])
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn checksum(&self) -> u32 {
+        self.0.iter().fold(0x811c9dc5, |hash, byte| {
+            (hash ^ *byte as u32).wrapping_mul(0x01000193)
+        })
+    }
+}
+
+pub fn fill(len: usize, byte: u8) -> impl Fn(Option<Blob>) -> Option<Blob> + Sync {
+    move |_| Some(Blob::new(len, byte))
+}
+
+pub fn append(len: usize, byte: u8) -> impl Fn(Option<Blob>) -> Option<Blob> + Sync {
+    move |doc| {
+        let mut bytes = doc.map(|b| b.0).unwrap_or_default();
+        bytes.resize(bytes.len() + len, byte);
+        Some(Blob(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    use crate::actor::Actor;
+    use crate::config::Config;
+    use crate::db::{Db, DbStore};
+    use crate::path::Path;
+
+    #[test]
+    fn reports_its_length_as_its_size() {
+        let blob = Blob::new(100, 7);
+        assert_eq!(blob.size(), 100);
+        assert_eq!(Db::doc(blob).size(), 100);
+    }
+
+    #[test]
+    fn formats_as_a_length_and_checksum() {
+        let a = Blob::from(&b"hello"[..]);
+        let b = Blob::from(&b"world"[..]);
+
+        assert_eq!(format!("{:?}", a), "Blob(5 bytes, #4f9f2cab)");
+        assert_ne!(format!("{:?}", a), format!("{:?}", b));
+    }
+
+    #[test]
+    fn builds_update_closures() {
+        let doc = append(3, 1)(fill(2, 9)(None)).unwrap();
+        assert_eq!(doc.bytes(), [9, 9, 1, 1, 1]);
+    }
+
+    #[test]
+    fn fails_to_write_a_blob_over_the_store_quota() {
+        let config = Config::new().quota(64);
+        let store = RefCell::new(DbStore::new(config.clone()));
+        let mut actor = Actor::new(&store, config);
+        let path = Path::from("/x.bin");
+
+        actor.get(&path);
+        actor.put(&path, "A", fill(32, 0));
+        assert_eq!(store.borrow().size, 32);
+
+        actor.put(&path, "A", append(64, 0));
+        assert_eq!(
+            store.borrow().get(&path),
+            Some(&Db::doc_from(Blob::new(32, 0), 0, "A"))
+        );
+        assert_eq!(actor.get(&path), None);
+    }
+}
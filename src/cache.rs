@@ -0,0 +1,289 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::graph::NodeId;
+
+const MAGIC: &[u8; 4] = b"mc2o";
+const HEADER_SIZE: usize = 16;
+
+// One file per plan: a header with its size and ordering count, then each
+// ordering as two-byte act ids. A file is renamed into place once complete.
+pub struct OrderingCache {
+    dir: PathBuf,
+    errors: Mutex<Vec<String>>,
+}
+
+impl OrderingCache {
+    pub fn open(dir: &str) -> Result<OrderingCache, String> {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        Ok(OrderingCache {
+            dir: dir.into(),
+            errors: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn take_errors(&self) -> Vec<String> {
+        std::mem::take(&mut *self.errors.lock().unwrap())
+    }
+
+    // Yields id numbers from 1 to `size`, for the plan's graph to turn into ids.
+    pub fn load(&self, key: u64, size: usize) -> Option<impl Iterator<Item = Vec<usize>> + Send> {
+        let mut file = File::open(self.path(key)).ok()?;
+        let mut header = [0; HEADER_SIZE];
+        file.read_exact(&mut header).ok()?;
+
+        let stored_size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        let count = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let length = file.metadata().ok()?.len();
+
+        let expected = HEADER_SIZE as u64 + 2 * size as u64 * count;
+        if &header[..4] != MAGIC || stored_size != size || length != expected {
+            return None;
+        }
+        Some(Entry {
+            file: BufReader::new(file),
+            size,
+            remaining: count,
+        })
+    }
+
+    // Write errors drop the entry and go to `take_errors()`.
+    pub fn store<'c, I>(
+        &'c self,
+        key: u64,
+        size: usize,
+        orderings: I,
+    ) -> impl Iterator<Item = Vec<NodeId>> + Send + 'c
+    where
+        I: Iterator<Item = Vec<NodeId>> + Send + 'c,
+    {
+        let path = self.path(key);
+        let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+
+        let out = match create(&temp, size) {
+            Ok(file) => Some(file),
+            Err(error) => {
+                self.errors.lock().unwrap().push(error.to_string());
+                None
+            }
+        };
+        Writer {
+            orderings,
+            errors: &self.errors,
+            out,
+            temp,
+            path,
+            count: 0,
+        }
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.orderings", key))
+    }
+}
+
+fn create(path: &PathBuf, size: usize) -> io::Result<BufWriter<File>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&(size as u32).to_le_bytes())?;
+    out.write_all(&0u64.to_le_bytes())?;
+    Ok(out)
+}
+
+struct Entry {
+    file: BufReader<File>,
+    size: usize,
+    remaining: u64,
+}
+
+impl Iterator for Entry {
+    type Item = Vec<usize>;
+
+    // The length was checked on open, so a bad read means the file changed.
+    fn next(&mut self) -> Option<Vec<usize>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let mut bytes = vec![0; 2 * self.size];
+        self.file
+            .read_exact(&mut bytes)
+            .expect("ordering cache entry could not be read");
+
+        let ids: Vec<_> = bytes
+            .chunks(2)
+            .map(|id| u16::from_le_bytes([id[0], id[1]]) as usize)
+            .collect();
+        assert!(
+            ids.iter().all(|id| (1..=self.size).contains(id)),
+            "ordering cache entry names an act outside its plan"
+        );
+        Some(ids)
+    }
+}
+
+struct Writer<'c, I> {
+    orderings: I,
+    errors: &'c Mutex<Vec<String>>,
+    out: Option<BufWriter<File>>,
+    temp: PathBuf,
+    path: PathBuf,
+    count: u64,
+}
+
+impl<I> Writer<'_, I> {
+    fn write(&mut self, ids: &[NodeId]) -> io::Result<()> {
+        let Some(out) = &mut self.out else {
+            return Ok(());
+        };
+        for id in ids {
+            let id = u16::try_from(id.number())
+                .map_err(|_| io::Error::other("plan too large to cache"))?;
+            out.write_all(&id.to_le_bytes())?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let Some(out) = self.out.take() else {
+            return Ok(());
+        };
+        let mut file = out.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(8))?;
+        file.write_all(&self.count.to_le_bytes())?;
+        fs::rename(&self.temp, &self.path)
+    }
+
+    fn abandon(&mut self, error: io::Error) {
+        self.errors.lock().unwrap().push(error.to_string());
+        self.out = None;
+        let _ = fs::remove_file(&self.temp);
+    }
+}
+
+impl<I> Iterator for Writer<'_, I>
+where
+    I: Iterator<Item = Vec<NodeId>>,
+{
+    type Item = Vec<NodeId>;
+
+    fn next(&mut self) -> Option<Vec<NodeId>> {
+        match self.orderings.next() {
+            Some(ids) => {
+                if let Err(error) = self.write(&ids) {
+                    self.abandon(error);
+                }
+                Some(ids)
+            }
+            None => {
+                if let Err(error) = self.finish() {
+                    self.abandon(error);
+                }
+                None
+            }
+        }
+    }
+}
+
+// An unfinished entry is thrown away.
+impl<I> Drop for Writer<'_, I> {
+    fn drop(&mut self) {
+        if self.out.take().is_some() {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::graph::Graph;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("mc2-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.to_str().unwrap().to_string()
+    }
+
+    fn numbers() -> Vec<Vec<usize>> {
+        vec![vec![1, 2, 3], vec![2, 1, 3], vec![2, 3, 1]]
+    }
+
+    fn orderings() -> Vec<Vec<NodeId>> {
+        let mut graph = Graph::new();
+        graph.fan_out(&[], ['a', 'b', 'c']).unwrap();
+
+        numbers()
+            .iter()
+            .map(|ids| ids.iter().map(|id| graph.node_id(*id).unwrap()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn stores_orderings_once_they_run_out() {
+        let dir = temp_dir("cache-store");
+        let cache = OrderingCache::open(&dir).unwrap();
+
+        assert!(cache.load(7, 3).is_none());
+
+        let stored: Vec<_> = cache.store(7, 3, orderings().into_iter()).collect();
+        assert_eq!(stored, orderings());
+
+        let loaded: Vec<_> = cache.load(7, 3).unwrap().collect();
+        assert_eq!(loaded, numbers());
+
+        assert!(cache.load(8, 3).is_none());
+        assert!(cache.load(7, 4).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn leaves_out_orderings_that_do_not_run_out() {
+        let dir = temp_dir("cache-partial");
+        let cache = OrderingCache::open(&dir).unwrap();
+
+        let taken: Vec<_> = cache.store(7, 3, orderings().into_iter()).take(2).collect();
+        assert_eq!(taken.len(), 2);
+
+        assert!(cache.load(7, 3).is_none());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_errors_of_entries_it_fails_to_store() {
+        let dir = temp_dir("cache-error");
+        let cache = OrderingCache::open(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let stored: Vec<_> = cache.store(7, 3, orderings().into_iter()).collect();
+        assert_eq!(stored, orderings());
+
+        assert_eq!(cache.take_errors().len(), 1);
+        assert_eq!(cache.take_errors(), Vec::<String>::new());
+        assert!(cache.load(7, 3).is_none());
+    }
+
+    #[test]
+    fn ignores_entries_of_the_wrong_length() {
+        let dir = temp_dir("cache-truncated");
+        let cache = OrderingCache::open(&dir).unwrap();
+
+        cache.store(7, 3, orderings().into_iter()).for_each(drop);
+
+        let path = cache.path(7);
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 2]).unwrap();
+
+        assert!(cache.load(7, 3).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
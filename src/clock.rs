@@ -0,0 +1,60 @@
+pub type Time = usize;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Clock {
+    now: Time,
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock::default()
+    }
+
+    pub fn now(&self) -> Time {
+        self.now
+    }
+
+    pub fn tick(&mut self) -> Time {
+        self.now += 1;
+        self.now
+    }
+
+    pub fn after(&self, steps: usize) -> Time {
+        self.now + steps
+    }
+
+    pub fn has_reached(&self, time: Time) -> bool {
+        self.now >= time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_at_zero() {
+        assert_eq!(Clock::new().now(), 0);
+    }
+
+    #[test]
+    fn advances_one_step_per_tick() {
+        let mut clock = Clock::new();
+
+        assert_eq!(clock.tick(), 1);
+        assert_eq!(clock.tick(), 2);
+        assert_eq!(clock.now(), 2);
+    }
+
+    #[test]
+    fn compares_against_deadlines() {
+        let mut clock = Clock::new();
+        let deadline = clock.after(2);
+
+        clock.tick();
+        assert!(!clock.has_reached(deadline));
+
+        clock.tick();
+        assert!(clock.has_reached(deadline));
+    }
+}
@@ -2,12 +2,21 @@
 pub enum Update {
     ReadsBeforeLinks,
     GetBeforePut,
+    LazyLinks,
+    SkipKnownLists,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Remove {
     UnlinkReverseSequential,
     UnlinkParallel,
+    NoUnlink,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reads {
+    PerKey,
+    Snapshot,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -16,23 +25,126 @@ pub enum Cas {
     MatchRev,
     NoRev,
     Lax,
+    Stale { window: usize },
+}
+
+impl Cas {
+    // How much the mode lets through that an exact rev check would refuse: a
+    // deleted key written with either its rev or none, with any rev at all,
+    // or any key written with a rev up to `window` writes behind.
+    pub fn laxness(&self) -> usize {
+        match self {
+            Cas::Strict | Cas::NoRev => 0,
+            Cas::MatchRev => 1,
+            Cas::Lax => 2,
+            Cas::Stale { window } => 2 + window,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Timeout {
+    Abort,
+    Continue,
+    Retry,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum Layout {
+    DirDocs,
+    Flat,
+    Manifest,
+    Packed { threshold: usize },
+    Buckets { count: usize },
+}
+
+// One of the config's options, which bisecting flips on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+    Layout,
+    Update,
+    Remove,
+    Reads,
+    SkipLinks,
+    RelistBeforeUnlink,
+    Store,
+    DirStore,
+    Timeout,
+    MaxValueSize,
+    Quota,
+    Repair,
+    Recovery,
+    ServerUpdate,
+    FreshReads,
+    WriteBack,
+    WriteBatch,
+    Retries,
+}
+
+const FIELDS: [Field; 18] = [
+    Field::Layout,
+    Field::Update,
+    Field::Remove,
+    Field::Reads,
+    Field::SkipLinks,
+    Field::RelistBeforeUnlink,
+    Field::Store,
+    Field::DirStore,
+    Field::Timeout,
+    Field::MaxValueSize,
+    Field::Quota,
+    Field::Repair,
+    Field::Recovery,
+    Field::ServerUpdate,
+    Field::FreshReads,
+    Field::WriteBack,
+    Field::WriteBatch,
+    Field::Retries,
+];
+
 #[derive(Clone, Debug)]
 pub struct Config {
+    pub layout: Layout,
     pub update: Update,
     pub remove: Remove,
+    pub reads: Reads,
     pub skip_links: bool,
+    pub relist_before_unlink: bool,
     pub store: Cas,
+    pub dir_store: Option<Cas>,
+    pub timeout: Timeout,
+    pub max_value_size: Option<usize>,
+    pub quota: Option<usize>,
+    pub repair: bool,
+    pub recovery: bool,
+    pub server_update: bool,
+    pub fresh_reads: bool,
+    pub write_back: bool,
+    pub write_batch: bool,
+    pub retries: usize,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
+            layout: Layout::DirDocs,
             update: Update::ReadsBeforeLinks,
             remove: Remove::UnlinkReverseSequential,
+            reads: Reads::PerKey,
             skip_links: false,
+            relist_before_unlink: false,
             store: Cas::Strict,
+            dir_store: None,
+            timeout: Timeout::Abort,
+            max_value_size: None,
+            quota: None,
+            repair: false,
+            recovery: false,
+            server_update: false,
+            fresh_reads: false,
+            write_back: false,
+            write_batch: false,
+            retries: 0,
         }
     }
 }
@@ -42,6 +154,11 @@ impl Config {
         Config::default()
     }
 
+    pub fn layout(mut self, mode: Layout) -> Config {
+        self.layout = mode;
+        self
+    }
+
     pub fn update(mut self, mode: Update) -> Config {
         self.update = mode;
         self
@@ -52,13 +169,159 @@ impl Config {
         self
     }
 
+    pub fn reads(mut self, mode: Reads) -> Config {
+        self.reads = mode;
+        self
+    }
+
     pub fn skip_links(mut self, mode: bool) -> Config {
         self.skip_links = mode;
         self
     }
 
+    pub fn relist_before_unlink(mut self, mode: bool) -> Config {
+        self.relist_before_unlink = mode;
+        self
+    }
+
     pub fn store(mut self, mode: Cas) -> Config {
         self.store = mode;
         self
     }
+
+    pub fn dir_store(mut self, mode: Cas) -> Config {
+        self.dir_store = Some(mode);
+        self
+    }
+
+    pub fn timeout(mut self, mode: Timeout) -> Config {
+        self.timeout = mode;
+        self
+    }
+
+    pub fn max_value_size(mut self, size: usize) -> Config {
+        self.max_value_size = Some(size);
+        self
+    }
+
+    pub fn quota(mut self, size: usize) -> Config {
+        self.quota = Some(size);
+        self
+    }
+
+    pub fn repair(mut self, mode: bool) -> Config {
+        self.repair = mode;
+        self
+    }
+
+    pub fn recovery(mut self, mode: bool) -> Config {
+        self.recovery = mode;
+        self
+    }
+
+    pub fn server_update(mut self, mode: bool) -> Config {
+        self.server_update = mode;
+        self
+    }
+
+    pub fn fresh_reads(mut self, mode: bool) -> Config {
+        self.fresh_reads = mode;
+        self
+    }
+
+    pub fn write_back(mut self, mode: bool) -> Config {
+        self.write_back = mode;
+        self
+    }
+
+    pub fn write_batch(mut self, mode: bool) -> Config {
+        self.write_batch = mode;
+        self
+    }
+
+    pub fn retries(mut self, count: usize) -> Config {
+        self.retries = count;
+        self
+    }
+
+    // The fields that are set differently in the other config.
+    pub fn differences(&self, other: &Config) -> Vec<Field> {
+        FIELDS
+            .into_iter()
+            .filter(|field| self.describe(*field) != other.describe(*field))
+            .collect()
+    }
+
+    // This config with the given fields set as they are in the other.
+    pub fn with_fields(&self, other: &Config, fields: &[Field]) -> Config {
+        let mut config = self.clone();
+        for field in fields {
+            match field {
+                Field::Layout => config.layout = other.layout.clone(),
+                Field::Update => config.update = other.update.clone(),
+                Field::Remove => config.remove = other.remove.clone(),
+                Field::Reads => config.reads = other.reads.clone(),
+                Field::SkipLinks => config.skip_links = other.skip_links,
+                Field::RelistBeforeUnlink => {
+                    config.relist_before_unlink = other.relist_before_unlink
+                }
+                Field::Store => config.store = other.store.clone(),
+                Field::DirStore => config.dir_store = other.dir_store.clone(),
+                Field::Timeout => config.timeout = other.timeout.clone(),
+                Field::MaxValueSize => config.max_value_size = other.max_value_size,
+                Field::Quota => config.quota = other.quota,
+                Field::Repair => config.repair = other.repair,
+                Field::Recovery => config.recovery = other.recovery,
+                Field::ServerUpdate => config.server_update = other.server_update,
+                Field::FreshReads => config.fresh_reads = other.fresh_reads,
+                Field::WriteBack => config.write_back = other.write_back,
+                Field::WriteBatch => config.write_batch = other.write_batch,
+                Field::Retries => config.retries = other.retries,
+            }
+        }
+        config
+    }
+
+    // The field and its value, written as in the config's debug output.
+    pub fn describe(&self, field: Field) -> String {
+        match field {
+            Field::Layout => format!("layout: {:?}", self.layout),
+            Field::Update => format!("update: {:?}", self.update),
+            Field::Remove => format!("remove: {:?}", self.remove),
+            Field::Reads => format!("reads: {:?}", self.reads),
+            Field::SkipLinks => format!("skip_links: {:?}", self.skip_links),
+            Field::RelistBeforeUnlink => {
+                format!("relist_before_unlink: {:?}", self.relist_before_unlink)
+            }
+            Field::Store => format!("store: {:?}", self.store),
+            Field::DirStore => format!("dir_store: {:?}", self.dir_store),
+            Field::Timeout => format!("timeout: {:?}", self.timeout),
+            Field::MaxValueSize => format!("max_value_size: {:?}", self.max_value_size),
+            Field::Quota => format!("quota: {:?}", self.quota),
+            Field::Repair => format!("repair: {:?}", self.repair),
+            Field::Recovery => format!("recovery: {:?}", self.recovery),
+            Field::ServerUpdate => format!("server_update: {:?}", self.server_update),
+            Field::FreshReads => format!("fresh_reads: {:?}", self.fresh_reads),
+            Field::WriteBack => format!("write_back: {:?}", self.write_back),
+            Field::WriteBatch => format!("write_batch: {:?}", self.write_batch),
+            Field::Retries => format!("retries: {:?}", self.retries),
+        }
+    }
+
+    // The laxness of the CAS modes for docs and for dirs, added together.
+    pub fn laxness(&self) -> usize {
+        let dirs = self.dir_store.as_ref().unwrap_or(&self.store);
+        self.store.laxness() + dirs.laxness()
+    }
+
+    // Whether every scenario gets the same plan under both configs. The
+    // planner only looks at the layout, the update and remove strategies, how
+    // reads are batched and whether dir writes are held back.
+    pub fn plans_like(&self, other: &Config) -> bool {
+        self.layout == other.layout
+            && self.update == other.update
+            && self.remove == other.remove
+            && self.reads == other.reads
+            && self.write_back == other.write_back
+    }
 }
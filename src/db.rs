@@ -1,44 +1,386 @@
 use std::cell::RefCell;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
+use crate::clock::Time;
+use crate::config::{Config, Layout};
+use crate::lease::Lease;
 use crate::path::Path;
-use crate::store::{Cache, Rev, Store};
+use crate::store::{Cache, Rev, Size, Store};
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Hash, PartialEq)]
 pub enum Db<T> {
-    Doc(T),
-    Dir(BTreeSet<String>),
+    Doc(T, Meta),
+    Dir(BTreeMap<String, Kind>),
+    Packed(BTreeMap<String, Option<T>>),
+    Lease(Lease),
+}
+
+// What a dir entry names: a doc, or another dir. Each entry carries the tag it
+// was linked with, so nothing reading a dir has to go by the shape of a name.
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+pub enum Kind {
+    Doc,
+    Dir,
 }
 
 impl<T> Db<T> {
+    // The kind of entry that names this record from the dir above it.
+    pub fn kind(&self) -> Option<Kind> {
+        match self {
+            Db::Doc(..) => Some(Kind::Doc),
+            Db::Dir(_) | Db::Packed(_) => Some(Kind::Dir),
+            Db::Lease(_) => None,
+        }
+    }
+}
+
+// What an actor records alongside each doc it writes: the logical time of the
+// write, the client that made it and the size of the value.
+#[derive(Clone, Default, PartialEq)]
+pub struct Meta {
+    pub mtime: Time,
+    pub writer: String,
+    pub size: usize,
+}
+
+impl fmt::Debug for Meta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.writer.is_empty() {
+            write!(f, "Meta(setup, size {})", self.size)
+        } else {
+            write!(
+                f,
+                "Meta({} at t={}, size {})",
+                self.writer, self.mtime, self.size
+            )
+        }
+    }
+}
+
+// Metadata is left out of a store's fingerprint, so executions that leave the
+// same values behind count as one state whenever they were written.
+impl Hash for Meta {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+impl<T> Db<T> {
+    // A doc with no writer, as a scenario's setup leaves it.
+    pub fn doc(value: T) -> Db<T>
+    where
+        T: Size,
+    {
+        Db::doc_from(value, 0, "")
+    }
+
+    // A doc as a client writes it, with the time of the write.
+    pub fn doc_from(value: T, mtime: Time, writer: &str) -> Db<T>
+    where
+        T: Size,
+    {
+        let meta = Meta {
+            mtime,
+            writer: writer.to_string(),
+            size: value.size(),
+        };
+        Db::Doc(value, meta)
+    }
+
+    // A dir holding the given names, tagged as dirs where they end in a slash
+    // and as docs otherwise, as a scenario would write them.
     pub fn dir_from(entries: &[&str]) -> Db<T> {
-        let set = entries.iter().map(|s| s.to_string()).collect();
-        Db::Dir(set)
+        let map = entries
+            .iter()
+            .map(|name| {
+                let kind = if name.ends_with('/') {
+                    Kind::Dir
+                } else {
+                    Kind::Doc
+                };
+                (name.to_string(), kind)
+            })
+            .collect();
+
+        Db::Dir(map)
+    }
+
+    pub fn packed_from(entries: &[(&str, Option<T>)]) -> Db<T>
+    where
+        T: Clone,
+    {
+        let map = entries
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+
+        Db::Packed(map)
+    }
+
+    pub fn has_entry(&self, name: &str) -> Option<bool> {
+        match self {
+            Db::Doc(..) | Db::Lease(_) => None,
+            Db::Dir(entries) => Some(entries.contains_key(name)),
+            Db::Packed(entries) => Some(entries.contains_key(name)),
+        }
+    }
+}
+
+impl<T: Size> Size for Db<T> {
+    fn size(&self) -> usize {
+        match self {
+            Db::Doc(value, _) => value.size(),
+            Db::Dir(entries) => entries.keys().map(String::len).sum(),
+            Db::Packed(entries) => entries
+                .iter()
+                .map(|(name, value)| name.len() + value.as_ref().map_or(0, Size::size))
+                .sum(),
+            Db::Lease(lease) => lease.size(),
+        }
+    }
+}
+
+pub const MANIFEST_KEY: &str = "/";
+
+pub const BUCKET_KEYS: [&str; 8] = ["#0/", "#1/", "#2/", "#3/", "#4/", "#5/", "#6/", "#7/"];
+
+pub fn bucket_key(count: usize, path: &Path) -> &'static str {
+    assert!(
+        count > 0 && count <= BUCKET_KEYS.len(),
+        "bucket count must be between 1 and {}",
+        BUCKET_KEYS.len()
+    );
+    let hash = path.full().bytes().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+    BUCKET_KEYS[hash as usize % count]
+}
+
+// The entries that must exist for the doc at `path` to be reached, as the dir
+// each is in, its name and the kind of record it names.
+pub fn links<'p>(layout: &Layout, path: &'p Path) -> Vec<(&'p str, &'p str, Kind)> {
+    match layout {
+        Layout::DirDocs | Layout::Packed { .. } => {
+            let mut links: Vec<_> = path
+                .links()
+                .map(|(dir, name)| (dir, name, Kind::Dir))
+                .collect();
+
+            if let Some(last) = links.last_mut().filter(|_| path.is_doc()) {
+                last.2 = Kind::Doc;
+            }
+            links
+        }
+        Layout::Flat => Vec::new(),
+        Layout::Manifest => vec![(MANIFEST_KEY, path.full(), Kind::Doc)],
+        Layout::Buckets { count } => vec![(bucket_key(*count, path), path.full(), Kind::Doc)],
+    }
+}
+
+pub fn child_path(dir: &str, name: &str) -> Path {
+    if name.starts_with('/') {
+        Path::from(name)
+    } else {
+        Path::from(format!("{}{}", dir, name).as_str())
+    }
+}
+
+pub fn child_entries<'a, I>(dir: &str, keys: I) -> Option<BTreeSet<String>>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let entries: BTreeSet<_> = keys
+        .filter_map(|key| key.strip_prefix(dir))
+        .filter(|rest| !rest.is_empty())
+        .map(|rest| match rest.find('/') {
+            Some(i) => rest[..=i].to_string(),
+            None => rest.to_string(),
+        })
+        .collect();
+
+    if entries.is_empty() {
+        None
+    } else {
+        Some(entries)
+    }
+}
+
+// The value of the doc at `key`, wherever the store's layout keeps it, as an
+// actor's `get()` would find it.
+pub fn doc_value<T>(store: &DbStore<T>, key: &str) -> Option<T>
+where
+    T: Clone + Size,
+{
+    let path = Path::from(key);
+
+    if let (Layout::Packed { .. }, Some((dir, name))) =
+        (&store.config().layout, path.links().last())
+    {
+        if let Some(Db::Packed(entries)) = store.get(dir) {
+            if let Some(Some(value)) = entries.get(name) {
+                return Some(value.clone());
+            }
+        }
+    }
+    match store.get(&path) {
+        Some(Db::Doc(value, _)) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+// The keys one record gives a repair pass to look at: the doc itself, or the
+// path each of a dir's entries names. Across every record in a store, these
+// cover all the links a repair pass may need to add or remove.
+pub fn repair_keys_of<T>(key: &Path, value: Option<&Db<T>>) -> Vec<Path> {
+    match value {
+        Some(Db::Doc(..)) if key.is_doc() => vec![key.clone()],
+        Some(Db::Dir(entries)) => entries
+            .keys()
+            .map(|name| child_path(key.full(), name))
+            .collect(),
+        Some(Db::Packed(entries)) => entries
+            .keys()
+            .map(|name| child_path(key.full(), name))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Builds a store holding the given docs and every dir entry the layout needs
+// to reach them, as a setup plan that wrote them one by one would leave it.
+pub fn build_store<T>(config: &Config, docs: &[(Path, T)]) -> DbStore<T>
+where
+    T: Clone + Size,
+{
+    let mut store = DbStore::new(config.clone());
+    let mut dirs: BTreeMap<&str, BTreeMap<String, (Kind, Option<T>)>> = BTreeMap::new();
+
+    for (path, value) in docs {
+        let links = links(&config.layout, path);
+
+        for (dir, name, kind) in &links {
+            dirs.entry(dir)
+                .or_default()
+                .entry(name.to_string())
+                .or_insert((*kind, None));
+        }
+        match (&config.layout, links.last()) {
+            (Layout::Packed { threshold }, Some((dir, name, kind)))
+                if value.size() <= *threshold =>
+            {
+                let entries = dirs.entry(dir).or_default();
+                entries.insert(name.to_string(), (*kind, Some(value.clone())));
+            }
+            _ => {
+                store.write(path.clone(), None, Db::doc(value.clone()));
+            }
+        }
+    }
+
+    for (dir, entries) in dirs {
+        let value = match config.layout {
+            Layout::Packed { .. } => Db::Packed(
+                entries
+                    .into_iter()
+                    .map(|(name, (_, value))| (name, value))
+                    .collect(),
+            ),
+            _ => Db::Dir(
+                entries
+                    .into_iter()
+                    .map(|(name, (kind, _))| (name, kind))
+                    .collect(),
+            ),
+        };
+        store.write(Path::from(dir), None, value);
+    }
+    store
+}
+
+// The key's record without the dir entries that lead to no doc, which a
+// design that never unlinks leaves behind for readers to skip. An entry is
+// live if it names a doc, or a dir with a live entry of its own.
+pub fn without_garbage<T>(store: &DbStore<T>, key: &Path) -> Option<Db<T>>
+where
+    T: Clone + Size,
+{
+    let live = |name: &str| is_live(store, &child_path(key.full(), name));
+
+    match store.get(key)? {
+        Db::Dir(entries) => Some(Db::Dir(
+            entries
+                .iter()
+                .filter(|(name, _)| live(name))
+                .map(|(name, kind)| (name.clone(), *kind))
+                .collect(),
+        )),
+        Db::Packed(entries) => Some(Db::Packed(
+            entries
+                .iter()
+                .filter(|(name, value)| value.is_some() || live(name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        )),
+        db => Some(db.clone()),
+    }
+}
+
+fn is_live<T>(store: &DbStore<T>, key: &Path) -> bool
+where
+    T: Clone + Size,
+{
+    let live = |name: &str| is_live(store, &child_path(key.full(), name));
+
+    match store.get(key) {
+        Some(Db::Doc(..)) => true,
+        Some(Db::Dir(entries)) => entries.keys().any(|name| live(name)),
+        Some(Db::Packed(entries)) => entries
+            .iter()
+            .any(|(name, value)| value.is_some() || live(name)),
+        _ => false,
     }
 }
 
 pub type DbCache<'a, T> = Cache<'a, Path, Db<T>>;
 pub type DbStore<T> = Store<Path, Db<T>>;
 
+// What a failed check found, for telling failures apart without reading
+// their messages.
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+pub enum Violation {
+    Unlinked,
+    MissingDir,
+    LeaseExpired,
+    FinalCheck,
+}
+
 pub struct Checker<'a, T> {
     store: &'a RefCell<DbStore<T>>,
     seq: Rev,
-    errors: Vec<String>,
+    errors: Vec<(Violation, String)>,
+    mtimes: BTreeMap<Path, Time>,
 }
 
 impl<T> Checker<'_, T>
 where
-    T: Clone,
+    T: Clone + Size,
 {
     pub fn new(store: &RefCell<DbStore<T>>) -> Checker<'_, T> {
         Checker {
             store,
             seq: 0,
             errors: Vec::new(),
+            mtimes: BTreeMap::new(),
         }
     }
 
     pub fn check(&mut self) -> Result<(), Vec<String>> {
+        self.check_violations()
+            .map_err(|errors| errors.into_iter().map(|(_, error)| error).collect())
+    }
+
+    // As for `check`, with the kind of each error.
+    pub fn check_violations(&mut self) -> Result<(), Vec<(Violation, String)>> {
         let store = self.store.borrow();
 
         if self.seq == store.seq {
@@ -46,11 +388,30 @@ where
         }
         self.errors = Vec::new();
 
+        let layout = &store.config().layout;
+
         for path in store.keys() {
-            if path.is_doc() && store.get(path).is_some() {
-                self.check_doc(path);
+            match store.get(path) {
+                Some(Db::Doc(value, meta)) if path.is_doc() => {
+                    self.check_doc(layout, path);
+                    self.check_meta(path, value, meta, store.clock.now());
+                }
+                Some(Db::Dir(entries)) => {
+                    self.check_tags(&store, path, entries);
+                }
+                Some(Db::Packed(entries)) => {
+                    for (name, value) in entries {
+                        if value.is_some() {
+                            self.check_doc(layout, &child_path(path.full(), name));
+                        }
+                    }
+                }
+                _ => {}
             }
         }
+        let reported = store.violations.iter().cloned();
+        self.errors
+            .extend(reported.map(|error| (Violation::LeaseExpired, error)));
 
         if self.errors.is_empty() {
             self.seq = store.seq;
@@ -60,20 +421,91 @@ where
         }
     }
 
-    fn check_doc(&mut self, doc: &Path) {
-        for (dir, name) in doc.links() {
-            if let Some(Db::Dir(entries)) = self.store.borrow().get(dir) {
-                if !entries.contains(name) {
-                    self.errors.push(format!(
-                        "dir '{}' does not include name '{}', required by doc '{}'",
-                        dir, name, doc
+    // A doc's metadata must match its value and must not be dated later than
+    // the store's clock, and its mtime must not go back from one check to
+    // the next.
+    fn check_meta(&mut self, doc: &Path, value: &T, meta: &Meta, now: Time) {
+        if meta.size != value.size() {
+            self.errors.push((
+                Violation::FinalCheck,
+                format!(
+                    "doc '{}' records a size of {}, but its value has size {}",
+                    doc,
+                    meta.size,
+                    value.size()
+                ),
+            ));
+        }
+        if meta.mtime > now {
+            self.errors.push((
+                Violation::FinalCheck,
+                format!(
+                    "doc '{}' has mtime {}, after the clock's {}",
+                    doc, meta.mtime, now
+                ),
+            ));
+        }
+        let last = self.mtimes.insert(doc.clone(), meta.mtime);
+        if let Some(last) = last.filter(|last| meta.mtime < *last) {
+            self.errors.push((
+                Violation::FinalCheck,
+                format!(
+                    "doc '{}' has mtime {}, earlier than the {} it had before",
+                    doc, meta.mtime, last
+                ),
+            ));
+        }
+    }
+
+    // Each of a dir's entries must be tagged as the kind of record it names,
+    // where there is one. An entry whose record is missing is a dangling
+    // link, which is left to the checks on the docs.
+    // This runs on every check, so each child's key is built in one buffer
+    // rather than parsed into a `Path`.
+    fn check_tags(&mut self, store: &DbStore<T>, dir: &Path, entries: &BTreeMap<String, Kind>) {
+        let mut child = String::new();
+
+        for (name, kind) in entries {
+            child.clear();
+            if !name.starts_with('/') {
+                child.push_str(dir.full());
+            }
+            child.push_str(name);
+
+            if let Some(actual) = store.get(child.as_str()).and_then(Db::kind) {
+                if actual != *kind {
+                    self.errors.push((
+                        Violation::FinalCheck,
+                        format!(
+                            "dir '{}' tags '{}' as a {:?}, but '{}' is a {:?}",
+                            dir, name, kind, child, actual
+                        ),
                     ));
                 }
-            } else {
-                self.errors.push(format!(
-                    "dir '{}', required by doc '{}', is missing",
-                    dir, doc
-                ));
+            }
+        }
+    }
+
+    fn check_doc(&mut self, layout: &Layout, doc: &Path) {
+        for (dir, name, _) in links(layout, doc) {
+            match self
+                .store
+                .borrow()
+                .get(dir)
+                .and_then(|db| db.has_entry(name))
+            {
+                Some(true) => {}
+                Some(false) => self.errors.push((
+                    Violation::Unlinked,
+                    format!(
+                        "dir '{}' does not include name '{}', required by doc '{}'",
+                        dir, name, doc
+                    ),
+                )),
+                None => self.errors.push((
+                    Violation::MissingDir,
+                    format!("dir '{}', required by doc '{}', is missing", dir, doc),
+                )),
             }
         }
     }
@@ -82,7 +514,6 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::Config;
 
     fn make_store() -> DbStore<char> {
         let mut store = DbStore::new(Config::new());
@@ -90,7 +521,7 @@ mod tests {
         store.write("/".into(), None, Db::dir_from(&["path/"]));
         store.write("/path/".into(), None, Db::dir_from(&["to/"]));
         store.write("/path/to/".into(), None, Db::dir_from(&["x.json"]));
-        store.write("/path/to/x.json".into(), None, Db::Doc('a'));
+        store.write("/path/to/x.json".into(), None, Db::doc('a'));
 
         store
     }
@@ -105,6 +536,24 @@ mod tests {
         assert_eq!(checker.check(), Ok(()));
     }
 
+    #[test]
+    fn leaves_out_entries_that_lead_to_no_doc() {
+        let mut store = make_store();
+        store.write("/path/".into(), Some(1), Db::dir_from(&["to/", "y.json"]));
+
+        assert_eq!(
+            without_garbage(&store, &"/path/".into()),
+            Some(Db::dir_from(&["to/"]))
+        );
+        assert_eq!(without_garbage(&store, &"/other/".into()), None);
+
+        store.remove("/path/to/x.json".into(), Some(1));
+        assert_eq!(
+            without_garbage(&store, &"/".into()),
+            Some(Db::dir_from(&[]))
+        );
+    }
+
     #[test]
     fn complains_if_a_doc_is_not_linked() {
         let mut store = make_store();
@@ -137,11 +586,80 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tells_each_kind_of_violation_apart() {
+        let mut store = make_store();
+        store.write("/path/to/".into(), Some(1), Db::dir_from(&[]));
+        store.write("/other/y.json".into(), None, Db::doc('b'));
+        store.violations.push(String::from("lease expired"));
+
+        let store_cell = RefCell::new(store);
+        let mut checker = Checker::new(&store_cell);
+
+        let errors = checker.check_violations().unwrap_err();
+        let kinds: Vec<_> = errors.iter().map(|(kind, _)| *kind).collect();
+
+        assert_eq!(
+            kinds,
+            [
+                Violation::Unlinked,
+                Violation::MissingDir,
+                Violation::Unlinked,
+                Violation::LeaseExpired
+            ]
+        );
+    }
+
+    #[test]
+    fn complains_about_metadata_that_does_not_match_the_doc() {
+        let mut store = make_store();
+        let mut doc = Db::doc_from('b', 1, "A");
+        if let Db::Doc(_, meta) = &mut doc {
+            meta.size = 3;
+        }
+        store.write("/path/to/x.json".into(), Some(1), doc);
+
+        let store_cell = RefCell::new(store);
+        let mut checker = Checker::new(&store_cell);
+
+        assert_eq!(
+            checker.check(),
+            Err(vec![
+                String::from("doc '/path/to/x.json' records a size of 3, but its value has size 1"),
+                String::from("doc '/path/to/x.json' has mtime 1, after the clock's 0"),
+            ])
+        );
+    }
+
+    #[test]
+    fn complains_if_a_doc_goes_back_in_time() {
+        let mut store = make_store();
+        store.clock.tick();
+        store.clock.tick();
+        store.write("/path/to/x.json".into(), Some(1), Db::doc_from('b', 2, "A"));
+
+        let store_cell = RefCell::new(store);
+        let mut checker = Checker::new(&store_cell);
+        assert_eq!(checker.check(), Ok(()));
+
+        let doc = Db::doc_from('c', 1, "B");
+        store_cell
+            .borrow_mut()
+            .write("/path/to/x.json".into(), Some(2), doc);
+
+        assert_eq!(
+            checker.check(),
+            Err(vec![String::from(
+                "doc '/path/to/x.json' has mtime 1, earlier than the 2 it had before"
+            )])
+        );
+    }
+
     #[test]
     fn complains_if_parent_dir_is_missing() {
         let mut store = make_store();
         store.write("/".into(), Some(1), Db::dir_from(&["other/", "path/"]));
-        store.write("/other/y.json".into(), None, Db::Doc('b'));
+        store.write("/other/y.json".into(), None, Db::doc('b'));
 
         let store_cell = RefCell::new(store);
         let mut checker = Checker::new(&store_cell);
@@ -186,6 +704,224 @@ mod tests {
         );
     }
 
+    #[test]
+    fn complains_if_an_entry_is_tagged_as_the_wrong_kind() {
+        let mut store = make_store();
+        let entries = BTreeMap::from([
+            ("to/".to_string(), Kind::Doc),
+            ("y.json".to_string(), Kind::Dir),
+        ]);
+        store.write("/path/".into(), Some(1), Db::Dir(entries));
+        store.write("/path/y.json".into(), None, Db::doc('b'));
+
+        let store_cell = RefCell::new(store);
+        let mut checker = Checker::new(&store_cell);
+
+        assert_eq!(
+            checker.check(),
+            Err(vec![
+                String::from("dir '/path/' tags 'to/' as a Doc, but '/path/to/' is a Dir"),
+                String::from("dir '/path/' tags 'y.json' as a Dir, but '/path/y.json' is a Doc"),
+            ])
+        );
+    }
+
+    #[test]
+    fn tags_the_last_link_to_a_doc_and_the_rest_as_dirs() {
+        let path = Path::from("/path/to/x.json");
+
+        assert_eq!(
+            links(&Layout::DirDocs, &path),
+            vec![
+                ("/", "path/", Kind::Dir),
+                ("/path/", "to/", Kind::Dir),
+                ("/path/to/", "x.json", Kind::Doc),
+            ]
+        );
+        assert_eq!(
+            links(&Layout::Manifest, &path),
+            vec![(MANIFEST_KEY, "/path/to/x.json", Kind::Doc)]
+        );
+    }
+
+    #[test]
+    fn does_not_require_links_with_a_flat_layout() {
+        let mut store = DbStore::new(Config::new().layout(Layout::Flat));
+        store.write("/path/to/x.json".into(), None, Db::doc('a'));
+
+        let store_cell = RefCell::new(store);
+        let mut checker = Checker::new(&store_cell);
+
+        assert_eq!(checker.check(), Ok(()));
+    }
+
+    #[test]
+    fn checks_a_valid_manifest() {
+        let mut store = DbStore::new(Config::new().layout(Layout::Manifest));
+        store.write("/".into(), None, Db::dir_from(&["/path/to/x.json"]));
+        store.write("/path/to/x.json".into(), None, Db::doc('a'));
+
+        let store_cell = RefCell::new(store);
+        let mut checker = Checker::new(&store_cell);
+
+        assert_eq!(checker.check(), Ok(()));
+    }
+
+    #[test]
+    fn complains_if_a_doc_is_not_in_the_manifest() {
+        let mut store = DbStore::new(Config::new().layout(Layout::Manifest));
+        store.write("/".into(), None, Db::dir_from(&["/path/to/x.json"]));
+        store.write("/path/to/x.json".into(), None, Db::doc('a'));
+        store.write("/y.json".into(), None, Db::doc('b'));
+
+        let store_cell = RefCell::new(store);
+        let mut checker = Checker::new(&store_cell);
+
+        assert_eq!(
+            checker.check(),
+            Err(vec![String::from(
+                "dir '/' does not include name '/y.json', required by doc '/y.json'"
+            )])
+        );
+    }
+
+    fn make_bucket_store() -> DbStore<char> {
+        let mut store = DbStore::new(Config::new().layout(Layout::Buckets { count: 2 }));
+        let x = Path::from("/path/x.json");
+        let y = Path::from("/y.json");
+
+        store.write(bucket_key(2, &x).into(), None, Db::dir_from(&[x.full()]));
+        store.write(x, None, Db::doc('a'));
+        store.write(y, None, Db::doc('b'));
+
+        store
+    }
+
+    #[test]
+    fn hashes_paths_into_a_fixed_set_of_buckets() {
+        let x = Path::from("/path/x.json");
+
+        assert_eq!(bucket_key(1, &x), "#0/");
+        assert_eq!(bucket_key(4, &x), bucket_key(4, &x.clone()));
+        assert_ne!(bucket_key(2, &x), bucket_key(2, &"/y.json".into()));
+    }
+
+    #[test]
+    fn complains_if_a_doc_is_not_in_its_bucket() {
+        let store_cell = RefCell::new(make_bucket_store());
+        let mut checker = Checker::new(&store_cell);
+        let bucket = bucket_key(2, &"/y.json".into());
+
+        assert_eq!(
+            checker.check(),
+            Err(vec![format!(
+                "dir '{}', required by doc '/y.json', is missing",
+                bucket
+            )])
+        );
+    }
+
+    #[test]
+    fn checks_a_valid_bucket_store() {
+        let mut store = make_bucket_store();
+        let y = Path::from("/y.json");
+        store.write(bucket_key(2, &y).into(), None, Db::dir_from(&[y.full()]));
+
+        let store_cell = RefCell::new(store);
+        let mut checker = Checker::new(&store_cell);
+
+        assert_eq!(checker.check(), Ok(()));
+    }
+
+    #[test]
+    fn complains_about_reported_violations() {
+        let mut store = make_store();
+        store.violations.push(String::from("lease expired"));
+
+        let store_cell = RefCell::new(store);
+        let mut checker = Checker::new(&store_cell);
+
+        assert_eq!(checker.check(), Err(vec![String::from("lease expired")]));
+    }
+
+    fn make_packed_store() -> DbStore<char> {
+        let mut store = DbStore::new(Config::new().layout(Layout::Packed { threshold: 1 }));
+
+        store.write("/".into(), None, Db::packed_from(&[("path/", None)]));
+        store.write(
+            "/path/".into(),
+            None,
+            Db::packed_from(&[("x.json", Some('a')), ("y.json", None)]),
+        );
+        store.write("/path/y.json".into(), None, Db::doc('b'));
+
+        store
+    }
+
+    #[test]
+    fn checks_a_valid_packed_store() {
+        let store_cell = RefCell::new(make_packed_store());
+        let mut checker = Checker::new(&store_cell);
+
+        assert_eq!(checker.check(), Ok(()));
+    }
+
+    #[test]
+    fn complains_if_a_packed_dir_is_not_linked() {
+        let mut store = make_packed_store();
+        store.write("/".into(), Some(1), Db::packed_from(&[]));
+
+        let store_cell = RefCell::new(store);
+        let mut checker = Checker::new(&store_cell);
+
+        assert_eq!(
+            checker.check(),
+            Err(vec![
+                String::from(
+                    "dir '/' does not include name 'path/', required by doc '/path/x.json'"
+                ),
+                String::from(
+                    "dir '/' does not include name 'path/', required by doc '/path/y.json'"
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn complains_if_a_spilled_doc_is_not_linked() {
+        let mut store = make_packed_store();
+        store.write(
+            "/path/".into(),
+            Some(1),
+            Db::packed_from(&[("x.json", Some('a'))]),
+        );
+
+        let store_cell = RefCell::new(store);
+        let mut checker = Checker::new(&store_cell);
+
+        assert_eq!(
+            checker.check(),
+            Err(vec![String::from(
+                "dir '/path/' does not include name 'y.json', required by doc '/path/y.json'"
+            )])
+        );
+    }
+
+    #[test]
+    fn lists_the_children_of_a_directory_from_full_keys() {
+        let keys = ["/a", "/path/", "/path/to/x", "/path/y", "/pathz"];
+
+        assert_eq!(
+            child_entries("/path/", keys.into_iter()),
+            Some(BTreeSet::from(["to/".into(), "y".into()]))
+        );
+        assert_eq!(
+            child_entries("/", keys.into_iter()),
+            Some(BTreeSet::from(["a".into(), "path/".into(), "pathz".into()]))
+        );
+        assert_eq!(child_entries("/q/", keys.into_iter()), None);
+    }
+
     #[test]
     fn does_not_complain_if_an_ancestor_of_a_deleted_doc_is_unlinked() {
         let mut store = make_store();
@@ -197,4 +933,92 @@ mod tests {
 
         assert_eq!(checker.check(), Ok(()));
     }
+
+    fn repair_keys<T>(store: &DbStore<T>) -> Vec<Path>
+    where
+        T: Clone + Size,
+    {
+        let mut keys = BTreeSet::new();
+
+        for key in store.keys() {
+            keys.extend(repair_keys_of(key, store.get(key)));
+        }
+        keys.into_iter().collect()
+    }
+
+    #[test]
+    fn lists_docs_and_dir_entries_as_repair_keys() {
+        let mut store = make_store();
+        store.write("/path/".into(), Some(1), Db::dir_from(&["to/", "y"]));
+        store.write("/z".into(), None, Db::doc('z'));
+
+        let keys: Vec<_> = repair_keys(&store)
+            .iter()
+            .map(|key| key.to_string())
+            .collect();
+
+        assert_eq!(
+            keys,
+            ["/path/", "/path/to/", "/path/to/x.json", "/path/y", "/z"]
+        );
+    }
+
+    fn build_docs() -> Vec<(Path, char)> {
+        vec![("/path/to/x.json".into(), 'x'), ("/path/y".into(), 'y')]
+    }
+
+    #[test]
+    fn builds_a_store_with_dirs_derived_from_doc_paths() {
+        let store = build_store(&Config::new(), &build_docs());
+
+        assert_eq!(store.read("/"), Some((1, Some(Db::dir_from(&["path/"])))));
+        assert_eq!(
+            store.read("/path/"),
+            Some((1, Some(Db::dir_from(&["to/", "y"]))))
+        );
+        assert_eq!(
+            store.read("/path/to/"),
+            Some((1, Some(Db::dir_from(&["x.json"]))))
+        );
+        assert_eq!(store.read("/path/y"), Some((1, Some(Db::doc('y')))));
+
+        assert_eq!(Checker::new(&RefCell::new(store)).check(), Ok(()));
+    }
+
+    #[test]
+    fn builds_a_store_for_each_layout() {
+        let layouts = [
+            Layout::Flat,
+            Layout::Manifest,
+            Layout::Packed { threshold: 1 },
+            Layout::Buckets { count: 2 },
+        ];
+        for layout in layouts {
+            let store = build_store(&Config::new().layout(layout), &build_docs());
+            assert_eq!(Checker::new(&RefCell::new(store)).check(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn stores_small_docs_inline_with_a_packed_layout() {
+        let config = Config::new().layout(Layout::Packed { threshold: 1 });
+        let store = build_store(&config, &build_docs());
+
+        assert_eq!(
+            store.read("/path/"),
+            Some((1, Some(Db::packed_from(&[("to/", None), ("y", Some('y'))]))))
+        );
+        assert_eq!(store.read("/path/y"), None);
+    }
+
+    #[test]
+    fn reads_doc_values_under_each_layout() {
+        for layout in [Layout::DirDocs, Layout::Packed { threshold: 1 }] {
+            let store = build_store(&Config::new().layout(layout), &build_docs());
+
+            assert_eq!(doc_value(&store, "/path/to/x.json"), Some('x'));
+            assert_eq!(doc_value(&store, "/path/y"), Some('y'));
+            assert_eq!(doc_value(&store, "/path/z"), None);
+        }
+    }
 }
@@ -0,0 +1,262 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::planner::Planner;
+use crate::rng::Rng;
+use crate::runner::Runner;
+
+// How large the generated programs may be.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    pub clients: usize,
+    pub ops: usize,
+    pub docs: usize,
+    pub depth: usize,
+    pub names: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            clients: 2,
+            ops: 1,
+            docs: 2,
+            depth: 2,
+            names: 2,
+        }
+    }
+}
+
+const NAMES: [&str; 4] = ["a", "b", "c", "d"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FuzzOp {
+    Update(String),
+    Create(String),
+    Remove(String),
+}
+
+impl fmt::Display for FuzzOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FuzzOp::Update(key) => write!(f, "update('{}')", key),
+            FuzzOp::Create(key) => write!(f, "create('{}')", key),
+            FuzzOp::Remove(key) => write!(f, "remove('{}')", key),
+        }
+    }
+}
+
+// A scenario made up at random from a seed, to hunt for protocol violations
+// in programs nobody thought to write by hand: the docs the store starts
+// with, each holding ('x', 1), and the ops of each client in turn, named A,
+// B, C and so on. Paths are drawn from a few names so that the ops run into
+// each other, on the same doc or on the dirs above it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub seed: u64,
+    pub docs: Vec<String>,
+    pub clients: Vec<Vec<FuzzOp>>,
+}
+
+impl Program {
+    pub fn generate(seed: u64, limits: &Limits) -> Program {
+        let mut rng = Rng::new(seed);
+        let mut docs = Vec::new();
+
+        for _ in 0..rng.below(limits.docs + 1) {
+            let key = random_key(&mut rng, limits);
+            if !docs.contains(&key) {
+                docs.push(key);
+            }
+        }
+
+        let clients = (0..1 + rng.below(limits.clients))
+            .map(|_| {
+                (0..1 + rng.below(limits.ops))
+                    .map(|_| random_op(&mut rng, limits, &docs))
+                    .collect()
+            })
+            .collect();
+
+        Program {
+            seed,
+            docs,
+            clients,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        format!("fuzz #{}", self.seed)
+    }
+
+    // Plans each client's ops, which run concurrently as a client's ops do
+    // by default. A client tags what it writes with the lowercase form of
+    // its id.
+    pub fn plan(&self, planner: &mut Planner<(char, i32)>) {
+        for (i, ops) in self.clients.iter().enumerate() {
+            let (id, tag) = client(i);
+            let mut client = planner.client(&id);
+
+            for op in ops {
+                match op {
+                    FuzzOp::Update(key) => {
+                        client.update(key, move |doc| doc.map(|(_, n)| (tag, n + 1)))
+                    }
+                    FuzzOp::Create(key) => client.update(key, move |_| Some((tag, 1))),
+                    FuzzOp::Remove(key) => client.remove(key),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "seed: {}", self.seed)?;
+
+        let docs: Vec<_> = self.docs.iter().map(|key| format!("'{}'", key)).collect();
+        writeln!(f, "docs: [{}]", docs.join(", "))?;
+
+        for (i, ops) in self.clients.iter().enumerate() {
+            let ops: Vec<_> = ops.iter().map(FuzzOp::to_string).collect();
+            writeln!(f, "{}: {}", client(i).0, ops.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// Adds a scenario for each seed in the range. A failing one prints the
+// program it was generated from, so it can be written up as a scenario of
+// its own.
+pub fn suite(runner: &mut Runner<(char, i32)>, seeds: Range<u64>, limits: &Limits) {
+    for seed in seeds {
+        let program = Program::generate(seed, limits);
+        let docs: Vec<_> = program
+            .docs
+            .iter()
+            .map(|key| (key.as_str(), ('x', 1)))
+            .collect();
+        let description = program.to_string();
+
+        let plan = program.clone();
+        runner.add(&program.name(), docs.as_slice(), move |planner| {
+            plan.plan(planner)
+        });
+        runner.describe(&description);
+    }
+}
+
+fn client(i: usize) -> (String, char) {
+    let tag = (b'a' + i as u8) as char;
+    (tag.to_ascii_uppercase().to_string(), tag)
+}
+
+fn random_key(rng: &mut Rng, limits: &Limits) -> String {
+    let names = &NAMES[..limits.names.clamp(1, NAMES.len())];
+
+    (0..1 + rng.below(limits.depth))
+        .map(|_| format!("/{}", names[rng.below(names.len())]))
+        .collect()
+}
+
+// Half the ops fall on a doc the store starts with, when there is one, so
+// that updates and removes find something to work on.
+fn random_op(rng: &mut Rng, limits: &Limits, docs: &[String]) -> FuzzOp {
+    let key = if !docs.is_empty() && rng.below(2) == 0 {
+        docs[rng.below(docs.len())].clone()
+    } else {
+        random_key(rng, limits)
+    };
+    match rng.below(3) {
+        0 => FuzzOp::Update(key),
+        1 => FuzzOp::Create(key),
+        _ => FuzzOp::Remove(key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+
+    #[test]
+    fn generates_the_same_program_from_the_same_seed() {
+        let limits = Limits::default();
+
+        assert_eq!(Program::generate(7, &limits), Program::generate(7, &limits));
+        let programs: Vec<_> = (0..20)
+            .map(|seed| Program::generate(seed, &limits))
+            .collect();
+        assert!(programs.iter().any(|program| *program != programs[0]));
+    }
+
+    #[test]
+    fn keeps_programs_within_their_limits() {
+        let limits = Limits {
+            clients: 3,
+            ops: 2,
+            docs: 2,
+            depth: 3,
+            names: 2,
+        };
+        for seed in 0..100 {
+            let program = Program::generate(seed, &limits);
+
+            assert!(program.docs.len() <= 2);
+            assert!((1..=3).contains(&program.clients.len()));
+
+            for ops in &program.clients {
+                assert!((1..=2).contains(&ops.len()));
+
+                for op in ops {
+                    let (FuzzOp::Update(key) | FuzzOp::Create(key) | FuzzOp::Remove(key)) = op;
+                    let segments: Vec<_> = key.split('/').skip(1).collect();
+                    assert!((1..=3).contains(&segments.len()));
+                    assert!(segments.iter().all(|name| ["a", "b"].contains(name)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn plans_every_clients_ops() {
+        let program = Program {
+            seed: 0,
+            docs: vec!["/a".into()],
+            clients: vec![
+                vec![FuzzOp::Update("/a".into())],
+                vec![FuzzOp::Remove("/a".into()), FuzzOp::Create("/b/a".into())],
+            ],
+        };
+        let mut planner = Planner::new(Config::new());
+        program.plan(&mut planner);
+
+        let clients: Vec<_> = planner.clients().collect();
+        assert_eq!(clients, ["A", "B"]);
+        assert!(planner
+            .graph()
+            .iter()
+            .any(|act| act.to_string() == "put('/b/a')"));
+    }
+
+    #[test]
+    fn describes_a_program() {
+        let program = Program {
+            seed: 3,
+            docs: vec!["/a".into(), "/b/a".into()],
+            clients: vec![
+                vec![FuzzOp::Update("/a".into())],
+                vec![FuzzOp::Remove("/b/a".into()), FuzzOp::Create("/b".into())],
+            ],
+        };
+        assert_eq!(
+            program.to_string(),
+            "seed: 3\n\
+             docs: ['/a', '/b/a']\n\
+             A: update('/a')\n\
+             B: remove('/b/a'), create('/b')\n"
+        );
+        assert_eq!(program.name(), "fuzz #3");
+    }
+}
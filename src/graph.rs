@@ -1,4 +1,62 @@
-pub type Id = usize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Debug};
+use std::hash::Hasher;
+
+use crate::json::Json;
+use crate::rng::Rng;
+
+// A node's place in the graph, counting from 1 in the order the nodes were
+// added. Only a graph hands these out, from `add` or from `node_id` for a
+// number it holds a node by, so one can't be confused with a plain number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    fn at(index: usize) -> NodeId {
+        NodeId(index + 1)
+    }
+
+    fn index(self) -> usize {
+        self.0 - 1
+    }
+
+    // The id as a number, for writing it out.
+    pub fn number(self) -> usize {
+        self.0
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Why a graph refused a node or an edge. The graph is left as it was.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GraphError {
+    // A dep named a node the graph does not hold, such as one from another
+    // graph.
+    UnknownNode(NodeId),
+    // An edge would have a node wait for one that already waits for it,
+    // directly or not, or for itself.
+    Cycle { dep: NodeId, id: NodeId },
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GraphError::UnknownNode(id) => write!(f, "node {} is not in the graph", id),
+            GraphError::Cycle { dep, id } => write!(
+                f,
+                "node {} cannot depend on {}, which already waits for it",
+                id, dep
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
 
 #[derive(Debug, Default)]
 pub struct Graph<T> {
@@ -7,77 +65,827 @@ pub struct Graph<T> {
 
 #[derive(Debug)]
 struct Node<T> {
-    id: Id,
-    deps: Vec<Id>,
+    deps: Vec<NodeId>,
+    dep_mask: Mask,
     value: T,
 }
 
+// A set of node indexes, held as one bit per node.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Mask(Vec<u64>);
+
+impl Mask {
+    fn new(size: usize) -> Mask {
+        Mask(vec![0; size.div_ceil(64)])
+    }
+
+    fn contains(&self, i: usize) -> bool {
+        self.0[i / 64] & 1 << (i % 64) != 0
+    }
+
+    fn insert(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+
+    fn remove(&mut self, i: usize) {
+        self.0[i / 64] &= !(1 << (i % 64));
+    }
+
+    fn len(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    // Adds every index in `other`, which must be no wider.
+    fn union(&mut self, other: &Mask) {
+        for (a, b) in self.0.iter_mut().zip(&other.0) {
+            *a |= b;
+        }
+    }
+
+    // Whether every index in this set is in `other`, which must be at least
+    // as wide.
+    fn is_subset(&self, other: &Mask) -> bool {
+        self.0.iter().zip(&other.0).all(|(a, b)| a & !b == 0)
+    }
+}
+
 impl<T> Graph<T> {
     pub fn new() -> Graph<T> {
         Graph { nodes: Vec::new() }
     }
 
-    pub fn add(&mut self, deps: &[Id], value: T) -> Id {
-        let node_id = self.nodes.len() + 1;
+    // Adds a node that waits for the nodes in `deps`, which must already be
+    // in the graph. Since a node can only wait for those added before it, the
+    // graph can never hold a cycle.
+    pub fn add(&mut self, deps: &[NodeId], value: T) -> Result<NodeId, GraphError> {
+        self.check(deps)?;
+        Ok(self.push(deps, value))
+    }
+
+    fn check(&self, deps: &[NodeId]) -> Result<(), GraphError> {
+        match deps.iter().find(|dep| dep.0 > self.nodes.len()) {
+            Some(dep) => Err(GraphError::UnknownNode(*dep)),
+            None => Ok(()),
+        }
+    }
+
+    // Adds a node whose deps are known to be in the graph.
+    fn push(&mut self, deps: &[NodeId], value: T) -> NodeId {
+        let node_id = NodeId::at(self.nodes.len());
+        let mut dep_mask = Mask::new(node_id.0);
+        for dep in deps {
+            dep_mask.insert(dep.index());
+        }
 
         self.nodes.push(Node {
-            id: node_id,
             deps: deps.into(),
+            dep_mask,
             value,
         });
 
         node_id
     }
 
-    pub fn orderings(&self) -> impl Iterator<Item = Vec<&T>> {
-        let nodes: Vec<_> = self
-            .nodes
+    // Adds the values in a line, each waiting for the one before it and the
+    // first waiting for `deps`. Returns what a node that follows the line
+    // should wait for: its last node, or `deps` if it is empty.
+    pub fn chain<I>(&mut self, deps: &[NodeId], values: I) -> Result<Vec<NodeId>, GraphError>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        self.check(deps)?;
+        let mut last = deps.to_vec();
+        for value in values {
+            last = vec![self.push(&last, value)];
+        }
+        Ok(last)
+    }
+
+    // Adds the values side by side, each waiting for `deps` and for nothing
+    // else. A node that waits for all of them is just an `add` of their ids.
+    pub fn fan_out<I>(&mut self, deps: &[NodeId], values: I) -> Result<Vec<NodeId>, GraphError>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        self.check(deps)?;
+        let ids = values.into_iter().map(|value| self.push(deps, value));
+        Ok(ids.collect())
+    }
+
+    // Appends `other`, with its roots waiting for `deps`.
+    pub fn merge(&mut self, deps: &[NodeId], other: Graph<T>) -> Result<IdMap, GraphError> {
+        self.check(deps)?;
+        let mut ids = Vec::with_capacity(other.nodes.len());
+
+        for node in other.nodes {
+            let mut node_deps: Vec<_> = node.deps.iter().map(|dep| ids[dep.index()]).collect();
+            if node_deps.is_empty() {
+                node_deps = deps.to_vec();
+            }
+            ids.push(self.push(&node_deps, node.value));
+        }
+        Ok(IdMap { ids })
+    }
+
+    // Drops the nodes that `keep` rejects, along with every node that waits
+    // for one of them, and renumbers the rest in the same order. The map it
+    // returns gives each kept node's old id the id it now has.
+    pub fn retain<F>(&mut self, mut keep: F) -> HashMap<NodeId, NodeId>
+    where
+        F: FnMut(NodeId, &T) -> bool,
+    {
+        let mut ids = HashMap::new();
+
+        for (i, node) in std::mem::take(&mut self.nodes).into_iter().enumerate() {
+            let deps: Option<Vec<_>> = node.deps.iter().map(|dep| ids.get(dep).copied()).collect();
+
+            if let Some(deps) = deps.filter(|_| keep(NodeId::at(i), &node.value)) {
+                ids.insert(NodeId::at(i), self.push(&deps, node.value));
+            }
+        }
+        ids
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.nodes.iter().map(|node| &node.value)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.nodes.iter_mut().map(|node| &mut node.value)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = NodeId> {
+        (0..self.nodes.len()).map(NodeId::at)
+    }
+
+    // The id numbered `number`, as `NodeId::number` gives it, if the graph
+    // holds a node by that number.
+    pub fn node_id(&self, number: usize) -> Option<NodeId> {
+        (1..=self.nodes.len())
+            .contains(&number)
+            .then_some(NodeId(number))
+    }
+
+    // Each node as its id, its value and the ids of the nodes it waits for,
+    // in the order they were added.
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &T, &[NodeId])> {
+        self.nodes
             .iter()
-            .map(|node| (node.id, node.deps.clone()))
-            .collect();
+            .enumerate()
+            .map(|(i, node)| (NodeId::at(i), &node.value, &node.deps[..]))
+    }
 
-        permute(nodes).map(|order| {
-            let values = order.iter().rev().map(|id| &self.nodes[id - 1].value);
-            values.collect()
-        })
+    pub fn deps_of(&self, id: NodeId) -> &[NodeId] {
+        &self.nodes[id.index()].deps
+    }
+
+    pub fn orderings(&self) -> impl Iterator<Item = Vec<&T>> + Send
+    where
+        T: Sync,
+    {
+        self.orderings_where(|_, _| true)
+    }
+
+    // Orderings in which every node is allowed to follow the nodes placed
+    // before it. A rejected node prunes every ordering with that prefix.
+    pub fn orderings_where<'a, F>(&'a self, allow: F) -> impl Iterator<Item = Vec<&'a T>> + Send
+    where
+        F: Fn(&[&T], &T) -> bool + Send + Sync + 'a,
+        T: Sync,
+    {
+        self.orderings_ranked(allow, |_, _| 0)
+    }
+
+    // As for `orderings_where`, but at each step the nodes that may come next
+    // are tried in order of their rank, lowest first, so the orderings that
+    // the ranking favours come out earliest. Nodes of equal rank keep their
+    // order in the graph.
+    pub fn orderings_ranked<'a, F, R>(
+        &'a self,
+        allow: F,
+        rank: R,
+    ) -> impl Iterator<Item = Vec<&'a T>> + Send
+    where
+        F: Fn(&[&T], &T) -> bool + Send + Sync + 'a,
+        R: Fn(&[&T], &T) -> usize + Send + Sync + 'a,
+        T: Sync,
+    {
+        Orderings::new(self, on_values(allow), rank)
+    }
+
+    pub fn ordering_ids_ranked<'a, F, R>(
+        &'a self,
+        allow: F,
+        rank: R,
+    ) -> impl Iterator<Item = Vec<NodeId>> + Send + 'a
+    where
+        F: Fn(&[&T], &T) -> bool + Send + Sync + 'a,
+        R: Fn(&[&T], &T) -> usize + Send + Sync + 'a,
+        T: Sync,
+    {
+        let mut walk = Orderings::new(self, on_values(allow), rank);
+        std::iter::from_fn(move || walk.next_ids())
+    }
+
+    // The `len` orderings that `orderings` gives from the `start`th on,
+    // counting from 0. Whole subtrees before `start` are skipped by counting
+    // them rather than walking them, so disjoint ranges split the orderings
+    // between processes without any of them listing the others' share.
+    pub fn orderings_range(&self, start: u128, len: usize) -> impl Iterator<Item = Vec<&T>> + Send
+    where
+        T: Sync,
+    {
+        let mut walk = Orderings::new(self, |_, _, _, _| true, |_, _| 0);
+        let mut placed = Mask::new(self.nodes.len());
+        let mut counts = Counts::default();
+        let mut skip = start;
+
+        if skip >= self.count_from(&mut placed, &mut counts.memo) {
+            walk.done = true;
+        }
+        while !walk.done && walk.placed.len() < self.nodes.len() {
+            let depth = walk.placed.len();
+            let i = walk.candidates[depth][walk.cursors[depth]];
+
+            walk.cursors[depth] += 1;
+            placed.insert(i);
+            let count = self.count_from(&mut placed, &mut counts.memo);
+
+            if skip < count {
+                walk.place(i);
+            } else {
+                placed.remove(i);
+                skip -= count;
+            }
+        }
+        walk.take(len)
+    }
+
+    // Orderings in which no node directly follows one added after it that
+    // it is `independent` of. Swapping two such nodes gives an ordering that
+    // behaves the same, and of each set of orderings that differ only by
+    // such swaps, the earliest by the order the nodes were added has no such
+    // pair, so at least one of each set is kept. Only adjacent nodes are
+    // compared, so some sets keep more than one. Deps are always added first,
+    // so no swap this rules out would break one.
+    pub fn reduced_orderings<'a, F>(
+        &'a self,
+        independent: F,
+    ) -> impl Iterator<Item = Vec<&'a T>> + Send
+    where
+        F: Fn(&T, &T) -> bool + Send + Sync + 'a,
+        T: Sync,
+    {
+        let allow = move |prefix: &[&T], node: &T, placed: &[usize], i: usize| {
+            let added_later = placed.last().is_none_or(|last| *last < i);
+            added_later || !independent(prefix[prefix.len() - 1], node)
+        };
+        Orderings::new(self, allow, |_, _| 0)
+    }
+
+    // As for `orderings`, but with each node's id alongside its value.
+    pub fn orderings_with_ids(&self) -> impl Iterator<Item = Vec<(NodeId, &T)>> + Send
+    where
+        T: Sync,
+    {
+        self.ordering_ids_ranked(|_, _| true, |_, _| 0)
+            .map(|ids| ids.into_iter().map(|id| (id, self.get(id))).collect())
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
     }
-}
 
-fn permute(nodes: Vec<(Id, Vec<Id>)>) -> Box<dyn Iterator<Item = Vec<Id>> + Send> {
-    if nodes.is_empty() {
-        return Box::new([Vec::new()].into_iter());
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
     }
 
-    let available: Vec<_> = nodes
-        .iter()
-        .filter(|(_, deps)| deps.is_empty())
-        .map(|(node_id, _)| *node_id)
-        .collect();
+    // The number of nodes on the longest path through the graph, which is
+    // the fewest steps it could run in.
+    pub fn depth(&self) -> usize {
+        self.levels().into_iter().max().map_or(0, |level| level + 1)
+    }
+
+    // The most nodes at any one level, where a node's level is the length of
+    // the longest path leading to it. Nodes at the same level never wait for
+    // each other, so this is a lower bound on how many can run at once.
+    pub fn width(&self) -> usize {
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+        for level in self.levels() {
+            *counts.entry(level).or_default() += 1;
+        }
+        counts.into_values().max().unwrap_or(0)
+    }
+
+    fn levels(&self) -> Vec<usize> {
+        let mut levels: Vec<usize> = Vec::with_capacity(self.nodes.len());
+
+        for node in &self.nodes {
+            let level = node.deps.iter().map(|dep| levels[dep.index()] + 1).max();
+            levels.push(level.unwrap_or(0));
+        }
+        levels
+    }
 
-    let states = available.into_iter().flat_map(move |action| {
-        let remaining: Vec<_> = nodes
+    pub fn hash_with<H, F>(&self, state: &mut H, hash_value: F)
+    where
+        H: Hasher,
+        F: Fn(&T, &mut H),
+    {
+        state.write_usize(self.nodes.len());
+
+        for node in &self.nodes {
+            state.write_usize(node.deps.len());
+            for dep in &node.deps {
+                state.write_usize(dep.0);
+            }
+            hash_value(&node.value, state);
+        }
+    }
+
+    // The number of orderings, found without listing them by counting the
+    // orderings of what remains after each set of nodes that can be placed
+    // first. Counts too large to hold saturate.
+    pub fn count_orderings(&self) -> u128 {
+        let mut placed = Mask::new(self.nodes.len());
+        self.count_from(&mut placed, &mut Counts::default().memo)
+    }
+
+    fn count_from(&self, placed: &mut Mask, memo: &mut HashMap<Mask, u128>) -> u128 {
+        if placed.len() == self.nodes.len() {
+            return 1;
+        }
+        if let Some(count) = memo.get(placed) {
+            return *count;
+        }
+        let mut count: u128 = 0;
+
+        for i in 0..self.nodes.len() {
+            if !placed.contains(i) && self.nodes[i].dep_mask.is_subset(placed) {
+                placed.insert(i);
+                count = count.saturating_add(self.count_from(placed, memo));
+                placed.remove(i);
+            }
+        }
+        memo.insert(placed.clone(), count);
+        count
+    }
+
+    // A random ordering in which every node is allowed to follow the nodes
+    // placed before it, built by picking one of the nodes that may come next
+    // at each step. Each is picked in proportion to the number of orderings
+    // that start with it, held in `counts` so repeated sampling counts them
+    // once, which makes every ordering equally likely when `allow` prunes
+    // none of them and keeps it close to that otherwise. Returns None if it
+    // reaches a point where no node may come next.
+    pub fn sample_where<F>(&self, allow: F, counts: &mut Counts, rng: &mut Rng) -> Option<Vec<&T>>
+    where
+        F: Fn(&[&T], &T) -> bool,
+    {
+        let mut walk = Orderings::new(self, on_values(allow), |_, _| 0);
+        let mut placed = Mask::new(self.nodes.len());
+
+        while walk.placed.len() < self.nodes.len() {
+            let candidates = &walk.candidates[walk.placed.len()];
+            if candidates.is_empty() {
+                return None;
+            }
+            let weights: Vec<_> = candidates
+                .iter()
+                .map(|i| {
+                    placed.insert(*i);
+                    let count = self.count_from(&mut placed, &mut counts.memo);
+                    placed.remove(*i);
+                    count
+                })
+                .collect();
+
+            let total = weights.iter().fold(0, |a: u128, b| a.saturating_add(*b));
+            let mut pick = rng.below_u128(total);
+            let mut chosen = candidates[candidates.len() - 1];
+
+            for (i, weight) in candidates.iter().zip(&weights) {
+                if pick < *weight {
+                    chosen = *i;
+                    break;
+                }
+                pick -= weight;
+            }
+            placed.insert(chosen);
+            walk.place(chosen);
+        }
+        Some(walk.prefix)
+    }
+
+    pub fn get(&self, id: NodeId) -> &T {
+        &self.nodes[id.index()].value
+    }
+
+    // Every dependency in the graph, as the id of the node depended on and
+    // the id of the node that waits for it, in the order the nodes were added.
+    pub fn edges(&self) -> Vec<(NodeId, NodeId)> {
+        self.nodes
             .iter()
-            .filter(|(node_id, _)| *node_id != action)
-            .map(|(node_id, deps)| {
-                let filtered = deps.iter().cloned().filter(|dep| *dep != action).collect();
-                (*node_id, filtered)
-            })
+            .enumerate()
+            .flat_map(|(i, node)| node.deps.iter().map(move |dep| (*dep, NodeId::at(i))))
+            .collect()
+    }
+
+    // The graph as a JSON array with an object for each node, in the order
+    // they were added, giving its id, the ids of its deps and whatever
+    // `value` makes of its value.
+    pub fn to_json<F>(&self, value: F) -> Json
+    where
+        F: Fn(&T) -> Json,
+    {
+        let nodes = self.nodes().map(|(id, node, deps)| {
+            let deps = deps.iter().map(|dep| Json::from(dep.0 as i64)).collect();
+            Json::object(&[
+                ("id", Json::from(id.0 as i64)),
+                ("deps", Json::Array(deps)),
+                ("value", value(node)),
+            ])
+        });
+        Json::Array(nodes.collect())
+    }
+
+    // Reads back a graph that `to_json` wrote, with `value` turning each
+    // node's value back into a T. The nodes must come in the order of their
+    // ids and only wait for nodes before them, as `to_json` writes them.
+    pub fn from_json<F>(json: &Json, value: F) -> Result<Graph<T>, String>
+    where
+        F: Fn(&Json) -> Result<T, String>,
+    {
+        let Json::Array(nodes) = json else {
+            return Err("expected an array of nodes".to_string());
+        };
+        let mut graph = Graph::new();
+
+        for (i, node) in nodes.iter().enumerate() {
+            if node.get("id") != Some(&Json::from(i as i64 + 1)) {
+                return Err(format!("expected node #{} to have id {}", i + 1, i + 1));
+            }
+            let Some(Json::Array(numbers)) = node.get("deps") else {
+                return Err(format!("expected node {} to have an array of deps", i + 1));
+            };
+            let deps = numbers
+                .iter()
+                .map(|number| match number {
+                    Json::Number(n) => usize::try_from(*n).ok().and_then(|n| graph.node_id(n)),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format!("node {} waits for a node not before it", i + 1))?;
+            let value = node
+                .get("value")
+                .ok_or_else(|| format!("node {} has no value", i + 1))
+                .and_then(&value)?;
+
+            graph.push(&deps, value);
+        }
+        Ok(graph)
+    }
+
+    // Drops every dep that a node would wait for anyway through its other
+    // deps. The graph has the same orderings with or without them, but with
+    // fewer edges there is less to update at each step of a walk.
+    pub fn transitive_reduction(&mut self) {
+        let mut ancestors: Vec<Mask> = Vec::with_capacity(self.nodes.len());
+
+        for i in 0..self.nodes.len() {
+            let node = &mut self.nodes[i];
+            let mut above = Mask::new(i + 1);
+
+            for dep in &node.deps {
+                above.union(&ancestors[dep.index()]);
+                above.insert(dep.index());
+            }
+            let implied: Vec<_> = node
+                .deps
+                .iter()
+                .filter(|dep| {
+                    node.deps
+                        .iter()
+                        .any(|other| ancestors[other.index()].contains(dep.index()))
+                })
+                .copied()
+                .collect();
+
+            for dep in implied {
+                node.deps.retain(|d| *d != dep);
+                node.dep_mask.remove(dep.index());
+            }
+            ancestors.push(above);
+        }
+    }
+
+    // The graph in Graphviz's DOT format, with a box for each node labelled
+    // with its value's Debug output and an arrow from each dep to the node
+    // that waits for it.
+    pub fn to_dot(&self) -> String
+    where
+        T: Debug,
+    {
+        let mut dot = String::from("digraph {\n");
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = format!("{:?}", node.value)
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"");
+            dot.push_str(&format!(
+                "    n{} [shape=box, label=\"{}\"];\n",
+                i + 1,
+                label
+            ));
+        }
+        for (dep, id) in self.edges() {
+            dot.push_str(&format!("    n{} -> n{};\n", dep, id));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // Makes the node `id` wait for `dep` as well as its other deps, as
+    // `add_edges` does for a single edge.
+    pub fn add_edge(&mut self, dep: NodeId, id: NodeId) -> Result<IdMap, GraphError> {
+        self.add_edges(&[(dep, id)])
+    }
+
+    // Makes each node `id` wait for its `dep` as well as its other deps. A
+    // node may come to wait for one added after it, as long as that one does
+    // not already wait for it. The nodes are then renumbered so that each
+    // still comes after its deps, keeping the order they were added in
+    // wherever the new edges allow, and the map it returns gives each node's
+    // old id the id it now has. If any edge would close a cycle, none of them
+    // is added.
+    pub fn add_edges(&mut self, edges: &[(NodeId, NodeId)]) -> Result<IdMap, GraphError> {
+        let mut deps: Vec<_> = self.nodes.iter().map(|node| node.deps.clone()).collect();
+
+        for (dep, id) in edges {
+            self.check(&[*dep, *id])?;
+            if waits_for(&deps, *dep, *id) {
+                return Err(GraphError::Cycle { dep: *dep, id: *id });
+            }
+            if !deps[id.index()].contains(dep) {
+                deps[id.index()].push(*dep);
+            }
+        }
+
+        // Places the earliest added node whose deps are all placed, until
+        // every node is, which the check above guarantees.
+        let mut waiting: Vec<_> = deps.iter().map(Vec::len).collect();
+        let mut dependents = vec![Vec::new(); deps.len()];
+        for (i, node_deps) in deps.iter().enumerate() {
+            for dep in node_deps {
+                dependents[dep.index()].push(i);
+            }
+        }
+        let mut ready: BTreeSet<_> = (0..deps.len()).filter(|i| waiting[*i] == 0).collect();
+        let mut order = Vec::with_capacity(deps.len());
+
+        while let Some(i) = ready.pop_first() {
+            order.push(i);
+            for j in &dependents[i] {
+                waiting[*j] -= 1;
+                if waiting[*j] == 0 {
+                    ready.insert(*j);
+                }
+            }
+        }
+
+        let mut ids = vec![NodeId::at(0); deps.len()];
+        for (new, old) in order.iter().enumerate() {
+            ids[*old] = NodeId::at(new);
+        }
+        let mut nodes: Vec<_> = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .map(Some)
             .collect();
 
-        permute(remaining).map(move |mut others| {
-            others.push(action);
-            others
-        })
-    });
+        for old in order {
+            let node_deps: Vec<_> = deps[old].iter().map(|dep| ids[dep.index()]).collect();
+            let value = nodes[old].take().unwrap().value;
+            self.push(&node_deps, value);
+        }
+        Ok(IdMap { ids })
+    }
+
+    // Lets the node `id` run without waiting for `dep`. The node still waits
+    // for its other deps.
+    pub fn remove_edge(&mut self, dep: NodeId, id: NodeId) -> Result<(), GraphError> {
+        self.check(&[dep, id])?;
+        let node = &mut self.nodes[id.index()];
+        node.deps.retain(|d| *d != dep);
+        node.dep_mask.remove(dep.index());
+        Ok(())
+    }
+}
+
+// Whether the node `from` waits for `to`, directly or not, or is `to`, where
+// `deps` gives each node's deps by its index.
+fn waits_for(deps: &[Vec<NodeId>], from: NodeId, to: NodeId) -> bool {
+    let mut seen = vec![false; deps.len()];
+    let mut stack = vec![from];
+
+    while let Some(id) = stack.pop() {
+        if id == to {
+            return true;
+        }
+        if !std::mem::replace(&mut seen[id.index()], true) {
+            stack.extend(&deps[id.index()]);
+        }
+    }
+    false
+}
+
+// The ids that a merged or renumbered graph's nodes were given, by the ids
+// they had before.
+#[derive(Debug)]
+pub struct IdMap {
+    ids: Vec<NodeId>,
+}
+
+impl IdMap {
+    pub fn get(&self, id: NodeId) -> NodeId {
+        self.ids[id.index()]
+    }
+
+    pub fn values(&self) -> &[NodeId] {
+        &self.ids
+    }
+}
+
+// The number of orderings that remain once a set of nodes has been placed,
+// for each set seen so far. One is only good for the graph it was filled
+// from.
+#[derive(Debug, Default)]
+pub struct Counts {
+    memo: HashMap<Mask, u128>,
+}
+
+// Turns a filter on the values of the nodes into one that `Orderings` can
+// call, which is also given the indices of the placed nodes and the candidate.
+fn on_values<T, F>(allow: F) -> impl Fn(&[&T], &T, &[usize], usize) -> bool
+where
+    F: Fn(&[&T], &T) -> bool,
+{
+    move |prefix, node, _, _| allow(prefix, node)
+}
+
+// A depth-first walk over the orderings of a graph. Rather than copying the
+// remaining nodes at each step, it keeps a count of each node's unplaced deps
+// and the set of nodes that are ready to place, and updates both in place as
+// nodes are placed and then taken back on backtracking. On reaching a depth
+// it lists the nodes to try there, and a cursor per depth tracks how far
+// through that list the walk has got.
+struct Orderings<'a, T, F, R> {
+    graph: &'a Graph<T>,
+    allow: F,
+    rank: R,
+    dependents: Vec<Vec<usize>>,
+    waiting: Vec<usize>,
+    ready: Vec<bool>,
+    prefix: Vec<&'a T>,
+    placed: Vec<usize>,
+    candidates: Vec<Vec<usize>>,
+    cursors: Vec<usize>,
+    done: bool,
+}
+
+impl<'a, T, F, R> Orderings<'a, T, F, R>
+where
+    F: Fn(&[&T], &T, &[usize], usize) -> bool,
+    R: Fn(&[&T], &T) -> usize,
+{
+    fn new(graph: &'a Graph<T>, allow: F, rank: R) -> Orderings<'a, T, F, R> {
+        let size = graph.nodes.len();
+        let mut dependents = vec![Vec::new(); size];
+        let mut waiting = vec![0; size];
+
+        for (i, node) in graph.nodes.iter().enumerate() {
+            for dep in &node.deps {
+                dependents[dep.index()].push(i);
+            }
+            waiting[i] = node.deps.len();
+        }
+        let ready = waiting.iter().map(|count| *count == 0).collect();
+
+        let mut orderings = Orderings {
+            graph,
+            allow,
+            rank,
+            dependents,
+            waiting,
+            ready,
+            prefix: Vec::with_capacity(size),
+            placed: Vec::with_capacity(size),
+            candidates: vec![Vec::new(); size + 1],
+            cursors: vec![0],
+            done: false,
+        };
+        orderings.find_candidates();
+        orderings
+    }
+
+    fn find_candidates(&mut self) {
+        let depth = self.placed.len();
+        let mut candidates = std::mem::take(&mut self.candidates[depth]);
+
+        candidates.clear();
+        candidates.extend((0..self.ready.len()).filter(|i| {
+            let node = self.graph.get(NodeId::at(*i));
+            self.ready[*i] && (self.allow)(&self.prefix, node, &self.placed, *i)
+        }));
+        candidates.sort_by_key(|i| (self.rank)(&self.prefix, self.graph.get(NodeId::at(*i))));
+
+        self.candidates[depth] = candidates;
+    }
+
+    fn place(&mut self, i: usize) {
+        self.ready[i] = false;
+
+        for j in &self.dependents[i] {
+            self.waiting[*j] -= 1;
+            if self.waiting[*j] == 0 {
+                self.ready[*j] = true;
+            }
+        }
+        self.prefix.push(self.graph.get(NodeId::at(i)));
+        self.placed.push(i);
+        self.cursors.push(0);
+        self.find_candidates();
+    }
+
+    fn backtrack(&mut self) {
+        self.cursors.pop();
+
+        let Some(i) = self.placed.pop() else {
+            self.done = true;
+            return;
+        };
+        self.prefix.pop();
+
+        for j in &self.dependents[i] {
+            self.ready[*j] = false;
+            self.waiting[*j] += 1;
+        }
+        self.ready[i] = true;
+    }
+
+    // Leaves the next complete ordering in `prefix` and `placed`.
+    fn advance(&mut self) -> bool {
+        while !self.done {
+            if self.placed.len() == self.ready.len() {
+                return true;
+            }
+
+            let depth = self.placed.len();
+
+            match self.candidates[depth].get(self.cursors[depth]) {
+                Some(&i) => {
+                    self.cursors[depth] += 1;
+                    self.place(i);
+                }
+                None => self.backtrack(),
+            }
+        }
+        false
+    }
+
+    fn next_ids(&mut self) -> Option<Vec<NodeId>> {
+        if !self.advance() {
+            return None;
+        }
+        let ids = self.placed.iter().map(|i| NodeId::at(*i)).collect();
+        self.backtrack();
+        Some(ids)
+    }
+}
+
+impl<'a, T, F, R> Iterator for Orderings<'a, T, F, R>
+where
+    F: Fn(&[&T], &T, &[usize], usize) -> bool,
+    R: Fn(&[&T], &T) -> usize,
+{
+    type Item = Vec<&'a T>;
 
-    Box::new(states)
+    fn next(&mut self) -> Option<Vec<&'a T>> {
+        if !self.advance() {
+            return None;
+        }
+        let ordering = self.prefix.clone();
+        self.backtrack();
+        Some(ordering)
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use std::collections::hash_map::DefaultHasher;
     use std::collections::{HashMap, HashSet};
     use std::fmt::Debug;
+    use std::hash::Hash;
 
     type NodeSpec<'a, T> = (&'a str, T, &'a [&'a str]);
 
@@ -85,7 +893,7 @@ pub mod tests {
     where
         T: Debug + PartialEq,
     {
-        let mut mapping: HashMap<String, Id> = HashMap::new();
+        let mut mapping: HashMap<String, NodeId> = HashMap::new();
 
         assert_eq!(
             graph.nodes.len(),
@@ -96,11 +904,12 @@ pub mod tests {
         );
 
         for (key, value, deps) in nodes {
-            let opt_node = graph.nodes.iter().find(|node| &node.value == value);
-            assert!(opt_node.is_some(), "no node matching {:?}", value);
+            let opt_index = graph.nodes.iter().position(|node| &node.value == value);
+            assert!(opt_index.is_some(), "no node matching {:?}", value);
 
-            let node = opt_node.unwrap();
-            mapping.insert(key.to_string(), node.id);
+            let index = opt_index.unwrap();
+            let node = &graph.nodes[index];
+            mapping.insert(key.to_string(), NodeId::at(index));
 
             let dep_ids: HashSet<_> = deps
                 .iter()
@@ -121,20 +930,40 @@ pub mod tests {
         assert_eq!(mapping.len(), nodes.len());
     }
 
+    #[test]
+    fn orders_an_empty_graph() {
+        let graph: Graph<char> = Graph::new();
+        let orderings: Vec<_> = graph.orderings().collect();
+
+        assert_eq!(orderings, [Vec::<&char>::new()]);
+    }
+
     #[test]
     fn orders_a_single_action() {
         let mut graph = Graph::new();
-        graph.add(&[], 'a');
+        graph.add(&[], 'a').unwrap();
         let orderings: Vec<_> = graph.orderings().collect();
 
         assert_eq!(orderings, [vec![&'a']]);
     }
 
+    #[test]
+    fn hands_out_ids_only_for_the_nodes_it_holds() {
+        let mut graph = Graph::new();
+        let a = graph.add(&[], 'a').unwrap();
+        let b = graph.add(&[a], 'b').unwrap();
+
+        assert_eq!(graph.node_id(1), Some(a));
+        assert_eq!(graph.node_id(b.number()), Some(b));
+        assert_eq!(graph.node_id(0), None);
+        assert_eq!(graph.node_id(3), None);
+    }
+
     #[test]
     fn orders_two_concurrent_events() {
         let mut graph = Graph::new();
-        graph.add(&[], 'a');
-        graph.add(&[], 'b');
+        graph.add(&[], 'a').unwrap();
+        graph.add(&[], 'b').unwrap();
         let orderings: Vec<_> = graph.orderings().collect();
 
         assert_eq!(orderings, [vec![&'a', &'b'], vec![&'b', &'a']]);
@@ -143,8 +972,8 @@ pub mod tests {
     #[test]
     fn orders_two_sequential_events() {
         let mut graph = Graph::new();
-        let a = graph.add(&[], 'a');
-        graph.add(&[a], 'b');
+        let a = graph.add(&[], 'a').unwrap();
+        graph.add(&[a], 'b').unwrap();
         let orderings: Vec<_> = graph.orderings().collect();
 
         assert_eq!(orderings, [vec![&'a', &'b']]);
@@ -154,10 +983,10 @@ pub mod tests {
     fn orders_a_diamond_shaped_graph() {
         let mut graph = Graph::new();
 
-        let a = graph.add(&[], 'a');
-        let b = graph.add(&[a], 'b');
-        let c = graph.add(&[a], 'c');
-        graph.add(&[b, c], 'd');
+        let a = graph.add(&[], 'a').unwrap();
+        let b = graph.add(&[a], 'b').unwrap();
+        let c = graph.add(&[a], 'c').unwrap();
+        graph.add(&[b, c], 'd').unwrap();
 
         let orderings: Vec<_> = graph.orderings().collect();
 
@@ -167,15 +996,136 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn orders_a_diamond_without_one_of_its_edges() {
+        let mut graph = Graph::new();
+
+        let a = graph.add(&[], 'a').unwrap();
+        let b = graph.add(&[a], 'b').unwrap();
+        let c = graph.add(&[a], 'c').unwrap();
+        let d = graph.add(&[b, c], 'd').unwrap();
+
+        assert_eq!(graph.edges(), [(a, b), (a, c), (b, d), (c, d)]);
+
+        graph.remove_edge(a, c).unwrap();
+        assert_eq!(graph.edges(), [(a, b), (b, d), (c, d)]);
+
+        let orderings: Vec<String> = graph
+            .orderings()
+            .map(|ordering| ordering.into_iter().collect())
+            .collect();
+        assert_eq!(orderings, ["abcd", "acbd", "cabd"]);
+    }
+
+    #[test]
+    fn orders_two_chains_with_an_edge_between_them() {
+        let mut graph = Graph::new();
+
+        let a = graph.add(&[], 'a').unwrap();
+        graph.add(&[a], 'b').unwrap();
+        let c = graph.add(&[], 'c').unwrap();
+        graph.add(&[c], 'd').unwrap();
+
+        graph.add_edge(a, c).unwrap();
+        graph.add_edge(a, c).unwrap();
+        assert_eq!(graph.deps_of(c), [a]);
+
+        let orderings: Vec<String> = graph
+            .orderings()
+            .map(|ordering| ordering.into_iter().collect())
+            .collect();
+        assert_eq!(orderings, ["abcd", "acbd", "acdb"]);
+    }
+
+    #[test]
+    fn drops_rejected_nodes_and_those_that_wait_for_them() {
+        let mut graph = Graph::new();
+
+        let a = graph.add(&[], 'a').unwrap();
+        let b = graph.add(&[a], 'b').unwrap();
+        let c = graph.add(&[], 'c').unwrap();
+        let d = graph.add(&[b, c], 'd').unwrap();
+        let e = graph.add(&[c], 'e').unwrap();
+
+        let ids = graph.retain(|_, value| *value != 'b');
+
+        assert_eq!(ids.get(&a), Some(&NodeId(1)));
+        assert_eq!(ids.get(&b), None);
+        assert_eq!(ids.get(&d), None);
+        assert_eq!(ids.get(&e), Some(&NodeId(3)));
+        assert_eq!(graph.edges(), [(NodeId(2), NodeId(3))]);
+        assert_eq!(graph.iter().collect::<String>(), "ace");
+    }
+
+    #[test]
+    fn renumbers_the_nodes_for_an_edge_from_a_later_node() {
+        let mut graph = Graph::new();
+        let a = graph.add(&[], 'a').unwrap();
+        let b = graph.add(&[a], 'b').unwrap();
+        let c = graph.add(&[], 'c').unwrap();
+
+        let ids = graph.add_edge(c, a).unwrap();
+        assert_eq!(ids.values(), [NodeId(2), NodeId(3), NodeId(1)]);
+
+        let nodes: Vec<_> = graph
+            .nodes()
+            .map(|(id, value, deps)| (id, *value, deps.to_vec()))
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                (NodeId(1), 'c', vec![]),
+                (NodeId(2), 'a', vec![ids.get(c)]),
+                (NodeId(3), 'b', vec![ids.get(a)]),
+            ]
+        );
+        assert_eq!(ids.get(b), NodeId(3));
+
+        let orderings: Vec<_> = graph.orderings().collect();
+        assert_eq!(orderings, [vec![&'c', &'a', &'b']]);
+    }
+
+    #[test]
+    fn rejects_edges_that_close_a_cycle() {
+        let mut graph = Graph::new();
+        let a = graph.add(&[], 'a').unwrap();
+        let b = graph.add(&[a], 'b').unwrap();
+        let c = graph.add(&[], 'c').unwrap();
+
+        let error = graph.add_edges(&[(c, a), (b, c)]).unwrap_err();
+        assert_eq!(error, GraphError::Cycle { dep: b, id: c });
+        assert_eq!(
+            error.to_string(),
+            "node 3 cannot depend on 2, which already waits for it"
+        );
+        assert_eq!(
+            graph.add_edge(a, a).unwrap_err(),
+            GraphError::Cycle { dep: a, id: a }
+        );
+        assert_eq!(graph.edges(), [(a, b)]);
+    }
+
+    #[test]
+    fn measures_the_depth_and_width_of_a_graph() {
+        let mut graph = Graph::new();
+        assert_eq!((graph.depth(), graph.width()), (0, 0));
+
+        let a = graph.add(&[], 'a').unwrap();
+        let b = graph.add(&[a], 'b').unwrap();
+        let c = graph.add(&[a], 'c').unwrap();
+        graph.add(&[a], 'd').unwrap();
+        graph.add(&[b, c], 'e').unwrap();
+        graph.add(&[], 'f').unwrap();
+
+        assert_eq!((graph.depth(), graph.width()), (3, 3));
+    }
+
     #[test]
     fn orders_two_sets_of_unconnected_sequences() {
         let mut graph = Graph::new();
 
-        for chain in vec![vec!['a', 'b'], vec!['c', 'd', 'e']] {
-            let mut deps = vec![];
-            for act in chain {
-                deps = vec![graph.add(&deps, act)];
-            }
+        for chain in [vec!['a', 'b'], vec!['c', 'd', 'e']] {
+            graph.chain(&[], chain).unwrap();
         }
         let orderings: Vec<_> = graph.orderings().collect();
 
@@ -196,13 +1146,86 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn builds_chains_and_fans() {
+        let mut graph = Graph::new();
+
+        let a = graph.add(&[], 'a').unwrap();
+        let fan = graph.fan_out(&[a], ['b', 'c']).unwrap();
+        let end = graph.chain(&fan, ['d', 'e']).unwrap();
+        let none = graph.chain(&end, []).unwrap();
+
+        check_graph(
+            &graph,
+            &[
+                ("a", 'a', &[]),
+                ("b", 'b', &["a"]),
+                ("c", 'c', &["a"]),
+                ("d", 'd', &["b", "c"]),
+                ("e", 'e', &["d"]),
+            ],
+        );
+        assert_eq!(none, end);
+    }
+
+    #[test]
+    fn merges_one_graph_into_another() {
+        let mut graph = Graph::new();
+        let a = graph.add(&[], 'a').unwrap();
+
+        let mut other = Graph::new();
+        let b = other.add(&[], 'b').unwrap();
+        let c = other.add(&[b], 'c').unwrap();
+
+        let ids = graph.merge(&[], other).unwrap();
+        graph.add(&[a, ids.get(c)], 'd').unwrap();
+
+        check_graph(
+            &graph,
+            &[
+                ("a", 'a', &[]),
+                ("b", 'b', &[]),
+                ("c", 'c', &["b"]),
+                ("d", 'd', &["a", "c"]),
+            ],
+        );
+        assert_eq!(graph.get(ids.get(b)), &'b');
+    }
+
+    #[test]
+    fn merges_a_graph_after_the_given_nodes() {
+        let mut graph = Graph::new();
+        let a = graph.add(&[], 'a').unwrap();
+
+        let mut other = Graph::new();
+        let b = other.add(&[], 'b').unwrap();
+        other.add(&[b], 'c').unwrap();
+        other.add(&[], 'd').unwrap();
+
+        let ids = graph.merge(&[a], other).unwrap();
+        assert_eq!(ids.values(), [NodeId(2), NodeId(3), NodeId(4)]);
+
+        check_graph(
+            &graph,
+            &[
+                ("a", 'a', &[]),
+                ("b", 'b', &["a"]),
+                ("c", 'c', &["b"]),
+                ("d", 'd', &["a"]),
+            ],
+        );
+    }
+
     #[test]
     fn orders_a_top_level_update_operation() {
         let mut graph = Graph::new();
 
-        let reads = [graph.add(&[], "LIST /"), graph.add(&[], "GET /x")];
-        let link = graph.add(&reads, "LINK / x");
-        graph.add(&[link], "PUT /x {}");
+        let reads = [
+            graph.add(&[], "LIST /").unwrap(),
+            graph.add(&[], "GET /x").unwrap(),
+        ];
+        let link = graph.add(&reads, "LINK / x").unwrap();
+        graph.add(&[link], "PUT /x {}").unwrap();
 
         let orderings: Vec<_> = graph.orderings().collect();
 
@@ -219,10 +1242,10 @@ pub mod tests {
     fn orders_a_top_level_update_operation_with_deferred_get() {
         let mut graph = Graph::new();
 
-        let list = graph.add(&[], "LIST /");
-        let link = graph.add(&[list], "LINK / x");
-        let get = graph.add(&[], "GET /x");
-        graph.add(&[get, link], "PUT /x {}");
+        let list = graph.add(&[], "LIST /").unwrap();
+        let link = graph.add(&[list], "LINK / x").unwrap();
+        let get = graph.add(&[], "GET /x").unwrap();
+        graph.add(&[get, link], "PUT /x {}").unwrap();
 
         let orderings: Vec<_> = graph.orderings().collect();
 
@@ -242,15 +1265,15 @@ pub mod tests {
 
         let reads: Vec<_> = ["GET /path/x", "LIST /path/", "LIST /"]
             .into_iter()
-            .map(|action| graph.add(&[], action))
+            .map(|action| graph.add(&[], action).unwrap())
             .collect();
 
         let links: Vec<_> = ["LINK /path/ x", "LINK / path/"]
             .into_iter()
-            .map(|action| graph.add(&reads, action))
+            .map(|action| graph.add(&reads, action).unwrap())
             .collect();
 
-        graph.add(&links, "PUT /path/x {}");
+        graph.add(&links, "PUT /path/x {}").unwrap();
 
         let orderings: Vec<_> = graph.orderings().collect();
 
@@ -360,18 +1383,381 @@ pub mod tests {
     fn example_graph() -> Graph<usize> {
         let mut graph = Graph::new();
 
-        let n3 = graph.add(&[], 3);
-        let n5 = graph.add(&[], 5);
-        let n7 = graph.add(&[], 7);
-        let n0 = graph.add(&[n3, n7], 0);
-        let n1 = graph.add(&[n5, n7], 1);
-        let _n2 = graph.add(&[n1], 2);
-        let _n4 = graph.add(&[n1, n3], 4);
-        let _n6 = graph.add(&[n0, n1], 6);
+        let n3 = graph.add(&[], 3).unwrap();
+        let n5 = graph.add(&[], 5).unwrap();
+        let n7 = graph.add(&[], 7).unwrap();
+        let n0 = graph.add(&[n3, n7], 0).unwrap();
+        let n1 = graph.add(&[n5, n7], 1).unwrap();
+        let _n2 = graph.add(&[n1], 2).unwrap();
+        let _n4 = graph.add(&[n1, n3], 4).unwrap();
+        let _n6 = graph.add(&[n0, n1], 6).unwrap();
 
         graph
     }
 
+    #[test]
+    fn prunes_orderings_with_a_rejected_prefix() {
+        let mut graph = Graph::new();
+        graph.add(&[], 'a').unwrap();
+        graph.add(&[], 'b').unwrap();
+        graph.add(&[], 'c').unwrap();
+
+        let orderings: Vec<_> = graph
+            .orderings_where(|prefix, node| !(prefix.last() == Some(&&'b') && *node == 'a'))
+            .collect();
+
+        assert_eq!(
+            orderings,
+            [
+                vec![&'a', &'b', &'c'],
+                vec![&'a', &'c', &'b'],
+                vec![&'b', &'c', &'a'],
+                vec![&'c', &'a', &'b'],
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_a_range_of_orderings() {
+        let graph = example_graph();
+        let orderings: Vec<_> = graph.orderings().collect();
+
+        for (start, len) in [(0, 150), (0, 7), (41, 30), (149, 5), (150, 1)] {
+            let range: Vec<_> = graph.orderings_range(start, len).collect();
+            let expected: Vec<_> = orderings.iter().skip(start as usize).take(len).collect();
+            assert!(range.iter().eq(expected), "range {}..+{}", start, len);
+        }
+
+        let shards: Vec<_> = (0..4)
+            .flat_map(|n| graph.orderings_range(n * 38, 38))
+            .collect();
+        assert_eq!(shards, orderings);
+    }
+
+    #[test]
+    fn rejects_a_dep_on_a_node_not_in_the_graph() {
+        let mut other = Graph::new();
+        let far = other.chain(&[], ['x', 'y', 'z']).unwrap()[0];
+
+        let mut graph = Graph::new();
+        let a = graph.add(&[], 'a').unwrap();
+
+        let error = graph.add(&[a, far], 'b').unwrap_err();
+        assert_eq!(error, GraphError::UnknownNode(far));
+        assert_eq!(error.to_string(), "node 3 is not in the graph");
+
+        assert_eq!(
+            graph.fan_out(&[far], ['b']),
+            Err(GraphError::UnknownNode(far))
+        );
+        assert_eq!(
+            graph.chain(&[far], ['b']),
+            Err(GraphError::UnknownNode(far))
+        );
+        assert_eq!(
+            graph.add_edge(far, a).unwrap_err(),
+            GraphError::UnknownNode(far)
+        );
+        assert_eq!(graph.len(), 1);
+    }
+
+    #[test]
+    fn rejects_removing_an_edge_to_a_node_not_in_the_graph() {
+        let mut other = Graph::new();
+        let far = other.chain(&[], ['x', 'y', 'z']).unwrap()[0];
+
+        let mut graph = Graph::new();
+        let a = graph.add(&[], 'a').unwrap();
+        let b = graph.add(&[a], 'b').unwrap();
+
+        assert_eq!(graph.remove_edge(far, b), Err(GraphError::UnknownNode(far)));
+        assert_eq!(graph.remove_edge(a, far), Err(GraphError::UnknownNode(far)));
+        assert_eq!(graph.edges(), [(a, b)]);
+    }
+
+    #[test]
+    fn lists_nodes_with_their_deps() {
+        let graph = example_graph();
+        let ids: Vec<_> = graph.ids().collect();
+
+        let nodes: Vec<_> = graph
+            .nodes()
+            .map(|(id, value, deps)| (id, *value, deps.to_vec()))
+            .collect();
+
+        assert_eq!(nodes.len(), 8);
+        assert_eq!(nodes[0], (ids[0], 3, vec![]));
+        assert_eq!(nodes[3], (ids[3], 0, vec![ids[0], ids[2]]));
+        assert_eq!(graph.deps_of(ids[6]), [ids[4], ids[0]]);
+    }
+
+    #[test]
+    fn writes_a_graph_as_json() {
+        let mut graph = Graph::new();
+        let a = graph.add(&[], 'a').unwrap();
+        graph.add(&[a], 'b').unwrap();
+
+        let json = graph.to_json(|value| Json::from(value.to_string()));
+        assert_eq!(
+            json.to_string(),
+            r#"[{"deps": [], "id": 1, "value": "a"}, {"deps": [1], "id": 2, "value": "b"}]"#
+        );
+    }
+
+    #[test]
+    fn reads_back_a_graph_it_wrote_as_json() {
+        let mut graph = Graph::new();
+        let a = graph.add(&[], 'a').unwrap();
+        let b = graph.add(&[], 'b').unwrap();
+        graph.add(&[b, a], 'c').unwrap();
+
+        let json = graph.to_json(|value| Json::from(value.to_string()));
+        let text = json.to_string();
+        let copy = Graph::from_json(&Json::parse(&text).unwrap(), |value| match value {
+            Json::String(s) => s.chars().next().ok_or("empty value".to_string()),
+            _ => Err("expected a string".to_string()),
+        })
+        .unwrap();
+
+        assert_eq!(copy.edges(), graph.edges());
+        assert_eq!(
+            copy.orderings().collect::<Vec<_>>(),
+            graph.orderings().collect::<Vec<_>>()
+        );
+        assert_eq!(copy.to_json(|value| Json::from(value.to_string())), json);
+    }
+
+    #[test]
+    fn refuses_json_for_a_graph_it_could_not_have_written() {
+        let read = |text: &str| {
+            let json = Json::parse(text).unwrap();
+            Graph::from_json(&json, |_| Ok(())).map(|graph| graph.len())
+        };
+
+        assert_eq!(read(r#"[{"id": 1, "deps": [], "value": null}]"#), Ok(1));
+        assert_eq!(
+            read(r#"[{"id": 2, "deps": [], "value": null}]"#),
+            Err("expected node #1 to have id 1".to_string())
+        );
+        assert_eq!(
+            read(r#"[{"id": 1, "deps": [1], "value": null}]"#),
+            Err("node 1 waits for a node not before it".to_string())
+        );
+        assert_eq!(
+            read(r#"[{"id": 1, "deps": []}]"#),
+            Err("node 1 has no value".to_string())
+        );
+        assert_eq!(read("{}"), Err("expected an array of nodes".to_string()));
+    }
+
+    #[test]
+    fn drops_deps_implied_by_other_deps() {
+        let mut graph = Graph::new();
+
+        let a = graph.add(&[], 'a').unwrap();
+        let b = graph.add(&[a], 'b').unwrap();
+        let c = graph.add(&[a], 'c').unwrap();
+        let d = graph.add(&[a, b, c], 'd').unwrap();
+        let e = graph.add(&[a, d], 'e').unwrap();
+
+        let text = |graph: &Graph<char>| -> Vec<String> {
+            graph.orderings().map(|o| o.into_iter().collect()).collect()
+        };
+        let orderings = text(&graph);
+        graph.transitive_reduction();
+
+        assert_eq!(graph.edges(), [(a, b), (a, c), (b, d), (c, d), (d, e)]);
+        assert_eq!(text(&graph), orderings);
+    }
+
+    #[test]
+    fn renders_a_graph_as_dot() {
+        let mut graph = Graph::new();
+        let a = graph.add(&[], "a").unwrap();
+        let b = graph.add(&[a], "say \"b\"").unwrap();
+        graph.add(&[a, b], "c").unwrap();
+
+        assert_eq!(
+            graph.to_dot(),
+            [
+                "digraph {",
+                "    n1 [shape=box, label=\"\\\"a\\\"\"];",
+                "    n2 [shape=box, label=\"\\\"say \\\\\\\"b\\\\\\\"\\\"\"];",
+                "    n3 [shape=box, label=\"\\\"c\\\"\"];",
+                "    n1 -> n2;",
+                "    n1 -> n3;",
+                "    n2 -> n3;",
+                "}\n",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn keeps_one_order_of_independent_nodes() {
+        let mut graph = Graph::new();
+        graph.add(&[], "a:x").unwrap();
+        graph.add(&[], "b:y").unwrap();
+        graph.add(&[], "c:x").unwrap();
+
+        let orderings: Vec<_> = graph.reduced_orderings(|a, b| a[2..] != b[2..]).collect();
+
+        assert_eq!(
+            orderings,
+            [
+                vec![&"a:x", &"b:y", &"c:x"],
+                vec![&"b:y", &"c:x", &"a:x"],
+                vec![&"c:x", &"a:x", &"b:y"],
+            ]
+        );
+
+        let all = graph.reduced_orderings(|_, _| false).count();
+        let none = graph.reduced_orderings(|_, _| true).count();
+        assert_eq!((all, none), (6, 1));
+    }
+
+    #[test]
+    fn tells_apart_nodes_whose_values_share_an_address() {
+        let mut graph = Graph::new();
+        graph.add(&[], ()).unwrap();
+        graph.add(&[], ()).unwrap();
+        graph.add(&[], ()).unwrap();
+
+        assert_eq!(graph.reduced_orderings(|_, _| true).count(), 1);
+        assert_eq!(graph.reduced_orderings(|_, _| false).count(), 6);
+    }
+
+    #[test]
+    fn tries_lower_ranked_nodes_first() {
+        let mut graph = Graph::new();
+        let a = graph.add(&[], 'a').unwrap();
+        graph.add(&[a], 'b').unwrap();
+        graph.add(&[], 'c').unwrap();
+
+        let rank = |prefix: &[&char], node: &char| match (prefix.last(), node) {
+            (Some('a'), 'c') => 0,
+            _ => 1,
+        };
+        let orderings: Vec<_> = graph.orderings_ranked(|_, _| true, rank).collect();
+
+        assert_eq!(
+            orderings,
+            [
+                vec![&'a', &'c', &'b'],
+                vec![&'a', &'b', &'c'],
+                vec![&'c', &'a', &'b'],
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_orderings_without_listing_them() {
+        let graph = example_graph();
+        assert_eq!(graph.count_orderings(), 150);
+
+        let graph: Graph<char> = Graph::new();
+        assert_eq!(graph.count_orderings(), 1);
+    }
+
+    #[test]
+    fn orders_deep_graphs_without_recursing() {
+        let mut chain = Graph::new();
+        chain.chain(&[], 0..10_000).unwrap();
+        let orderings: Vec<_> = chain.orderings().collect();
+
+        assert_eq!(orderings.len(), 1);
+        assert!(orderings[0].iter().map(|n| **n).eq(0..10_000));
+
+        let mut clients = Graph::new();
+        for client in 0..3 {
+            clients.chain(&[], (0..6).map(|n| client * 6 + n)).unwrap();
+        }
+        assert_eq!(clients.count_orderings(), 17_153_136);
+        assert_eq!(clients.orderings().take(1000).count(), 1000);
+    }
+
+    #[test]
+    fn samples_allowed_orderings() {
+        let graph = example_graph();
+        let allow = |prefix: &[&usize], node: &usize| !prefix.is_empty() || *node == 5;
+
+        let orderings: HashSet<_> = graph.orderings_where(allow).collect();
+        let mut counts = Counts::default();
+        let mut rng = Rng::new(0);
+
+        for _ in 0..50 {
+            let sample = graph.sample_where(allow, &mut counts, &mut rng).unwrap();
+            assert!(orderings.contains(&sample));
+        }
+    }
+
+    #[test]
+    fn samples_orderings_uniformly() {
+        let mut graph = Graph::new();
+        let a = graph.add(&[], 'a').unwrap();
+        graph.add(&[a], 'b').unwrap();
+        graph.add(&[], 'c').unwrap();
+
+        let mut counts = Counts::default();
+        let mut rng = Rng::new(0);
+        let mut seen: HashMap<String, usize> = HashMap::new();
+
+        for _ in 0..3000 {
+            let sample = graph
+                .sample_where(|_, _| true, &mut counts, &mut rng)
+                .unwrap();
+            *seen.entry(sample.into_iter().collect()).or_insert(0) += 1;
+        }
+        assert_eq!(seen.len(), 3);
+        assert!(seen.values().all(|n| *n > 900 && *n < 1100), "{:?}", seen);
+    }
+
+    #[test]
+    fn lists_orderings_by_node_id() {
+        let graph = example_graph();
+        let allow = |prefix: &[&usize], node: &usize| !prefix.is_empty() || *node == 5;
+
+        let orderings: Vec<_> = graph.orderings_ranked(allow, |_, node| *node).collect();
+        let ids: Vec<Vec<_>> = graph
+            .ordering_ids_ranked(allow, |_, node| *node)
+            .map(|ids| ids.into_iter().map(|id| graph.get(id)).collect())
+            .collect();
+
+        assert_eq!(ids, orderings);
+    }
+
+    #[test]
+    fn lists_orderings_with_node_ids() {
+        let graph = example_graph();
+        let orderings: Vec<_> = graph.orderings().collect();
+
+        for (ordering, pairs) in orderings.iter().zip(graph.orderings_with_ids()) {
+            assert!(pairs.iter().all(|(id, value)| graph.get(*id) == *value));
+            assert!(pairs.iter().map(|(_, value)| value).eq(ordering));
+        }
+        assert_eq!(graph.orderings_with_ids().count(), orderings.len());
+    }
+
+    #[test]
+    fn hashes_the_shape_of_a_graph() {
+        let hash = |graph: &Graph<usize>| {
+            let mut hasher = DefaultHasher::new();
+            graph.hash_with(&mut hasher, |value, state| value.hash(state));
+            hasher.finish()
+        };
+
+        let mut chain = Graph::new();
+        let a = chain.add(&[], 1).unwrap();
+        chain.add(&[a], 2).unwrap();
+
+        let mut pair = Graph::new();
+        pair.add(&[], 1).unwrap();
+        pair.add(&[], 2).unwrap();
+
+        assert_eq!(hash(&example_graph()), hash(&example_graph()));
+        assert_ne!(hash(&chain), hash(&pair));
+    }
+
     #[test]
     fn returns_a_uniqe_set_of_orderings() {
         let graph = example_graph();
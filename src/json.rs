@@ -0,0 +1,599 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::runner::Accept;
+use crate::store::Size;
+
+const FIELD_SEP: char = '.';
+
+#[derive(Clone, Eq, Hash, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl fmt::Debug for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write!(f, "{:?}", s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl Size for Json {
+    fn size(&self) -> usize {
+        self.to_string().len()
+    }
+}
+
+impl Accept for Json {}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::Bool(value)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(value: i64) -> Json {
+        Json::Number(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Json {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Json {
+        Json::String(value)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(value: Vec<Json>) -> Json {
+        Json::Array(value)
+    }
+}
+
+impl Json {
+    pub fn object(fields: &[(&str, Json)]) -> Json {
+        let map = fields
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+
+        Json::Object(map)
+    }
+
+    pub fn get(&self, field: &str) -> Option<&Json> {
+        let mut value = self;
+
+        for key in field.split(FIELD_SEP) {
+            if let Json::Object(fields) = value {
+                value = fields.get(key)?;
+            } else {
+                return None;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn set(&mut self, field: &str, new_value: Json) {
+        let mut value = self;
+
+        for key in field.split(FIELD_SEP) {
+            if !matches!(value, Json::Object(_)) {
+                *value = Json::Object(BTreeMap::new());
+            }
+            if let Json::Object(fields) = value {
+                value = fields.entry(key.to_string()).or_insert(Json::Null);
+            }
+        }
+        *value = new_value;
+    }
+
+    pub fn remove(&mut self, field: &str) -> Option<Json> {
+        let (parent, key) = match field.rsplit_once(FIELD_SEP) {
+            Some((parent, key)) => (self.get_mut(parent)?, key),
+            None => (self, field),
+        };
+
+        if let Json::Object(fields) = parent {
+            fields.remove(key)
+        } else {
+            None
+        }
+    }
+
+    // Reads a document as `Display` writes it. That is JSON with strings
+    // escaped as Rust escapes them, so `\u{1f}` and `\0` are read as well as
+    // the escapes JSON itself defines. Numbers must be integers that fit in
+    // an i64.
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { text, pos: 0 };
+        let value = parser.value()?;
+
+        parser.skip_space();
+        if parser.pos < text.len() {
+            return Err(parser.error("end of input"));
+        }
+        Ok(value)
+    }
+
+    fn get_mut(&mut self, field: &str) -> Option<&mut Json> {
+        let mut value = self;
+
+        for key in field.split(FIELD_SEP) {
+            if let Json::Object(fields) = value {
+                value = fields.get_mut(key)?;
+            } else {
+                return None;
+            }
+        }
+        Some(value)
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_space(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn error(&self, expected: &str) -> String {
+        format!("expected {} at offset {}", expected, self.pos)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.bump();
+            Ok(())
+        } else {
+            Err(self.error(&format!("{:?}", c)))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_space();
+
+        match self.peek() {
+            Some('n') => self.keyword("null", Json::Null),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => self.array(),
+            Some('{') => self.object(),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if self.text[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error(word))
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.bump();
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+        }
+        match self.text[start..self.pos].parse() {
+            Ok(n) => Ok(Json::Number(n)),
+            Err(_) => {
+                self.pos = start;
+                Err(self.error("an integer that fits in an i64"))
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(string),
+                Some('\\') => string.push(self.escape()?),
+                Some(c) => string.push(c),
+                None => return Err(self.error("'\"'")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, String> {
+        let c = match self.bump() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('0') => '\0',
+            Some(c @ ('"' | '\\' | '/' | '\'')) => c,
+            Some('u') if self.peek() == Some('{') => {
+                self.bump();
+                let code = self.hex(6)?;
+                self.expect('}')?;
+                return char::from_u32(code).ok_or_else(|| self.error("a unicode scalar value"));
+            }
+            Some('u') => return self.utf16_escape(),
+            _ => return Err(self.error("an escape")),
+        };
+        Ok(c)
+    }
+
+    // A `\uXXXX` escape, which for a character outside the basic plane is
+    // one half of a surrogate pair whose other half follows as a second one.
+    fn utf16_escape(&mut self) -> Result<char, String> {
+        let high = self.hex(4)?;
+
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("a unicode scalar value"));
+        }
+        self.expect('\\')?;
+        self.expect('u')?;
+        let low = self.hex(4)?;
+
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(self.error("a low surrogate"));
+        }
+        let code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+        char::from_u32(code).ok_or_else(|| self.error("a unicode scalar value"))
+    }
+
+    // Reads between one and `max` hex digits as a number.
+    fn hex(&mut self, max: usize) -> Result<u32, String> {
+        let start = self.pos;
+        while self.pos - start < max && self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.bump();
+        }
+        u32::from_str_radix(&self.text[start..self.pos], 16).map_err(|_| self.error("hex digits"))
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+
+        self.skip_space();
+        if self.peek() == Some(']') {
+            self.bump();
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_space();
+            match self.bump() {
+                Some(',') => continue,
+                Some(']') => return Ok(Json::Array(items)),
+                _ => return Err(self.error("',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut fields = BTreeMap::new();
+
+        self.skip_space();
+        if self.peek() == Some('}') {
+            self.bump();
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_space();
+            let key = self.string()?;
+            self.skip_space();
+            self.expect(':')?;
+            fields.insert(key, self.value()?);
+            self.skip_space();
+            match self.bump() {
+                Some(',') => continue,
+                Some('}') => return Ok(Json::Object(fields)),
+                _ => return Err(self.error("',' or '}'")),
+            }
+        }
+    }
+}
+
+pub fn set_field<V>(field: &str, value: V) -> impl Fn(Option<Json>) -> Option<Json> + Sync
+where
+    V: Into<Json>,
+{
+    let field = field.to_string();
+    let value = value.into();
+
+    move |doc| {
+        let mut doc = doc.unwrap_or(Json::Object(BTreeMap::new()));
+        doc.set(&field, value.clone());
+        Some(doc)
+    }
+}
+
+pub fn remove_field(field: &str) -> impl Fn(Option<Json>) -> Option<Json> + Sync {
+    let field = field.to_string();
+
+    move |doc| {
+        let mut doc = doc?;
+        doc.remove(&field);
+        Some(doc)
+    }
+}
+
+// Adds `n` to a numeric field, treating a missing or non-numeric one as 0.
+// The sum saturates at the bounds of i64 rather than overflowing, since the
+// values come from scenarios and a wrapped count would read as a real one.
+pub fn incr_field(field: &str, n: i64) -> impl Fn(Option<Json>) -> Option<Json> + Sync {
+    let field = field.to_string();
+
+    move |doc| {
+        let mut doc = doc.unwrap_or(Json::Object(BTreeMap::new()));
+        let old = match doc.get(&field) {
+            Some(Json::Number(old)) => *old,
+            _ => 0,
+        };
+        doc.set(&field, Json::Number(old.saturating_add(n)));
+        Some(doc)
+    }
+}
+
+pub fn diff(old: &Json, new: &Json) -> Vec<String> {
+    let mut lines = Vec::new();
+    diff_at("", old, new, &mut lines);
+    lines
+}
+
+fn diff_at(prefix: &str, old: &Json, new: &Json, lines: &mut Vec<String>) {
+    let name = if prefix.is_empty() { "." } else { prefix };
+
+    match (old, new) {
+        (Json::Object(a), Json::Object(b)) => {
+            for (key, value) in a {
+                let field = join_field(prefix, key);
+                match b.get(key) {
+                    Some(other) => diff_at(&field, value, other, lines),
+                    None => lines.push(format!("- {}: {}", field, value)),
+                }
+            }
+            for (key, value) in b {
+                if !a.contains_key(key) {
+                    lines.push(format!("+ {}: {}", join_field(prefix, key), value));
+                }
+            }
+        }
+        _ if old != new => lines.push(format!("~ {}: {} -> {}", name, old, new)),
+        _ => {}
+    }
+}
+
+fn join_field(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}{}{}", prefix, FIELD_SEP, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Json {
+        Json::object(&[
+            ("name", "alice".into()),
+            (
+                "stats",
+                Json::object(&[("count", 3.into()), ("ok", true.into())]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn formats_as_compact_json() {
+        assert_eq!(
+            format!("{:?}", example()),
+            r#"{"name": "alice", "stats": {"count": 3, "ok": true}}"#
+        );
+    }
+
+    #[test]
+    fn parses_what_it_formats() {
+        let mut doc = example();
+        doc.set(
+            "list",
+            Json::Array(vec![Json::Null, (-7).into(), "".into()]),
+        );
+        doc.set(
+            "text",
+            "quote \" slash \\ tab \t nul \0 bell \u{7} é 😀".into(),
+        );
+
+        assert_eq!(Json::parse(&doc.to_string()), Ok(doc));
+    }
+
+    #[test]
+    fn parses_json_escapes_and_spacing() {
+        let text = "\n{ \"a\" : [ 1 , true ] ,\"b\":\"\\u0041\\/\\ud83d\\ude00\" }\n";
+
+        assert_eq!(
+            Json::parse(text),
+            Ok(Json::object(&[
+                ("a", Json::Array(vec![1.into(), true.into()])),
+                ("b", "A/😀".into()),
+            ]))
+        );
+    }
+
+    #[test]
+    fn says_where_it_fails_to_parse() {
+        assert_eq!(
+            Json::parse("[1, 2"),
+            Err("expected ',' or ']' at offset 5".to_string())
+        );
+        assert_eq!(
+            Json::parse("{\"a\": 1} x"),
+            Err("expected end of input at offset 9".to_string())
+        );
+        assert_eq!(
+            Json::parse("1.5"),
+            Err("expected end of input at offset 1".to_string())
+        );
+        assert_eq!(
+            Json::parse("99999999999999999999"),
+            Err("expected an integer that fits in an i64 at offset 0".to_string())
+        );
+        assert!(Json::parse("\"\\ud800\"").is_err());
+    }
+
+    #[test]
+    fn gets_a_nested_field() {
+        let doc = example();
+
+        assert_eq!(doc.get("stats.count"), Some(&Json::Number(3)));
+        assert_eq!(doc.get("stats.missing"), None);
+        assert_eq!(doc.get("name.first"), None);
+    }
+
+    #[test]
+    fn sets_a_nested_field_creating_parents() {
+        let mut doc = example();
+        doc.set("meta.tags", Json::Array(vec!["x".into()]));
+
+        assert_eq!(doc.get("meta.tags"), Some(&Json::Array(vec!["x".into()])));
+        assert_eq!(doc.get("stats.count"), Some(&Json::Number(3)));
+    }
+
+    #[test]
+    fn removes_a_nested_field() {
+        let mut doc = example();
+
+        assert_eq!(doc.remove("stats.ok"), Some(Json::Bool(true)));
+        assert_eq!(
+            doc.get("stats"),
+            Some(&Json::object(&[("count", 3.into())]))
+        );
+        assert_eq!(doc.remove("stats.ok"), None);
+    }
+
+    #[test]
+    fn saturates_an_incremented_field() {
+        let incr = incr_field("count", 1);
+        let doc = Json::object(&[("count", i64::MAX.into())]);
+
+        assert_eq!(
+            incr(Some(doc)),
+            Some(Json::object(&[("count", i64::MAX.into())]))
+        );
+    }
+
+    #[test]
+    fn builds_update_closures() {
+        let set = set_field("stats.ok", false);
+        let incr = incr_field("stats.count", 2);
+        let remove = remove_field("name");
+
+        let doc = remove(incr(set(Some(example())))).unwrap();
+
+        assert_eq!(
+            doc,
+            Json::object(&[(
+                "stats",
+                Json::object(&[("count", 5.into()), ("ok", false.into())])
+            )])
+        );
+    }
+
+    #[test]
+    fn creates_a_missing_doc_when_setting_a_field() {
+        let doc = set_field("a.b", 1)(None);
+        assert_eq!(
+            doc,
+            Some(Json::object(&[("a", Json::object(&[("b", 1.into())]))]))
+        );
+
+        assert_eq!(remove_field("a")(None), None);
+    }
+
+    #[test]
+    fn diffs_two_documents() {
+        let old = example();
+        let mut new = example();
+
+        new.set("stats.count", 4.into());
+        new.remove("name");
+        new.set("extra", "y".into());
+
+        assert_eq!(
+            diff(&old, &new),
+            [
+                "- name: \"alice\"",
+                "~ stats.count: 3 -> 4",
+                "+ extra: \"y\""
+            ]
+        );
+    }
+
+    #[test]
+    fn diffs_non_object_documents() {
+        assert_eq!(diff(&1.into(), &2.into()), ["~ .: 1 -> 2"]);
+        assert!(diff(&example(), &example()).is_empty());
+    }
+}
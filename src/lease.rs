@@ -0,0 +1,73 @@
+use crate::clock::{Clock, Time};
+use crate::path::Path;
+use crate::store::Size;
+
+const LOCK_PREFIX: &str = "#lock";
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Lease {
+    pub owner: String,
+    pub expires: Time,
+}
+
+impl Size for Lease {
+    fn size(&self) -> usize {
+        self.owner.len() + self.expires.size()
+    }
+}
+
+impl Lease {
+    pub fn new(owner: &str, expires: Time) -> Lease {
+        Lease {
+            owner: owner.to_string(),
+            expires,
+        }
+    }
+
+    pub fn is_live(&self, clock: &Clock) -> bool {
+        !clock.has_reached(self.expires)
+    }
+}
+
+pub fn lock_key(dir: &Path) -> Path {
+    Path::from(format!("{}{}", LOCK_PREFIX, dir).as_str())
+}
+
+pub fn covers(dir: &Path, key: &Path) -> bool {
+    key.full().starts_with(dir.full())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_at_the_given_time() {
+        let lease = Lease::new("A", 2);
+        let mut clock = Clock::new();
+
+        clock.tick();
+        assert!(lease.is_live(&clock));
+
+        clock.tick();
+        assert!(!lease.is_live(&clock));
+    }
+
+    #[test]
+    fn stores_locks_outside_the_tree() {
+        let key = lock_key(&"/path/".into());
+
+        assert_eq!(key.full(), "#lock/path/");
+        assert!(!key.is_valid());
+    }
+
+    #[test]
+    fn covers_keys_in_its_subtree() {
+        let dir = Path::from("/path/");
+
+        assert!(covers(&dir, &"/path/".into()));
+        assert!(covers(&dir, &"/path/to/x".into()));
+        assert!(!covers(&dir, &"/".into()));
+        assert!(!covers(&dir, &"/other/x".into()));
+    }
+}
@@ -1,8 +1,85 @@
-pub mod actor;
+// Every type a public signature names must be exported below, or programs
+// outside the crate could not name it, say to implement `CustomOp`.
+#![warn(unnameable_types)]
+
+mod actor;
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod blob;
+mod cache;
+mod clock;
 pub mod config;
-pub mod db;
-pub mod graph;
-pub mod path;
-pub mod planner;
-pub mod runner;
-pub mod store;
+mod db;
+pub mod fuzz;
+mod graph;
+pub mod json;
+mod lease;
+pub mod migration;
+mod path;
+mod planner;
+mod pmap;
+mod rng;
+mod runner;
+pub mod scenarios;
+mod store;
+
+pub use actor::{Actor, Outcome, Trace};
+pub use cache::OrderingCache;
+pub use clock::{Clock, Time};
+pub use config::Config;
+pub use db::{Checker, Db, DbStore, Kind, Meta, Violation};
+pub use graph::{Counts, Graph, GraphError, IdMap, NodeId};
+pub use lease::Lease;
+pub use path::Path;
+pub use planner::{
+    Act, Change, Client, CustomOp, GuardFn, ListFn, Op, PlanStats, Planner, PutFn, SavedAct,
+};
+pub use rng::Rng;
+pub use runner::{Accept, Acks, CheckFn, DiffFn, Fixture, IntoFixture, Record, Runner};
+pub use store::{Key, Rev, Size, Store};
+
+// The README's custom op, compiled as a doctest so that the example keeps
+// building from outside the crate. Lines starting with "# " set it up and are
+// left out of the README.
+#[cfg(any(test, doctest))]
+macro_rules! custom_op_example {
+    () => {
+        r#"```
+# use mc2::{Actor, Config, CustomOp, Path, Planner};
+# let mut planner = Planner::new(Config::new());
+struct Copy(Path);
+
+impl CustomOp<(char, i32)> for Copy {
+    fn name(&self) -> &str {
+        "copy"
+    }
+
+    fn execute(&self, actor: &mut Actor<'_, (char, i32)>, path: &Path) {
+        let value = actor.read_raw(path);
+        actor.read_raw(&self.0);
+        actor.write_raw(&self.0, value);
+    }
+}
+
+planner.client("A").custom("/x", Copy("/y".into()));
+```
+"#
+    };
+}
+
+#[cfg(doctest)]
+#[doc = custom_op_example!()]
+struct CustomOpExample;
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn shows_the_compiled_custom_op_example_in_the_readme() {
+        let example: Vec<_> = custom_op_example!()
+            .lines()
+            .filter(|line| !line.starts_with("# ") && !line.starts_with("```"))
+            .collect();
+
+        assert!(include_str!("../README.md").contains(&example.join("\n")));
+    }
+}
@@ -1,187 +1,88 @@
-use mc2::config::{Cas, Config, Remove, Update};
-use mc2::runner::Runner;
+use std::fmt::Debug;
+use std::hash::Hash;
 
-fn main() {
-    let mut runner = Runner::new();
-
-    runner.configs(&[
-        Config::new().update(Update::GetBeforePut),
-        Config::new().remove(Remove::UnlinkParallel),
-        Config::new().skip_links(true),
-        Config::new().store(Cas::Lax),
-        Config::new().store(Cas::NoRev),
-        Config::new().store(Cas::MatchRev),
-        Config::new().store(Cas::Strict),
-    ]);
-
-    runner.add(
-        "update/update conflict",
-        |mut db| {
-            db.update("/path/x", |_| Some(('x', 1)));
-        },
-        |planner| {
-            planner.client("A").update("/path/x", |_| Some(('x', 2)));
-            planner.client("B").update("/path/x", |_| Some(('x', 3)));
-        },
-    );
-
-    runner.add(
-        "update/update conflict (missing)",
-        |mut db| {
-            db.update("/path/x", |_| Some(('x', 1)));
-        },
-        |planner| {
-            planner.client("A").update("/path/y", |_| Some(('y', 2)));
-            planner.client("B").update("/path/y", |_| Some(('y', 3)));
-        },
-    );
-
-    runner.add(
-        "update/delete conflict",
-        |mut db| {
-            db.update("/path/x", |_| Some(('x', 1)));
-        },
-        |planner| {
-            planner.client("A").update("/path/x", |_| Some(('x', 2)));
-            planner.client("B").remove("/path/x");
-        },
-    );
-
-    runner.add(
-        "update/delete conflict (missing)",
-        |mut db| {
-            db.update("/path/x", |_| Some(('x', 1)));
-        },
-        |planner| {
-            planner.client("A").update("/path/y", |_| Some(('y', 2)));
-            planner.client("B").remove("/path/y");
-        },
-    );
-
-    runner.add(
-        "delete, create sibling",
-        |mut db| {
-            db.update("/path/x", |_| Some(('x', 1)));
-        },
-        |planner| {
-            planner.client("A").remove("/path/x");
-            planner.client("B").update("/path/y", |_| Some(('y', 2)));
-        },
-    );
-
-    runner.add(
-        "delete, create in parent",
-        |mut db| {
-            db.update("/path/to/x", |_| Some(('x', 1)));
-        },
-        |planner| {
-            planner.client("A").remove("/path/to/x");
-            planner.client("B").update("/path/y", |_| Some(('y', 2)));
-        },
-    );
-
-    runner.add(
-        "delete, create in grandparent",
-        |mut db| {
-            db.update("/path/to/x", |_| Some(('x', 1)));
-        },
-        |planner| {
-            planner.client("A").remove("/path/to/x");
-            planner.client("B").update("/y", |_| Some(('y', 2)));
-        },
-    );
+use mc2::fuzz::{self, Limits};
+use mc2::{scenarios, Config, Runner, Size};
 
-    runner.add(
-        "delete, create in child",
-        |mut db| {
-            db.update("/path/x", |_| Some(('x', 1)));
-        },
-        |planner| {
-            planner.client("A").remove("/path/x");
-            planner.client("B").update("/path/to/y", |_| Some(('y', 2)));
-        },
-    );
-
-    runner.add(
-        "delete, create in grandchild",
-        |mut db| {
-            db.update("/x", |_| Some(('x', 1)));
-        },
-        |planner| {
-            planner.client("A").remove("/x");
-            planner.client("B").update("/path/to/y", |_| Some(('y', 2)));
-        },
-    );
-
-    runner.add(
-        "delete, update sibling",
-        |mut db| {
-            db.update("/path/x", |_| Some(('x', 1)));
-            db.update("/path/y", |_| Some(('y', 1)));
-        },
-        |planner| {
-            planner.client("A").remove("/path/x");
-            planner
-                .client("B")
-                .update("/path/y", |doc| doc.map(|(k, n)| (k, n + 1)));
-        },
-    );
-
-    runner.add(
-        "delete, update in parent",
-        |mut db| {
-            db.update("/path/to/x", |_| Some(('x', 1)));
-            db.update("/path/y", |_| Some(('y', 1)));
-        },
-        |planner| {
-            planner.client("A").remove("/path/to/x");
-            planner
-                .client("B")
-                .update("/path/y", |doc| doc.map(|(k, n)| (k, n + 1)));
-        },
-    );
-
-    runner.add(
-        "delete, update in grandparent",
-        |mut db| {
-            db.update("/path/to/x", |_| Some(('x', 1)));
-            db.update("/y", |_| Some(('y', 1)));
-        },
-        |planner| {
-            planner.client("A").remove("/path/to/x");
-            planner
-                .client("B")
-                .update("/y", |doc| doc.map(|(k, n)| (k, n + 1)));
-        },
-    );
-
-    runner.add(
-        "delete, update in child",
-        |mut db| {
-            db.update("/path/x", |_| Some(('x', 1)));
-            db.update("/path/to/y", |_| Some(('y', 1)));
-        },
-        |planner| {
-            planner.client("A").remove("/path/x");
-            planner
-                .client("B")
-                .update("/path/to/y", |doc| doc.map(|(k, n)| (k, n + 1)));
-        },
-    );
+fn main() {
+    if let Some(count) = parse_arg::<u64>("--fuzz") {
+        let start = parse_arg("--seed").unwrap_or(0);
+        run(scenarios::standard_configs(), |runner| {
+            fuzz::suite(runner, start..start + count, &Limits::default())
+        });
+        return;
+    }
+    run(scenarios::standard_configs(), scenarios::standard_suite);
+    run(scenarios::standard_configs(), scenarios::pairs_suite);
+    run(scenarios::standard_configs(), scenarios::three_way_suite);
+    run(scenarios::cas_configs(), scenarios::standard_suite);
+    run(scenarios::stale_cas_configs(), scenarios::standard_suite);
+    run(scenarios::wide_dir_configs(), scenarios::wide_dir_suite);
+    run(scenarios::blob_configs(), scenarios::blob_suite);
+    run(scenarios::migration_configs(), scenarios::migration_suite);
+    run(scenarios::repair_configs(), scenarios::repair_suite);
+    run(scenarios::lazy_configs(), scenarios::standard_suite);
+    run(scenarios::lease_configs(), scenarios::lease_suite);
+    run(scenarios::partition_configs(), scenarios::partition_suite);
+    run(scenarios::timeout_configs(), scenarios::timeout_suite);
+}
 
-    runner.add(
-        "delete, update in grandchild",
-        |mut db| {
-            db.update("/x", |_| Some(('x', 1)));
-            db.update("/path/to/y", |_| Some(('y', 1)));
-        },
-        |planner| {
-            planner.client("A").remove("/x");
-            planner
-                .client("B")
-                .update("/path/to/y", |doc| doc.map(|(k, n)| (k, n + 1)));
-        },
-    );
+fn run<T, S>(configs: Vec<Config>, suite: S)
+where
+    S: FnOnce(&mut Runner<T>),
+    T: Clone + Debug + Hash + Send + Sync + Size + 'static,
+{
+    let mut runner = Runner::new();
 
+    #[cfg(feature = "archive")]
+    if let Some(path) = parse_arg::<String>("--archive") {
+        match mc2::archive::Archive::open(&path) {
+            Ok(archive) => runner.archive(archive),
+            Err(error) => eprintln!("archive error: {}", error),
+        }
+    }
+
+    if std::env::args().any(|arg| arg == "--deepen") {
+        runner.deepen();
+    }
+    if let Some(bound) = parse_arg("--max-preemptions") {
+        runner.max_preemptions(bound);
+    }
+    if std::env::args().any(|arg| arg == "--stop-early") {
+        runner.stop_early();
+    }
+    if std::env::args().any(|arg| arg == "--teardown") {
+        runner.teardown();
+    }
+    if std::env::args().any(|arg| arg == "--force") {
+        runner.force();
+    }
+    if let Some(budget) = parse_arg("--budget") {
+        runner.budget(budget);
+    }
+    if let Some(seed) = parse_arg("--seed") {
+        runner.seed(seed);
+    }
+    if std::env::args().any(|arg| arg == "--bisect") {
+        runner.bisect(Config::new());
+    }
+    if std::env::args().any(|arg| arg == "--necessity") {
+        runner.necessity();
+    }
+    if let Some(dir) = parse_arg::<String>("--cache") {
+        match mc2::OrderingCache::open(&dir) {
+            Ok(cache) => runner.cache(cache),
+            Err(error) => eprintln!("cache error: {}", error),
+        }
+    }
+
+    runner.configs(&configs);
+    suite(&mut runner);
     runner.run();
 }
+
+fn parse_arg<N: std::str::FromStr>(name: &str) -> Option<N> {
+    let args: Vec<_> = std::env::args().collect();
+    let i = args.iter().position(|arg| arg == name)?;
+    args.get(i + 1)?.parse().ok()
+}
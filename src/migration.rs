@@ -0,0 +1,223 @@
+use crate::db::{Db, DbStore};
+use crate::planner::GuardFn;
+use crate::runner::Accept;
+use crate::store::Size;
+
+type Update<T> = Box<dyn Fn(Option<Schema<T>>) -> Option<Schema<T>> + Sync>;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Schema<T> {
+    Version(usize),
+    Doc(usize, T),
+}
+
+impl<T: Size> Size for Schema<T> {
+    fn size(&self) -> usize {
+        match self {
+            Schema::Version(_) => 0usize.size(),
+            Schema::Doc(_, value) => 0usize.size() + value.size(),
+        }
+    }
+}
+
+impl<T: Accept> Accept for Schema<T> {
+    fn accepts(&self, actual: &Schema<T>) -> bool {
+        match (self, actual) {
+            (Schema::Doc(v, a), Schema::Doc(w, b)) => v == w && a.accepts(b),
+            _ => self == actual,
+        }
+    }
+}
+
+pub fn is_version<T>(version: usize) -> GuardFn<Schema<T>> {
+    Box::new(move |doc| matches!(doc, Some(Schema::Version(v)) if *v == version))
+}
+
+pub fn write_as<T, F>(version: usize, update: F) -> Update<T>
+where
+    F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+{
+    Box::new(move |doc| {
+        let value = match doc {
+            Some(Schema::Doc(_, value)) => Some(value),
+            Some(Schema::Version(_)) => return None,
+            None => None,
+        };
+        update(value).map(|value| Schema::Doc(version, value))
+    })
+}
+
+pub fn convert<T, F>(version: usize, convert: F) -> Update<T>
+where
+    F: Fn(T) -> T + Sync + 'static,
+{
+    Box::new(move |doc| match doc {
+        Some(Schema::Doc(old, value)) if old < version => {
+            Some(Schema::Doc(version, convert(value)))
+        }
+        _ => None,
+    })
+}
+
+pub fn bump<T>(version: usize) -> Update<T> {
+    Box::new(move |doc| match doc {
+        Some(Schema::Version(old)) if old + 1 == version => Some(Schema::Version(version)),
+        _ => None,
+    })
+}
+
+pub fn check<T>(store: &DbStore<Schema<T>>) -> Result<(), Vec<String>>
+where
+    T: Clone + Size,
+{
+    let current = store.keys().find_map(|key| match store.get(key) {
+        Some(Db::Doc(Schema::Version(version), _)) => Some((key, *version)),
+        _ => None,
+    });
+
+    let (version_key, version) = match current {
+        Some(current) => current,
+        None => return Ok(()),
+    };
+
+    let errors: Vec<_> = store
+        .keys()
+        .filter_map(|key| match store.get(key) {
+            Some(Db::Doc(Schema::Doc(v, _), _)) if *v != version => Some(format!(
+                "doc '{}' has version {}, but '{}' is at version {}",
+                key, v, version_key, version
+            )),
+            _ => None,
+        })
+        .collect();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    use crate::actor::Actor;
+    use crate::config::Config;
+    use crate::planner::Planner;
+
+    fn make_store() -> DbStore<Schema<char>> {
+        let mut store = DbStore::new(Config::new());
+
+        store.write("/".into(), None, Db::dir_from(&["version", "x", "y"]));
+        store.write("/version".into(), None, Db::doc(Schema::Version(1)));
+        store.write("/x".into(), None, Db::doc(Schema::Doc(1, 'a')));
+        store.write("/y".into(), None, Db::doc(Schema::Doc(1, 'b')));
+
+        store
+    }
+
+    fn run(store: DbStore<Schema<char>>, planner: &Planner<Schema<char>>) -> DbStore<Schema<char>> {
+        let store = RefCell::new(store);
+        let mut actor = Actor::new(&store, Config::new());
+
+        for act in planner.orderings().next().unwrap() {
+            actor.dispatch(act);
+        }
+        store.into_inner()
+    }
+
+    #[test]
+    fn accepts_a_fully_migrated_store() {
+        let mut planner: Planner<Schema<char>> = Planner::new(Config::new());
+        planner
+            .client("M")
+            .migrate("/version", 2, &["/x", "/y"], |c| c.to_ascii_uppercase());
+
+        let store = run(make_store(), &planner);
+
+        assert_eq!(
+            store.get("/version"),
+            Some(&Db::doc_from(Schema::Version(2), 6, "M"))
+        );
+        assert_eq!(
+            store.get("/x"),
+            Some(&Db::doc_from(Schema::Doc(2, 'A'), 3, "M"))
+        );
+        assert_eq!(
+            store.get("/y"),
+            Some(&Db::doc_from(Schema::Doc(2, 'B'), 5, "M"))
+        );
+        assert_eq!(check(&store), Ok(()));
+    }
+
+    #[test]
+    fn does_not_migrate_from_the_wrong_version() {
+        let mut planner: Planner<Schema<char>> = Planner::new(Config::new());
+        planner
+            .client("M")
+            .migrate("/version", 3, &["/x"], |c| c.to_ascii_uppercase());
+
+        let store = run(make_store(), &planner);
+
+        assert_eq!(store.get("/version"), Some(&Db::doc(Schema::Version(1))));
+        assert_eq!(store.get("/x"), Some(&Db::doc(Schema::Doc(1, 'a'))));
+    }
+
+    #[test]
+    fn writes_docs_under_the_current_version() {
+        let mut planner: Planner<Schema<char>> = Planner::new(Config::new());
+        planner
+            .client("A")
+            .update_versioned("/version", 1, "/z", |_| Some('c'));
+
+        let store = run(make_store(), &planner);
+
+        assert_eq!(
+            store.get("/z"),
+            Some(&Db::doc_from(Schema::Doc(1, 'c'), 5, "A"))
+        );
+        assert_eq!(check(&store), Ok(()));
+    }
+
+    #[test]
+    fn does_not_write_docs_under_a_stale_version() {
+        let mut planner: Planner<Schema<char>> = Planner::new(Config::new());
+        planner
+            .client("A")
+            .update_versioned("/version", 0, "/z", |_| Some('c'));
+
+        let store = run(make_store(), &planner);
+
+        assert_eq!(store.get("/z"), None);
+    }
+
+    #[test]
+    fn complains_about_a_half_migrated_store() {
+        let mut store = make_store();
+        store.write("/version".into(), Some(1), Db::doc(Schema::Version(2)));
+        store.write("/x".into(), Some(1), Db::doc(Schema::Doc(2, 'A')));
+
+        assert_eq!(
+            check(&store),
+            Err(vec![String::from(
+                "doc '/y' has version 1, but '/version' is at version 2"
+            )])
+        );
+    }
+
+    #[test]
+    fn complains_about_docs_ahead_of_the_version() {
+        let mut store = make_store();
+        store.write("/x".into(), Some(1), Db::doc(Schema::Doc(2, 'A')));
+
+        assert_eq!(
+            check(&store),
+            Err(vec![String::from(
+                "doc '/x' has version 2, but '/version' is at version 1"
+            )])
+        );
+    }
+}
@@ -1,5 +1,8 @@
 use std::borrow::Borrow;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::store::Key;
 
 const SEP: char = '/';
 
@@ -27,12 +30,24 @@ impl Borrow<str> for Path {
     }
 }
 
+impl Key for Path {
+    fn is_dir(&self) -> bool {
+        Path::is_dir(self)
+    }
+}
+
 impl PartialEq for Path {
     fn eq(&self, other: &Path) -> bool {
         self.original.eq(&other.original)
     }
 }
 
+impl Hash for Path {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.original.hash(state);
+    }
+}
+
 impl PartialOrd for Path {
     fn partial_cmp(&self, other: &Path) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
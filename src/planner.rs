@@ -1,15 +1,27 @@
-use std::collections::BTreeSet;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
-
-use crate::config::{Config, Remove, Update};
-use crate::graph::{Graph, Id};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::actor::Actor;
+use crate::cache::OrderingCache;
+use crate::config::{Config, Layout, Reads, Remove, Update};
+use crate::db::{self, Kind};
+use crate::graph::{Counts, Graph, GraphError, IdMap, NodeId};
+use crate::json::Json;
+use crate::migration::{self, Schema};
 use crate::path::Path;
+use crate::rng::Rng;
+use crate::store::Size;
 
 #[derive(PartialEq)]
 pub struct Act<T> {
     pub client_id: String,
     pub path: Path,
     pub op: Op<T>,
+    // Names the value a put writes, for telling puts apart in reports.
+    pub label: Option<String>,
 }
 
 impl<T> Act<T> {
@@ -18,45 +30,262 @@ impl<T> Act<T> {
             client_id: client_id.to_string(),
             path,
             op,
+            label: None,
+        }
+    }
+}
+
+impl<T> Act<T> {
+    // Two acts commute if running them in either order leaves the store and
+    // both actors in the same state: they belong to different clients, and
+    // either both are reads or one is a read of a key the other doesn't write.
+    // A commit may write any key its transaction wrote, and a custom op that
+    // writes may write any key at all, so they commute with nothing.
+    fn commutes_with(&self, other: &Act<T>) -> bool {
+        if self.client_id == other.client_id {
+            return false;
+        }
+        if self.op.writes_anywhere() || other.op.writes_anywhere() {
+            return false;
+        }
+        match (self.op.may_write(), other.op.may_write()) {
+            (false, false) => true,
+            (true, true) => false,
+            (true, false) => !other.reads(&self.path),
+            (false, true) => !self.reads(&other.path),
         }
     }
+
+    // A batched read reads the dirs it lists as well as its own path. A sync
+    // may read any key, since some layouts keep a dir's listing elsewhere,
+    // and so may a custom op.
+    fn reads(&self, path: &Path) -> bool {
+        match &self.op {
+            Op::ReadMany(dirs) => self.path == *path || dirs.contains(path),
+            Op::Sync | Op::Custom(_) => true,
+            _ => self.path == *path,
+        }
+    }
+
+    // Two acts conflict if they belong to different clients and running them
+    // in the other order could change the outcome.
+    fn conflicts_with(&self, other: &Act<T>) -> bool {
+        self.client_id != other.client_id && !self.commutes_with(other)
+    }
 }
 
 impl<T> fmt::Debug for Act<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Act<{}: ", self.client_id)?;
+        write!(f, "Act<{}: {}>", self.client_id, self)
+    }
+}
 
+// An act's op and path, without the client that performs it.
+impl<T> fmt::Display for Act<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self.op {
             Op::Get => write!(f, "get('{}')", self.path)?,
+            Op::Observe => write!(f, "observe('{}')", self.path)?,
+            Op::ExpectList(_) => write!(f, "expect_list('{}')", self.path)?,
+            Op::Guard(_) => write!(f, "guard('{}')", self.path)?,
             Op::Put(_) => write!(f, "put('{}')", self.path)?,
             Op::Rm => write!(f, "rm('{}')", self.path)?,
             Op::List => write!(f, "list('{}')", self.path)?,
-            Op::Link(name) => write!(f, "link('{}', '{}')", self.path, name)?,
+            Op::ReadMany(dirs) => {
+                write!(f, "read_many(")?;
+                for dir in dirs {
+                    write!(f, "'{}', ", dir)?;
+                }
+                write!(f, "'{}')", self.path)?
+            }
+            Op::Link(name, _) => write!(f, "link('{}', '{}')", self.path, name)?,
             Op::Unlink(name) => write!(f, "unlink('{}', '{}')", self.path, name)?,
+            Op::Flush(changes) => {
+                write!(f, "flush('{}'", self.path)?;
+                for change in changes {
+                    match change {
+                        Change::Link(name, _) => write!(f, ", +'{}'", name)?,
+                        Change::Unlink(name) => write!(f, ", -'{}'", name)?,
+                    }
+                }
+                write!(f, ")")?
+            }
+            Op::Sync => write!(f, "sync('{}')", self.path)?,
+            Op::Repair(name, _) => write!(f, "repair('{}', '{}')", self.path, name)?,
+            Op::Acquire(ttl) => write!(f, "acquire('{}', {})", self.path, ttl)?,
+            Op::Release => write!(f, "release('{}')", self.path)?,
+            Op::Begin => write!(f, "begin()")?,
+            Op::Commit => write!(f, "commit()")?,
+            Op::Custom(op) => write!(f, "{}('{}')", op.name(), self.path)?,
+        };
+
+        if let Some(label) = &self.label {
+            write!(f, " as '{}'", label)?;
+        }
+        Ok(())
+    }
+}
+
+// An act as a saved plan holds it: its client, its path and its op as the
+// act displays it, as in "put('/x')", which stands in for any function the
+// op carries. A plan is read back as a graph of these by passing `from_json`
+// to `Graph::from_json`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SavedAct {
+    pub client_id: String,
+    pub path: String,
+    pub op: String,
+}
+
+impl SavedAct {
+    pub fn to_json(&self) -> Json {
+        Json::object(&[
+            ("client", Json::from(self.client_id.as_str())),
+            ("path", Json::from(self.path.as_str())),
+            ("op", Json::from(self.op.as_str())),
+        ])
+    }
+
+    pub fn from_json(json: &Json) -> Result<SavedAct, String> {
+        let field = |name| match json.get(name) {
+            Some(Json::String(value)) => Ok(value.clone()),
+            _ => Err(format!(
+                "expected an act with a string {:?} in {}",
+                name, json
+            )),
         };
+        Ok(SavedAct {
+            client_id: field("client")?,
+            path: field("path")?,
+            op: field("op")?,
+        })
+    }
+}
 
-        write!(f, ">")
+impl<T> From<&Act<T>> for SavedAct {
+    fn from(act: &Act<T>) -> SavedAct {
+        SavedAct {
+            client_id: act.client_id.clone(),
+            path: act.path.full().to_string(),
+            op: act.to_string(),
+        }
     }
 }
 
+pub type GuardFn<T> = Box<dyn Fn(Option<&T>) -> bool + Sync>;
+pub type PutFn<T> = Box<dyn Fn(Option<T>) -> Option<T> + Sync>;
+pub type ListFn = Box<dyn Fn(&BTreeSet<String>) -> Result<(), String> + Sync>;
+
 pub enum Op<T> {
     Get,
-    Put(Box<dyn Fn(Option<T>) -> Option<T> + Sync>),
+    Observe,
+    ExpectList(ListFn),
+    Guard(GuardFn<T>),
+    Put(PutFn<T>),
     Rm,
     List,
-    Link(String),
+    ReadMany(Vec<Path>),
+    Link(String, Kind),
     Unlink(String),
+    Flush(Vec<Change>),
+    Sync,
+    Repair(String, Kind),
+    Acquire(usize),
+    Release,
+    Begin,
+    Commit,
+    Custom(Box<dyn CustomOp<T>>),
+}
+
+// An op defined outside the planner, for trying out a store primitive such
+// as a copy or a compare-and-swap on a dir entry without adding a variant
+// for it. The actor runs it on the act's path, and it reads and writes the
+// store through the actor, so that it fails and crashes the actor on a
+// conflict as the built-in ops do.
+pub trait CustomOp<T>: Sync {
+    // The op's name, as acts are shown and named, e.g. in `Planner::order`.
+    fn name(&self) -> &str;
+
+    // Whether the op may write. Since it may write any key, one that does is
+    // taken to conflict with every other act.
+    fn may_write(&self) -> bool {
+        true
+    }
+
+    fn execute(&self, actor: &mut Actor<'_, T>, path: &Path);
+}
+
+// An fsck tool that scans the whole store when it runs and repairs whatever
+// links it finds broken, as `Actor::recover()` does. Since it may touch any
+// key, it runs on its own between the acts of the other clients.
+pub struct Recovery;
+
+impl<T> CustomOp<T> for Recovery
+where
+    T: Clone + Size,
+{
+    fn name(&self) -> &str {
+        "recover"
+    }
+
+    fn execute(&self, actor: &mut Actor<'_, T>, _: &Path) {
+        actor.recover();
+    }
+}
+
+// A change to a dir's entries that write-back holds until the end of the
+// operation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Link(String, Kind),
+    Unlink(String),
+}
+
+impl<T> Op<T> {
+    pub fn may_write(&self) -> bool {
+        !matches!(
+            self,
+            Op::Get
+                | Op::Observe
+                | Op::ExpectList(_)
+                | Op::Guard(_)
+                | Op::List
+                | Op::ReadMany(_)
+                | Op::Sync
+                | Op::Begin
+        ) && !matches!(self, Op::Custom(op) if !op.may_write())
+    }
+
+    fn writes_anywhere(&self) -> bool {
+        match self {
+            Op::Commit => true,
+            Op::Custom(op) => op.may_write(),
+            _ => false,
+        }
+    }
 }
 
 impl<T> PartialEq for Op<T> {
     fn eq(&self, other: &Op<T>) -> bool {
         match (self, other) {
             (Op::Get, Op::Get) => true,
+            (Op::Observe, Op::Observe) => true,
+            (Op::ExpectList(_), Op::ExpectList(_)) => true,
+            (Op::Guard(_), Op::Guard(_)) => true,
             (Op::Put(_), Op::Put(_)) => true,
             (Op::Rm, Op::Rm) => true,
             (Op::List, Op::List) => true,
-            (Op::Link(a), Op::Link(b)) if a == b => true,
+            (Op::ReadMany(a), Op::ReadMany(b)) if a == b => true,
+            (Op::Link(a, j), Op::Link(b, k)) if a == b && j == k => true,
             (Op::Unlink(a), Op::Unlink(b)) if a == b => true,
+            (Op::Flush(a), Op::Flush(b)) if a == b => true,
+            (Op::Sync, Op::Sync) => true,
+            (Op::Repair(a, j), Op::Repair(b, k)) if a == b && j == k => true,
+            (Op::Acquire(a), Op::Acquire(b)) if a == b => true,
+            (Op::Release, Op::Release) => true,
+            (Op::Begin, Op::Begin) => true,
+            (Op::Commit, Op::Commit) => true,
+            (Op::Custom(a), Op::Custom(b)) if a.name() == b.name() => true,
             _ => false,
         }
     }
@@ -66,6 +295,58 @@ pub struct Planner<T> {
     graph: Graph<Act<T>>,
     config: Config,
     clients: BTreeSet<String>,
+    partitioned: BTreeSet<String>,
+    timeouts: BTreeSet<String>,
+    restarts: BTreeSet<String>,
+    crashed: BTreeSet<String>,
+    symmetric: Vec<String>,
+    reduce: bool,
+    conflicts_first: bool,
+    alternate_first: bool,
+    constraints: Vec<Constraint>,
+    synchronized: bool,
+    sessions: BTreeMap<String, Session>,
+}
+
+// The size of a plan, to tell how much work a scenario is before running
+// it: how many acts each client has, the depth and width of the graph, and
+// the number of orderings before symmetry and reduction.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlanStats {
+    pub acts: BTreeMap<String, usize>,
+    pub depth: usize,
+    pub width: usize,
+    pub orderings: u128,
+}
+
+impl PlanStats {
+    pub fn total_acts(&self) -> usize {
+        self.acts.values().sum()
+    }
+
+    // The stats of a plan that runs this one and then `next`, as phases do:
+    // acts add up, and so does depth, since the second waits for the first.
+    pub fn then(&self, next: &PlanStats) -> PlanStats {
+        let mut acts = self.acts.clone();
+        for (client_id, n) in &next.acts {
+            *acts.entry(client_id.clone()).or_default() += n;
+        }
+        PlanStats {
+            acts,
+            depth: self.depth + next.depth,
+            width: self.width.max(next.width),
+            orderings: self.orderings.saturating_mul(next.orderings),
+        }
+    }
+}
+
+// Limits the orderings to those in which every act in `then` comes after
+// every act in `first`, and with `adjacent`, directly after one of them.
+#[derive(Hash)]
+struct Constraint {
+    first: Vec<NodeId>,
+    then: Vec<NodeId>,
+    adjacent: bool,
 }
 
 impl<T> Planner<T> {
@@ -74,259 +355,1925 @@ impl<T> Planner<T> {
             graph: Graph::new(),
             config,
             clients: BTreeSet::new(),
+            partitioned: BTreeSet::new(),
+            timeouts: BTreeSet::new(),
+            restarts: BTreeSet::new(),
+            crashed: BTreeSet::new(),
+            symmetric: Vec::new(),
+            reduce: false,
+            conflicts_first: false,
+            alternate_first: false,
+            constraints: Vec::new(),
+            synchronized: false,
+            sessions: BTreeMap::new(),
         }
     }
 
     pub fn client(&mut self, id: &str) -> Client<'_, T> {
         self.clients.insert(id.to_string());
-        Client::new(&mut self.graph, id, self.config.clone())
+        let session = self.sessions.entry(id.to_string()).or_default();
+        Client::new(&mut self.graph, session, id, self.config.clone())
     }
 
-    pub fn clients(&self) -> impl Iterator<Item = &str> {
-        self.clients.iter().map(|s| s.as_ref())
+    // The config the plan is made under, for planning a fragment to merge.
+    pub fn config(&self) -> &Config {
+        &self.config
     }
 
-    pub fn orderings(&self) -> impl Iterator<Item = Vec<&Act<T>>> {
-        self.graph.orderings()
+    // Adds the clients and acts of a fragment planned on its own, such as a
+    // client that deletes whatever the others work on, so that scenarios can
+    // share it. Its acts run alongside this planner's, and keep their deps and
+    // constraints under their new ids. A client the two planners share ends
+    // up with the ops of both. Options that either planner set stay set, but
+    // clients are only kept symmetric if one of the planners named none, as
+    // two groups of them cannot be told apart once combined. The fragment
+    // should be planned under the same config as this planner.
+    pub fn merge(&mut self, other: Planner<T>) {
+        let ids = self
+            .graph
+            .merge(&[], other.graph)
+            .expect("a merge that waits for nothing has nothing to refuse");
+        let remap = |list: &[NodeId]| list.iter().map(|id| ids.get(*id)).collect::<Vec<_>>();
+
+        self.clients.extend(other.clients);
+        self.partitioned.extend(other.partitioned);
+        self.timeouts.extend(other.timeouts);
+        self.restarts.extend(other.restarts);
+        self.crashed.extend(other.crashed);
+
+        if self.symmetric.is_empty() {
+            self.symmetric = other.symmetric;
+        } else if !other.symmetric.is_empty() {
+            self.symmetric.clear();
+        }
+        self.reduce |= other.reduce;
+        self.conflicts_first |= other.conflicts_first;
+        self.alternate_first |= other.alternate_first;
+        self.synchronized |= other.synchronized;
+
+        for constraint in other.constraints {
+            self.constraints.push(Constraint {
+                first: remap(&constraint.first),
+                then: remap(&constraint.then),
+                adjacent: constraint.adjacent,
+            });
+        }
+        for (client_id, theirs) in other.sessions {
+            let session = self.sessions.entry(client_id).or_default();
+            for (dir, id) in theirs.lists {
+                session.lists.entry(dir).or_insert(ids.get(id));
+            }
+            session.start.extend(remap(&theirs.start));
+        }
     }
-}
 
-pub struct Client<'a, T> {
-    id: String,
-    graph: &'a mut Graph<Act<T>>,
-    config: Config,
-}
+    // Gives a client a new id, so that a fragment's clients can be made
+    // distinct from another's before they are merged.
+    pub fn rename_client(&mut self, from: &str, to: &str) {
+        if self.clients.contains(to) {
+            panic!("client '{}' is already in the plan", to);
+        }
+        for act in self.graph.iter_mut() {
+            if act.client_id == from {
+                act.client_id = to.to_string();
+            }
+        }
+        for ids in [
+            &mut self.clients,
+            &mut self.partitioned,
+            &mut self.timeouts,
+            &mut self.restarts,
+            &mut self.crashed,
+        ] {
+            if ids.remove(from) {
+                ids.insert(to.to_string());
+            }
+        }
+        for id in &mut self.symmetric {
+            if id == from {
+                *id = to.to_string();
+            }
+        }
+        self.symmetric.sort();
 
-impl<'a, T> Client<'a, T> {
-    fn new(graph: &'a mut Graph<Act<T>>, id: &str, config: Config) -> Client<'a, T> {
-        Client {
-            id: id.to_string(),
-            graph,
-            config,
+        if let Some(session) = self.sessions.remove(from) {
+            self.sessions.insert(to.to_string(), session);
         }
     }
 
-    fn act<P>(&self, path: P, op: Op<T>) -> Act<T>
-    where
-        P: Into<Path>,
-    {
-        Act::new(&self.id, path.into(), op)
+    pub fn clients(&self) -> impl Iterator<Item = &str> {
+        self.clients.iter().map(|s| s.as_ref())
     }
 
-    fn do_reads(&mut self, path: &Path) -> Vec<Id> {
-        let mut reads: Vec<_> = path
-            .dirs()
-            .map(|dir| self.graph.add(&[], self.act(dir, Op::List)))
-            .collect();
+    pub fn partition(&mut self, id: &str) {
+        self.partitioned.insert(id.to_string());
+    }
 
-        let get = self.act(path, Op::Get);
-        reads.push(self.graph.add(&[], get));
+    pub fn partitioned_clients(&self) -> impl Iterator<Item = &str> {
+        self.partitioned.iter().map(|s| s.as_ref())
+    }
 
-        reads
+    pub fn time_out(&mut self, id: &str) {
+        self.timeouts.insert(id.to_string());
     }
 
-    pub fn update<F>(&mut self, key: &str, update: F)
-    where
-        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
-    {
-        if self.config.update == Update::GetBeforePut {
-            self.update_get_before_put(key, update);
-        } else {
-            self.update_reads_before_links(key, update);
-        }
+    pub fn timed_out_clients(&self) -> impl Iterator<Item = &str> {
+        self.timeouts.iter().map(|s| s.as_ref())
     }
 
-    fn update_reads_before_links<F>(&mut self, key: &str, update: F)
-    where
-        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
-    {
-        let path = Path::from(key);
-        let reads = self.do_reads(&path);
+    // Has the runner restart the client's process before each of its acts
+    // in turn, after which its remaining acts run with an empty cache.
+    pub fn restart(&mut self, id: &str) {
+        self.restarts.insert(id.to_string());
+    }
 
-        let links: Vec<_> = path
-            .links()
-            .map(|(dir, name)| {
-                let link = self.act(dir, Op::Link(name.to_string()));
-                self.graph.add(&reads, link)
-            })
-            .collect();
+    pub fn restarted_clients(&self) -> impl Iterator<Item = &str> {
+        self.restarts.iter().map(|s| s.as_ref())
+    }
 
-        let put = self.act(&path, Op::Put(Box::new(update)));
-        self.graph.add(&links, put);
+    // Cuts a client's plan short after its first `n` acts, counted in the
+    // order they were planned, as if it died partway through its ops. Any
+    // act that waited for one it lost is dropped as well, whichever client
+    // it belongs to. A client cut short is never acknowledged.
+    pub fn crash_after(&mut self, id: &str, n: usize) {
+        let mut seen = 0;
+        let ids = self.graph.retain(|_, act| {
+            if act.client_id != id {
+                return true;
+            }
+            seen += 1;
+            seen <= n
+        });
+        let remap = |list: &mut Vec<NodeId>| {
+            *list = list.iter().filter_map(|id| ids.get(id).copied()).collect()
+        };
+
+        for session in self.sessions.values_mut() {
+            session.lists = session
+                .lists
+                .iter()
+                .filter_map(|(dir, id)| Some((dir.clone(), *ids.get(id)?)))
+                .collect();
+            remap(&mut session.start);
+        }
+        for constraint in &mut self.constraints {
+            remap(&mut constraint.first);
+            remap(&mut constraint.then);
+        }
+        self.crashed.insert(id.to_string());
     }
 
-    fn update_get_before_put<F>(&mut self, key: &str, update: F)
-    where
-        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
-    {
-        let path = Path::from(key);
+    pub fn crashed_clients(&self) -> impl Iterator<Item = &str> {
+        self.crashed.iter().map(|s| s.as_ref())
+    }
 
-        let mut links: Vec<_> = path
-            .links()
-            .map(|(dir, name)| {
-                let list = self.graph.add(&[], self.act(dir, Op::List));
-                let link = self.act(dir, Op::Link(name.to_string()));
-                self.graph.add(&[list], link)
-            })
-            .collect();
+    // The docs the plan puts or removes, once for each act that writes one.
+    pub fn written_docs(&self) -> impl Iterator<Item = &str> {
+        self.graph
+            .iter()
+            .filter(|act| matches!(act.op, Op::Put(_) | Op::Rm))
+            .map(|act| act.path.full())
+    }
 
-        let get = self.graph.add(&[], self.act(&path, Op::Get));
-        links.insert(0, get);
+    // The acts of the plan and the deps between them, for anything that
+    // needs to look over the plan as a whole.
+    pub fn graph(&self) -> &Graph<Act<T>> {
+        &self.graph
+    }
 
-        let put = self.act(&path, Op::Put(Box::new(update)));
-        self.graph.add(&links, put);
+    // The dependencies between the plan's acts, each as the act that must
+    // come first and the act that waits for it.
+    pub fn edges(&self) -> Vec<(NodeId, NodeId)> {
+        self.graph.edges()
     }
 
-    pub fn remove(&mut self, key: &str) {
-        if self.config.remove == Remove::UnlinkParallel {
-            self.remove_unlink_parallel(key);
-        } else {
-            self.remove_unlink_reverse_sequential(key);
+    // The plan's acts and their deps in Graphviz's DOT format, each act
+    // labelled as in "Act<A: get('/x')>".
+    pub fn to_dot(&self) -> String {
+        self.graph.to_dot()
+    }
+
+    // The plan as JSON, to be saved and compared between runs. Each act is
+    // written as a `SavedAct`, which is what reading the plan back gives.
+    pub fn to_json(&self) -> Json {
+        self.graph.to_json(|act| SavedAct::from(act).to_json())
+    }
+
+    // Lists each client's acts in the order they were planned, each under
+    // its node id as in the DOT rendering and followed by the acts it waits
+    // for. Those are named by their op, with their client as well if it is
+    // another one, as in:
+    //
+    //     A:
+    //         n1 list('/')
+    //         n2 get('/x')
+    //         n3 link('/', 'x'), after list('/'), get('/x')
+    //     B:
+    //         n4 get('/x'), after A: link('/', 'x')
+    pub fn describe(&self) -> String {
+        let mut out = String::new();
+
+        for client_id in &self.clients {
+            out.push_str(&format!("{}:\n", client_id));
+
+            for (id, act, deps) in self.graph.nodes() {
+                if act.client_id != *client_id {
+                    continue;
+                }
+                out.push_str(&format!("    n{} {}", id, act));
+
+                let deps: Vec<_> = deps
+                    .iter()
+                    .map(|dep| {
+                        let dep = self.graph.get(*dep);
+                        if dep.client_id == act.client_id {
+                            dep.to_string()
+                        } else {
+                            format!("{}: {}", dep.client_id, dep)
+                        }
+                    })
+                    .collect();
+                if !deps.is_empty() {
+                    out.push_str(&format!(", after {}", deps.join(", ")));
+                }
+                out.push('\n');
+            }
         }
+        out
     }
 
-    fn remove_unlink_reverse_sequential(&mut self, key: &str) {
-        let path = Path::from(key);
-        let reads = self.do_reads(&path);
+    // Names an edge by its two acts, as in
+    // "A: list('/') -> A: link('/', 'path/')".
+    pub fn describe_edge(&self, (first, then): (NodeId, NodeId)) -> String {
+        let (first, then) = (self.graph.get(first), self.graph.get(then));
+        format!(
+            "{}: {} -> {}: {}",
+            first.client_id, first, then.client_id, then
+        )
+    }
 
-        let mut op = self.graph.add(&reads, self.act(&path, Op::Rm));
+    // Lets an act run without waiting for one of its deps. Clients that
+    // were planned alike may no longer be, so none are treated as symmetric.
+    pub fn drop_edge(&mut self, (first, then): (NodeId, NodeId)) -> Result<(), GraphError> {
+        self.graph.remove_edge(first, then)?;
+        self.symmetric.clear();
+        Ok(())
+    }
 
-        for (dir, name) in path.links().rev() {
-            let unlink = self.act(dir, Op::Unlink(name.to_string()));
-            op = self.graph.add(&[op], unlink);
-        }
+    // Drops the deps that acts would wait for anyway through their other
+    // deps. The plan's orderings stay the same.
+    pub fn drop_redundant_edges(&mut self) {
+        self.graph.transitive_reduction();
     }
 
-    fn remove_unlink_parallel(&mut self, key: &str) {
-        let path = Path::from(key);
-        let reads = self.do_reads(&path);
+    pub fn symmetric(&mut self, ids: &[&str]) {
+        self.symmetric = ids.iter().map(|id| id.to_string()).collect();
+        self.symmetric.sort();
+    }
 
-        let rm = self.graph.add(&reads, self.act(&path, Op::Rm));
+    pub fn reduce(&mut self) {
+        self.reduce = true;
+    }
 
-        for (dir, name) in path.links() {
-            let unlink = self.act(dir, Op::Unlink(name.to_string()));
-            self.graph.add(&[rm], unlink);
-        }
+    pub fn conflicts_first(&mut self) {
+        self.conflicts_first = true;
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    pub fn alternate_first(&mut self) {
+        self.alternate_first = true;
+    }
 
-    use std::cell::RefCell;
+    // Only explores the orderings in which the acts named `then` come after
+    // those named `first`. An act is named by its client and op, as in
+    // "B: unlink('/path/', 'x')", and a name that several acts share covers
+    // all of them. A client's id on its own names all of its acts. Call this
+    // once the clients' ops are planned.
+    pub fn order(&mut self, first: &str, then: &str) {
+        self.constrain(first, then, false);
+    }
 
-    use crate::actor::Actor;
-    use crate::config::Update;
-    use crate::db::{Db, DbStore};
-    use crate::graph::tests::check_graph;
+    // As for `order`, but the acts named `then` must come directly after one
+    // of those named `first`, with no other act between them.
+    pub fn adjacent(&mut self, first: &str, then: &str) {
+        self.constrain(first, then, true);
+    }
 
-    #[test]
-    fn returns_the_ids_of_registered_clients() {
-        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+    // Makes every act named `then` wait for every act named `first`, as when
+    // one client only starts once it hears that another has finished a
+    // write. Unlike `order`, this adds edges to the graph, so the orderings
+    // it rules out are never walked or counted. Acts are named as for
+    // `order`, in either order of planning. If an act named `first` already
+    // waits for one named `then`, the graph refuses the edges, and the error
+    // says which.
+    pub fn happens_before(&mut self, first: &str, then: &str) -> Result<(), GraphError> {
+        let (first, then) = (self.named(first), self.named(then));
+        let edges: Vec<_> = first
+            .iter()
+            .flat_map(|dep| then.iter().map(move |id| (*dep, *id)))
+            .collect();
 
-        planner.client("alice").update("/x", |_| Some(vec!['x']));
-        planner.client("bob").remove("/y");
+        let ids = self.graph.add_edges(&edges)?;
+        self.renumber(&ids);
+        self.synchronized = true;
+        Ok(())
+    }
 
-        let clients: Vec<_> = planner.clients().collect();
-        assert_eq!(clients, ["alice", "bob"]);
+    // Gives every act id the planner holds the id that the graph renumbered
+    // it to.
+    fn renumber(&mut self, ids: &IdMap) {
+        for session in self.sessions.values_mut() {
+            for id in session.lists.values_mut() {
+                *id = ids.get(*id);
+            }
+            for id in &mut session.start {
+                *id = ids.get(*id);
+            }
+        }
+        for constraint in &mut self.constraints {
+            for id in constraint.first.iter_mut().chain(&mut constraint.then) {
+                *id = ids.get(*id);
+            }
+        }
     }
 
-    #[test]
-    fn produces_instructions_to_create_a_document() {
-        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
-        planner
-            .client("A")
-            .update("/path/x.json", |_| Some(vec!['a']));
+    fn constrain(&mut self, first: &str, then: &str, adjacent: bool) {
+        let constraint = Constraint {
+            first: self.named(first),
+            then: self.named(then),
+            adjacent,
+        };
+        self.constraints.push(constraint);
+    }
 
-        let store = RefCell::new(DbStore::new(Config::new()));
-        let mut actor = Actor::new(&store, Config::new());
+    fn named(&self, name: &str) -> Vec<NodeId> {
+        let ids: Vec<_> = self
+            .graph
+            .ids()
+            .filter(|id| {
+                let act = self.graph.get(*id);
+                act.client_id == name || format!("{}: {}", act.client_id, act) == name
+            })
+            .collect();
 
-        for act in planner.orderings().next().unwrap() {
-            actor.dispatch(act);
+        if ids.is_empty() {
+            panic!("no act is named {}", name);
         }
+        ids
+    }
 
-        let s = store.into_inner();
+    pub fn orderings(&self) -> impl Iterator<Item = Vec<&Act<T>>> + Send {
+        let reduce = self.reduce && self.can_reduce();
+        self.graph.orderings_ranked(
+            move |prefix, act| self.is_canonical(prefix, act, reduce),
+            move |prefix, act| self.rank(prefix, act),
+        )
+    }
 
-        assert_eq!(s.read("/"), Some((1, Some(Db::dir_from(&["path/"])))));
-        assert_eq!(s.read("/path/"), Some((1, Some(Db::dir_from(&["x.json"])))));
-        assert_eq!(s.read("/path/x.json"), Some((1, Some(Db::Doc(vec!['a'])))));
+    // Reads a complete entry from `cache`, or stores one while generating.
+    pub fn orderings_cached<'a>(
+        &'a self,
+        cache: &'a OrderingCache,
+    ) -> Box<dyn Iterator<Item = Vec<&'a Act<T>>> + Send + 'a> {
+        let (key, size) = (self.fingerprint(), self.len());
+
+        // The cache only loads numbers from 1 to the plan's size, each of
+        // which names one of its acts.
+        let ids: Box<dyn Iterator<Item = Vec<NodeId>> + Send> = match cache.load(key, size) {
+            Some(entry) => Box::new(entry.map(|numbers| {
+                let ids = numbers.into_iter().map(|n| self.graph.node_id(n));
+                ids.collect::<Option<_>>().unwrap()
+            })),
+            None => Box::new(cache.store(key, size, self.ordering_ids())),
+        };
+        Box::new(ids.map(|ids| ids.into_iter().map(|id| self.graph.get(id)).collect()))
     }
 
-    #[test]
-    fn produces_instructions_to_update_a_document() {
-        let mut planner: Planner<(char, usize)> = Planner::new(Config::new());
-        planner
-            .client("A")
-            .update("/path/x.json", |_| Some(('a', 50)));
-        planner
-            .client("B")
-            .update("/path/x.json", |doc| doc.map(|(c, n)| (c, n + 3)));
+    // As for `orderings`, but each ordering is given as the ids of its acts,
+    // which `act` turns back into acts. The ids own nothing of the planner,
+    // so they can be held, batched or sent to other threads apart from it.
+    pub fn ordering_ids(&self) -> impl Iterator<Item = Vec<NodeId>> + Send + '_ {
+        let reduce = self.reduce && self.can_reduce();
+        self.graph.ordering_ids_ranked(
+            move |prefix, act| self.is_canonical(prefix, act, reduce),
+            move |prefix, act| self.rank(prefix, act),
+        )
+    }
 
-        let store = RefCell::new(DbStore::new(Config::new()));
-        let mut actor = Actor::new(&store, Config::new());
+    pub fn act(&self, id: NodeId) -> &Act<T> {
+        self.graph.get(id)
+    }
 
-        for act in planner.orderings().next().unwrap() {
-            actor.dispatch(act);
+    // Acts that differ only in the values they write hash alike.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        self.graph
+            .hash_with(&mut hasher, |act, state| format!("{:?}", act).hash(state));
+        self.symmetric.hash(&mut hasher);
+        (self.reduce && self.can_reduce()).hash(&mut hasher);
+        self.conflicts_first.hash(&mut hasher);
+        self.alternate_first.hash(&mut hasher);
+        self.constraints.hash(&mut hasher);
+
+        hasher.finish()
+    }
+
+    pub fn stats(&self) -> PlanStats {
+        let mut acts = BTreeMap::new();
+        for act in self.graph.iter() {
+            *acts.entry(act.client_id.clone()).or_default() += 1;
+        }
+        PlanStats {
+            acts,
+            depth: self.graph.depth(),
+            width: self.graph.width(),
+            orderings: self.count_orderings(),
         }
+    }
 
-        let s = store.into_inner();
+    // The number of orderings before symmetry and reduction are applied, so
+    // an upper bound on the number `orderings()` gives.
+    pub fn count_orderings(&self) -> u128 {
+        self.graph.count_orderings()
+    }
 
-        assert_eq!(s.read("/"), Some((2, Some(Db::dir_from(&["path/"])))));
-        assert_eq!(s.read("/path/"), Some((2, Some(Db::dir_from(&["x.json"])))));
-        assert_eq!(s.read("/path/x.json"), Some((2, Some(Db::Doc(('a', 53))))));
+    // A random ordering, drawn as `Graph::sample_where` draws them. `counts`
+    // must only ever be passed to this planner.
+    pub fn sample(&self, counts: &mut Counts, rng: &mut Rng) -> Option<Vec<&Act<T>>> {
+        let reduce = self.reduce && self.can_reduce();
+        self.graph.sample_where(
+            |prefix, act| self.is_canonical(prefix, act, reduce),
+            counts,
+            rng,
+        )
     }
 
-    #[test]
-    fn produces_instructions_to_remove_a_document() {
-        let mut planner: Planner<(char, usize)> = Planner::new(Config::new());
-        planner
-            .client("A")
-            .update("/path/x.json", |_| Some(('a', 50)));
-        planner.client("B").remove("/path/x.json");
+    // Sets `exceeded` if an ordering is cut off for passing `bound`.
+    pub fn orderings_within<'a>(
+        &'a self,
+        bound: usize,
+        exceeded: &'a AtomicBool,
+    ) -> impl Iterator<Item = Vec<&'a Act<T>>> + Send {
+        let reduce = self.reduce && self.can_reduce();
+        let counts = self.act_counts();
+
+        self.graph.orderings_ranked(
+            move |prefix, act| {
+                if !self.is_canonical(prefix, act, reduce) {
+                    return false;
+                }
+                let acts = prefix.iter().copied().chain(Some(act));
+                if count_preemptions(&counts, acts) > bound {
+                    exceeded.store(true, Ordering::Relaxed);
+                    return false;
+                }
+                true
+            },
+            move |prefix, act| self.rank(prefix, act),
+        )
+    }
 
-        let store = RefCell::new(DbStore::new(Config::new()));
-        let mut actor = Actor::new(&store, Config::new());
+    // Switching away from a client with acts still to run.
+    pub fn preemptions(&self, plan: &[&Act<T>]) -> usize {
+        count_preemptions(&self.act_counts(), plan.iter().copied())
+    }
 
-        for act in planner.orderings().next().unwrap() {
-            actor.dispatch(act);
+    pub fn len(&self) -> usize {
+        self.graph.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn act_counts(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for act in self.graph.iter() {
+            *counts.entry(act.client_id.as_str()).or_insert(0) += 1;
         }
+        counts
+    }
 
-        let s = store.into_inner();
+    // With conflicts first, an act that conflicts with the last one placed is
+    // tried before any other, so the earliest orderings are those in which
+    // clients interleave at the points where their acts race. With alternate
+    // first, an act from a client other than the last one's is tried before
+    // one from the same client, so the earliest orderings switch between
+    // clients as often as they can.
+    fn rank(&self, prefix: &[&Act<T>], act: &Act<T>) -> usize {
+        match prefix.last() {
+            Some(last) if self.conflicts_first && last.conflicts_with(act) => 0,
+            Some(last) if self.alternate_first && last.client_id != act.client_id => 1,
+            _ => 2,
+        }
+    }
 
-        assert_eq!(s.read("/"), Some((2, Some(Db::dir_from(&[])))));
-        assert_eq!(s.read("/path/"), Some((2, Some(Db::dir_from(&[])))));
-        assert_eq!(s.read("/path/x.json"), Some((2, None)));
+    // Acts commute by their paths, which only name the keys they touch when
+    // every dir is a key of its own. Other layouts list a dir by a scan, or
+    // from a manifest or buckets, and pack docs into their dirs. And with
+    // leases, moving an act changes the time at which other acts check them.
+    // Reduction keeps one order of two commuting acts, which may not be the
+    // one a constraint or an edge between clients allows.
+    fn can_reduce(&self) -> bool {
+        let timed = self
+            .graph
+            .iter()
+            .any(|act| matches!(act.op, Op::Acquire(_)));
+        !timed
+            && self.config.layout == Layout::DirDocs
+            && self.constraints.is_empty()
+            && !self.synchronized
     }
 
-    #[test]
-    fn plans_a_top_level_document_update() {
-        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+    fn meets_constraints(&self, prefix: &[&Act<T>], act: &Act<T>) -> bool {
+        let is =
+            |a: &Act<T>, ids: &[NodeId]| ids.iter().any(|id| std::ptr::eq(a, self.graph.get(*id)));
 
-        planner.client("A").update("/x.json", |doc| doc);
+        self.constraints
+            .iter()
+            .filter(|constraint| is(act, &constraint.then))
+            .all(|constraint| {
+                let placed = prefix.iter().filter(|a| is(a, &constraint.first)).count();
+                let follows = prefix
+                    .last()
+                    .is_some_and(|last| is(last, &constraint.first));
 
-        check_graph(
-            &planner.graph,
-            &[
-                ("get", Act::new("A", "/x.json".into(), Op::Get), &[]),
-                ("list", Act::new("A", "/".into(), Op::List), &[]),
-                (
-                    "link",
-                    Act::new("A", "/".into(), Op::Link("x.json".into())),
-                    &["get", "list"],
-                ),
-                (
-                    "put",
-                    Act::new("A", "/x.json".into(), Op::Put(Box::new(|d| d))),
-                    &["link"],
-                ),
-            ],
-        );
+                placed == constraint.first.len() && (follows || !constraint.adjacent)
+            })
     }
 
-    #[test]
-    fn plans_an_update_in_a_top_level_directory() {
-        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+    // Symmetry is ignored under constraints and edges between clients, since
+    // they may name a client whose orderings it would prune.
+    fn is_canonical(&self, prefix: &[&Act<T>], act: &Act<T>, reduce: bool) -> bool {
+        if !self.meets_constraints(prefix, act) {
+            return false;
+        }
+        let symmetric = if self.constraints.is_empty() && !self.synchronized {
+            &self.symmetric[..]
+        } else {
+            &[]
+        };
+        if let Some(i) = symmetric.iter().position(|id| *id == act.client_id) {
+            let started = |id: &String| prefix.iter().any(|a| a.client_id == *id);
+
+            if !started(&act.client_id) && !symmetric[..i].iter().all(started) {
+                return false;
+            }
+        }
+        match prefix.last() {
+            Some(last) if reduce => last.client_id < act.client_id || !last.commutes_with(act),
+            _ => true,
+        }
+    }
+}
+
+fn count_preemptions<'a, T, I>(counts: &BTreeMap<&str, usize>, acts: I) -> usize
+where
+    T: 'a,
+    I: Iterator<Item = &'a Act<T>>,
+{
+    let mut remaining = counts.clone();
+    let mut last: Option<&str> = None;
+    let mut preemptions = 0;
+
+    for act in acts {
+        let client_id = act.client_id.as_str();
+
+        if let Some(last) = last {
+            if last != client_id && remaining.get(last).is_some_and(|n| *n > 0) {
+                preemptions += 1;
+            }
+        }
+        if let Some(n) = remaining.get_mut(client_id) {
+            *n -= 1;
+        }
+        last = Some(client_id);
+    }
+    preemptions
+}
+
+// What the planner keeps of a client between calls to `client()`: the act
+// that last listed each dir, so that later updates can rely on what its
+// cache already holds, and the acts that each new op must wait for since
+// the client's last barrier.
+#[derive(Default)]
+struct Session {
+    lists: BTreeMap<String, NodeId>,
+    start: Vec<NodeId>,
+}
+
+pub struct Client<'a, T> {
+    id: String,
+    graph: &'a mut Graph<Act<T>>,
+    session: &'a mut Session,
+    config: Config,
+    label: Option<String>,
+}
+
+impl<'a, T> Client<'a, T> {
+    fn new(
+        graph: &'a mut Graph<Act<T>>,
+        session: &'a mut Session,
+        id: &str,
+        config: Config,
+    ) -> Client<'a, T> {
+        Client {
+            id: id.to_string(),
+            graph,
+            session,
+            config,
+            label: None,
+        }
+    }
+
+    fn start(&self) -> Vec<NodeId> {
+        self.session.start.clone()
+    }
+
+    // Makes every op the client plans from now on wait for all the acts it
+    // has planned so far, as a client that finishes each op before starting
+    // the next would. Without one, a client's ops run concurrently.
+    pub fn barrier(&mut self) {
+        let mine: Vec<_> = self
+            .graph
+            .nodes()
+            .filter(|(_, act, _)| act.client_id == self.id)
+            .map(|(id, _, _)| id)
+            .collect();
+        let waited: BTreeSet<_> = mine.iter().flat_map(|id| self.graph.deps_of(*id)).collect();
+
+        self.session.start = mine.into_iter().filter(|id| !waited.contains(id)).collect();
+    }
+
+    // Labels each put the client plans from now on, so that reports show
+    // which of its updates wrote a value, e.g. `put('/x') as 'increment'`.
+    // The label lasts until the next call, or until the client is dropped.
+    pub fn label(&mut self, label: &str) {
+        self.label = Some(label.to_string());
+    }
+
+    fn act<P>(&self, path: P, op: Op<T>) -> Act<T>
+    where
+        P: Into<Path>,
+    {
+        let mut act = Act::new(&self.id, path.into(), op);
+        if matches!(act.op, Op::Put(_)) {
+            act.label = self.label.clone();
+        }
+        act
+    }
+
+    // Adds an act to the plan. Its deps are always acts this client planned
+    // in the same graph, so the graph has no grounds to refuse it.
+    fn add(&mut self, deps: &[NodeId], act: Act<T>) -> NodeId {
+        self.graph
+            .add(deps, act)
+            .expect("a client only waits for acts in its own plan")
+    }
+
+    fn fan_out<I>(&mut self, deps: &[NodeId], acts: I) -> Vec<NodeId>
+    where
+        I: IntoIterator<Item = Act<T>>,
+    {
+        self.graph
+            .fan_out(deps, acts)
+            .expect("a client only waits for acts in its own plan")
+    }
+
+    fn chain<I>(&mut self, deps: &[NodeId], acts: I) -> Vec<NodeId>
+    where
+        I: IntoIterator<Item = Act<T>>,
+    {
+        self.graph
+            .chain(deps, acts)
+            .expect("a client only waits for acts in its own plan")
+    }
+
+    fn links<'p>(&self, path: &'p Path) -> Vec<(&'p str, &'p str, Kind)> {
+        db::links(&self.config.layout, path)
+    }
+
+    // Lists every dir above the path and gets the path. With snapshot reads
+    // these are batched into one act, which reads all the keys from the same
+    // state of the store. Otherwise each is an act of its own, as a batch of
+    // per-key reads would be, since other clients may write between them.
+    fn do_reads(&mut self, path: &Path, deps: &[NodeId]) -> Vec<NodeId> {
+        let dirs: Vec<_> = self
+            .links(path)
+            .into_iter()
+            .map(|(dir, _, _)| dir)
+            .collect();
+        self.read_dirs(path, &dirs, deps)
+    }
+
+    // Reads as `do_reads()` does, but only lists the dirs the client has not
+    // listed before. For the others it waits for the act that listed them,
+    // and relies on the records its cache kept.
+    fn do_reads_skipping_known(&mut self, path: &Path) -> Vec<NodeId> {
+        let (known, unknown): (Vec<_>, Vec<_>) = self
+            .links(path)
+            .into_iter()
+            .map(|(dir, _, _)| dir)
+            .partition(|dir| self.session.lists.contains_key(*dir));
+
+        let mut reads: Vec<_> = known.iter().map(|dir| self.session.lists[*dir]).collect();
+        reads.extend(self.read_dirs(path, &unknown, &self.start()));
+        reads
+    }
+
+    fn read_dirs(&mut self, path: &Path, dirs: &[&str], deps: &[NodeId]) -> Vec<NodeId> {
+        if self.config.reads == Reads::Snapshot {
+            let paths = dirs.iter().map(|dir| Path::from(*dir));
+            let read = self.act(path, Op::ReadMany(paths.collect()));
+            let read = self.add(deps, read);
+            self.note_lists(dirs, &vec![read; dirs.len()]);
+            return vec![read];
+        }
+
+        let mut reads: Vec<_> = dirs.iter().map(|dir| self.act(*dir, Op::List)).collect();
+        reads.push(self.act(path, Op::Get));
+
+        let reads = self.fan_out(deps, reads);
+        self.note_lists(dirs, &reads);
+        reads
+    }
+
+    fn note_lists(&mut self, dirs: &[&str], ids: &[NodeId]) {
+        for (dir, id) in dirs.iter().zip(ids) {
+            self.session.lists.insert(dir.to_string(), *id);
+        }
+    }
+
+    pub fn update<F>(&mut self, key: &str, update: F)
+    where
+        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+    {
+        // With write-back the links follow the put under either strategy,
+        // which leaves nothing to tell them apart.
+        match self.config.update {
+            Update::GetBeforePut if !self.config.write_back => {
+                self.update_get_before_put(key, update)
+            }
+            Update::LazyLinks => self.update_lazy_links(key, update),
+            Update::SkipKnownLists => self.update_skipping_known_lists(key, update),
+            _ => self.update_reads_before_links(key, update),
+        }
+    }
+
+    // Creates the doc only if it is missing, as a write made with
+    // If-None-Match would. The get gates the put: a guard on what it read
+    // halts the client before it links anything if the doc exists, and the
+    // put writes nothing if the doc has appeared by the time it runs.
+    pub fn create(&mut self, key: &str, value: T)
+    where
+        T: Clone + Sync + 'static,
+    {
+        self.update_if(key, false, move |doc| match doc {
+            Some(_) => None,
+            None => Some(value.clone()),
+        });
+    }
+
+    // Updates the doc only if it exists, as a write made with If-Match
+    // would. Like `create()`, a guard halts the client if the get found no
+    // doc, and the put writes nothing if the doc is gone by the time it runs.
+    pub fn update_existing<F>(&mut self, key: &str, update: F)
+    where
+        T: 'static,
+        F: Fn(T) -> T + Sync + 'static,
+    {
+        self.update_if(key, true, move |doc| doc.map(&update));
+    }
+
+    // Makes sure the doc exists, writing a default value if it is missing, as
+    // a client that opens a doc before filling it in would. Unlike
+    // `create()`, finding the doc is not a failure: the links are made
+    // either way and the put writes nothing, so a touch that races a remove
+    // can leave a doc it believes exists unlinked or gone.
+    pub fn touch(&mut self, key: &str)
+    where
+        T: Default + 'static,
+    {
+        self.update(key, |doc| match doc {
+            Some(_) => None,
+            None => Some(T::default()),
+        });
+    }
+
+    fn update_if<F>(&mut self, key: &str, exists: bool, update: F)
+    where
+        T: 'static,
+        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+    {
+        let path = Path::from(key);
+        let reads = self.do_reads(&path, &self.start());
+        let check = move |doc: Option<&T>| doc.is_some() == exists;
+        let guard = self.act(&path, Op::Guard(Box::new(check)));
+        let guard = self.add(&reads, guard);
+
+        self.link_then_put(&path, vec![guard], update);
+    }
+
+    // Updates several docs in one go, as a client flushing a batch of changes
+    // would. The reads are shared: each dir above any of the keys is listed
+    // once, and each entry the keys need is linked once, before the puts of
+    // every doc below it. Links and puts otherwise follow reads-before-links,
+    // whichever update strategy the config names.
+    pub fn update_many(&mut self, updates: Vec<(&str, PutFn<T>)>) {
+        let paths: Vec<_> = updates.iter().map(|(key, _)| Path::from(*key)).collect();
+        let reads = self.do_reads_many(&paths);
+        let updates = paths
+            .iter()
+            .zip(updates.into_iter().map(|(_, update)| update));
+
+        if self.config.write_back {
+            let puts: Vec<_> = updates
+                .map(|(path, update)| self.act(path, Op::Put(update)))
+                .collect();
+            let puts = self.fan_out(&reads, puts);
+
+            let mut links = Vec::new();
+            for path in &paths {
+                for link in self.changes(path, Change::Link) {
+                    if !links.contains(&link) {
+                        links.push(link);
+                    }
+                }
+            }
+            self.flush(links, &puts);
+            return;
+        }
+
+        let mut linked: Vec<(&str, &str, NodeId)> = Vec::new();
+
+        for path in &paths {
+            for (dir, name, kind) in self.links(path) {
+                if linked.iter().any(|(d, n, _)| (*d, *n) == (dir, name)) {
+                    continue;
+                }
+                let link = self.act(dir, Op::Link(name.to_string(), kind));
+                linked.push((dir, name, self.add(&reads, link)));
+            }
+        }
+
+        for (path, update) in updates {
+            let mut deps: Vec<_> = self
+                .links(path)
+                .into_iter()
+                .filter_map(|(dir, name, _)| {
+                    let link = linked.iter().find(|(d, n, _)| (*d, *n) == (dir, name));
+                    link.map(|(_, _, id)| *id)
+                })
+                .collect();
+
+            if deps.is_empty() {
+                deps = reads.clone();
+            }
+            self.add(&deps, self.act(path, Op::Put(update)));
+        }
+    }
+
+    // Lists each dir above any of the paths once and gets each path. With
+    // snapshot reads each path keeps a batched read of its own, since one
+    // covers the dirs above a single doc.
+    fn do_reads_many(&mut self, paths: &[Path]) -> Vec<NodeId> {
+        if self.config.reads == Reads::Snapshot {
+            return paths
+                .iter()
+                .flat_map(|path| self.do_reads(path, &self.start()))
+                .collect();
+        }
+
+        let mut dirs: Vec<&str> = Vec::new();
+        for path in paths {
+            for (dir, _, _) in self.links(path) {
+                if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                }
+            }
+        }
+
+        let mut reads: Vec<_> = dirs.iter().map(|dir| self.act(*dir, Op::List)).collect();
+        reads.extend(paths.iter().map(|path| self.act(path, Op::Get)));
+
+        let reads = self.fan_out(&self.start(), reads);
+        self.note_lists(&dirs, &reads);
+        reads
+    }
+
+    fn update_reads_before_links<F>(&mut self, key: &str, update: F)
+    where
+        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+    {
+        let path = Path::from(key);
+        let reads = self.do_reads(&path, &self.start());
+        self.link_then_put(&path, reads, update);
+    }
+
+    fn update_skipping_known_lists<F>(&mut self, key: &str, update: F)
+    where
+        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+    {
+        let path = Path::from(key);
+        let reads = self.do_reads_skipping_known(&path);
+        self.link_then_put(&path, reads, update);
+    }
+
+    fn update_get_before_put<F>(&mut self, key: &str, update: F)
+    where
+        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+    {
+        let path = Path::from(key);
+
+        let mut links: Vec<_> = self
+            .links(&path)
+            .into_iter()
+            .map(|(dir, name, kind)| {
+                let list = self.add(&self.start(), self.act(dir, Op::List));
+                let link = self.act(dir, Op::Link(name.to_string(), kind));
+                self.add(&[list], link)
+            })
+            .collect();
+
+        let get = self.add(&self.start(), self.act(&path, Op::Get));
+        links.insert(0, get);
+
+        let put = self.act(&path, Op::Put(Box::new(update)));
+        self.add(&links, put);
+    }
+
+    // Writes the doc straight away and leaves its links to a repair of its
+    // path, which may run at any later point, as an index maintained in the
+    // background would.
+    fn update_lazy_links<F>(&mut self, key: &str, update: F)
+    where
+        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+    {
+        let get = self.act(key, Op::Get);
+        let put = self.act(key, Op::Put(Box::new(update)));
+        let put = self.chain(&self.start(), [get, put]);
+
+        self.repair_after(&[key], put);
+    }
+
+    fn update_guarded<F>(&mut self, key: &str, guard: Act<T>, update: F)
+    where
+        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+    {
+        let path = Path::from(key);
+        let mut reads = self.do_reads(&path, &self.start());
+        reads.push(self.add(&self.start(), guard));
+
+        self.link_then_put(&path, reads, update);
+    }
+
+    // Returns the acts that finish the update: the put, or with write-back
+    // the dir writes that follow it.
+    fn link_then_put<F>(&mut self, path: &Path, reads: Vec<NodeId>, update: F) -> Vec<NodeId>
+    where
+        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+    {
+        if self.config.write_back {
+            let put = self.act(path, Op::Put(Box::new(update)));
+            let put = self.add(&reads, put);
+            let links = self.changes(path, Change::Link);
+            return self.flush(links, &[put]);
+        }
+
+        let links: Vec<_> = self
+            .links(path)
+            .into_iter()
+            .map(|(dir, name, kind)| self.act(dir, Op::Link(name.to_string(), kind)))
+            .collect();
+        let mut deps = self.fan_out(&reads, links);
+
+        if deps.is_empty() {
+            deps = reads;
+        }
+
+        let put = self.act(path, Op::Put(Box::new(update)));
+        vec![self.add(&deps, put)]
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        let path = Path::from(key);
+        let reads = self.do_reads(&path, &self.start());
+
+        self.rm_then_unlink(&path, reads);
+    }
+
+    pub fn rename<F>(&mut self, from: &str, to: &str, update: F)
+    where
+        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+    {
+        let src = Path::from(from);
+        let dst = Path::from(to);
+
+        let mut reads = self.do_reads(&src, &self.start());
+        reads.extend(self.do_reads(&dst, &self.start()));
+
+        if self.config.write_back {
+            let put = self.act(&dst, Op::Put(Box::new(update)));
+            let put = self.add(&reads, put);
+            let rm = self.add(&[put], self.act(&src, Op::Rm));
+
+            // Where the two paths share a dir, the unlink goes first so that
+            // the dir keeps any entry the new path still needs.
+            let mut changes = if self.config.remove == Remove::NoUnlink {
+                Vec::new()
+            } else {
+                self.changes(&src, |name, _| Change::Unlink(name))
+            };
+            changes.extend(self.changes(&dst, Change::Link));
+            self.flush(changes, &[rm]);
+            return;
+        }
+
+        let put = self.link_then_put(&dst, reads, update);
+        self.rm_then_unlink(&src, put);
+    }
+
+    // Without unlinks the doc's entries are left for readers to skip, as
+    // garbage that a repair may clean up later.
+    fn rm_then_unlink(&mut self, path: &Path, deps: Vec<NodeId>) {
+        if self.config.remove == Remove::NoUnlink {
+            self.add(&deps, self.act(path, Op::Rm));
+        } else if self.config.write_back {
+            let rm = self.add(&deps, self.act(path, Op::Rm));
+            let unlinks = self.changes(path, |name, _| Change::Unlink(name));
+            self.flush(unlinks, &[rm]);
+        } else if self.config.remove == Remove::UnlinkParallel {
+            self.remove_unlink_parallel(path, deps);
+        } else {
+            self.remove_unlink_reverse_sequential(path, deps);
+        }
+    }
+
+    // The change to each dir above the path, from the top down.
+    fn changes<'p, C>(&self, path: &'p Path, change: C) -> Vec<(&'p str, Change)>
+    where
+        C: Fn(String, Kind) -> Change,
+    {
+        self.links(path)
+            .into_iter()
+            .map(|(dir, name, kind)| (dir, change(name.to_string(), kind)))
+            .collect()
+    }
+
+    // Writes each dir once with all of its changes, after the acts in `deps`,
+    // and returns the writes. They don't depend on each other, so under
+    // write-back a remove has no order in which it unlinks its dirs.
+    fn flush(&mut self, changes: Vec<(&str, Change)>, deps: &[NodeId]) -> Vec<NodeId> {
+        let mut dirs: Vec<(&str, Vec<Change>)> = Vec::new();
+
+        for (dir, change) in changes {
+            match dirs.iter_mut().find(|(d, _)| *d == dir) {
+                Some((_, changes)) => changes.push(change),
+                None => dirs.push((dir, vec![change])),
+            }
+        }
+        let flushes: Vec<_> = dirs
+            .into_iter()
+            .map(|(dir, changes)| self.act(dir, Op::Flush(changes)))
+            .collect();
+        self.fan_out(deps, flushes)
+    }
+
+    fn remove_unlink_reverse_sequential(&mut self, path: &Path, deps: Vec<NodeId>) {
+        let mut acts = vec![self.act(path, Op::Rm)];
+
+        for (dir, name, _) in self.links(path).into_iter().rev() {
+            acts.push(self.act(dir, Op::Unlink(name.to_string())));
+        }
+        self.chain(&deps, acts);
+    }
+
+    fn remove_unlink_parallel(&mut self, path: &Path, deps: Vec<NodeId>) {
+        let rm = self.add(&deps, self.act(path, Op::Rm));
+
+        let unlinks: Vec<_> = self
+            .links(path)
+            .into_iter()
+            .map(|(dir, name, _)| self.act(dir, Op::Unlink(name.to_string())))
+            .collect();
+        self.fan_out(&[rm], unlinks);
+    }
+
+    pub fn repair(&mut self, keys: &[&str]) {
+        self.repair_after(keys, self.start());
+    }
+
+    fn repair_after(&mut self, keys: &[&str], deps: Vec<NodeId>) {
+        let mut last = deps;
+
+        for key in keys {
+            let path = Path::from(*key);
+
+            for (dir, name, kind) in self.links(&path).into_iter().rev() {
+                let child = db::child_path(dir, name);
+                let check = match kind {
+                    Kind::Dir => Op::List,
+                    Kind::Doc => Op::Get,
+                };
+
+                let list = self.act(dir, Op::List);
+                let read = self.act(child, check);
+                let repair = self.act(dir, Op::Repair(name.to_string(), kind));
+                last = self.chain(&last, [list, read, repair]);
+            }
+        }
+    }
+
+    // Plans the acts `build` adds between a begin and a commit.
+    pub fn transaction<F>(&mut self, build: F)
+    where
+        F: FnOnce(&mut Client<T>),
+    {
+        let mut graph = Graph::new();
+        let mut session = Session::default();
+        let mut tx = Client::new(&mut graph, &mut session, &self.id, self.config.clone());
+        tx.label = self.label.clone();
+        build(&mut tx);
+
+        let begin = self.add(&self.start(), self.act("/", Op::Begin));
+        let ids = self
+            .graph
+            .merge(&[begin], graph)
+            .expect("a client only waits for acts in its own plan");
+        let deps = [&[begin], ids.values()].concat();
+        self.add(&deps, self.act("/", Op::Commit));
+    }
+
+    // Reads the doc as a client that only reads would, and records what it
+    // saw, for the scenario's observation checks to judge.
+    pub fn get(&mut self, key: &str) {
+        self.add(&self.start(), self.act(key, Op::Observe));
+    }
+
+    // Lists the dir as a client browsing it would, and fails the execution
+    // if the entries it saw are not exactly `entries`. The dir's name ends
+    // with a '/', and so do the entries for the dirs inside it.
+    pub fn expect_list(&mut self, dir: &str, entries: &[&str]) {
+        let expected: BTreeSet<_> = entries.iter().map(|entry| entry.to_string()).collect();
+        let key = dir.to_string();
+
+        self.expect_list_with(dir, move |seen| {
+            if *seen == expected {
+                return Ok(());
+            }
+            Err(format!(
+                "listed {:?} in '{}', expected {:?}",
+                seen, key, expected
+            ))
+        });
+    }
+
+    // Like `expect_list()`, but judges the entries seen with `check`, for
+    // properties such as a lister never seeing a doc that was never created.
+    pub fn expect_list_with<F>(&mut self, dir: &str, check: F)
+    where
+        F: Fn(&BTreeSet<String>) -> Result<(), String> + Sync + 'static,
+    {
+        let act = self.act(dir, Op::ExpectList(Box::new(check)));
+        self.add(&self.start(), act);
+    }
+
+    // Plans a single act that runs a custom op on the key. An op made of
+    // several steps is planned as one custom op per step, placed with
+    // barriers.
+    pub fn custom<O>(&mut self, key: &str, op: O)
+    where
+        O: CustomOp<T> + 'static,
+    {
+        self.add(&self.start(), self.act(key, Op::Custom(Box::new(op))));
+    }
+
+    // Plans a recovery: a single act that scans the store and repairs every
+    // broken link it finds, unlike `repair()`, which only repairs the links
+    // of the keys it is given and reads them one act at a time.
+    pub fn recover(&mut self)
+    where
+        T: Clone + Size + 'static,
+    {
+        self.custom("/", Recovery);
+    }
+
+    // Brings the client's cache up to date with everything under `dir`. Like
+    // any op, it only waits for the client's acts before its last barrier, so
+    // a scenario places it with a barrier or by giving it a phase of its own.
+    pub fn sync(&mut self, dir: &str) {
+        self.add(&self.start(), self.act(dir, Op::Sync));
+    }
+
+    pub fn acquire(&mut self, dir: &str, ttl: usize) {
+        self.add(&self.start(), self.act(dir, Op::Acquire(ttl)));
+    }
+
+    pub fn update_leased<F>(&mut self, dir: &str, ttl: usize, key: &str, update: F)
+    where
+        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+    {
+        let path = Path::from(key);
+        let acquire = self.add(&self.start(), self.act(dir, Op::Acquire(ttl)));
+        let reads = self.do_reads(&path, &[acquire]);
+        let done = self.link_then_put(&path, reads, update);
+
+        self.add(&done, self.act(dir, Op::Release));
+    }
+
+    pub fn put_unlinked<F>(&mut self, key: &str, update: F)
+    where
+        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+    {
+        let get = self.act(key, Op::Get);
+        let put = self.act(key, Op::Put(Box::new(update)));
+        self.chain(&self.start(), [get, put]);
+    }
+
+    pub fn link_only(&mut self, key: &str) {
+        let path = Path::from(key);
+
+        for (dir, name, kind) in self.links(&path) {
+            let list = self.act(dir, Op::List);
+            let link = self.act(dir, Op::Link(name.to_string(), kind));
+            self.chain(&self.start(), [list, link]);
+        }
+    }
+}
+
+impl<T> Client<'_, Schema<T>>
+where
+    T: 'static,
+{
+    pub fn update_versioned<F>(&mut self, version_key: &str, version: usize, key: &str, update: F)
+    where
+        F: Fn(Option<T>) -> Option<T> + Sync + 'static,
+    {
+        let guard = self.act(version_key, Op::Guard(migration::is_version(version)));
+        self.update_guarded(key, guard, migration::write_as(version, update));
+    }
+
+    pub fn migrate<F>(&mut self, version_key: &str, version: usize, keys: &[&str], convert: F)
+    where
+        F: Fn(T) -> T + Clone + Sync + 'static,
+    {
+        let guard = self.act(version_key, Op::Guard(migration::is_version(version - 1)));
+        let guard = self.add(&self.start(), guard);
+
+        let puts: Vec<_> = keys
+            .iter()
+            .flat_map(|key| {
+                let get = self.act(*key, Op::Get);
+                let convert = migration::convert(version, convert.clone());
+                let put = self.act(*key, Op::Put(convert));
+                self.chain(&[guard], [get, put])
+            })
+            .collect();
+
+        let bump = Op::Put(migration::bump(version));
+        self.add(&puts, self.act(version_key, bump));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+
+    use crate::actor::Actor;
+    use crate::config::{Layout, Reads, Update};
+    use crate::db::{Db, DbStore};
+    use crate::graph::tests::check_graph;
+
+    #[test]
+    fn returns_the_ids_of_partitioned_clients() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+
+        planner.client("alice").update("/x", |_| Some(vec!['x']));
+        planner.client("bob").remove("/y");
+        planner.partition("bob");
+
+        let clients: Vec<_> = planner.partitioned_clients().collect();
+        assert_eq!(clients, ["bob"]);
+    }
+
+    #[test]
+    fn returns_the_ids_of_registered_clients() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+
+        planner.client("alice").update("/x", |_| Some(vec!['x']));
+        planner.client("bob").remove("/y");
+
+        let clients: Vec<_> = planner.clients().collect();
+        assert_eq!(clients, ["alice", "bob"]);
+    }
+
+    #[test]
+    fn returns_the_docs_a_plan_writes() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+
+        planner.client("alice").update("/a/x", |_| Some(vec!['x']));
+        planner.client("bob").rename("/a/y", "/b/y", |doc| doc);
+        planner.client("carol").link_only("/c/z");
+
+        let docs: Vec<_> = planner.written_docs().collect();
+        assert_eq!(docs, ["/a/x", "/b/y", "/a/y"]);
+    }
+
+    #[test]
+    fn renders_a_plan_as_dot() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+        planner.client("A").remove("/x");
+
+        assert_eq!(
+            planner.to_dot(),
+            [
+                "digraph {",
+                "    n1 [shape=box, label=\"Act<A: list('/')>\"];",
+                "    n2 [shape=box, label=\"Act<A: get('/x')>\"];",
+                "    n3 [shape=box, label=\"Act<A: rm('/x')>\"];",
+                "    n4 [shape=box, label=\"Act<A: unlink('/', 'x')>\"];",
+                "    n1 -> n3;",
+                "    n2 -> n3;",
+                "    n3 -> n4;",
+                "}\n",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn describes_each_clients_acts_and_what_they_wait_for() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+        planner.client("A").remove("/x");
+        planner.client("B").get("/x");
+        planner.happens_before("A: rm('/x')", "B").unwrap();
+
+        assert_eq!(
+            planner.describe(),
+            [
+                "A:",
+                "    n1 list('/')",
+                "    n2 get('/x')",
+                "    n3 rm('/x'), after list('/'), get('/x')",
+                "    n4 unlink('/', 'x'), after rm('/x')",
+                "B:",
+                "    n5 observe('/x'), after A: rm('/x')",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn shows_the_label_of_each_put() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+        let mut client = planner.client("A");
+        client.label("append");
+        client.update("/x", |doc| doc.map(|d| [d, vec!['a']].concat()));
+        client.transaction(|tx| tx.put_unlinked("/y", |_| Some(vec!['y'])));
+        planner.client("A").update("/z", |doc| doc);
+
+        let puts: Vec<_> = planner
+            .graph
+            .iter()
+            .filter(|act| matches!(act.op, Op::Put(_)))
+            .map(|act| format!("{:?}", act))
+            .collect();
+        assert_eq!(
+            puts,
+            [
+                "Act<A: put('/x') as 'append'>",
+                "Act<A: put('/y') as 'append'>",
+                "Act<A: put('/z')>",
+            ]
+        );
+        assert!(planner
+            .describe()
+            .contains("put('/x') as 'append', after link('/', 'x')"));
+    }
+
+    #[test]
+    fn writes_a_plan_as_json() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+        planner.client("A").remove("/x");
+
+        let json = planner.to_json();
+        let acts = match &json {
+            Json::Array(acts) => acts,
+            _ => panic!("expected an array of acts"),
+        };
+        assert_eq!(acts.len(), 4);
+        assert_eq!(
+            acts[2].to_string(),
+            r#"{"deps": [1, 2], "id": 3, "value": {"client": "A", "op": "rm('/x')", "path": "/x"}}"#
+        );
+    }
+
+    #[test]
+    fn reads_back_a_plan_it_wrote_as_json() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+        planner.client("A").update("/x/y", |_| Some(vec!['a']));
+        planner.client("B").remove("/x/y");
+
+        let text = planner.to_json().to_string();
+        let saved = Graph::from_json(&Json::parse(&text).unwrap(), SavedAct::from_json).unwrap();
+
+        assert_eq!(saved.len(), planner.len());
+        assert_eq!(saved.edges(), planner.graph.edges());
+        assert_eq!(saved.to_json(SavedAct::to_json), planner.to_json());
+
+        let (id, act, _) = saved.nodes().last().unwrap();
+        assert_eq!(
+            (id.number(), act),
+            (
+                planner.len(),
+                &SavedAct {
+                    client_id: "B".to_string(),
+                    path: "/".to_string(),
+                    op: "unlink('/', 'x/')".to_string(),
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn refuses_a_saved_act_without_its_fields() {
+        let json = Json::object(&[("client", "A".into()), ("path", "/x".into())]);
+
+        assert_eq!(
+            SavedAct::from_json(&json),
+            Err(
+                r#"expected an act with a string "op" in {"client": "A", "path": "/x"}"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn produces_instructions_to_create_a_document() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+        planner
+            .client("A")
+            .update("/path/x.json", |_| Some(vec!['a']));
+
+        let store = RefCell::new(DbStore::new(Config::new()));
+        let mut actor = Actor::new(&store, Config::new());
+
+        for act in planner.orderings().next().unwrap() {
+            actor.dispatch(act);
+        }
+
+        let s = store.into_inner();
+
+        assert_eq!(s.read("/"), Some((1, Some(Db::dir_from(&["path/"])))));
+        assert_eq!(s.read("/path/"), Some((1, Some(Db::dir_from(&["x.json"])))));
+        assert_eq!(
+            s.read("/path/x.json"),
+            Some((1, Some(Db::doc_from(vec!['a'], 6, "A"))))
+        );
+    }
+
+    #[test]
+    fn produces_instructions_to_update_a_document() {
+        let mut planner: Planner<(char, usize)> = Planner::new(Config::new());
+        planner
+            .client("A")
+            .update("/path/x.json", |_| Some(('a', 50)));
+        planner
+            .client("B")
+            .update("/path/x.json", |doc| doc.map(|(c, n)| (c, n + 3)));
+
+        let store = RefCell::new(DbStore::new(Config::new()));
+        let mut actor = Actor::new(&store, Config::new());
+
+        for act in planner.orderings().next().unwrap() {
+            actor.dispatch(act);
+        }
+
+        let s = store.into_inner();
+
+        assert_eq!(s.read("/"), Some((2, Some(Db::dir_from(&["path/"])))));
+        assert_eq!(s.read("/path/"), Some((2, Some(Db::dir_from(&["x.json"])))));
+        assert_eq!(
+            s.read("/path/x.json"),
+            Some((2, Some(Db::doc_from(('a', 53), 12, "B"))))
+        );
+    }
+
+    #[test]
+    fn produces_instructions_to_remove_a_document() {
+        let mut planner: Planner<(char, usize)> = Planner::new(Config::new());
+        planner
+            .client("A")
+            .update("/path/x.json", |_| Some(('a', 50)));
+        planner.client("B").remove("/path/x.json");
+
+        let store = RefCell::new(DbStore::new(Config::new()));
+        let mut actor = Actor::new(&store, Config::new());
+
+        for act in planner.orderings().next().unwrap() {
+            actor.dispatch(act);
+        }
+
+        let s = store.into_inner();
+
+        assert_eq!(s.read("/"), Some((2, Some(Db::dir_from(&[])))));
+        assert_eq!(s.read("/path/"), Some((2, Some(Db::dir_from(&[])))));
+        assert_eq!(s.read("/path/x.json"), Some((2, None)));
+    }
+
+    #[test]
+    fn produces_instructions_to_rename_a_document() {
+        let mut planner: Planner<(char, usize)> = Planner::new(Config::new());
+        planner
+            .client("A")
+            .update("/path/x.json", |_| Some(('a', 50)));
+        planner
+            .client("B")
+            .rename("/path/x.json", "/path/y.json", |_| Some(('a', 50)));
+
+        let store = RefCell::new(DbStore::new(Config::new()));
+        let mut actor = Actor::new(&store, Config::new());
+
+        for act in planner.orderings().next().unwrap() {
+            actor.dispatch(act);
+        }
+
+        let s = store.into_inner();
+
+        assert_eq!(s.read("/path/"), Some((3, Some(Db::dir_from(&["y.json"])))));
+        assert_eq!(s.read("/path/x.json"), Some((2, None)));
+        assert_eq!(
+            s.read("/path/y.json"),
+            Some((1, Some(Db::doc_from(('a', 50), 15, "B"))))
+        );
+    }
+
+    #[test]
+    fn plans_a_top_level_document_update() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+
+        planner.client("A").update("/x.json", |doc| doc);
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("get", Act::new("A", "/x.json".into(), Op::Get), &[]),
+                ("list", Act::new("A", "/".into(), Op::List), &[]),
+                (
+                    "link",
+                    Act::new("A", "/".into(), Op::Link("x.json".into(), Kind::Doc)),
+                    &["get", "list"],
+                ),
+                (
+                    "put",
+                    Act::new("A", "/x.json".into(), Op::Put(Box::new(|d| d))),
+                    &["link"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn plans_an_update_in_a_top_level_directory() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+
+        planner.client("A").update("/path/x.json", |doc| doc);
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("get", Act::new("A", "/path/x.json".into(), Op::Get), &[]),
+                ("list1", Act::new("A", "/".into(), Op::List), &[]),
+                ("list2", Act::new("A", "/path/".into(), Op::List), &[]),
+                (
+                    "link1",
+                    Act::new("A", "/".into(), Op::Link("path/".into(), Kind::Dir)),
+                    &["get", "list1", "list2"],
+                ),
+                (
+                    "link2",
+                    Act::new("A", "/path/".into(), Op::Link("x.json".into(), Kind::Doc)),
+                    &["get", "list1", "list2"],
+                ),
+                (
+                    "put",
+                    Act::new("A", "/path/x.json".into(), Op::Put(Box::new(|d| d))),
+                    &["link1", "link2"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn plans_a_create_whose_get_gates_its_put() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+
+        planner.client("A").create("/x.json", vec!['a']);
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("list", Act::new("A", "/".into(), Op::List), &[]),
+                ("get", Act::new("A", "/x.json".into(), Op::Get), &[]),
+                (
+                    "guard",
+                    Act::new("A", "/x.json".into(), Op::Guard(Box::new(|_| true))),
+                    &["list", "get"],
+                ),
+                (
+                    "link",
+                    Act::new("A", "/".into(), Op::Link("x.json".into(), Kind::Doc)),
+                    &["guard"],
+                ),
+                (
+                    "put",
+                    Act::new("A", "/x.json".into(), Op::Put(Box::new(|d| d))),
+                    &["link"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn plans_a_list_expectation_as_a_single_read() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+
+        planner.client("A").expect_list("/path/", &["x.json"]);
+
+        check_graph(
+            &planner.graph,
+            &[(
+                "list",
+                Act::new("A", "/path/".into(), Op::ExpectList(Box::new(|_| Ok(())))),
+                &[],
+            )],
+        );
+
+        let Some(Op::ExpectList(check)) = planner.graph.iter().map(|act| &act.op).next() else {
+            panic!("expected a list expectation");
+        };
+        assert_eq!(check(&BTreeSet::from(["x.json".to_string()])), Ok(()));
+        assert_eq!(
+            check(&BTreeSet::new()),
+            Err("listed {} in '/path/', expected {\"x.json\"}".to_string())
+        );
+    }
+
+    #[test]
+    fn plans_a_touch_as_an_update_that_only_writes_a_missing_doc() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+
+        planner.client("A").touch("/x.json");
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("list", Act::new("A", "/".into(), Op::List), &[]),
+                ("get", Act::new("A", "/x.json".into(), Op::Get), &[]),
+                (
+                    "link",
+                    Act::new("A", "/".into(), Op::Link("x.json".into(), Kind::Doc)),
+                    &["list", "get"],
+                ),
+                (
+                    "put",
+                    Act::new("A", "/x.json".into(), Op::Put(Box::new(|d| d))),
+                    &["link"],
+                ),
+            ],
+        );
+
+        let Some(Op::Put(put)) = planner.graph.iter().map(|act| &act.op).last() else {
+            panic!("expected the touch to end with a put");
+        };
+        assert_eq!(put(None), Some(vec![]));
+        assert_eq!(put(Some(vec!['a'])), None);
+    }
+
+    #[test]
+    fn plans_a_batch_of_updates_with_shared_reads() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+
+        planner.client("A").update_many(vec![
+            ("/path/x.json", Box::new(|doc| doc)),
+            ("/path/y.json", Box::new(|doc| doc)),
+        ]);
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("list1", Act::new("A", "/".into(), Op::List), &[]),
+                ("list2", Act::new("A", "/path/".into(), Op::List), &[]),
+                ("get_x", Act::new("A", "/path/x.json".into(), Op::Get), &[]),
+                ("get_y", Act::new("A", "/path/y.json".into(), Op::Get), &[]),
+                (
+                    "link1",
+                    Act::new("A", "/".into(), Op::Link("path/".into(), Kind::Dir)),
+                    &["list1", "list2", "get_x", "get_y"],
+                ),
+                (
+                    "link_x",
+                    Act::new("A", "/path/".into(), Op::Link("x.json".into(), Kind::Doc)),
+                    &["list1", "list2", "get_x", "get_y"],
+                ),
+                (
+                    "link_y",
+                    Act::new("A", "/path/".into(), Op::Link("y.json".into(), Kind::Doc)),
+                    &["list1", "list2", "get_x", "get_y"],
+                ),
+                (
+                    "put_x",
+                    Act::new("A", "/path/x.json".into(), Op::Put(Box::new(|d| d))),
+                    &["link1", "link_x"],
+                ),
+                (
+                    "put_y",
+                    Act::new("A", "/path/y.json".into(), Op::Put(Box::new(|d| d))),
+                    &["link1", "link_y"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn plans_a_batch_of_updates_with_write_back() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new().write_back(true));
+
+        planner.client("A").update_many(vec![
+            ("/path/x.json", Box::new(|doc| doc)),
+            ("/path/y.json", Box::new(|doc| doc)),
+        ]);
+
+        let reads: &[&str] = &["list1", "list2", "get_x", "get_y"];
+        check_graph(
+            &planner.graph,
+            &[
+                ("list1", Act::new("A", "/".into(), Op::List), &[]),
+                ("list2", Act::new("A", "/path/".into(), Op::List), &[]),
+                ("get_x", Act::new("A", "/path/x.json".into(), Op::Get), &[]),
+                ("get_y", Act::new("A", "/path/y.json".into(), Op::Get), &[]),
+                (
+                    "put_x",
+                    Act::new("A", "/path/x.json".into(), Op::Put(Box::new(|d| d))),
+                    reads,
+                ),
+                (
+                    "put_y",
+                    Act::new("A", "/path/y.json".into(), Op::Put(Box::new(|d| d))),
+                    reads,
+                ),
+                (
+                    "flush1",
+                    Act::new(
+                        "A",
+                        "/".into(),
+                        Op::Flush(vec![Change::Link("path/".into(), Kind::Dir)]),
+                    ),
+                    &["put_x", "put_y"],
+                ),
+                (
+                    "flush2",
+                    Act::new(
+                        "A",
+                        "/path/".into(),
+                        Op::Flush(vec![
+                            Change::Link("x.json".into(), Kind::Doc),
+                            Change::Link("y.json".into(), Kind::Doc),
+                        ]),
+                    ),
+                    &["put_x", "put_y"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn plans_a_transaction_between_a_begin_and_a_commit() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new().layout(Layout::Flat));
+
+        planner.client("A").transaction(|tx| {
+            tx.update("/x", |doc| doc);
+            tx.remove("/y");
+        });
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("begin", Act::new("A", "/".into(), Op::Begin), &[]),
+                ("get_x", Act::new("A", "/x".into(), Op::Get), &["begin"]),
+                (
+                    "put",
+                    Act::new("A", "/x".into(), Op::Put(Box::new(|d| d))),
+                    &["get_x"],
+                ),
+                ("get_y", Act::new("A", "/y".into(), Op::Get), &["begin"]),
+                ("rm", Act::new("A", "/y".into(), Op::Rm), &["get_y"]),
+                (
+                    "commit",
+                    Act::new("A", "/".into(), Op::Commit),
+                    &["begin", "get_x", "put", "get_y", "rm"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn skips_lists_of_dirs_the_client_has_listed() {
+        let config = Config::new().update(Update::SkipKnownLists);
+        let mut planner: Planner<Vec<char>> = Planner::new(config);
+
+        let mut client = planner.client("A");
+        client.update("/path/x.json", |doc| doc);
+        client.update("/path/y.json", |doc| doc);
+
+        let acts: Vec<_> = planner
+            .graph
+            .nodes()
+            .map(|(_, act, deps)| (act.to_string(), deps.to_vec()))
+            .collect();
+
+        let lists = acts.iter().filter(|(act, _)| act.starts_with("list"));
+        assert_eq!(lists.count(), 2);
+
+        let id = |n| planner.graph.node_id(n).unwrap();
+        let reads = vec![id(1), id(2), id(7)];
+        assert_eq!(
+            acts[6..],
+            [
+                ("get('/path/y.json')".into(), vec![]),
+                ("link('/', 'path/')".into(), reads.clone()),
+                ("link('/path/', 'y.json')".into(), reads),
+                ("put('/path/y.json')".into(), vec![id(8), id(9)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn plans_fewer_orderings_when_skipping_known_lists() {
+        let count = |update| {
+            let mut planner: Planner<Vec<char>> = Planner::new(Config::new().update(update));
+            let mut client = planner.client("A");
+            client.update("/path/x.json", |doc| doc);
+            client.update("/path/y.json", |doc| doc);
+            planner.client("B").update("/path/y.json", |doc| doc);
+            planner.count_orderings()
+        };
+        assert!(count(Update::SkipKnownLists) < count(Update::ReadsBeforeLinks));
+    }
+
+    #[test]
+    fn makes_ops_after_a_barrier_wait_for_the_clients_earlier_acts() {
+        let config = Config::new().layout(Layout::Flat);
+        let mut planner: Planner<Vec<char>> = Planner::new(config);
+
+        planner.client("A").update("/x", |doc| doc);
+        planner.client("B").update("/x", |doc| doc);
+        planner.client("A").barrier();
+        planner.client("A").remove("/y");
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("get_a", Act::new("A", "/x".into(), Op::Get), &[]),
+                (
+                    "put_a",
+                    Act::new("A", "/x".into(), Op::Put(Box::new(|d| d))),
+                    &["get_a"],
+                ),
+                ("get_b", Act::new("B", "/x".into(), Op::Get), &[]),
+                (
+                    "put_b",
+                    Act::new("B", "/x".into(), Op::Put(Box::new(|d| d))),
+                    &["get_b"],
+                ),
+                ("get_y", Act::new("A", "/y".into(), Op::Get), &["put_a"]),
+                ("rm", Act::new("A", "/y".into(), Op::Rm), &["get_y"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn plans_fewer_orderings_for_a_sequential_client() {
+        let count = |barrier: bool| {
+            let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+            let mut client = planner.client("A");
+            client.update("/path/x.json", |doc| doc);
+            if barrier {
+                client.barrier();
+            }
+            client.update("/path/y.json", |doc| doc);
+            planner.count_orderings()
+        };
+        assert!(count(true) < count(false));
+    }
+
+    #[test]
+    fn plans_an_update_with_write_back() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new().write_back(true));
 
         planner.client("A").update("/path/x.json", |doc| doc);
 
@@ -337,24 +2284,116 @@ mod tests {
                 ("list1", Act::new("A", "/".into(), Op::List), &[]),
                 ("list2", Act::new("A", "/path/".into(), Op::List), &[]),
                 (
-                    "link1",
-                    Act::new("A", "/".into(), Op::Link("path/".into())),
+                    "put",
+                    Act::new("A", "/path/x.json".into(), Op::Put(Box::new(|d| d))),
                     &["get", "list1", "list2"],
                 ),
                 (
-                    "link2",
-                    Act::new("A", "/path/".into(), Op::Link("x.json".into())),
-                    &["get", "list1", "list2"],
+                    "flush1",
+                    Act::new(
+                        "A",
+                        "/".into(),
+                        Op::Flush(vec![Change::Link("path/".into(), Kind::Dir)]),
+                    ),
+                    &["put"],
+                ),
+                (
+                    "flush2",
+                    Act::new(
+                        "A",
+                        "/path/".into(),
+                        Op::Flush(vec![Change::Link("x.json".into(), Kind::Doc)]),
+                    ),
+                    &["put"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn plans_a_rename_with_one_write_to_each_dir_with_write_back() {
+        let config = Config::new().write_back(true).reads(Reads::Snapshot);
+        let mut planner: Planner<Vec<char>> = Planner::new(config);
+
+        planner
+            .client("A")
+            .rename("/path/x.json", "/y.json", |doc| doc);
+
+        check_graph(
+            &planner.graph,
+            &[
+                (
+                    "read1",
+                    Act::new(
+                        "A",
+                        "/path/x.json".into(),
+                        Op::ReadMany(vec!["/".into(), "/path/".into()]),
+                    ),
+                    &[],
+                ),
+                (
+                    "read2",
+                    Act::new("A", "/y.json".into(), Op::ReadMany(vec!["/".into()])),
+                    &[],
                 ),
                 (
                     "put",
-                    Act::new("A", "/path/x.json".into(), Op::Put(Box::new(|d| d))),
-                    &["link1", "link2"],
+                    Act::new("A", "/y.json".into(), Op::Put(Box::new(|d| d))),
+                    &["read1", "read2"],
+                ),
+                ("rm", Act::new("A", "/path/x.json".into(), Op::Rm), &["put"]),
+                (
+                    "flush1",
+                    Act::new(
+                        "A",
+                        "/".into(),
+                        Op::Flush(vec![
+                            Change::Unlink("path/".into()),
+                            Change::Link("y.json".into(), Kind::Doc),
+                        ]),
+                    ),
+                    &["rm"],
+                ),
+                (
+                    "flush2",
+                    Act::new(
+                        "A",
+                        "/path/".into(),
+                        Op::Flush(vec![Change::Unlink("x.json".into())]),
+                    ),
+                    &["rm"],
                 ),
             ],
         );
     }
 
+    #[test]
+    fn plans_an_update_with_lazy_links() {
+        let config = Config::new().update(Update::LazyLinks);
+        let mut planner: Planner<Vec<char>> = Planner::new(config);
+
+        planner.client("A").update("/path/x.json", |doc| doc);
+
+        let plans: Vec<Vec<String>> = planner
+            .orderings()
+            .map(|plan| plan.iter().map(|act| act.to_string()).collect())
+            .collect();
+
+        assert_eq!(
+            plans,
+            [[
+                "get('/path/x.json')",
+                "put('/path/x.json')",
+                "list('/path/')",
+                "get('/path/x.json')",
+                "repair('/path/', 'x.json')",
+                "list('/')",
+                "list('/path/')",
+                "repair('/', 'path/')",
+            ]]
+        );
+    }
+
     #[test]
     fn plans_an_update_in_a_nested_directory() {
         let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
@@ -370,32 +2409,132 @@ mod tests {
                 ("list3", Act::new("A", "/path/to/".into(), Op::List), &[]),
                 (
                     "link1",
-                    Act::new("A", "/".into(), Op::Link("path/".into())),
+                    Act::new("A", "/".into(), Op::Link("path/".into(), Kind::Dir)),
                     &["get", "list1", "list2", "list3"],
                 ),
                 (
                     "link2",
-                    Act::new("A", "/path/".into(), Op::Link("to/".into())),
+                    Act::new("A", "/path/".into(), Op::Link("to/".into(), Kind::Dir)),
+                    &["get", "list1", "list2", "list3"],
+                ),
+                (
+                    "link3",
+                    Act::new(
+                        "A",
+                        "/path/to/".into(),
+                        Op::Link("x.json".into(), Kind::Doc),
+                    ),
                     &["get", "list1", "list2", "list3"],
                 ),
                 (
-                    "link3",
-                    Act::new("A", "/path/to/".into(), Op::Link("x.json".into())),
-                    &["get", "list1", "list2", "list3"],
+                    "put",
+                    Act::new("A", "/path/to/x.json".into(), Op::Put(Box::new(|d| d))),
+                    &["link1", "link2", "link3"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn plans_an_update_in_a_top_level_directory_with_get_before_put() {
+        let mut planner: Planner<Vec<char>> =
+            Planner::new(Config::new().update(Update::GetBeforePut));
+
+        planner.client("A").update("/path/x.json", |doc| doc);
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("get", Act::new("A", "/path/x.json".into(), Op::Get), &[]),
+                ("list1", Act::new("A", "/".into(), Op::List), &[]),
+                ("list2", Act::new("A", "/path/".into(), Op::List), &[]),
+                (
+                    "link1",
+                    Act::new("A", "/".into(), Op::Link("path/".into(), Kind::Dir)),
+                    &["list1"],
+                ),
+                (
+                    "link2",
+                    Act::new("A", "/path/".into(), Op::Link("x.json".into(), Kind::Doc)),
+                    &["list2"],
+                ),
+                (
+                    "put",
+                    Act::new("A", "/path/x.json".into(), Op::Put(Box::new(|d| d))),
+                    &["get", "link1", "link2"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn plans_an_update_with_a_flat_layout() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new().layout(Layout::Flat));
+
+        planner.client("A").update("/path/x.json", |doc| doc);
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("get", Act::new("A", "/path/x.json".into(), Op::Get), &[]),
+                (
+                    "put",
+                    Act::new("A", "/path/x.json".into(), Op::Put(Box::new(|d| d))),
+                    &["get"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn plans_a_deletion_with_a_flat_layout() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new().layout(Layout::Flat));
+
+        planner.client("A").remove("/path/to/y.json");
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("get", Act::new("A", "/path/to/y.json".into(), Op::Get), &[]),
+                (
+                    "rm",
+                    Act::new("A", "/path/to/y.json".into(), Op::Rm),
+                    &["get"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn plans_an_update_with_a_manifest_layout() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new().layout(Layout::Manifest));
+
+        planner.client("A").update("/path/x.json", |doc| doc);
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("get", Act::new("A", "/path/x.json".into(), Op::Get), &[]),
+                ("list", Act::new("A", "/".into(), Op::List), &[]),
+                (
+                    "link",
+                    Act::new("A", "/".into(), Op::Link("/path/x.json".into(), Kind::Doc)),
+                    &["get", "list"],
                 ),
                 (
                     "put",
-                    Act::new("A", "/path/to/x.json".into(), Op::Put(Box::new(|d| d))),
-                    &["link1", "link2", "link3"],
+                    Act::new("A", "/path/x.json".into(), Op::Put(Box::new(|d| d))),
+                    &["link"],
                 ),
             ],
         );
     }
 
     #[test]
-    fn plans_an_update_in_a_top_level_directory_with_get_before_put() {
-        let mut planner: Planner<Vec<char>> =
-            Planner::new(Config::new().update(Update::GetBeforePut));
+    fn plans_an_update_with_a_bucket_layout() {
+        let config = Config::new().layout(Layout::Buckets { count: 4 });
+        let mut planner: Planner<Vec<char>> = Planner::new(config);
+        let bucket = db::bucket_key(4, &"/path/x.json".into());
 
         planner.client("A").update("/path/x.json", |doc| doc);
 
@@ -403,22 +2542,20 @@ mod tests {
             &planner.graph,
             &[
                 ("get", Act::new("A", "/path/x.json".into(), Op::Get), &[]),
-                ("list1", Act::new("A", "/".into(), Op::List), &[]),
-                ("list2", Act::new("A", "/path/".into(), Op::List), &[]),
-                (
-                    "link1",
-                    Act::new("A", "/".into(), Op::Link("path/".into())),
-                    &["list1"],
-                ),
+                ("list", Act::new("A", bucket.into(), Op::List), &[]),
                 (
-                    "link2",
-                    Act::new("A", "/path/".into(), Op::Link("x.json".into())),
-                    &["list2"],
+                    "link",
+                    Act::new(
+                        "A",
+                        bucket.into(),
+                        Op::Link("/path/x.json".into(), Kind::Doc),
+                    ),
+                    &["get", "list"],
                 ),
                 (
                     "put",
                     Act::new("A", "/path/x.json".into(), Op::Put(Box::new(|d| d))),
-                    &["get", "link1", "link2"],
+                    &["link"],
                 ),
             ],
         );
@@ -449,6 +2586,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn plans_a_deletion_without_unlinks() {
+        let config = Config::new().remove(Remove::NoUnlink);
+        let mut planner: Planner<Vec<char>> = Planner::new(config);
+
+        planner.client("A").remove("/y.json");
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("get", Act::new("A", "/y.json".into(), Op::Get), &[]),
+                ("list", Act::new("A", "/".into(), Op::List), &[]),
+                (
+                    "rm",
+                    Act::new("A", "/y.json".into(), Op::Rm),
+                    &["get", "list"],
+                ),
+            ],
+        );
+    }
+
     #[test]
     fn plans_a_deletion_in_a_nested_directory() {
         let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
@@ -523,4 +2681,611 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn plans_an_update_with_snapshot_reads() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new().reads(Reads::Snapshot));
+
+        planner.client("A").update("/path/x.json", |doc| doc);
+
+        check_graph(
+            &planner.graph,
+            &[
+                (
+                    "read",
+                    Act::new(
+                        "A",
+                        "/path/x.json".into(),
+                        Op::ReadMany(vec!["/".into(), "/path/".into()]),
+                    ),
+                    &[],
+                ),
+                (
+                    "link1",
+                    Act::new("A", "/".into(), Op::Link("path/".into(), Kind::Dir)),
+                    &["read"],
+                ),
+                (
+                    "link2",
+                    Act::new("A", "/path/".into(), Op::Link("x.json".into(), Kind::Doc)),
+                    &["read"],
+                ),
+                (
+                    "put",
+                    Act::new("A", "/path/x.json".into(), Op::Put(Box::new(|d| d))),
+                    &["link1", "link2"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn does_not_commute_a_snapshot_read_with_a_write_to_a_dir_it_lists() {
+        let read = Act::<Vec<char>>::new(
+            "A",
+            "/path/x.json".into(),
+            Op::ReadMany(vec!["/".into(), "/path/".into()]),
+        );
+        let link = Act::new("B", "/path/".into(), Op::Link("y.json".into(), Kind::Doc));
+        let put = Act::new("B", "/path/y.json".into(), Op::Put(Box::new(|d| d)));
+
+        assert!(!read.commutes_with(&link));
+        assert!(!link.commutes_with(&read));
+        assert!(read.commutes_with(&put));
+        assert!(put.commutes_with(&read));
+    }
+
+    struct Probe(bool);
+
+    impl CustomOp<Vec<char>> for Probe {
+        fn name(&self) -> &str {
+            "probe"
+        }
+
+        fn may_write(&self) -> bool {
+            self.0
+        }
+
+        fn execute(&self, _: &mut Actor<'_, Vec<char>>, _: &Path) {}
+    }
+
+    #[test]
+    fn commutes_custom_ops_only_with_acts_that_do_not_write() {
+        let write = Act::<Vec<char>>::new("A", "/x".into(), Op::Custom(Box::new(Probe(true))));
+        let read = Act::new("A", "/x".into(), Op::Custom(Box::new(Probe(false))));
+        let get = Act::new("B", "/y".into(), Op::Get);
+        let put = Act::new("B", "/y".into(), Op::Put(Box::new(|d| d)));
+
+        assert_eq!(write.to_string(), "probe('/x')");
+        assert!(!write.commutes_with(&get));
+        assert!(!get.commutes_with(&write));
+        assert!(read.commutes_with(&get));
+        assert!(!read.commutes_with(&put));
+        assert!(!put.commutes_with(&read));
+    }
+
+    #[test]
+    fn plans_a_recovery_as_a_single_act() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+        planner.client("R").recover();
+
+        let acts: Vec<_> = planner.graph.iter().map(|act| act.to_string()).collect();
+        assert_eq!(acts, ["recover('/')"]);
+    }
+
+    #[test]
+    fn plans_a_repair_of_a_top_level_document() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+
+        planner.client("R").repair(&["/x.json"]);
+
+        check_graph(
+            &planner.graph,
+            &[
+                ("list", Act::new("R", "/".into(), Op::List), &[]),
+                ("get", Act::new("R", "/x.json".into(), Op::Get), &["list"]),
+                (
+                    "repair",
+                    Act::new("R", "/".into(), Op::Repair("x.json".into(), Kind::Doc)),
+                    &["get"],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn plans_a_repair_as_a_single_sequence_from_the_deepest_link() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+
+        planner.client("R").repair(&["/path/x.json", "/y.json"]);
+
+        let orderings: Vec<_> = planner.orderings().collect();
+        assert_eq!(orderings.len(), 1);
+        assert_eq!(
+            format!("{:?}", orderings[0]),
+            "[Act<R: list('/path/')>, Act<R: get('/path/x.json')>, \
+             Act<R: repair('/path/', 'x.json')>, Act<R: list('/')>, \
+             Act<R: list('/path/')>, Act<R: repair('/', 'path/')>, \
+             Act<R: list('/')>, Act<R: get('/y.json')>, Act<R: repair('/', 'y.json')>]"
+        );
+    }
+
+    #[test]
+    fn plans_a_leased_update() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+
+        planner
+            .client("A")
+            .update_leased("/path/", 4, "/path/x.json", |doc| doc);
+
+        check_graph(
+            &planner.graph,
+            &[
+                (
+                    "acquire",
+                    Act::new("A", "/path/".into(), Op::Acquire(4)),
+                    &[],
+                ),
+                (
+                    "get",
+                    Act::new("A", "/path/x.json".into(), Op::Get),
+                    &["acquire"],
+                ),
+                ("list1", Act::new("A", "/".into(), Op::List), &["acquire"]),
+                (
+                    "list2",
+                    Act::new("A", "/path/".into(), Op::List),
+                    &["acquire"],
+                ),
+                (
+                    "link1",
+                    Act::new("A", "/".into(), Op::Link("path/".into(), Kind::Dir)),
+                    &["get", "list1", "list2"],
+                ),
+                (
+                    "link2",
+                    Act::new("A", "/path/".into(), Op::Link("x.json".into(), Kind::Doc)),
+                    &["get", "list1", "list2"],
+                ),
+                (
+                    "put",
+                    Act::new("A", "/path/x.json".into(), Op::Put(Box::new(|d| d))),
+                    &["link1", "link2"],
+                ),
+                (
+                    "release",
+                    Act::new("A", "/path/".into(), Op::Release),
+                    &["put"],
+                ),
+            ],
+        );
+    }
+
+    fn final_states(planner: &Planner<(char, usize)>) -> BTreeSet<String> {
+        planner
+            .orderings()
+            .map(|plan| {
+                let store = RefCell::new(DbStore::new(Config::new()));
+                let mut actors: BTreeMap<_, _> = planner
+                    .clients()
+                    .map(|id| (id, Actor::new(&store, Config::new())))
+                    .collect();
+
+                for act in plan {
+                    actors
+                        .get_mut(act.client_id.as_str())
+                        .unwrap()
+                        .dispatch(act);
+                }
+                drop(actors);
+
+                let s = store.into_inner();
+                let state: Vec<_> = s.keys().map(|k| format!("{:?}", s.read(k))).collect();
+                state.join(", ")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn starts_symmetric_clients_in_a_fixed_order() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.client("B").update("/x", |_| Some(('b', 1)));
+        let all = planner.orderings().count();
+
+        planner.symmetric(&["B", "A"]);
+        let plans: Vec<_> = planner.orderings().collect();
+
+        assert_eq!(plans.len() * 2, all);
+
+        for plan in plans {
+            let a = plan.iter().position(|act| act.client_id == "A");
+            let b = plan.iter().position(|act| act.client_id == "B");
+            assert!(a < b);
+        }
+    }
+
+    #[test]
+    fn reduces_orderings_without_losing_final_states() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.client("B").remove("/x");
+
+        let all = planner.orderings().count();
+        let states = final_states(&planner);
+
+        planner.reduce();
+
+        assert!(planner.orderings().count() < all);
+        assert_eq!(final_states(&planner), states);
+    }
+
+    #[test]
+    fn explores_only_the_orderings_that_keep_named_acts_in_order() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.client("B").remove("/x");
+        let all: BTreeSet<_> = planner.orderings().map(|p| format!("{:?}", p)).collect();
+
+        planner.order("A: link('/', 'x')", "B: unlink('/', 'x')");
+        planner.order("B: unlink('/', 'x')", "A: put('/x')");
+        let plans: Vec<_> = planner.orderings().collect();
+
+        assert!(!plans.is_empty());
+        assert!(plans.len() < all.len());
+
+        for plan in plans {
+            assert!(all.contains(&format!("{:?}", plan)));
+
+            let names: Vec<_> = plan.iter().map(|act| act.to_string()).collect();
+            let find = |name: &str| names.iter().position(|n| n == name);
+            assert!(find("link('/', 'x')") < find("unlink('/', 'x')"));
+            assert!(find("unlink('/', 'x')") < find("put('/x')"));
+        }
+    }
+
+    #[test]
+    fn explores_only_the_orderings_that_keep_named_acts_adjacent() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.client("B").update("/x", |_| Some(('b', 1)));
+        planner.symmetric(&["A", "B"]);
+        planner.reduce();
+
+        planner.adjacent("B: get('/x')", "A: put('/x')");
+        let plans: Vec<_> = planner.orderings().collect();
+
+        assert!(!plans.is_empty());
+
+        for plan in plans {
+            let i = plan
+                .iter()
+                .position(|act| act.client_id == "A" && act.to_string() == "put('/x')");
+            let prev = &plan[i.unwrap() - 1];
+            assert_eq!(format!("{:?}", prev), "Act<B: get('/x')>");
+        }
+    }
+
+    #[test]
+    fn starts_a_client_only_after_the_acts_it_waits_for() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.client("B").update("/x", |_| Some(('b', 1)));
+        planner.symmetric(&["A", "B"]);
+        planner.reduce();
+        let all = planner.count_orderings();
+
+        planner.happens_before("A: put('/x')", "B").unwrap();
+        let plans: Vec<_> = planner.orderings().collect();
+
+        assert!(!plans.is_empty());
+        assert_eq!(plans.len() as u128, planner.count_orderings());
+        assert!(planner.count_orderings() < all);
+
+        for plan in plans {
+            let put = plan
+                .iter()
+                .position(|act| act.client_id == "A" && act.to_string() == "put('/x')");
+            let first_b = plan.iter().position(|act| act.client_id == "B");
+            assert!(put < first_b);
+        }
+    }
+
+    #[test]
+    fn orders_a_client_after_acts_planned_later() {
+        let config = Config::new().update(Update::SkipKnownLists);
+        let mut planner = Planner::new(config.clone());
+        planner.client("B").get("/x");
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.order("A: list('/')", "A: get('/x')");
+
+        planner.happens_before("A: put('/x')", "B").unwrap();
+        planner.client("B").update("/x", |_| Some(('b', 1)));
+        let plans: Vec<_> = planner.orderings().collect();
+
+        let mut forward = Planner::new(config);
+        forward.client("A").update("/x", |_| Some(('a', 1)));
+        forward.order("A: list('/')", "A: get('/x')");
+        forward.client("B").get("/x");
+        forward.happens_before("A: put('/x')", "B").unwrap();
+        forward.client("B").update("/x", |_| Some(('b', 1)));
+
+        assert!(!plans.is_empty());
+        assert_eq!(plans.len(), forward.orderings().count());
+
+        for plan in plans {
+            let acts: Vec<_> = plan.iter().map(|act| format!("{:?}", act)).collect();
+            let at = |name: &str| acts.iter().position(|act| act == name).unwrap();
+
+            assert!(at("Act<A: put('/x')>") < at("Act<B: observe('/x')>"));
+            assert!(at("Act<A: list('/')>") < at("Act<A: get('/x')>"));
+            assert!(at("Act<B: list('/')>") < at("Act<B: put('/x')>"));
+        }
+    }
+
+    #[test]
+    fn refuses_to_make_an_act_wait_for_one_that_waits_for_it() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        let edges = planner.edges();
+
+        assert!(planner
+            .happens_before("A: put('/x')", "A: get('/x')")
+            .is_err());
+        assert_eq!(planner.edges(), edges);
+    }
+
+    #[test]
+    fn cuts_a_clients_plan_short_after_its_nth_act() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.client("B").get("/x");
+        planner.happens_before("A: put('/x')", "B").unwrap();
+
+        planner.crash_after("A", 2);
+
+        let acts: Vec<_> = planner
+            .graph()
+            .iter()
+            .map(|act| format!("{:?}", act))
+            .collect();
+        assert_eq!(acts, ["Act<A: list('/')>", "Act<A: get('/x')>"]);
+        assert_eq!(planner.crashed_clients().collect::<Vec<_>>(), ["A"]);
+
+        planner.client("A").update("/y", |_| Some(('a', 1)));
+        assert!(planner.orderings().next().is_some());
+    }
+
+    #[test]
+    fn sizes_up_a_plan() {
+        let mut planner: Planner<Vec<char>> = Planner::new(Config::new());
+        planner.client("A").update("/path/x.json", |doc| doc);
+        planner.client("B").remove("/path/x.json");
+
+        let stats = planner.stats();
+
+        assert_eq!(
+            stats.acts,
+            BTreeMap::from([("A".into(), 6), ("B".into(), 6)])
+        );
+        assert_eq!(stats.total_acts(), planner.len());
+        assert_eq!(stats.depth, 4);
+        assert_eq!(stats.width, 6);
+        assert_eq!(stats.orderings, planner.count_orderings());
+    }
+
+    fn deleter(config: Config, id: &str) -> Planner<(char, i32)> {
+        let mut planner = Planner::new(config);
+        planner.client(id).remove("/x");
+        planner.client(id).remove("/y");
+        planner.order(&format!("{}: rm('/y')", id), &format!("{}: rm('/x')", id));
+        planner
+    }
+
+    #[test]
+    fn merges_a_fragment_as_if_it_were_planned_in_place() {
+        let mut merged = Planner::new(Config::new());
+        merged.client("A").update("/x", |_| Some(('a', 1)));
+        merged.merge(deleter(merged.config().clone(), "D"));
+
+        let mut direct = Planner::new(Config::new());
+        direct.client("A").update("/x", |_| Some(('a', 1)));
+        direct.client("D").remove("/x");
+        direct.client("D").remove("/y");
+        direct.order("D: rm('/y')", "D: rm('/x')");
+
+        assert_eq!(merged.describe(), direct.describe());
+        assert_eq!(merged.fingerprint(), direct.fingerprint());
+
+        let plans: Vec<_> = merged.orderings().map(|p| format!("{:?}", p)).collect();
+        let expected: Vec<_> = direct.orderings().map(|p| format!("{:?}", p)).collect();
+        assert_eq!(plans, expected);
+    }
+
+    #[test]
+    fn renames_a_fragments_client_before_merging_it() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.merge(deleter(Config::new(), "A"));
+
+        let mut fragment = deleter(Config::new(), "A");
+        fragment.rename_client("A", "B");
+        fragment.symmetric(&["B"]);
+        planner.merge(fragment);
+
+        let clients: Vec<_> = planner.clients().collect();
+        assert_eq!(clients, ["A", "B"]);
+        let acts = planner.stats().acts;
+        assert_eq!((acts["A"], acts["B"]), (12, 8));
+        assert_eq!(planner.symmetric, ["B"]);
+
+        planner.client("B").barrier();
+        planner.client("B").update("/z", |_| Some(('b', 1)));
+        assert!(planner.orderings().next().is_some());
+    }
+
+    #[test]
+    fn tries_conflicting_acts_first_without_changing_the_orderings() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.client("B").update("/x", |_| Some(('b', 1)));
+
+        let all: BTreeSet<_> = planner.orderings().map(|p| format!("{:?}", p)).collect();
+
+        let first = planner.orderings().next().unwrap();
+        assert!(first
+            .windows(2)
+            .all(|pair| !pair[0].conflicts_with(pair[1])));
+
+        planner.conflicts_first();
+
+        let first = planner.orderings().next().unwrap();
+        assert!(first.windows(2).any(|pair| pair[0].conflicts_with(pair[1])));
+
+        let ranked: BTreeSet<_> = planner.orderings().map(|p| format!("{:?}", p)).collect();
+        assert_eq!(ranked, all);
+    }
+
+    #[test]
+    fn lists_orderings_by_act_id() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.client("B").remove("/x");
+
+        let ids: Vec<Vec<NodeId>> = planner.ordering_ids().collect();
+        let orderings: Vec<_> = planner.orderings().map(|p| format!("{:?}", p)).collect();
+
+        let resolved = std::thread::scope(|scope| {
+            let planner = &planner;
+            let resolve = move || {
+                let acts = ids.iter().map(|ids| ids.iter().map(|id| planner.act(*id)));
+                acts.map(|acts| format!("{:?}", acts.collect::<Vec<_>>()))
+                    .collect::<Vec<_>>()
+            };
+            scope.spawn(resolve).join().unwrap()
+        });
+        assert_eq!(resolved, orderings);
+    }
+
+    #[test]
+    fn tries_orderings_that_switch_clients_first() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.client("B").update("/y", |_| Some(('b', 1)));
+
+        let all: BTreeSet<_> = planner.orderings().map(|p| format!("{:?}", p)).collect();
+
+        let first = planner.orderings().next().unwrap();
+        assert_eq!(planner.preemptions(&first), 0);
+
+        planner.alternate_first();
+
+        let first = planner.orderings().next().unwrap();
+        assert!(first
+            .windows(2)
+            .all(|pair| pair[0].client_id != pair[1].client_id));
+
+        let ranked: BTreeSet<_> = planner.orderings().map(|p| format!("{:?}", p)).collect();
+        assert_eq!(ranked, all);
+    }
+
+    #[test]
+    fn counts_switches_away_from_unfinished_clients() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.client("B").update("/y", |_| Some(('b', 1)));
+
+        let counts: BTreeSet<_> = planner
+            .orderings()
+            .map(|plan| planner.preemptions(&plan))
+            .collect();
+
+        assert_eq!(counts, (0..=6).collect());
+
+        for plan in planner.orderings() {
+            let switches = plan
+                .windows(2)
+                .filter(|pair| pair[0].client_id != pair[1].client_id)
+                .count();
+            assert_eq!(planner.preemptions(&plan), switches.saturating_sub(1));
+        }
+    }
+
+    #[test]
+    fn bounds_the_preemptions_in_each_ordering() {
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.client("B").update("/y", |_| Some(('b', 1)));
+
+        let exceeded = AtomicBool::new(false);
+        let plans: Vec<_> = planner.orderings_within(0, &exceeded).collect();
+
+        assert_eq!(plans.len(), 8);
+        assert!(plans.iter().all(|plan| planner.preemptions(plan) == 0));
+        assert!(exceeded.load(Ordering::Relaxed));
+
+        let exceeded = AtomicBool::new(false);
+        let all = planner.orderings().count();
+
+        assert_eq!(planner.orderings_within(10, &exceeded).count(), all);
+        assert!(!exceeded.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn fingerprints_plans_by_their_acts_and_options() {
+        let plan = |value: i32| {
+            let mut planner = Planner::new(Config::new());
+            planner
+                .client("A")
+                .update("/x", move |_| Some(('a', value)));
+            planner
+                .client("B")
+                .update("/x", move |_| Some(('b', value)));
+            planner
+        };
+        assert_eq!(plan(1).fingerprint(), plan(2).fingerprint());
+
+        let mut reduced = plan(1);
+        reduced.reduce();
+        assert_ne!(reduced.fingerprint(), plan(1).fingerprint());
+
+        let mut other = Planner::new(Config::new());
+        other.client("A").update("/x", |_| Some(('a', 1)));
+        other.client("B").remove("/x");
+        assert_ne!(other.fingerprint(), plan(1).fingerprint());
+    }
+
+    #[test]
+    fn reuses_cached_orderings() {
+        let dir = std::env::temp_dir().join(format!("mc2-planner-{}", std::process::id()));
+        let cache = OrderingCache::open(dir.to_str().unwrap()).unwrap();
+
+        let mut planner = Planner::new(Config::new());
+        planner.client("A").update("/path/x", |_| Some(('a', 1)));
+        planner.client("B").update("/path/x", |_| Some(('b', 1)));
+        planner.conflicts_first();
+
+        let orderings: Vec<_> = planner.orderings().collect();
+
+        for _ in 0..2 {
+            let cached: Vec<_> = planner.orderings_cached(&cache).collect();
+            assert_eq!(cached, orderings);
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn does_not_reduce_leased_orderings() {
+        let mut planner: Planner<(char, usize)> = Planner::new(Config::new());
+        planner
+            .client("A")
+            .update_leased("/", 10, "/x", |_| Some(('a', 1)));
+        planner.client("B").update("/y", |_| Some(('b', 1)));
+        let all = planner.orderings().count();
+
+        planner.reduce();
+        assert_eq!(planner.orderings().count(), all);
+    }
 }
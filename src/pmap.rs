@@ -0,0 +1,367 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+// An ordered map whose clones share structure. Cloning is O(1), and a write
+// copies only the nodes on the path from the root to the key it touches, and
+// only those still shared with another clone. Keys are never removed, since
+// the store keeps a record for every key it has seen.
+pub struct PMap<K, V> {
+    root: Link<K, V>,
+    len: usize,
+}
+
+type Link<K, V> = Option<Arc<Node<K, V>>>;
+
+#[derive(Clone)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    height: usize,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+impl<K, V> Clone for PMap<K, V> {
+    fn clone(&self) -> PMap<K, V> {
+        PMap {
+            root: self.root.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<K, V> Default for PMap<K, V> {
+    fn default() -> PMap<K, V> {
+        PMap::new()
+    }
+}
+
+impl<K, V> PMap<K, V> {
+    pub fn new() -> PMap<K, V> {
+        PMap { root: None, len: 0 }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut link = &self.root;
+
+        while let Some(node) = link {
+            match key.cmp(node.key.borrow()) {
+                Ordering::Less => link = &node.left,
+                Ordering::Greater => link = &node.right,
+                Ordering::Equal => return Some(&node.value),
+            }
+        }
+        None
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_left(&self.root);
+        iter
+    }
+
+    // Iterates over the entries whose keys are not less than `start`.
+    pub fn iter_from<Q>(&self, start: &Q) -> Iter<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut iter = Iter { stack: Vec::new() };
+        let mut link = &self.root;
+
+        while let Some(node) = link {
+            if start.cmp(node.key.borrow()) == Ordering::Greater {
+                link = &node.right;
+            } else {
+                iter.stack.push(node);
+                link = &node.left;
+            }
+        }
+        iter
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(key, _)| key)
+    }
+}
+
+impl<K, V> PMap<K, V>
+where
+    K: Clone + Ord,
+    V: Clone,
+{
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        get_mut(&mut self.root, key)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if insert(&mut self.root, key, value) {
+            self.len += 1;
+        }
+    }
+
+    pub fn get_or_insert_with<F>(&mut self, key: K, default: F) -> &mut V
+    where
+        F: FnOnce() -> V,
+    {
+        if self.get(&key).is_none() {
+            self.insert(key.clone(), default());
+        }
+        self.get_mut(&key).unwrap()
+    }
+}
+
+fn get_mut<'a, K, V, Q>(link: &'a mut Link<K, V>, key: &Q) -> Option<&'a mut V>
+where
+    K: Borrow<Q> + Clone,
+    V: Clone,
+    Q: Ord + ?Sized,
+{
+    let node = Arc::make_mut(link.as_mut()?);
+
+    match key.cmp(node.key.borrow()) {
+        Ordering::Less => get_mut(&mut node.left, key),
+        Ordering::Greater => get_mut(&mut node.right, key),
+        Ordering::Equal => Some(&mut node.value),
+    }
+}
+
+// Returns true if the key was not already present, in which case the tree
+// may need rebalancing on the way back up.
+fn insert<K, V>(link: &mut Link<K, V>, key: K, value: V) -> bool
+where
+    K: Clone + Ord,
+    V: Clone,
+{
+    let node = match link {
+        Some(node) => Arc::make_mut(node),
+        None => {
+            *link = Some(Arc::new(Node {
+                key,
+                value,
+                height: 1,
+                left: None,
+                right: None,
+            }));
+            return true;
+        }
+    };
+
+    let added = match key.cmp(&node.key) {
+        Ordering::Less => insert(&mut node.left, key, value),
+        Ordering::Greater => insert(&mut node.right, key, value),
+        Ordering::Equal => {
+            node.value = value;
+            false
+        }
+    };
+    if added {
+        rebalance(link);
+    }
+    added
+}
+
+fn height<K, V>(link: &Link<K, V>) -> usize {
+    link.as_ref().map_or(0, |node| node.height)
+}
+
+impl<K, V> Node<K, V> {
+    fn update_height(&mut self) {
+        self.height = 1 + height(&self.left).max(height(&self.right));
+    }
+
+    fn balance(&self) -> isize {
+        height(&self.left) as isize - height(&self.right) as isize
+    }
+}
+
+fn rebalance<K, V>(link: &mut Link<K, V>)
+where
+    K: Clone,
+    V: Clone,
+{
+    let node = Arc::make_mut(link.as_mut().unwrap());
+    node.update_height();
+
+    let balance = node.balance();
+
+    if balance > 1 {
+        if node.left.as_ref().is_some_and(|left| left.balance() < 0) {
+            rotate_left(&mut node.left);
+        }
+        rotate_right(link);
+    } else if balance < -1 {
+        if node.right.as_ref().is_some_and(|right| right.balance() > 0) {
+            rotate_right(&mut node.right);
+        }
+        rotate_left(link);
+    }
+}
+
+fn rotate_right<K, V>(link: &mut Link<K, V>)
+where
+    K: Clone,
+    V: Clone,
+{
+    let mut root = link.take().unwrap();
+    let node = Arc::make_mut(&mut root);
+    let mut pivot = node.left.take().unwrap();
+    let top = Arc::make_mut(&mut pivot);
+
+    node.left = top.right.take();
+    node.update_height();
+    top.right = Some(root);
+    top.update_height();
+
+    *link = Some(pivot);
+}
+
+fn rotate_left<K, V>(link: &mut Link<K, V>)
+where
+    K: Clone,
+    V: Clone,
+{
+    let mut root = link.take().unwrap();
+    let node = Arc::make_mut(&mut root);
+    let mut pivot = node.right.take().unwrap();
+    let top = Arc::make_mut(&mut pivot);
+
+    node.right = top.left.take();
+    node.update_height();
+    top.left = Some(root);
+    top.update_height();
+
+    *link = Some(pivot);
+}
+
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left(&mut self, mut link: &'a Link<K, V>) {
+        while let Some(node) = link {
+            self.stack.push(node);
+            link = &node.left;
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        let node = self.stack.pop()?;
+        self.push_left(&node.right);
+        Some((&node.key, &node.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_map(keys: &[i32]) -> PMap<i32, char> {
+        let mut map = PMap::new();
+        for key in keys {
+            map.insert(*key, 'a');
+        }
+        map
+    }
+
+    fn check_balanced<K, V>(link: &Link<K, V>) -> usize {
+        match link {
+            None => 0,
+            Some(node) => {
+                let left = check_balanced(&node.left);
+                let right = check_balanced(&node.right);
+                assert!(left.abs_diff(right) <= 1);
+                assert_eq!(node.height, 1 + left.max(right));
+                node.height
+            }
+        }
+    }
+
+    #[test]
+    fn iterates_over_keys_in_order() {
+        let map = make_map(&[5, 3, 8, 1, 4, 9, 2, 7, 6]);
+
+        assert_eq!(map.len(), 9);
+        assert_eq!(
+            map.keys().copied().collect::<Vec<_>>(),
+            (1..=9).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn stays_balanced_under_sequential_inserts() {
+        let map = make_map(&(0..1000).collect::<Vec<_>>());
+
+        assert!(check_balanced(&map.root) <= 15);
+    }
+
+    #[test]
+    fn replaces_the_value_of_an_existing_key() {
+        let mut map = make_map(&[1, 2, 3]);
+        map.insert(2, 'b');
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&2), Some(&'b'));
+    }
+
+    #[test]
+    fn iterates_from_a_start_key() {
+        let map = make_map(&[10, 20, 30, 40]);
+
+        let keys: Vec<_> = map.iter_from(&25).map(|(key, _)| *key).collect();
+        assert_eq!(keys, [30, 40]);
+
+        let keys: Vec<_> = map.iter_from(&20).map(|(key, _)| *key).collect();
+        assert_eq!(keys, [20, 30, 40]);
+    }
+
+    #[test]
+    fn does_not_change_a_clone_when_written() {
+        let mut map = make_map(&[1, 2, 3]);
+        let snapshot = map.clone();
+
+        map.insert(4, 'b');
+        *map.get_mut(&2).unwrap() = 'c';
+        *map.get_or_insert_with(5, || 'd') = 'e';
+
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(snapshot.get(&2), Some(&'a'));
+        assert_eq!(snapshot.get(&4), None);
+
+        assert_eq!(map.get(&2), Some(&'c'));
+        assert_eq!(map.get(&5), Some(&'e'));
+    }
+
+    #[test]
+    fn shares_untouched_nodes_with_a_clone() {
+        let mut map = make_map(&[1, 2, 3]);
+        let snapshot = map.clone();
+
+        *map.get_mut(&1).unwrap() = 'b';
+
+        let left = |m: &PMap<i32, char>| m.root.as_ref().unwrap().left.clone().unwrap();
+        let right = |m: &PMap<i32, char>| m.root.as_ref().unwrap().right.clone().unwrap();
+
+        assert!(!Arc::ptr_eq(&left(&map), &left(&snapshot)));
+        assert!(Arc::ptr_eq(&right(&map), &right(&snapshot)));
+    }
+}
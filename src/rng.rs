@@ -0,0 +1,65 @@
+// A small seeded generator (SplitMix64) for sampling orderings. The same seed
+// always gives the same sequence, which is all sampling needs; it is not fit
+// for anything that has to be unpredictable.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A number in 0..n, for n > 0.
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+
+    // A number in 0..n, for n > 0, for limits too wide for `below`. It is
+    // taken as a remainder, which favours low numbers only by a margin that
+    // is negligible unless n is near 2^128.
+    pub fn below_u128(&mut self, n: u128) -> u128 {
+        let wide = (self.next_u64() as u128) << 64 | self.next_u64() as u128;
+        wide % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_a_sequence_for_the_same_seed() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let mut c = Rng::new(43);
+
+        let xs: Vec<_> = (0..8).map(|_| a.next_u64()).collect();
+        let ys: Vec<_> = (0..8).map(|_| b.next_u64()).collect();
+        let zs: Vec<_> = (0..8).map(|_| c.next_u64()).collect();
+
+        assert_eq!(xs, ys);
+        assert_ne!(xs, zs);
+    }
+
+    #[test]
+    fn picks_every_number_below_a_limit() {
+        let mut rng = Rng::new(1);
+        let mut seen = [0; 5];
+
+        for _ in 0..1000 {
+            seen[rng.below(5)] += 1;
+        }
+        assert!(seen.iter().all(|n| *n > 100));
+    }
+}
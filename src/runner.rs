@@ -1,371 +1,4314 @@
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::fmt::Debug;
-use std::sync::{mpsc, Mutex};
-use std::thread;
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Debug, Write};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-use crate::actor::Actor;
-use crate::config::Config;
-use crate::db::{Checker, Db, DbStore};
-use crate::planner::{Act, Client, Planner};
+use rayon::prelude::*;
+
+use crate::actor::{Actor, Trace};
+#[cfg(feature = "archive")]
+use crate::archive::Archive;
+use crate::cache::OrderingCache;
+use crate::clock::Time;
+use crate::config::{Config, Field, Remove};
+use crate::db::{self, Checker, Db, DbStore, Violation};
+use crate::graph::{Counts, NodeId};
+use crate::path::Path;
+use crate::planner::{Act, Client, Op, PlanStats, Planner};
+use crate::rng::Rng;
+use crate::store::{Rev, Size};
 
 const SPLIT: &str = "========================================================================";
 
+type InitFn<T> = Box<dyn Fn(Client<T>)>;
+type PlanFn<T> = Box<dyn Fn(&mut Planner<T>)>;
+type ExpectFn<T> = Box<dyn Fn(&Acks, Option<&T>) -> Result<(), String> + Sync>;
+type StepFn<T> = Box<dyn Fn(&DbStore<T>, &DbStore<T>) -> Result<(), String> + Sync>;
+type EndFn<T> = Box<dyn Fn(&DbStore<T>) -> Result<(), String> + Sync>;
+type ObserveFn<T> = Box<dyn Fn(&str, Option<&T>, &DbStore<T>) -> Result<(), String> + Sync>;
+
+pub enum Fixture<T> {
+    Plan(InitFn<T>),
+    Docs(Vec<(Path, T)>),
+}
+
+pub trait IntoFixture<T> {
+    fn into_fixture(self) -> Fixture<T>;
+}
+
+impl<T, F> IntoFixture<T> for F
+where
+    F: Fn(Client<T>) + 'static,
+{
+    fn into_fixture(self) -> Fixture<T> {
+        Fixture::Plan(Box::new(self))
+    }
+}
+
+impl<T: Clone> IntoFixture<T> for &[(&str, T)] {
+    fn into_fixture(self) -> Fixture<T> {
+        let docs = self
+            .iter()
+            .map(|(key, value)| (Path::from(*key), value.clone()))
+            .collect();
+
+        Fixture::Docs(docs)
+    }
+}
+
+impl<T: Clone, const N: usize> IntoFixture<T> for &[(&str, T); N] {
+    fn into_fixture(self) -> Fixture<T> {
+        self.as_slice().into_fixture()
+    }
+}
+
 struct Scenario<T> {
     name: String,
-    init: Box<dyn Fn(Client<T>)>,
-    plan: Box<dyn Fn(&mut Planner<T>)>,
+    description: Option<String>,
+    init: Fixture<T>,
+    phases: Vec<PlanFn<T>>,
+    expects: Vec<(Path, ExpectFn<T>)>,
+    step_checks: Vec<StepFn<T>>,
+    observe_checks: Vec<ObserveFn<T>>,
+    end_checks: Vec<EndFn<T>>,
+}
+
+#[derive(Debug)]
+pub struct Acks {
+    clients: BTreeSet<String>,
+}
+
+impl Acks {
+    pub fn acked(&self, client_id: &str) -> bool {
+        self.clients.contains(client_id)
+    }
+}
+
+// Whether a doc's value is one a scenario expects. Most types accept only an
+// equal value, as the default method does, but a set-like doc may ignore the
+// order of its items, or a merged doc tolerate what the merge leaves behind.
+pub trait Accept: PartialEq {
+    fn accepts(&self, actual: &Self) -> bool {
+        self == actual
+    }
+}
+
+macro_rules! impl_accept_by_eq {
+    ($($t:ty),*) => {
+        $(
+            impl Accept for $t {}
+        )*
+    };
+}
+
+impl_accept_by_eq!((), bool, char, u8, i32, i64, usize, String);
+
+impl<T: Accept> Accept for Vec<T> {
+    fn accepts(&self, actual: &Vec<T>) -> bool {
+        self.len() == actual.len() && self.iter().zip(actual).all(|(a, b)| a.accepts(b))
+    }
+}
+
+impl<A: Accept, B: Accept> Accept for (A, B) {
+    fn accepts(&self, actual: &(A, B)) -> bool {
+        self.0.accepts(&actual.0) && self.1.accepts(&actual.1)
+    }
+}
+
+struct Outcome {
+    name: String,
+    passed: bool,
+    count: usize,
+    conflicts: usize,
+    repaired: usize,
+    edges: usize,
+    stats: Stats,
+    skipped: Option<u128>,
+}
+
+impl Outcome {
+    fn skipped(name: &str, count: u128, edges: usize) -> Outcome {
+        Outcome {
+            name: name.to_string(),
+            passed: false,
+            count: 0,
+            conflicts: 0,
+            repaired: 0,
+            edges,
+            stats: Stats::default(),
+            skipped: Some(count),
+        }
+    }
+}
+
+type Edge = (NodeId, NodeId);
+
+// How constrained a config leaves a scenario: the dependencies between acts in
+// its plan, then how strictly the store checks revs, so that the lower of two
+// is the less constrained.
+type Strength = (usize, Reverse<usize>);
+
+pub type DiffFn<T> = fn(&T, &T) -> Vec<String>;
+pub type CheckFn<T> = fn(&DbStore<T>) -> Result<(), Vec<String>>;
+
+struct Hooks<T> {
+    diff: Option<DiffFn<T>>,
+    final_check: Option<CheckFn<T>>,
+}
+
+impl<T> Default for Hooks<T> {
+    fn default() -> Hooks<T> {
+        Hooks {
+            diff: None,
+            final_check: None,
+        }
+    }
 }
 
-type Outcome = (String, bool, usize);
+pub struct Record {
+    pub ordering: usize,
+    pub fault: Option<String>,
+    pub violation: Option<Violation>,
+    pub error: Option<String>,
+}
 
 #[derive(Default)]
 pub struct Runner<T> {
     configs: Vec<Config>,
     scenarios: Vec<Scenario<T>>,
     results: Vec<(Config, Vec<Outcome>)>,
+    hooks: Hooks<T>,
+    deepen: bool,
+    max_preemptions: Option<usize>,
+    stop_early: bool,
+    teardown: bool,
+    budget: Option<usize>,
+    seed: u64,
+    force: bool,
+    cache: Option<OrderingCache>,
+    bisect: Option<Config>,
+    necessity: bool,
+    #[cfg(feature = "archive")]
+    archive: Option<Archive>,
 }
 
 impl<T> Runner<T>
 where
-    T: Clone + Debug + Send,
+    T: Clone + Debug + Hash + Send + Sync + Size + 'static,
 {
     pub fn new() -> Runner<T> {
         Runner {
             configs: Vec::new(),
             scenarios: Vec::new(),
             results: Vec::new(),
+            hooks: Hooks::default(),
+            deepen: false,
+            max_preemptions: None,
+            stop_early: false,
+            teardown: false,
+            budget: None,
+            seed: 0,
+            force: false,
+            cache: None,
+            bisect: None,
+            necessity: false,
+            #[cfg(feature = "archive")]
+            archive: None,
         }
     }
 
+    #[cfg(feature = "archive")]
+    pub fn archive(&mut self, archive: Archive) {
+        self.archive = Some(archive);
+    }
+
+    fn is_archiving(&self) -> bool {
+        #[cfg(feature = "archive")]
+        return self.archive.is_some();
+
+        #[cfg(not(feature = "archive"))]
+        return false;
+    }
+
     pub fn configs(&mut self, configs: &[Config]) {
         self.configs.extend(configs.iter().cloned());
     }
 
+    pub fn diff_values(&mut self, diff: DiffFn<T>) {
+        self.hooks.diff = Some(diff);
+    }
+
+    pub fn check_final(&mut self, check: CheckFn<T>) {
+        self.hooks.final_check = Some(check);
+    }
+
+    pub fn deepen(&mut self) {
+        self.deepen = true;
+    }
+
+    // As for `deepen`, but stops after the round of orderings with `bound`
+    // preemptions, even where some orderings have more. A scenario that
+    // passes is reported as bounded, since only part of it was checked, and
+    // scenarios over the cap are run rather than skipped.
+    pub fn max_preemptions(&mut self, bound: usize) {
+        self.deepen = true;
+        self.max_preemptions = Some(bound);
+    }
+
+    // Runs each ordering once more for every act at which a client could stop,
+    // with the client issuing nothing from that act on while the others carry
+    // on. Clients a scenario partitions are left out, since their partition
+    // faults already cover this.
+    pub fn stop_early(&mut self) {
+        self.stop_early = true;
+    }
+
+    // After each execution that passes, undoes the docs its plan writes, by
+    // putting back their initial values or removing them, and checks that
+    // the store returns to its initial state.
+    pub fn teardown(&mut self) {
+        self.teardown = true;
+    }
+
+    // Scenarios with more orderings than the budget, counted before symmetry
+    // and reduction, have that many orderings sampled at random instead of
+    // every one checked.
+    pub fn budget(&mut self, orderings: usize) {
+        self.budget = Some(orderings);
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    // Runs scenarios with more orderings than `ORDERING_CAP`, which are
+    // otherwise skipped unless a budget is set.
+    pub fn force(&mut self) {
+        self.force = true;
+    }
+
+    pub fn cache(&mut self, cache: OrderingCache) {
+        self.cache = Some(cache);
+    }
+
+    // Each scenario that fails under a config but passes under the baseline
+    // is run again with subsets of the fields where the two differ set as in
+    // the failing config, to find the fewest that still make it fail.
+    pub fn bisect(&mut self, baseline: Config) {
+        self.bisect = Some(baseline);
+    }
+
+    // After the summary, reruns each passing scenario once for every
+    // dependency its plan has between two acts, with just that dependency
+    // dropped, and reports which ones it fails without.
+    pub fn necessity(&mut self) {
+        self.necessity = true;
+    }
+
     pub fn add<S, R>(&mut self, name: &str, setup: S, run: R)
     where
-        S: Fn(Client<T>) + 'static,
+        S: IntoFixture<T>,
         R: Fn(&mut Planner<T>) + 'static,
     {
         self.scenarios.push(Scenario {
             name: name.to_string(),
-            init: Box::new(setup),
-            plan: Box::new(run),
+            description: None,
+            init: setup.into_fixture(),
+            phases: vec![Box::new(run)],
+            expects: Vec::new(),
+            step_checks: Vec::new(),
+            observe_checks: Vec::new(),
+            end_checks: Vec::new(),
         });
     }
 
+    pub fn then<R>(&mut self, run: R)
+    where
+        R: Fn(&mut Planner<T>) + 'static,
+    {
+        let scenario = self
+            .scenarios
+            .last_mut()
+            .expect("then() called before add()");
+        scenario.phases.push(Box::new(run));
+    }
+
+    // Describes the last scenario added, for a report to print if it fails,
+    // where its name alone would not say what it does.
+    pub fn describe(&mut self, description: &str) {
+        let scenario = self
+            .scenarios
+            .last_mut()
+            .expect("describe() called before add()");
+        scenario.description = Some(description.to_string());
+    }
+
+    pub fn expect<F>(&mut self, key: &str, values: F)
+    where
+        F: Fn(&Acks) -> Vec<Option<T>> + Sync + 'static,
+        T: Accept,
+    {
+        let scenario = self
+            .scenarios
+            .last_mut()
+            .expect("expect() called before add()");
+        let path = Path::from(key);
+        let name = path.to_string();
+
+        let check = move |acks: &Acks, value: Option<&T>| {
+            let values = values(acks);
+            let accepts = |expected: &Option<T>| match (expected, value) {
+                (Some(expected), Some(actual)) => expected.accepts(actual),
+                (expected, actual) => expected.is_none() && actual.is_none(),
+            };
+            if values.iter().any(accepts) {
+                return Ok(());
+            }
+            Err(format!(
+                "doc '{}' has value {:?}, expected one of {:?} with acknowledged clients {:?}",
+                name, value, values, acks.clients
+            ))
+        };
+        scenario.expects.push((path, Box::new(check)));
+    }
+
+    // Runs `check` after every act of the last scenario added, with the store
+    // as it was before the act and as the act left it, for properties of the
+    // scenario that must hold at each step and not only at the end.
+    pub fn check_each_step<F>(&mut self, check: F)
+    where
+        F: Fn(&DbStore<T>, &DbStore<T>) -> Result<(), String> + Sync + 'static,
+    {
+        let scenario = self
+            .scenarios
+            .last_mut()
+            .expect("check_each_step() called before add()");
+        scenario.step_checks.push(Box::new(check));
+    }
+
+    // Runs `check` after each observe act of the last scenario added, with the
+    // key observed, the value the client saw and the store as the act left
+    // it, for properties of what clients may read while others write.
+    pub fn check_observations<F>(&mut self, check: F)
+    where
+        F: Fn(&str, Option<&T>, &DbStore<T>) -> Result<(), String> + Sync + 'static,
+    {
+        let scenario = self
+            .scenarios
+            .last_mut()
+            .expect("check_observations() called before add()");
+        scenario.observe_checks.push(Box::new(check));
+    }
+
+    // Runs `check` against the store that each execution of the last scenario
+    // added ends in, along with the checks that apply to every scenario.
+    pub fn check_at_end<F>(&mut self, check: F)
+    where
+        F: Fn(&DbStore<T>) -> Result<(), String> + Sync + 'static,
+    {
+        let scenario = self
+            .scenarios
+            .last_mut()
+            .expect("check_at_end() called before add()");
+        scenario.end_checks.push(Box::new(check));
+    }
+
     pub fn run(&mut self) {
-        for config in &self.configs {
-            println!("{}\n\n{:?}\n", SPLIT, config);
-            let mut results = Vec::new();
+        let groups = self.config_groups();
+        let counts = self.preflight(&groups);
+
+        for (configs, counts) in groups.into_iter().zip(counts) {
+            let mut reports: Vec<_> = configs.iter().map(|_| Report::default()).collect();
+            let mut results: Vec<_> = configs.iter().map(|_| Vec::new()).collect();
 
-            for scenario in &self.scenarios {
-                let runner = RunnerScenario::new(config.clone(), scenario);
-                let result = runner.run();
-                results.push((scenario.name.clone(), result.is_pass(), result.count()));
+            // The first config's report can be printed as it goes.
+            reports[0].live = true;
+
+            for (report, config) in reports.iter_mut().zip(&configs) {
+                writeln!(report, "{}\n\n{:?}\n", SPLIT, config).unwrap();
+            }
+
+            for (scenario, count) in self.scenarios.iter().zip(counts) {
+                let mut runner = RunnerScenario::new(configs.clone(), scenario, &self.hooks);
+                let edges = runner.planners.iter().map(|p| p.edges().len()).sum();
+
+                if self.refuses(count) {
+                    for (report, results) in reports.iter_mut().zip(&mut results) {
+                        writeln!(report, "Scenario: {}", scenario.name).unwrap();
+                        writeln!(report, "    result: SKIPPED").unwrap();
+                        writeln!(
+                            report,
+                            "    orderings: {}, over the cap of {}\n",
+                            format_number(count),
+                            format_number(ORDERING_CAP)
+                        )
+                        .unwrap();
+                        results.push(Outcome::skipped(&scenario.name, count, edges));
+                    }
+                    continue;
+                }
+                runner.record = self.is_archiving();
+                runner.deepen = self.deepen;
+                runner.max_preemptions = self.max_preemptions;
+                runner.stop_early = self.stop_early;
+                runner.teardown = self.teardown;
+                runner.budget = self.budget;
+                runner.seed = self.seed;
+                runner.cache = self.cache.as_ref();
+
+                let checks = runner.run(&mut reports);
+
+                // A failed cache write still passed every ordering through.
+                let errors = self.cache.as_ref().map(OrderingCache::take_errors);
+                for error in errors.unwrap_or_default() {
+                    for report in &mut reports {
+                        writeln!(report, "    cache error: {}\n", error).unwrap();
+                    }
+                }
+
+                for (i, (result, stats, records)) in checks.into_iter().enumerate() {
+                    #[cfg(feature = "archive")]
+                    if let Some(archive) = &mut self.archive {
+                        if let Err(error) = archive.write(&scenario.name, &configs[i], &records) {
+                            eprintln!("archive error: {}", error);
+                        }
+                    }
+                    #[cfg(not(feature = "archive"))]
+                    drop(records);
+
+                    results[i].push(Outcome {
+                        name: scenario.name.clone(),
+                        passed: result.is_pass(),
+                        count: result.count(),
+                        conflicts: result.conflicts(),
+                        repaired: result.repaired(),
+                        edges,
+                        stats,
+                        skipped: None,
+                    });
+                }
             }
-            self.results.push((config.clone(), results));
+            for report in reports {
+                print!("{}", report.text);
+            }
+            self.results.extend(configs.into_iter().zip(results));
         }
         self.print_summary();
+
+        if self.results.len() > 1 {
+            self.print_weakest();
+        }
+        if let Some(baseline) = &self.bisect {
+            self.print_bisections(baseline);
+        }
+        if self.necessity {
+            self.print_necessity();
+        }
     }
 
-    fn print_summary(&self) {
+    // Names the least constrained config that passes each scenario, then the
+    // configs that pass every scenario and trade dependencies against CAS
+    // laxness, each with the ways of relaxing it by one field that fail.
+    fn print_weakest(&self) {
         println!("{}", SPLIT);
-        println!("SUMMARY");
+        println!("WEAKEST");
         println!("{}", SPLIT);
         println!();
 
-        let mut total = 0;
+        let weakest: Vec<_> = (0..self.scenarios.len()).map(|i| self.weakest(i)).collect();
 
-        for (config, results) in &self.results {
+        for (index, (config, _)) in self.results.iter().enumerate() {
+            let scenarios: Vec<_> = (0..self.scenarios.len())
+                .filter(|i| weakest[*i] == Some(index))
+                .collect();
+
+            if scenarios.is_empty() {
+                continue;
+            }
             println!("{:?}", config);
-            for (name, passed, count) in results {
-                let status = if *passed { "PASS" } else { "FAIL" };
-                total += count;
-                println!("    - {} ({}): {}", status, format_number(*count), name);
+            for i in scenarios {
+                let (edges, Reverse(laxness)) = self.strength(index, Some(i));
+                println!(
+                    "    - {}: {} dependencies, CAS laxness {}",
+                    self.scenarios[i].name, edges, laxness
+                );
+            }
+            println!();
+        }
+        for (scenario, weakest) in self.scenarios.iter().zip(&weakest) {
+            if weakest.is_none() {
+                println!("{}: passes under no config", scenario.name);
+                println!();
+            }
+        }
+
+        let frontier = self.frontier();
+        if frontier.is_empty() {
+            println!("No config passes every scenario");
+            println!();
+        }
+        for index in frontier {
+            let (edges, Reverse(laxness)) = self.strength(index, None);
+            println!("{:?}", self.results[index].0);
+            println!(
+                "    passes every scenario: {} dependencies, CAS laxness {}",
+                edges, laxness
+            );
+            for (field, failures) in self.failed_relaxations(index) {
+                println!("    - relaxing {} fails: {}", field, failures.join(", "));
             }
             println!();
         }
-        println!("Total executions checked = {}", format_number(total));
-        println!();
     }
-}
 
-struct RunnerScenario<'s, T> {
-    config: Config,
-    scenario: &'s Scenario<T>,
-    planner: Planner<T>,
-}
+    // The least constrained config under which the scenario passes. The
+    // earliest config wins a tie.
+    fn weakest(&self, scenario: usize) -> Option<usize> {
+        (0..self.results.len())
+            .filter(|i| self.outcomes(*i, Some(scenario))[0].passed)
+            .min_by_key(|i| self.strength(*i, Some(scenario)))
+    }
 
-impl<T> RunnerScenario<'_, T>
-where
-    T: Clone + Send,
-{
-    fn new(config: Config, scenario: &Scenario<T>) -> RunnerScenario<'_, T> {
-        let mut planner = Planner::new(config.clone());
-        (scenario.plan)(&mut planner);
+    // The configs that pass every scenario and that no other such config
+    // betters on both dependencies and laxness, least constrained first.
+    fn frontier(&self) -> Vec<usize> {
+        let mut passing: Vec<_> = (0..self.results.len())
+            .filter(|i| self.results[*i].1.iter().all(|o| o.passed))
+            .collect();
+        passing.sort_by_key(|i| self.strength(*i, None));
 
-        RunnerScenario {
-            config,
-            scenario,
-            planner,
+        let mut frontier: Vec<usize> = Vec::new();
+        for index in passing {
+            let laxness = self.results[index].0.laxness();
+            if frontier
+                .iter()
+                .all(|i| self.results[*i].0.laxness() < laxness)
+            {
+                frontier.push(index);
+            }
         }
+        frontier
     }
 
-    fn run(&self) -> TestResult<'_, T>
-    where
-        T: Debug,
-    {
-        println!("Scenario: {}", self.scenario.name);
+    // Each config that sets one field differently from the given config, is
+    // less constrained than it over every scenario, and fails some of them,
+    // with the field as that config sets it and the scenarios it fails.
+    fn failed_relaxations(&self, index: usize) -> Vec<(String, Vec<&str>)> {
+        let (config, _) = &self.results[index];
+        let strength = self.strength(index, None);
 
-        let result = self.check_execution();
-        result.print();
+        let relaxed = self
+            .results
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (other, results))| {
+                let fields = other.differences(config);
+                if fields.len() != 1 || self.strength(i, None) >= strength {
+                    return None;
+                }
+                let failures: Vec<_> = results
+                    .iter()
+                    .filter(|outcome| !outcome.passed && outcome.skipped.is_none())
+                    .map(|outcome| outcome.name.as_str())
+                    .collect();
 
-        println!();
+                Some((other.describe(fields[0]), failures))
+            });
+        relaxed
+            .filter(|(_, failures)| !failures.is_empty())
+            .collect()
+    }
 
-        result
+    fn strength(&self, index: usize, scenario: Option<usize>) -> Strength {
+        let edges = self.outcomes(index, scenario).iter().map(|o| o.edges).sum();
+        (edges, Reverse(self.results[index].0.laxness()))
     }
 
-    fn create_store(&self) -> DbStore<T> {
-        let mut planner = Planner::new(self.config.clone());
-        (self.scenario.init)(planner.client("tmp"));
+    fn outcomes(&self, index: usize, scenario: Option<usize>) -> &[Outcome] {
+        let results = &self.results[index].1;
+        match scenario {
+            Some(i) => std::slice::from_ref(&results[i]),
+            None => results,
+        }
+    }
 
-        let store = RefCell::new(DbStore::new(self.config.clone()));
-        let mut actor = Actor::new(&store, self.config.clone());
+    fn print_bisections(&self, baseline: &Config) {
+        println!("{}", SPLIT);
+        println!("BISECT");
+        println!("{}", SPLIT);
+        println!();
+        println!("baseline: {:?}", baseline);
+        println!();
 
-        for act in planner.orderings().next().unwrap() {
-            actor.dispatch(act);
+        for (config, results) in &self.results {
+            let failures: Vec<_> = self
+                .scenarios
+                .iter()
+                .zip(results)
+                .filter(|(_, outcome)| !outcome.passed && outcome.skipped.is_none())
+                .collect();
+
+            if failures.is_empty() || config.differences(baseline).is_empty() {
+                continue;
+            }
+            println!("{:?}", config);
+
+            for (scenario, _) in failures {
+                let cause = match self.bisect_failure(scenario, baseline, config) {
+                    Some(fields) => {
+                        let flips: Vec<_> = fields.iter().map(|f| config.describe(*f)).collect();
+                        flips.join(", ")
+                    }
+                    None => String::from("(fails under the baseline too)"),
+                };
+                println!("    - {}: {}", scenario.name, cause);
+            }
+            println!();
         }
+    }
 
-        store.into_inner()
+    // The fewest fields that, set on the baseline as they are in the config,
+    // make the scenario fail. None if it fails under the baseline already.
+    fn bisect_failure(
+        &self,
+        scenario: &Scenario<T>,
+        baseline: &Config,
+        config: &Config,
+    ) -> Option<Vec<Field>> {
+        if self.fails(scenario, baseline, None) {
+            return None;
+        }
+        let fields = config.differences(baseline);
+        let minimal = ddmin(fields, |flips| {
+            self.fails(scenario, &baseline.with_fields(config, flips), None)
+        });
+        Some(minimal)
     }
 
-    fn check_execution(&self) -> TestResult<'_, T> {
-        let plans = Mutex::new(Box::new(self.planner.orderings().enumerate()) as PlanQueue<T>);
-        let client_ids: Vec<_> = self.planner.clients().collect();
-        let store = self.create_store();
+    // Lists each dependency of each passing scenario's plan as needed or
+    // not, by whether the scenario fails with it dropped.
+    fn print_necessity(&self) {
+        println!("{}", SPLIT);
+        println!("NECESSITY");
+        println!("{}", SPLIT);
+        println!();
 
-        let mut supervisor = Supervisor {
-            result: mpsc::channel(),
-            aborts: Vec::new(),
-        };
+        for (config, results) in &self.results {
+            println!("{:?}", config);
+            let (mut needed, mut total) = (0, 0);
 
-        let mut workers = Vec::new();
+            for (scenario, outcome) in self.scenarios.iter().zip(results) {
+                if !outcome.passed {
+                    continue;
+                }
+                println!("    {}:", scenario.name);
 
-        for _ in 0..WORKER_COUNT {
-            let (abort_send, abort_recv) = mpsc::channel();
+                for (edge, need) in self.check_necessity(scenario, config) {
+                    let label = if need { "needed" } else { "not needed" };
+                    println!("        - {}: {}", label, edge);
+                    needed += need as usize;
+                    total += 1;
+                }
+            }
+            println!("    {} of {} dependencies needed", needed, total);
+            println!();
+        }
+    }
 
-            let worker = Worker {
-                config: self.config.clone(),
-                plans: &plans,
-                client_ids: &client_ids,
-                store: store.clone(),
-                result_ch: supervisor.result.0.clone(),
-                abort_ch: abort_recv,
-            };
+    // Each dependency of the scenario's plan, named by its acts and by its
+    // phase where there is more than one, and whether the scenario fails
+    // with just that dependency dropped.
+    fn check_necessity(&self, scenario: &Scenario<T>, config: &Config) -> Vec<(String, bool)> {
+        let runner = RunnerScenario::planned(vec![config.clone()], scenario, &self.hooks);
+        let phased = runner.planners.len() > 1;
+        let mut edges = Vec::new();
 
-            workers.push(worker);
-            supervisor.aborts.push(abort_send);
+        for (phase, planner) in runner.planners.iter().enumerate() {
+            for edge in planner.edges() {
+                let mut name = planner.describe_edge(edge);
+                if phased {
+                    name = format!("phase {}: {}", phase + 1, name);
+                }
+                edges.push((name, self.fails(scenario, config, Some((phase, edge)))));
+            }
         }
+        edges
+    }
 
-        thread::scope(|scope| {
-            for worker in &mut workers {
-                scope.spawn(|| worker.run());
-            }
+    // Runs the scenario under the one config, with its report thrown away,
+    // and with the given dependency dropped from one of its phases. A config
+    // that takes it over the cap is counted as passing.
+    fn fails(&self, scenario: &Scenario<T>, config: &Config, drop: Option<(usize, Edge)>) -> bool {
+        let mut runner = RunnerScenario::planned(vec![config.clone()], scenario, &self.hooks);
+        if let Some((phase, edge)) = drop {
+            runner.planners[phase]
+                .drop_edge(edge)
+                .expect("the edge comes from the plan");
+        }
+        for planner in &mut runner.planners {
+            planner.drop_redundant_edges();
+        }
+        if self.refuses(runner.count_orderings()) {
+            return false;
+        }
+        runner.deepen = self.deepen;
+        runner.max_preemptions = self.max_preemptions;
+        runner.stop_early = self.stop_early;
+        runner.teardown = self.teardown;
+        runner.budget = self.budget;
+        runner.seed = self.seed;
+        runner.cache = self.cache.as_ref();
 
-            supervisor.collect_result()
-        })
+        let checks = runner.run(&mut [String::new()]);
+        !checks[0].0.is_pass()
     }
-}
 
-const WORKER_COUNT: usize = 4;
+    // Counts each scenario's orderings under each group of configs and prints
+    // them as a table, one column per config, before anything runs. Cells
+    // over the cap are marked, and noted as skipped if they will be.
+    fn preflight(&self, groups: &[Vec<Config>]) -> Vec<Vec<u128>> {
+        let counts: Vec<Vec<_>> = groups
+            .iter()
+            .map(|configs| {
+                self.scenarios
+                    .iter()
+                    .map(|scenario| {
+                        RunnerScenario::new(configs.clone(), scenario, &self.hooks)
+                            .count_orderings()
+                    })
+                    .collect()
+            })
+            .collect();
 
-type PlanQueue<'a, T> = Box<dyn Iterator<Item = (usize, Vec<&'a Act<T>>)> + Send + 'a>;
+        let mut columns = Vec::new();
+        for (configs, counts) in groups.iter().zip(&counts) {
+            for config in configs {
+                let cells = counts.iter().map(|count| {
+                    let mark = if *count > ORDERING_CAP { "*" } else { "" };
+                    format!("{}{}", format_number(count), mark)
+                });
+                columns.push((config, cells.collect::<Vec<_>>()));
+            }
+        }
 
-struct Worker<'a, 'e, T> {
-    config: Config,
-    plans: &'e Mutex<PlanQueue<'a, T>>,
-    client_ids: &'e [&'a str],
-    store: DbStore<T>,
-    result_ch: mpsc::Sender<TestResult<'a, T>>,
-    abort_ch: mpsc::Receiver<()>,
-}
+        println!("{}", SPLIT);
+        println!("PRE-FLIGHT");
+        println!("{}", SPLIT);
+        println!();
 
-impl<'a, 'e, T> Worker<'a, 'e, T>
-where
-    T: Clone,
-{
-    fn run(&mut self) {
-        let mut result = TestResult::Pass { count: 0 };
+        for (i, (config, _)) in columns.iter().enumerate() {
+            println!("#{}: {:?}", i + 1, config);
+        }
+        println!();
 
-        while let Some((n, plan)) = self.next_plan() {
-            if self.abort_ch.try_recv().is_ok() {
-                return;
-            }
+        let names: Vec<_> = self.scenarios.iter().map(|s| s.name.as_str()).collect();
+        let name_width = names.iter().map(|name| name.len()).max().unwrap_or(0);
 
-            let state = RefCell::new(self.store.clone());
-            let mut actors = self.create_actors(&state);
-            let mut checker = Checker::new(&state);
+        let widths: Vec<_> = columns
+            .iter()
+            .enumerate()
+            .map(|(i, (_, cells))| {
+                let header = format!("#{}", i + 1).len();
+                cells.iter().map(|cell| cell.len()).fold(header, usize::max)
+            })
+            .collect();
 
-            for (i, act) in plan.iter().enumerate() {
-                actors.get_mut(&act.client_id).unwrap().dispatch(act);
+        let mut header = format!("    {:name_width$}", "orderings");
+        for (i, width) in widths.iter().enumerate() {
+            header.push_str(&format!("  {:>width$}", format!("#{}", i + 1)));
+        }
+        println!("{}", header.trim_end());
 
-                if let Err(errors) = checker.check() {
-                    self.send_result(TestResult::Fail {
-                        count: n + 1,
-                        errors,
-                        plan,
-                        state: state.borrow().clone(),
-                        step: i,
-                    });
-                    return;
-                }
+        for (row, name) in names.iter().enumerate() {
+            let mut line = format!("    {:name_width$}", name);
+            for ((_, cells), width) in columns.iter().zip(&widths) {
+                line.push_str(&format!("  {:>width$}", cells[row]));
             }
-            result = TestResult::Pass { count: n + 1 };
+            println!("{}", line);
         }
-        self.send_result(result);
-    }
+        println!();
 
-    fn next_plan(&self) -> Option<(usize, Vec<&'a Act<T>>)> {
-        self.plans.lock().unwrap().next()
-    }
+        // Configs in a group plan alike, so one table serves the group.
+        let mut first = 0;
+        for configs in groups {
+            let numbers: Vec<_> = (first..first + configs.len())
+                .map(|i| format!("#{}", i + 1))
+                .collect();
+            let label = format!("workload ({})", numbers.join(", "));
+            self.print_workloads(&configs[0], &label, &names, name_width);
+            first += configs.len();
+        }
 
-    fn create_actors<'r>(&self, store: &'r RefCell<DbStore<T>>) -> HashMap<String, Actor<'r, T>> {
-        self.client_ids
-            .iter()
-            .map(|name| (name.to_string(), Actor::new(store, self.config.clone())))
-            .collect()
+        let over = counts.iter().flatten().any(|count| *count > ORDERING_CAP);
+        if over {
+            let note = if self.refuses(ORDERING_CAP + 1) {
+                ", so skipped"
+            } else {
+                ""
+            };
+            println!(
+                "* over the cap of {} orderings{}",
+                format_number(ORDERING_CAP),
+                note
+            );
+            println!();
+        }
+        counts
     }
 
-    fn send_result(&self, result: TestResult<'a, T>) {
-        self.result_ch.send(result).unwrap();
+    // Prints the size of each scenario's plan under one config, so that the
+    // cost of a scenario can be traced to its clients and the shape of its
+    // graph. `label` names the configs the table stands for.
+    fn print_workloads(&self, config: &Config, label: &str, names: &[&str], name_width: usize) {
+        let name_width = name_width.max(label.len());
+        println!(
+            "    {:name_width$}  {:>5}  {:>5}  {:>5}  per client",
+            label, "acts", "depth", "width"
+        );
+        for (scenario, name) in self.scenarios.iter().zip(names) {
+            let stats = RunnerScenario::new(vec![config.clone()], scenario, &self.hooks).stats();
+            let clients: Vec<_> = stats
+                .acts
+                .iter()
+                .map(|(client_id, n)| format!("{}: {}", client_id, n))
+                .collect();
+            println!(
+                "    {:name_width$}  {:>5}  {:>5}  {:>5}  {}",
+                name,
+                stats.total_acts(),
+                stats.depth,
+                stats.width,
+                clients.join(", ")
+            );
+        }
+        println!();
     }
-}
 
-type Channel<T> = (mpsc::Sender<T>, mpsc::Receiver<T>);
+    // A scenario over the cap is only run if the run is forced, sampled
+    // down to the budget, or bounded by preemptions.
+    fn refuses(&self, count: u128) -> bool {
+        count > ORDERING_CAP
+            && !self.force
+            && self.budget.is_none()
+            && self.max_preemptions.is_none()
+    }
 
-struct Supervisor<'a, T> {
-    result: Channel<TestResult<'a, T>>,
-    aborts: Vec<mpsc::Sender<()>>,
-}
+    // Configs that plan alike share a pass; deepening reports as it goes, so it keeps every config apart.
+    fn config_groups(&self) -> Vec<Vec<Config>> {
+        let mut groups: Vec<Vec<Config>> = Vec::new();
 
-impl<'a, T> Supervisor<'a, T> {
-    fn collect_result(&self) -> TestResult<'a, T> {
-        let mut result = TestResult::Pass { count: 0 };
-        let mut finished = 0;
+        for config in &self.configs {
+            let group = groups
+                .iter_mut()
+                .find(|group| !self.deepen && group[0].plans_like(config));
 
-        for worker_result in &self.result.1 {
-            if worker_result.is_pass() {
-                if worker_result.count() > result.count() {
-                    result = worker_result
-                }
-                finished += 1;
-                if finished == self.aborts.len() {
-                    break;
-                }
-            } else {
-                result = worker_result;
-                self.abort();
-                break;
+            match group {
+                Some(group) => group.push(config.clone()),
+                None => groups.push(vec![config.clone()]),
             }
         }
-        result
+        groups
     }
 
-    fn abort(&self) {
-        for abort in &self.aborts {
-            abort.send(()).unwrap();
-        }
+    fn print_summary(&self) {
+        println!("{}", SPLIT);
+        println!("SUMMARY");
+        println!("{}", SPLIT);
+        println!();
+
+        let mut total = 0;
+
+        for (config, results) in &self.results {
+            println!("{:?}", config);
+            for outcome in results {
+                if let Some(count) = outcome.skipped {
+                    println!(
+                        "    - SKIP ({} orderings): {}",
+                        format_number(count),
+                        outcome.name
+                    );
+                    continue;
+                }
+                total += outcome.count;
+                let consistency = if config.repair {
+                    format!(", {}", format_consistency(outcome.passed, outcome.repaired))
+                } else {
+                    String::new()
+                };
+                if outcome.passed {
+                    println!(
+                        "    - PASS ({}, {} states, {} conflicts/exec{}{}): {}",
+                        format_count(outcome.count, outcome.stats.sampled),
+                        format_number(outcome.stats.states),
+                        format_rate(outcome.conflicts, outcome.count),
+                        format_preemptions(&outcome.stats),
+                        consistency,
+                        outcome.name
+                    );
+                } else {
+                    println!(
+                        "    - FAIL ({}, {} states{}{}): {}",
+                        format_count(outcome.count, outcome.stats.sampled),
+                        format_number(outcome.stats.states),
+                        format_preemptions(&outcome.stats),
+                        consistency,
+                        outcome.name
+                    );
+                }
+                println!("        time: {}", outcome.stats.timings);
+            }
+            println!();
+        }
+        println!("Total executions checked = {}", format_number(total));
+        println!();
     }
 }
 
-enum TestResult<'a, T> {
-    Pass {
-        count: usize,
-    },
-    Fail {
-        count: usize,
-        errors: Vec<String>,
-        state: DbStore<T>,
-        plan: Vec<&'a Act<T>>,
-        step: usize,
-    },
+#[derive(Default)]
+struct Report {
+    live: bool,
+    text: String,
 }
 
-impl<T> TestResult<'_, T> {
-    fn is_pass(&self) -> bool {
-        match self {
-            TestResult::Pass { .. } => true,
-            TestResult::Fail { .. } => false,
+impl fmt::Write for Report {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.live {
+            print!("{}", s);
+        } else {
+            self.text.push_str(s);
         }
+        Ok(())
     }
+}
 
-    fn count(&self) -> usize {
-        match self {
-            TestResult::Pass { count } => *count,
-            TestResult::Fail { count, .. } => *count,
+struct RunnerScenario<'s, T> {
+    configs: Vec<Config>,
+    scenario: &'s Scenario<T>,
+    planners: Vec<Planner<T>>,
+    hooks: &'s Hooks<T>,
+    record: bool,
+    deepen: bool,
+    max_preemptions: Option<usize>,
+    stop_early: bool,
+    teardown: bool,
+    budget: Option<usize>,
+    seed: u64,
+    cache: Option<&'s OrderingCache>,
+    exceeded: AtomicBool,
+}
+
+impl<'s, T> RunnerScenario<'s, T>
+where
+    T: Clone + Hash + Send + Sync + Size + 'static,
+{
+    // Every config must plan like the first, whose planners they share.
+    fn new(
+        configs: Vec<Config>,
+        scenario: &'s Scenario<T>,
+        hooks: &'s Hooks<T>,
+    ) -> RunnerScenario<'s, T> {
+        let mut runner = RunnerScenario::planned(configs, scenario, hooks);
+        for planner in &mut runner.planners {
+            planner.drop_redundant_edges();
+        }
+        runner
+    }
+
+    // As for `new`, but with every dep the plans were given, redundant or not.
+    fn planned(
+        configs: Vec<Config>,
+        scenario: &'s Scenario<T>,
+        hooks: &'s Hooks<T>,
+    ) -> RunnerScenario<'s, T> {
+        let planners = scenario
+            .phases
+            .iter()
+            .map(|plan| {
+                let mut planner = Planner::new(configs[0].clone());
+                plan(&mut planner);
+                planner
+            })
+            .collect();
+
+        RunnerScenario {
+            configs,
+            scenario,
+            planners,
+            hooks,
+            record: false,
+            deepen: false,
+            max_preemptions: None,
+            stop_early: false,
+            teardown: false,
+            budget: None,
+            seed: 0,
+            cache: None,
+            exceeded: AtomicBool::new(false),
         }
     }
 
-    fn print(&self)
+    fn run<W>(&self, reports: &mut [W]) -> Vec<(TestResult<'_, T>, Stats, Vec<Record>)>
     where
-        T: Clone + Debug,
+        T: Debug,
+        W: fmt::Write,
     {
-        let status = if self.is_pass() { "PASS" } else { "FAIL" };
-        println!("    result: {}", status);
-        println!("    checked executions: {}", format_number(self.count()));
+        for report in reports.iter_mut() {
+            writeln!(report, "Scenario: {}", self.scenario.name).unwrap();
+        }
+        let stores: Vec<_> = self
+            .configs
+            .iter()
+            .map(|config| self.create_store(config))
+            .collect();
 
-        if let TestResult::Fail {
-            errors,
-            state,
-            plan,
-            step,
-            ..
-        } = self
-        {
-            println!("    errors:");
-            for error in errors {
-                println!("        - {}", error);
+        let checks = self.check_execution(&stores, &mut reports[0]);
+
+        for (i, (result, stats, _)) in checks.iter().enumerate() {
+            let report = &mut reports[i];
+            result
+                .print(
+                    report,
+                    stats,
+                    &stores[i],
+                    &self.configs[i],
+                    &self.planners,
+                    self.hooks.diff,
+                )
+                .unwrap();
+
+            if let (false, Some(description)) = (result.is_pass(), &self.scenario.description) {
+                writeln!(report, "    scenario:").unwrap();
+                for line in description.lines() {
+                    writeln!(report, "        {}", line).unwrap();
+                }
             }
-            println!("    state:");
-            for key in state.keys() {
-                let value = format_value(state.read(key));
-                println!("        '{}' => {}", key, value);
+            writeln!(report).unwrap();
+        }
+        checks
+    }
+
+    fn create_store(&self, config: &Config) -> DbStore<T> {
+        let init = match &self.scenario.init {
+            Fixture::Plan(init) => init,
+            Fixture::Docs(docs) => return db::build_store(config, docs),
+        };
+        let mut planner = Planner::new(config.clone());
+        init(planner.client("tmp"));
+
+        let store = RefCell::new(DbStore::new(config.clone()));
+        let mut actor = Actor::new(&store, config.clone());
+
+        for act in planner.orderings().next().unwrap() {
+            actor.dispatch(act);
+        }
+
+        store.into_inner()
+    }
+
+    fn check_execution<W>(
+        &self,
+        stores: &[DbStore<T>],
+        out: &mut W,
+    ) -> Vec<(TestResult<'_, T>, Stats, Vec<Record>)>
+    where
+        W: fmt::Write,
+    {
+        let client_ids = self.collect_ids(|planner| planner.clients());
+        let partitioned = self.collect_ids(|planner| planner.partitioned_clients());
+        let timeouts = self.collect_ids(|planner| planner.timed_out_clients());
+        let restarts = self.collect_ids(|planner| planner.restarted_clients());
+        let crashed = self.collect_ids(|planner| planner.crashed_clients());
+        let stopping: Vec<_> = client_ids
+            .iter()
+            .filter(|id| self.stop_early && !partitioned.contains(id))
+            .copied()
+            .collect();
+        let teardown = self
+            .teardown
+            .then(|| self.collect_ids(|planner| planner.written_docs()));
+
+        let known_errors: Vec<_> = stores
+            .iter()
+            .map(|store| {
+                Checker::new(&RefCell::new(store.clone()))
+                    .check()
+                    .err()
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let workers: Vec<_> = self
+            .configs
+            .iter()
+            .zip(stores)
+            .zip(&known_errors)
+            .map(|((config, store), known_errors)| Worker {
+                config: config.clone(),
+                client_ids: &client_ids,
+                partitioned: &partitioned,
+                timeouts: &timeouts,
+                restarts: &restarts,
+                stopping: &stopping,
+                crashed: &crashed,
+                teardown: teardown.as_deref(),
+                known_errors,
+                store,
+                final_check: self.hooks.final_check,
+                expects: &self.scenario.expects,
+                step_checks: &self.scenario.step_checks,
+                observe_checks: &self.scenario.observe_checks,
+                end_checks: &self.scenario.end_checks,
+                record: self.record,
+                records: Mutex::new(Vec::new()),
+                timings: Mutex::new(Timings::default()),
+                states: Mutex::new(HashMap::new()),
+                coverage: Mutex::new(HashMap::new()),
+            })
+            .collect();
+
+        let mut generating = Duration::ZERO;
+        let mut preemptions = None;
+        let mut bounded = false;
+        let mut sampled = None;
+
+        let oversized = self.budget.and_then(|budget| {
+            let total = self.count_orderings();
+            (total > budget as u128).then_some((budget, total))
+        });
+
+        let tallies = match oversized {
+            Some((budget, total)) => {
+                sampled = Some(total);
+                self.check_orderings(&workers, self.sample_orderings(budget), 0, &mut generating)
             }
-            println!("    execution:");
-            for (i, act) in plan.iter().enumerate() {
-                if i == *step {
-                    println!("    ==> {:?}", act);
-                } else {
-                    println!("        {:?}", act);
+            None if self.deepen => {
+                let (tally, bound, cut) = self.check_by_preemptions(&workers, &mut generating, out);
+                preemptions = Some(bound);
+                bounded = cut;
+                vec![tally]
+            }
+            None => self.check_orderings(&workers, self.orderings(), 0, &mut generating),
+        };
+
+        workers
+            .into_iter()
+            .zip(tallies)
+            .map(|(worker, tally)| {
+                let result = match tally {
+                    Ok(tally) => TestResult::Pass {
+                        count: tally.count,
+                        conflicts: tally.conflicts,
+                        repaired: tally.repaired,
+                    },
+                    Err(failure) => *failure,
+                };
+
+                // Orderings after a failure may have run before it was found.
+                // What they did is left out, so that the results only depend
+                // on which ordering failed first.
+                let last = match result {
+                    TestResult::Pass { .. } => usize::MAX,
+                    TestResult::Fail { count, .. } => count,
+                };
+                let mut records = worker.records.into_inner().unwrap();
+                records.retain(|record| record.ordering <= last);
+                records.sort_by_key(|record| record.ordering);
+
+                let mut timings = worker.timings.into_inner().unwrap();
+                timings.generate = generating;
+
+                let states = worker.states.into_inner().unwrap();
+
+                let coverage = worker.coverage.into_inner().unwrap();
+                let mut unreachable: Vec<_> = coverage
+                    .iter()
+                    .filter(|(_, first)| first.is_none_or(|ordering| ordering > last))
+                    .map(|(fault, _)| *fault)
+                    .collect();
+                unreachable.sort();
+
+                let stats = Stats {
+                    states: states
+                        .values()
+                        .filter(|ordering| **ordering <= last)
+                        .count(),
+                    timings,
+                    preemptions,
+                    bounded,
+                    sampled,
+                    faults: coverage.len(),
+                    unreachable: unreachable.iter().map(|f| f.describe()).collect(),
+                };
+                (result, stats, records)
+            })
+            .collect()
+    }
+
+    // Returns the last round's bound and whether it cut any ordering off.
+    fn check_by_preemptions<'a, W>(
+        &'a self,
+        workers: &[Worker<'a, '_, T>],
+        generating: &mut Duration,
+        out: &mut W,
+    ) -> (Result<Tally, Box<TestResult<'a, T>>>, usize, bool)
+    where
+        W: fmt::Write,
+    {
+        assert_eq!(workers.len(), 1, "deepening runs under a single config");
+        let mut total = Tally::default();
+
+        for bound in 0.. {
+            self.exceeded.store(false, Ordering::Relaxed);
+
+            let orderings = self.orderings_within(bound);
+            let mut tallies = self.check_orderings(workers, orderings, total.count, generating);
+
+            let tally = match tallies.pop().unwrap() {
+                Ok(tally) => tally,
+                Err(failure) => {
+                    writeln!(out, "    preemptions = {}: FAIL", bound).unwrap();
+                    return (Err(failure), bound, false);
                 }
+            };
+            writeln!(
+                out,
+                "    preemptions = {}: PASS ({})",
+                bound,
+                format_number(tally.count.saturating_sub(total.count))
+            )
+            .unwrap();
+            total = total.merge(tally);
+
+            if !self.exceeded.load(Ordering::Relaxed) {
+                return (Ok(total), bound, false);
+            }
+            if self.max_preemptions == Some(bound) {
+                return (Ok(total), bound, true);
+            }
+        }
+        unreachable!()
+    }
+
+    fn check_orderings<'a>(
+        &'a self,
+        workers: &[Worker<'a, '_, T>],
+        orderings: Orderings<'a, T>,
+        offset: usize,
+        generating: &mut Duration,
+    ) -> Vec<Checked<'a, T>> {
+        let first_failures: Vec<_> = workers
+            .iter()
+            .map(|_| AtomicUsize::new(usize::MAX))
+            .collect();
+
+        let orderings = orderings.enumerate().map(|(n, plan)| (n + offset, plan));
+        let mut orderings = chunks(orderings, CHUNK_SIZE);
+        let mut next = offset;
+
+        // Once every config has failed on an ordering before the next one to
+        // be generated there is nothing left to check, so no more orderings
+        // are generated.
+        let batches = std::iter::from_fn(|| {
+            if first_failures
+                .iter()
+                .all(|f| f.load(Ordering::Relaxed) < next)
+            {
+                return None;
+            }
+            let start = Instant::now();
+            let chunk = orderings.next();
+            *generating += start.elapsed();
+
+            if let Some((n, _)) = chunk.as_ref().and_then(|chunk| chunk.last()) {
+                next = n + 1;
+            }
+            chunk
+        });
+
+        // Earlier orderings still run after a failure, so the first is reported.
+        batches
+            .par_bridge()
+            .flat_map(|chunk| chunk.into_par_iter())
+            .map_init(
+                || {
+                    workers
+                        .iter()
+                        .map(|worker| RefCell::new(worker.store.clone()))
+                        .collect::<Vec<_>>()
+                },
+                |states, (n, plan)| {
+                    workers
+                        .iter()
+                        .zip(states.iter())
+                        .zip(&first_failures)
+                        .map(|((worker, state), first_failure)| {
+                            if n > first_failure.load(Ordering::Relaxed) {
+                                return Ok(Tally::default());
+                            }
+                            let checked = worker.check_plan(state, n, plan.clone());
+                            if checked.is_err() {
+                                first_failure.fetch_min(n, Ordering::Relaxed);
+                            }
+                            checked
+                        })
+                        .collect::<Vec<_>>()
+                },
+            )
+            .reduce(
+                || workers.iter().map(|_| Ok(Tally::default())).collect(),
+                merge_checks,
+            )
+    }
+
+    // Each phase starts from every state the previous phases can leave
+    // behind, so a phased scenario's orderings are every ordering of phase 1
+    // followed by every ordering of phase 2, and so on.
+    fn orderings(&self) -> Orderings<'_, T> {
+        match self.cache {
+            Some(cache) => self.phase_orderings(move |planner| planner.orderings_cached(cache)),
+            None => self.phase_orderings(|planner| Box::new(planner.orderings())),
+        }
+    }
+
+    // Only those with exactly `bound` preemptions, summed over the phases.
+    fn orderings_within(&self, bound: usize) -> Orderings<'_, T> {
+        let (planners, exceeded) = (&self.planners, &self.exceeded);
+
+        let orderings = self
+            .phase_orderings(move |planner| Box::new(planner.orderings_within(bound, exceeded)));
+
+        Box::new(orderings.filter(move |plan| {
+            let preemptions = preemptions(planners, plan);
+            if preemptions > bound {
+                exceeded.store(true, Ordering::Relaxed);
             }
+            preemptions == bound
+        }))
+    }
+
+    // The stats of the scenario's phases, run one after another.
+    fn stats(&self) -> PlanStats {
+        self.planners
+            .iter()
+            .map(|planner| planner.stats())
+            .reduce(|stats, next| stats.then(&next))
+            .unwrap_or_default()
+    }
+
+    fn count_orderings(&self) -> u128 {
+        self.planners
+            .iter()
+            .map(|planner| planner.count_orderings())
+            .fold(1, u128::saturating_mul)
+    }
+
+    // `n` orderings picked at random with a generator seeded from the run's
+    // seed. The same orderings may be picked more than once.
+    fn sample_orderings(&self, n: usize) -> Orderings<'_, T> {
+        let planners = &self.planners;
+        let mut counts: Vec<_> = planners.iter().map(|_| Counts::default()).collect();
+        let mut rng = Rng::new(self.seed);
+
+        Box::new((0..n).filter_map(move |_| {
+            (0..SAMPLE_ATTEMPTS).find_map(|_| sample(planners, &mut counts, &mut rng))
+        }))
+    }
+
+    fn phase_orderings<'a, F>(&'a self, orderings: F) -> Orderings<'a, T>
+    where
+        F: Fn(&'a Planner<T>) -> Orderings<'a, T> + Copy + Send + 'a,
+    {
+        let (first, rest) = self.planners.split_first().unwrap();
+        let mut plans = orderings(first);
+
+        for planner in rest {
+            plans = Box::new(plans.flat_map(move |prefix| {
+                orderings(planner).map(move |ordering| {
+                    let mut plan = prefix.clone();
+                    plan.extend(ordering);
+                    plan
+                })
+            }));
         }
+        plans
+    }
+
+    // The distinct ids, of clients or keys, that any phase's planner lists,
+    // in order.
+    fn collect_ids<'p, F, I>(&'p self, ids: F) -> Vec<&'p str>
+    where
+        F: Fn(&'p Planner<T>) -> I,
+        I: Iterator<Item = &'p str>,
+    {
+        let ids: BTreeSet<_> = self.planners.iter().flat_map(ids).collect();
+        ids.into_iter().collect()
     }
 }
 
-fn format_number(n: usize) -> String {
-    n.to_string()
-        .as_bytes()
-        .rchunks(3)
-        .rev()
-        .map(|byte| std::str::from_utf8(byte))
-        .collect::<Result<Vec<&str>, _>>()
-        .unwrap()
-        .join(",")
+const REPAIR_CLIENT: &str = "repair";
+const TEARDOWN_CLIENT: &str = "teardown";
+const CHUNK_SIZE: usize = 256;
+const ORDERING_CAP: u128 = 1_000_000_000;
+const SAMPLE_ATTEMPTS: usize = 100;
+
+type Orderings<'a, T> = Box<dyn Iterator<Item = Vec<&'a Act<T>>> + Send + 'a>;
+
+type Checked<'a, T> = Result<Tally, Box<TestResult<'a, T>>>;
+
+// Where both failed, the earlier ordering's failure is kept.
+fn merge_checks<'a, T>(a: Vec<Checked<'a, T>>, b: Vec<Checked<'a, T>>) -> Vec<Checked<'a, T>> {
+    a.into_iter()
+        .zip(b)
+        .map(|pair| match pair {
+            (Ok(a), Ok(b)) => Ok(a.merge(b)),
+            (Err(a), Err(b)) if b.count() < a.count() => Err(b),
+            (Err(failure), _) | (_, Err(failure)) => Err(failure),
+        })
+        .collect()
 }
 
-fn format_value<T>(value: Option<(usize, Option<Db<T>>)>) -> String
+fn preemptions<T>(planners: &[Planner<T>], plan: &[&Act<T>]) -> usize {
+    let mut rest = plan;
+
+    planners
+        .iter()
+        .map(|planner| {
+            let (phase, tail) = rest.split_at(planner.len());
+            rest = tail;
+            planner.preemptions(phase)
+        })
+        .sum()
+}
+
+// A random ordering of each phase in turn. Sampling a phase fails when the
+// walk reaches a point where symmetry or reduction rule out every act that
+// could come next.
+fn sample<'a, T>(
+    planners: &'a [Planner<T>],
+    counts: &mut [Counts],
+    rng: &mut Rng,
+) -> Option<Vec<&'a Act<T>>> {
+    let mut plan = Vec::new();
+    for (planner, counts) in planners.iter().zip(counts) {
+        plan.extend(planner.sample(counts, rng)?);
+    }
+    Some(plan)
+}
+
+fn chunks<I>(mut iter: I, size: usize) -> impl Iterator<Item = Vec<I::Item>>
 where
-    T: Debug,
+    I: Iterator,
 {
-    if let Some((rev, value)) = value {
-        if let Some(value) = value {
-            format!("{{ rev: {}, value: {:?} }}", rev, value)
-        } else {
-            format!("{{ rev: {}, value: <null> }}", rev)
+    std::iter::from_fn(move || {
+        let chunk: Vec<_> = iter.by_ref().take(size).collect();
+        (!chunk.is_empty()).then_some(chunk)
+    })
+}
+
+// Delta debugging: shrinks a set of items that makes the test fail down to
+// one where removing any single item makes it pass. It tries each of n
+// chunks and then each of their complements, refining n when neither fails.
+fn ddmin<I, F>(mut items: Vec<I>, mut fails: F) -> Vec<I>
+where
+    I: Clone,
+    F: FnMut(&[I]) -> bool,
+{
+    let mut n = 2;
+
+    while items.len() >= 2 {
+        let size = items.len().div_ceil(n);
+        let parts: Vec<_> = items.chunks(size).map(<[I]>::to_vec).collect();
+
+        if let Some(part) = parts.iter().find(|part| fails(part)) {
+            items = part.clone();
+            n = 2;
+            continue;
         }
-    } else {
-        String::from("<null>")
+
+        // With two parts, each complement is the other part.
+        let complement = (0..parts.len()).filter(|_| parts.len() > 2).find_map(|i| {
+            let rest: Vec<_> = parts
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .flat_map(|(_, part)| part.iter().cloned())
+                .collect();
+            fails(&rest).then_some(rest)
+        });
+        if let Some(rest) = complement {
+            items = rest;
+            n = (n - 1).max(2);
+            continue;
+        }
+
+        if n >= items.len() {
+            break;
+        }
+        n = (n * 2).min(items.len());
+    }
+    items
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Fault<'a> {
+    Partition(&'a str, usize),
+    Timeout(&'a str, usize, bool),
+    Restart(&'a str, usize),
+    Stop(&'a str, usize),
+}
+
+impl<'a> Fault<'a> {
+    fn client_id(&self) -> &'a str {
+        match self {
+            Fault::Partition(client_id, _) => client_id,
+            Fault::Timeout(client_id, _, _) => client_id,
+            Fault::Restart(client_id, _) => client_id,
+            Fault::Stop(client_id, _) => client_id,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Fault::Partition(client_id, start) => format!(
+                "client '{}' loses access from its act #{}",
+                client_id,
+                start + 1
+            ),
+            Fault::Timeout(client_id, index, applied) => format!(
+                "client '{}' times out on its act #{} ({})",
+                client_id,
+                index + 1,
+                if *applied { "applied" } else { "not applied" }
+            ),
+            Fault::Restart(client_id, index) => format!(
+                "client '{}' restarts before its act #{}",
+                client_id,
+                index + 1
+            ),
+            Fault::Stop(client_id, start) => {
+                format!("client '{}' stops before its act #{}", client_id, start + 1)
+            }
+        }
+    }
+
+    // Applies the fault to its client's actor as the client reaches the act
+    // it strikes at. `seen` counts the acts of the client so far. A client
+    // that stops is cut off just as a partitioned one is.
+    fn inject<T>(&self, act: &Act<T>, actor: &mut Actor<'_, T>, seen: &mut usize)
+    where
+        T: Clone + Size,
+    {
+        if act.client_id != self.client_id() {
+            return;
+        }
+        match *self {
+            Fault::Partition(_, start) | Fault::Stop(_, start) if *seen == start => {
+                actor.partition()
+            }
+            Fault::Timeout(_, index, applied) if *seen == index => actor.time_out(applied),
+            Fault::Restart(_, index) if *seen == index => actor.restart(),
+            _ => {}
+        }
+        *seen += 1;
+    }
+
+    fn note(&self, index: usize) -> &'static str {
+        match self {
+            Fault::Partition(_, start) if index >= *start => " (partitioned)",
+            Fault::Stop(_, start) if index >= *start => " (stopped)",
+            Fault::Timeout(_, i, _) if index == *i => " (timed out)",
+            Fault::Restart(_, i) if index == *i => " (restarted)",
+            _ => "",
+        }
+    }
+}
+
+struct Failure<T> {
+    violation: Violation,
+    errors: Vec<String>,
+    state: Box<DbStore<T>>,
+    times: Vec<Time>,
+    step: usize,
+}
+
+type Actors<'r, T> = HashMap<String, Actor<'r, T>>;
+type Cached<T> = Option<(Rev, Option<Db<T>>)>;
+
+// The record each client had cached for a key named in a failure's errors,
+// or None for a client that had no record of it.
+struct View<T> {
+    key: String,
+    cached: Vec<(String, Option<Cached<T>>)>,
+}
+
+// What one act of a failed execution did, as notes on the keys it read and
+// wrote and the checker errors it raised or cleared.
+struct Step {
+    label: String,
+    notes: Vec<String>,
+}
+
+struct Execution {
+    conflicts: usize,
+    repaired: bool,
+}
+
+// Time spent in each part of checking a scenario. Everything but ordering
+// generation is summed across the threads of the pool, so it can exceed the
+// wall-clock time of the run.
+#[derive(Clone, Copy, Default)]
+struct Timings {
+    generate: Duration,
+    clone: Duration,
+    dispatch: Duration,
+    check: Duration,
+}
+
+impl Timings {
+    fn add(&mut self, other: &Timings) {
+        self.generate += other.generate;
+        self.clone += other.clone;
+        self.dispatch += other.dispatch;
+        self.check += other.check;
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "generate {}, clone {}, dispatch {}, check {}",
+            format_duration(self.generate),
+            format_duration(self.clone),
+            format_duration(self.dispatch),
+            format_duration(self.check)
+        )
+    }
+}
+
+// Counts of the work that went into checking a scenario, reported next to
+// its result. `states` is the number of distinct stores that executions
+// ended in, which can be far smaller than the number of executions.
+// `faults` is the number of fault points the scenario admits, and
+// `unreachable` describes those that struck in no execution.
+#[derive(Clone, Default)]
+struct Stats {
+    states: usize,
+    timings: Timings,
+    preemptions: Option<usize>,
+    bounded: bool,
+    sampled: Option<u128>,
+    faults: usize,
+    unreachable: Vec<String>,
+}
+
+#[derive(Default)]
+struct Tally {
+    count: usize,
+    conflicts: usize,
+    repaired: usize,
+}
+
+impl Tally {
+    fn merge(self, other: Tally) -> Tally {
+        Tally {
+            count: self.count.max(other.count),
+            conflicts: self.conflicts + other.conflicts,
+            repaired: self.repaired + other.repaired,
+        }
+    }
+}
+
+struct Worker<'a, 'e, T> {
+    config: Config,
+    client_ids: &'e [&'a str],
+    partitioned: &'e [&'a str],
+    timeouts: &'e [&'a str],
+    restarts: &'e [&'a str],
+    stopping: &'e [&'a str],
+    crashed: &'e [&'a str],
+    teardown: Option<&'e [&'a str]>,
+    known_errors: &'e [String],
+    store: &'e DbStore<T>,
+    final_check: Option<CheckFn<T>>,
+    expects: &'e [(Path, ExpectFn<T>)],
+    step_checks: &'e [StepFn<T>],
+    observe_checks: &'e [ObserveFn<T>],
+    end_checks: &'e [EndFn<T>],
+    record: bool,
+    records: Mutex<Vec<Record>>,
+    timings: Mutex<Timings>,
+    states: Mutex<HashMap<u64, usize>>,
+    coverage: Mutex<HashMap<Fault<'a>, Option<usize>>>,
+}
+
+impl<'a, 'e, T> Worker<'a, 'e, T>
+where
+    T: Clone + Hash + Sync + Size + 'static,
+{
+    // Runs a plan once without faults and once under each fault it admits.
+    // The plan's executions share one set of actors, reset before each run.
+    fn check_plan(
+        &self,
+        state: &RefCell<DbStore<T>>,
+        n: usize,
+        plan: Vec<&'a Act<T>>,
+    ) -> Result<Tally, Box<TestResult<'a, T>>> {
+        let mut actors = self.create_actors(state);
+        let mut timings = Timings::default();
+        let mut tally = Tally {
+            count: n + 1,
+            ..Tally::default()
+        };
+        let mut struck = Vec::new();
+
+        for fault in self.faults(&plan) {
+            let outcome = self.execute(n + 1, &plan, fault, state, &mut actors, &mut timings);
+
+            if let Some(fault) = fault {
+                struck.push((fault, actors[fault.client_id()].is_faulted()));
+            }
+
+            if self.record {
+                self.records.lock().unwrap().push(Record {
+                    ordering: n + 1,
+                    fault: fault.map(|f| f.describe()),
+                    violation: outcome.as_ref().err().map(|f| f.violation),
+                    error: outcome.as_ref().err().map(|f| f.errors[0].clone()),
+                });
+            }
+
+            match outcome {
+                Ok(execution) => {
+                    if fault.is_none() {
+                        tally.conflicts += execution.conflicts;
+                    }
+                    if execution.repaired {
+                        tally.repaired += 1;
+                    }
+                }
+                Err(failure) => {
+                    self.timings.lock().unwrap().add(&timings);
+                    self.cover(n + 1, &struck);
+
+                    let keys = named_keys(&failure.errors, &failure.state);
+                    let views = self.views(&keys, &actors);
+
+                    let (snapshots, reads, traces) =
+                        self.replay(&plan[..=failure.step], fault, state, &mut actors);
+                    let blame = blame(&keys, &plan, &failure.times, &snapshots, &failure.state);
+                    let steps = impacts(
+                        &plan,
+                        &failure.times,
+                        &reads,
+                        &traces,
+                        &snapshots,
+                        &failure.state,
+                    );
+
+                    return Err(Box::new(TestResult::Fail {
+                        count: n + 1,
+                        errors: failure.errors,
+                        blame,
+                        steps,
+                        views,
+                        state: failure.state,
+                        plan,
+                        fault,
+                        times: failure.times,
+                        step: failure.step,
+                    }));
+                }
+            }
+        }
+        self.timings.lock().unwrap().add(&timings);
+        self.cover(n + 1, &struck);
+
+        Ok(tally)
+    }
+
+    // Notes each fault point a plan ran under, along with the first
+    // ordering in which it struck, or None if it has yet to strike.
+    fn cover(&self, ordering: usize, struck: &[(Fault<'a>, bool)]) {
+        let mut coverage = self.coverage.lock().unwrap();
+
+        for (fault, hit) in struck {
+            let first = coverage.entry(*fault).or_insert(None);
+            if *hit {
+                *first = Some(first.map_or(ordering, |first| first.min(ordering)));
+            }
+        }
+    }
+
+    fn faults(&self, plan: &[&'a Act<T>]) -> Vec<Option<Fault<'a>>> {
+        let mut faults = vec![None];
+
+        for client_id in self.partitioned {
+            let acts = plan.iter().filter(|act| act.client_id == *client_id);
+            faults.extend((0..acts.count()).map(|i| Some(Fault::Partition(client_id, i))));
+        }
+
+        // Stopping before the first act is left out, as that is the scenario
+        // without the client rather than a client that stops partway.
+        for client_id in self.stopping {
+            let acts = plan.iter().filter(|act| act.client_id == *client_id);
+            faults.extend((1..acts.count()).map(|i| Some(Fault::Stop(client_id, i))));
+        }
+
+        for client_id in self.timeouts {
+            let acts = plan.iter().filter(|act| act.client_id == *client_id);
+
+            for (i, act) in acts.enumerate() {
+                if act.op.may_write() {
+                    faults.push(Some(Fault::Timeout(client_id, i, false)));
+                    faults.push(Some(Fault::Timeout(client_id, i, true)));
+                }
+            }
+        }
+
+        // As with stopping, a restart before the first act would find the
+        // client with nothing to forget.
+        for client_id in self.restarts {
+            let acts = plan.iter().filter(|act| act.client_id == *client_id);
+            faults.extend((1..acts.count()).map(|i| Some(Fault::Restart(client_id, i))));
+        }
+        faults
+    }
+
+    fn execute(
+        &self,
+        ordering: usize,
+        plan: &[&'a Act<T>],
+        fault: Option<Fault<'a>>,
+        state: &RefCell<DbStore<T>>,
+        actors: &mut Actors<'_, T>,
+        timings: &mut Timings,
+    ) -> Result<Execution, Failure<T>> {
+        let start = Instant::now();
+        *state.borrow_mut() = self.store.clone();
+        for actor in actors.values_mut() {
+            actor.reset();
+        }
+        timings.clone += start.elapsed();
+
+        let mut checker = Checker::new(state);
+        let mut times = Vec::new();
+        let mut seen = 0;
+        let mut broken = false;
+
+        for (i, act) in plan.iter().enumerate() {
+            let actor = actors.get_mut(&act.client_id).unwrap();
+
+            if let Some(fault) = fault {
+                fault.inject(act, actor, &mut seen);
+            }
+            let start = Instant::now();
+            let before = (!self.step_checks.is_empty()).then(|| state.borrow().clone());
+            timings.clone += start.elapsed();
+
+            let observed = actor.observations().len();
+            let listed = actor.listings().len();
+            let start = Instant::now();
+            actor.dispatch(act);
+            times.push(state.borrow().clock.now());
+            timings.dispatch += start.elapsed();
+
+            if let Some((path, value)) = actor.observations().get(observed) {
+                let start = Instant::now();
+                let errors = self.check_observation(path, value.as_ref(), &state.borrow());
+                timings.check += start.elapsed();
+
+                if !errors.is_empty() {
+                    return Err(Failure {
+                        violation: Violation::FinalCheck,
+                        errors,
+                        state: Box::new(state.borrow().clone()),
+                        times,
+                        step: i,
+                    });
+                }
+            }
+
+            // A listing is judged by the expectation its act carries, so a
+            // client's view of a dir fails the execution where it was seen.
+            if let (Some((_, entries)), Op::ExpectList(check)) =
+                (actor.listings().get(listed), &act.op)
+            {
+                if let Err(error) = check(entries) {
+                    return Err(Failure {
+                        violation: Violation::FinalCheck,
+                        errors: vec![error],
+                        state: Box::new(state.borrow().clone()),
+                        times,
+                        step: i,
+                    });
+                }
+            }
+
+            // A scenario's own checks are about the values it writes, which
+            // a repair pass would not restore, so they fail the execution
+            // even under a config that repairs.
+            if let Some(before) = before {
+                let start = Instant::now();
+                let errors = self.check_step(&before, &state.borrow());
+                timings.check += start.elapsed();
+
+                if !errors.is_empty() {
+                    return Err(Failure {
+                        violation: Violation::FinalCheck,
+                        errors,
+                        state: Box::new(state.borrow().clone()),
+                        times,
+                        step: i,
+                    });
+                }
+            }
+
+            if broken {
+                continue;
+            }
+            let start = Instant::now();
+            let checked = checker.check_violations();
+            timings.check += start.elapsed();
+
+            let errors = match checked {
+                Ok(()) => continue,
+                Err(errors) => self.new_errors(errors),
+            };
+            if errors.is_empty() {
+                continue;
+            }
+            if self.config.recovery {
+                let start = Instant::now();
+                let errors = self.recover_from(&state.borrow());
+                timings.check += start.elapsed();
+
+                if !errors.is_empty() {
+                    let (violation, errors) = split_violations(errors);
+                    return Err(Failure {
+                        violation,
+                        errors,
+                        state: Box::new(state.borrow().clone()),
+                        times,
+                        step: i,
+                    });
+                }
+                continue;
+            }
+            if !self.config.repair {
+                let (violation, errors) = split_violations(errors);
+                return Err(Failure {
+                    violation,
+                    errors,
+                    state: Box::new(state.borrow().clone()),
+                    times,
+                    step: i,
+                });
+            }
+            broken = true;
+        }
+        let conflicts = state.borrow().conflicts - self.store.conflicts;
+
+        if self.config.repair {
+            let start = Instant::now();
+            self.repair(state);
+            timings.dispatch += start.elapsed();
+        }
+
+        let start = Instant::now();
+        let fingerprint = state.borrow().fingerprint();
+        self.states
+            .lock()
+            .unwrap()
+            .entry(fingerprint)
+            .and_modify(|first| *first = ordering.min(*first))
+            .or_insert(ordering);
+
+        let checked = self.check_final_state(plan, state, actors, &mut checker);
+        timings.check += start.elapsed();
+
+        if let Err((violation, errors)) = checked {
+            return Err(Failure {
+                violation,
+                errors,
+                state: Box::new(state.borrow().clone()),
+                times,
+                step: plan.len() - 1,
+            });
+        }
+
+        if let Some(keys) = self.teardown {
+            let start = Instant::now();
+            self.tear_down(keys, state);
+            timings.dispatch += start.elapsed();
+
+            let start = Instant::now();
+            let errors = self.check_residue(&state.borrow());
+            timings.check += start.elapsed();
+
+            if !errors.is_empty() {
+                return Err(Failure {
+                    violation: Violation::FinalCheck,
+                    errors,
+                    state: Box::new(state.borrow().clone()),
+                    times,
+                    step: plan.len() - 1,
+                });
+            }
+        }
+        Ok(Execution {
+            conflicts,
+            repaired: broken,
+        })
+    }
+
+    // What each client had cached of each key when its execution failed.
+    fn views(&self, keys: &[&str], actors: &Actors<'_, T>) -> Vec<View<T>> {
+        keys.iter()
+            .map(|key| View {
+                key: key.to_string(),
+                cached: self
+                    .client_ids
+                    .iter()
+                    .map(|id| (id.to_string(), actors[*id].cached(key)))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    // Runs the acts of a failed execution again, under the same fault, and
+    // returns the state of the store before the first act and after each,
+    // along with the keys each act read from the store and its actor's trace
+    // of it.
+    fn replay(
+        &self,
+        plan: &[&'a Act<T>],
+        fault: Option<Fault<'a>>,
+        state: &RefCell<DbStore<T>>,
+        actors: &mut Actors<'_, T>,
+    ) -> (Vec<DbStore<T>>, Vec<Vec<Path>>, Vec<Trace>) {
+        *state.borrow_mut() = self.store.clone();
+        for actor in actors.values_mut() {
+            actor.reset();
+            actor.trace_reads(true);
+            actor.trace_acts(true);
+        }
+        let mut snapshots = vec![self.store.clone()];
+        let mut reads = Vec::new();
+        let mut traces = Vec::new();
+        let mut seen = 0;
+
+        for act in plan {
+            let actor = actors.get_mut(&act.client_id).unwrap();
+
+            if let Some(fault) = fault {
+                fault.inject(act, actor, &mut seen);
+            }
+            actor.dispatch(act);
+            snapshots.push(state.borrow().clone());
+            reads.push(actor.take_reads());
+            traces.extend(actor.take_trace());
+        }
+        for actor in actors.values_mut() {
+            actor.trace_reads(false);
+            actor.trace_acts(false);
+        }
+        (snapshots, reads, traces)
+    }
+
+    // The checks that apply only to the state an execution ends in, after
+    // any repair pass has run.
+    fn check_final_state(
+        &self,
+        plan: &[&Act<T>],
+        state: &RefCell<DbStore<T>>,
+        actors: &Actors<'_, T>,
+        checker: &mut Checker<'_, T>,
+    ) -> Result<(), (Violation, Vec<String>)> {
+        if self.config.repair {
+            let errors = match checker.check_violations() {
+                Ok(()) => Vec::new(),
+                Err(errors) => self.new_errors(errors),
+            };
+            if !errors.is_empty() {
+                return Err(split_violations(errors));
+            }
+        }
+
+        let errors = check_writers(plan, self.store, &state.borrow());
+        if !errors.is_empty() {
+            return Err((Violation::FinalCheck, errors));
+        }
+
+        if let Some(Err(errors)) = self.final_check.map(|check| check(&state.borrow())) {
+            return Err((Violation::FinalCheck, errors));
+        }
+
+        let errors: Vec<_> = self
+            .end_checks
+            .iter()
+            .filter_map(|check| check(&state.borrow()).err())
+            .collect();
+        if !errors.is_empty() {
+            return Err((Violation::FinalCheck, errors));
+        }
+
+        let errors = self.check_expects(state, actors);
+        if !errors.is_empty() {
+            return Err((Violation::FinalCheck, errors));
+        }
+        Ok(())
+    }
+
+    // The standard cleanup pass appended to every execution when the config
+    // asks for it: a single client recovering the store, which repairs every
+    // link in it in turn.
+    fn repair(&self, state: &RefCell<DbStore<T>>) {
+        let mut planner = Planner::new(self.config.clone());
+        planner.client(REPAIR_CLIENT).recover();
+
+        let mut actor = Actor::new(state, self.config.clone());
+        for act in planner.orderings().next().unwrap() {
+            actor.dispatch(act);
+        }
+    }
+
+    // The errors left in a copy of the store once the repair pass has run on
+    // it, as they would be if every client stopped here and an fsck tool was
+    // run, for checking that recovery works from each broken state.
+    fn recover_from(&self, store: &DbStore<T>) -> Vec<(Violation, String)> {
+        let scratch = RefCell::new(store.clone());
+        self.repair(&scratch);
+
+        let errors = Checker::new(&scratch).check_violations().err();
+        self.new_errors(errors.unwrap_or_default())
+            .into_iter()
+            .map(|(violation, error)| (violation, format!("after recovery: {}", error)))
+            .collect()
+    }
+
+    // Undoes an execution with a single client, which puts each doc the plan
+    // writes back to its initial value, or removes it if it had none. Each
+    // doc is planned and run on its own, as the acts of ops planned together
+    // could interleave and conflict with each other.
+    fn tear_down(&self, keys: &[&str], state: &RefCell<DbStore<T>>) {
+        for key in keys {
+            let mut planner = Planner::new(self.config.clone());
+            let mut client = planner.client(TEARDOWN_CLIENT);
+
+            match db::doc_value(self.store, key) {
+                Some(value) => client.update(key, move |_| Some(value.clone())),
+                None => client.remove(key),
+            }
+
+            let mut actor = Actor::new(state, self.config.clone());
+            for act in planner.orderings().next().unwrap() {
+                actor.dispatch(act);
+            }
+        }
+    }
+
+    // The keys a teardown left different from the initial store. Values are
+    // compared by hash, which leaves out each doc's metadata.
+    fn check_residue(&self, state: &DbStore<T>) -> Vec<String> {
+        let keys: BTreeSet<_> = self.store.keys().chain(state.keys()).collect();
+
+        keys.into_iter()
+            .filter_map(|key| {
+                let before = self.residue(self.store, key);
+                let after = self.residue(state, key);
+
+                match (lasting(before.as_deref()), lasting(after.as_deref())) {
+                    (None, Some(_)) => Some(format!("teardown left '{}' behind", key)),
+                    (Some(_), None) => Some(format!("teardown did not restore '{}'", key)),
+                    (Some(a), Some(b)) if hash_of(a) != hash_of(b) => {
+                        Some(format!("teardown left '{}' changed", key))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    // A key's record as the teardown check sees it. A config that never
+    // unlinks leaves entries behind by design, so they are not counted
+    // against it.
+    fn residue<'s>(&self, store: &'s DbStore<T>, key: &Path) -> Option<Cow<'s, Db<T>>> {
+        if self.config.remove == Remove::NoUnlink {
+            db::without_garbage(store, key).map(Cow::Owned)
+        } else {
+            store.get(key).map(Cow::Borrowed)
+        }
+    }
+
+    // Expected values are read through a fresh actor so that docs stored
+    // inline in their parent dir are found under every layout.
+    fn check_expects(&self, state: &RefCell<DbStore<T>>, actors: &Actors<'_, T>) -> Vec<String> {
+        if self.expects.is_empty() {
+            return Vec::new();
+        }
+        let clients = actors
+            .iter()
+            .filter(|(client_id, actor)| {
+                actor.is_acknowledged() && !self.crashed.contains(&client_id.as_str())
+            })
+            .map(|(client_id, _)| client_id.clone())
+            .collect();
+        let acks = Acks { clients };
+
+        let mut reader = Actor::new(state, self.config.clone());
+
+        self.expects
+            .iter()
+            .filter_map(|(path, check)| check(&acks, reader.get(path).as_ref()).err())
+            .collect()
+    }
+
+    fn check_step(&self, before: &DbStore<T>, after: &DbStore<T>) -> Vec<String> {
+        self.step_checks
+            .iter()
+            .filter_map(|check| check(before, after).err())
+            .collect()
+    }
+
+    fn check_observation(&self, path: &Path, value: Option<&T>, store: &DbStore<T>) -> Vec<String> {
+        let key = path.to_string();
+        self.observe_checks
+            .iter()
+            .filter_map(|check| check(&key, value, store).err())
+            .collect()
+    }
+
+    fn new_errors(&self, errors: Vec<(Violation, String)>) -> Vec<(Violation, String)> {
+        errors
+            .into_iter()
+            .filter(|(_, error)| !self.known_errors.contains(error))
+            .collect()
+    }
+
+    fn create_actors<'r>(&self, store: &'r RefCell<DbStore<T>>) -> Actors<'r, T> {
+        self.client_ids
+            .iter()
+            .map(|name| (name.to_string(), Actor::new(store, self.config.clone())))
+            .collect()
+    }
+}
+
+enum TestResult<'a, T> {
+    Pass {
+        count: usize,
+        conflicts: usize,
+        repaired: usize,
+    },
+    Fail {
+        count: usize,
+        errors: Vec<String>,
+        blame: Vec<String>,
+        steps: Vec<Step>,
+        views: Vec<View<T>>,
+        state: Box<DbStore<T>>,
+        plan: Vec<&'a Act<T>>,
+        fault: Option<Fault<'a>>,
+        times: Vec<Time>,
+        step: usize,
+    },
+}
+
+impl<T> TestResult<'_, T> {
+    fn is_pass(&self) -> bool {
+        match self {
+            TestResult::Pass { .. } => true,
+            TestResult::Fail { .. } => false,
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self {
+            TestResult::Pass { count, .. } => *count,
+            TestResult::Fail { count, .. } => *count,
+        }
+    }
+
+    fn conflicts(&self) -> usize {
+        match self {
+            TestResult::Pass { conflicts, .. } => *conflicts,
+            TestResult::Fail { .. } => 0,
+        }
+    }
+
+    fn repaired(&self) -> usize {
+        match self {
+            TestResult::Pass { repaired, .. } => *repaired,
+            TestResult::Fail { .. } => 0,
+        }
+    }
+
+    fn print<W>(
+        &self,
+        out: &mut W,
+        stats: &Stats,
+        initial: &DbStore<T>,
+        config: &Config,
+        planners: &[Planner<T>],
+        diff: Option<DiffFn<T>>,
+    ) -> fmt::Result
+    where
+        T: Clone + Debug + Size,
+        W: fmt::Write,
+    {
+        let status = if self.is_pass() { "PASS" } else { "FAIL" };
+        if stats.sampled.is_some() {
+            writeln!(out, "    result: {} (statistical)", status)?;
+        } else if stats.bounded {
+            writeln!(out, "    result: {} (bounded)", status)?;
+        } else {
+            writeln!(out, "    result: {}", status)?;
+        }
+        writeln!(
+            out,
+            "    checked executions: {}",
+            format_count(self.count(), stats.sampled)
+        )?;
+        writeln!(out, "    distinct states: {}", format_number(stats.states))?;
+        writeln!(out, "    time: {}", stats.timings)?;
+
+        if self.is_pass() {
+            writeln!(
+                out,
+                "    write conflicts: {} ({} per execution)",
+                format_number(self.conflicts()),
+                format_rate(self.conflicts(), self.count())
+            )?;
+        }
+        if config.repair {
+            writeln!(
+                out,
+                "    consistency: {}",
+                format_consistency(self.is_pass(), self.repaired())
+            )?;
+        }
+
+        // A failure stops its ordering's later faults from running, so
+        // coverage is only reported for a scenario that passes.
+        if self.is_pass() && stats.faults > 0 {
+            writeln!(
+                out,
+                "    fault points: {} of {} exercised",
+                format_number(stats.faults - stats.unreachable.len()),
+                format_number(stats.faults)
+            )?;
+            if !stats.unreachable.is_empty() {
+                writeln!(out, "    unreachable faults:")?;
+                for fault in &stats.unreachable {
+                    writeln!(out, "        - {}", fault)?;
+                }
+            }
+        }
+
+        if let TestResult::Fail {
+            errors,
+            blame,
+            steps,
+            views,
+            state,
+            plan,
+            fault,
+            times,
+            step,
+            ..
+        } = self
+        {
+            writeln!(out, "    errors:")?;
+            for error in errors {
+                writeln!(out, "        - {}", error)?;
+            }
+            if !blame.is_empty() {
+                writeln!(out, "    blame:")?;
+                for line in blame {
+                    writeln!(out, "        - {}", line)?;
+                }
+            }
+            writeln!(out, "    clock: {}", state.clock.now())?;
+            writeln!(out, "    changes to the store:")?;
+            let mut unchanged = 0;
+
+            for key in state.keys() {
+                if state.rev(key) == initial.rev(key) {
+                    unchanged += 1;
+                    continue;
+                }
+                let change = match (initial.get(key), state.get(key)) {
+                    (None, Some(_)) => "added",
+                    (Some(_), None) => "removed",
+                    _ => "changed",
+                };
+                let value = format_value(state.read(key));
+                writeln!(out, "        {} '{}' => {}", change, key, value)?;
+
+                if let (Some(diff), Some(Db::Doc(old, _)), Some(Db::Doc(new, _))) =
+                    (diff, initial.get(key), state.get(key))
+                {
+                    for line in diff(old, new) {
+                        writeln!(out, "            {}", line)?;
+                    }
+                } else if initial.rev(key) > 0 {
+                    let value = format_value(initial.read(key));
+                    writeln!(out, "            was {}", value)?;
+                }
+            }
+            match unchanged {
+                0 => {}
+                1 => writeln!(out, "        (1 key unchanged)")?,
+                n => writeln!(out, "        ({} keys unchanged)", format_number(n))?,
+            }
+            if !views.is_empty() {
+                writeln!(out, "    client views:")?;
+            }
+            for view in views {
+                writeln!(out, "        '{}':", view.key)?;
+                let value = format_value(state.read(view.key.as_str()));
+                writeln!(out, "            store => {}", value)?;
+
+                for (client_id, cached) in &view.cached {
+                    let value = match cached {
+                        Some(record) => format_value(record.clone()),
+                        None => String::from("not cached"),
+                    };
+                    writeln!(out, "            {} => {}", client_id, value)?;
+                }
+            }
+            if let Some(fault) = fault {
+                writeln!(out, "    fault: {}", fault.describe())?;
+            }
+            writeln!(out, "    plan:")?;
+            for (phase, planner) in planners.iter().enumerate() {
+                let mut indent = "        ";
+                if planners.len() > 1 {
+                    writeln!(out, "{}phase {}:", indent, phase + 1)?;
+                    indent = "            ";
+                }
+                for line in planner.describe().lines() {
+                    writeln!(out, "{}{}", indent, line)?;
+                }
+            }
+            if let Some(node) = locate(planners, plan[*step]) {
+                writeln!(out, "    failing act: {}", node)?;
+            }
+            writeln!(out, "    execution:")?;
+            for line in timeline(plan, times, *fault, *step) {
+                writeln!(out, "{}", line)?;
+            }
+            writeln!(out, "    steps:")?;
+            for step in steps {
+                writeln!(out, "        {}", step.label)?;
+                for note in &step.notes {
+                    writeln!(out, "            {}", note)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Names an act by its node in its phase's graph, numbered as in the
+// planner's DOT rendering, along with the nodes it waits for.
+fn locate<T>(planners: &[Planner<T>], act: &Act<T>) -> Option<String> {
+    for (phase, planner) in planners.iter().enumerate() {
+        let node = planner
+            .graph()
+            .nodes()
+            .find(|(_, a, _)| std::ptr::eq(*a, act));
+
+        if let Some((id, act, deps)) = node {
+            let mut name = format!("n{} ({}: {})", id, act.client_id, act);
+            if planners.len() > 1 {
+                name.push_str(&format!(" in phase {}", phase + 1));
+            }
+            if !deps.is_empty() {
+                let deps: Vec<_> = deps.iter().map(|dep| format!("n{}", dep)).collect();
+                name.push_str(&format!(", after {}", deps.join(", ")));
+            }
+            return Some(name);
+        }
+    }
+    None
+}
+
+// Lays out an execution with a column per client and time running down the
+// page, so each client's acts read straight down its column. Acts after the
+// failing step never ran and so have no time.
+fn timeline<T>(
+    plan: &[&Act<T>],
+    times: &[Time],
+    fault: Option<Fault<'_>>,
+    step: usize,
+) -> Vec<String> {
+    let clients: Vec<&str> = plan
+        .iter()
+        .map(|act| act.client_id.as_str())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let mut seen = HashMap::new();
+    let cells: Vec<(usize, String)> = plan
+        .iter()
+        .map(|act| {
+            let index = seen.entry(act.client_id.as_str()).or_insert(0);
+            let note = match fault {
+                Some(fault) if act.client_id == fault.client_id() => fault.note(*index),
+                _ => "",
+            };
+            *index += 1;
+            let column = clients.iter().position(|c| *c == act.client_id).unwrap();
+            (column, format!("{}{}", act, note))
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = clients.iter().map(|c| c.len()).collect();
+    for (column, cell) in &cells {
+        widths[*column] = widths[*column].max(cell.len());
+    }
+    let times: Vec<String> = times.iter().map(|t| format!("[t={}]", t)).collect();
+    let time_width = times.iter().map(String::len).max().unwrap_or(0);
+
+    let row = |marker: &str, time: &str, cells: String| {
+        let line = format!("    {:3} {:w$}   {}", marker, time, cells, w = time_width);
+        line.trim_end().to_string()
+    };
+    let header = clients
+        .iter()
+        .zip(&widths)
+        .map(|(client_id, w)| format!("{:w$}", client_id, w = w))
+        .collect::<Vec<_>>()
+        .join("   ");
+
+    let mut lines = vec![row("", "", header)];
+
+    for (i, (column, cell)) in cells.into_iter().enumerate() {
+        let marker = if i == step { "==>" } else { "" };
+        let time = times.get(i).map(String::as_str).unwrap_or("");
+        let indent: usize = widths[..column].iter().map(|w| w + 3).sum();
+        lines.push(row(
+            marker,
+            time,
+            format!("{:indent$}{}", "", cell, indent = indent),
+        ));
+    }
+    lines
+}
+
+// Each doc an execution wrote must name a client that planned a put to it
+// as its writer. Docs the execution left alone keep the setup's metadata.
+fn check_writers<T>(plan: &[&Act<T>], initial: &DbStore<T>, state: &DbStore<T>) -> Vec<String>
+where
+    T: Clone + Size,
+{
+    let puts: BTreeSet<_> = plan
+        .iter()
+        .filter(|act| matches!(act.op, Op::Put(_)))
+        .map(|act| (act.client_id.as_str(), &act.path))
+        .collect();
+
+    state
+        .keys()
+        .filter(|key| state.rev(*key) != initial.rev(*key))
+        .filter_map(|key| match state.get(key) {
+            Some(Db::Doc(_, meta)) if !puts.contains(&(meta.writer.as_str(), key)) => {
+                Some(format!(
+                    "doc '{}' was written by '{}', which has no put to it",
+                    key, meta.writer
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// A failure is classed by its first error.
+fn split_violations(errors: Vec<(Violation, String)>) -> (Violation, Vec<String>) {
+    let violation = errors[0].0;
+    (
+        violation,
+        errors.into_iter().map(|(_, error)| error).collect(),
+    )
+}
+
+// The keys quoted in a failure's errors, in the order they first appear.
+// Errors quote other things too, such as the names of dir entries, so only
+// quotes that match a key the store has a record of are kept.
+fn named_keys<'e, T>(errors: &'e [String], state: &DbStore<T>) -> Vec<&'e str>
+where
+    T: Clone + Size,
+{
+    let mut keys = Vec::new();
+
+    for error in errors {
+        for key in error.split('\'').skip(1).step_by(2) {
+            if state.rev(key) > 0 && !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+    keys
+}
+
+// The acts that left behind the given keys: the last act to write each key
+// and, for each entry that act removed from a dir, the act that had added
+// it. `snapshots` holds the store before the first act and after each one,
+// and `state` is where the failure was found, which a repair pass may have
+// written to since the last act.
+fn blame<T>(
+    keys: &[&str],
+    plan: &[&Act<T>],
+    times: &[Time],
+    snapshots: &[DbStore<T>],
+    state: &DbStore<T>,
+) -> Vec<String>
+where
+    T: Clone + Size,
+{
+    let act = |i: usize| format!("{:?} at t={}", plan[i], times[i]);
+    let has = |i: usize, key: &str, name: &str| {
+        let db = snapshots[i].get(key);
+        db.and_then(|db| db.has_entry(name)) == Some(true)
+    };
+    let last = snapshots.len() - 1;
+    let mut lines = Vec::new();
+
+    for &key in keys {
+        if state.rev(key) != snapshots[last].rev(key) {
+            lines.push(format!("'{}' was last written by the repair pass", key));
+            continue;
+        }
+        let written = (0..last)
+            .rev()
+            .find(|&i| snapshots[i].rev(key) != snapshots[i + 1].rev(key));
+
+        let Some(i) = written else {
+            lines.push(format!("'{}' was set up by the scenario", key));
+            continue;
+        };
+        let mut line = format!("'{}' was last written by {}", key, act(i));
+
+        for name in entries(snapshots[i].get(key)) {
+            if has(i + 1, key, name) {
+                continue;
+            }
+            match (0..i)
+                .rev()
+                .find(|&j| !has(j, key, name) && has(j + 1, key, name))
+            {
+                Some(j) => {
+                    line.push_str(&format!(", which removed '{}' that {} added", name, act(j)))
+                }
+                None => line.push_str(&format!(
+                    ", which removed '{}' that the scenario set up",
+                    name
+                )),
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// Tells the story of a failed execution one act at a time: how the act went
+// for its actor, as `traces` has it, the keys it read from the store, the
+// keys it wrote and how it changed their entries, and the checker errors it
+// raised or cleared. Each error an act raised also names whichever act last
+// wrote the other keys it quotes. `snapshots` and `state` are as for
+// `blame()`, and a repair pass that wrote to the store after the last act
+// gets a step of its own.
+fn impacts<T>(
+    plan: &[&Act<T>],
+    times: &[Time],
+    reads: &[Vec<Path>],
+    traces: &[Trace],
+    snapshots: &[DbStore<T>],
+    state: &DbStore<T>,
+) -> Vec<Step>
+where
+    T: Clone + Size,
+{
+    let mut stores: Vec<_> = snapshots.iter().collect();
+    if state.seq != snapshots[snapshots.len() - 1].seq {
+        stores.push(state);
+    }
+    let errors: Vec<BTreeSet<String>> = stores
+        .iter()
+        .map(|store| {
+            let errors = Checker::new(&RefCell::new((*store).clone())).check();
+            errors.err().unwrap_or_default().into_iter().collect()
+        })
+        .collect();
+
+    let act = |i: usize| format!("{:?} at t={}", plan[i], times[i]);
+    let writer = |i: usize, key: &str| match (0..i)
+        .rev()
+        .find(|&j| stores[j].rev(key) != stores[j + 1].rev(key))
+    {
+        Some(j) => format!("'{}' written by {}", key, act(j)),
+        None => format!("'{}' set up by the scenario", key),
+    };
+
+    (0..stores.len() - 1)
+        .map(|i| {
+            let (before, after) = (stores[i], stores[i + 1]);
+            let mut notes: Vec<_> = traces.get(i).map(Trace::to_string).into_iter().collect();
+
+            let mut read: Vec<String> = Vec::new();
+            for key in reads.get(i).into_iter().flatten() {
+                let key = format!("'{}'", key);
+                if !read.contains(&key) {
+                    read.push(key);
+                }
+            }
+            if !read.is_empty() {
+                notes.push(format!("read {}", read.join(", ")));
+            }
+
+            let written: Vec<&str> = after
+                .keys()
+                .filter(|key| before.rev(*key) != after.rev(*key))
+                .map(|key| key.full())
+                .collect();
+
+            for key in &written {
+                let note = match (before.get(*key), after.get(*key)) {
+                    (None, Some(_)) => format!("created '{}'", key),
+                    (Some(_), None) => format!("deleted '{}'", key),
+                    (old, new) => {
+                        let (old, new) = (entries(old), entries(new));
+                        let changes: Vec<_> = new
+                            .iter()
+                            .filter(|name| !old.contains(name))
+                            .map(|name| format!("added '{}'", name))
+                            .chain(
+                                old.iter()
+                                    .filter(|name| !new.contains(name))
+                                    .map(|name| format!("removed '{}'", name)),
+                            )
+                            .collect();
+                        if changes.is_empty() {
+                            format!("wrote '{}'", key)
+                        } else {
+                            format!("wrote '{}': {}", key, changes.join(", "))
+                        }
+                    }
+                };
+                notes.push(note);
+            }
+
+            for error in errors[i + 1].difference(&errors[i]) {
+                let others: Vec<_> = named_keys(std::slice::from_ref(error), after)
+                    .into_iter()
+                    .filter(|key| !written.contains(key))
+                    .map(|key| writer(i, key))
+                    .collect();
+
+                if others.is_empty() {
+                    notes.push(format!("broke: {}", error));
+                } else {
+                    notes.push(format!("broke: {} ({})", error, others.join(", ")));
+                }
+            }
+            for error in errors[i].difference(&errors[i + 1]) {
+                notes.push(format!("fixed: {}", error));
+            }
+
+            let label = match plan.get(i) {
+                Some(_) => act(i),
+                None => String::from("repair pass"),
+            };
+            Step { label, notes }
+        })
+        .collect()
+}
+
+// A key's record as a teardown is judged, with empty dirs and leases counted
+// as no record. Removing a doc empties its dirs without deleting them, and an
+// empty dir reads the same as a missing one. Leases expire by themselves.
+fn lasting<T>(db: Option<&Db<T>>) -> Option<&Db<T>> {
+    db.filter(|db| match db {
+        Db::Doc(..) => true,
+        Db::Dir(entries) => !entries.is_empty(),
+        Db::Packed(entries) => !entries.is_empty(),
+        Db::Lease(_) => false,
+    })
+}
+
+fn hash_of<H: Hash>(value: &H) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn entries<T>(db: Option<&Db<T>>) -> Vec<&str> {
+    match db {
+        Some(Db::Dir(entries)) => entries.keys().map(String::as_str).collect(),
+        Some(Db::Packed(entries)) => entries.keys().map(String::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn format_number<N: ToString>(n: N) -> String {
+    n.to_string()
+        .as_bytes()
+        .rchunks(3)
+        .rev()
+        .map(|byte| std::str::from_utf8(byte))
+        .collect::<Result<Vec<&str>, _>>()
+        .unwrap()
+        .join(",")
+}
+
+fn format_rate(n: usize, total: usize) -> String {
+    if total == 0 {
+        String::from("0.00")
+    } else {
+        format!("{:.2}", n as f64 / total as f64)
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
+
+fn format_count(count: usize, sampled: Option<u128>) -> String {
+    match sampled {
+        Some(total) => format!(
+            "{} sampled of {}",
+            format_number(count),
+            format_number(total)
+        ),
+        None => format_number(count),
+    }
+}
+
+fn format_preemptions(stats: &Stats) -> String {
+    match stats.preemptions {
+        Some(bound) if stats.bounded => format!(", bounded at {} preemptions", bound),
+        Some(bound) => format!(", {} preemptions", bound),
+        None => String::new(),
+    }
+}
+
+fn format_consistency(passed: bool, repaired: usize) -> String {
+    match (passed, repaired) {
+        (false, _) => String::from("never"),
+        (true, 0) => String::from("always"),
+        (true, n) => format!("after repair ({} executions)", format_number(n)),
+    }
+}
+
+fn format_value<T>(value: Option<(usize, Option<Db<T>>)>) -> String
+where
+    T: Debug,
+{
+    if let Some((rev, value)) = value {
+        if let Some(value) = value {
+            format!("{{ rev: {}, value: {:?} }}", rev, value)
+        } else {
+            format!("{{ rev: {}, value: <null> }}", rev)
+        }
+    } else {
+        String::from("<null>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::config::{Cas, Layout, Timeout, Update};
+    use crate::db::Kind;
+    use crate::planner::CustomOp;
+
+    fn check_single<'r>(
+        runner: &'r RunnerScenario<'_, (char, i32)>,
+    ) -> (TestResult<'r, (char, i32)>, Stats, Vec<Record>) {
+        let store = runner.create_store(&runner.configs[0]);
+        let mut checks = runner.check_execution(&[store], &mut String::new());
+        checks.pop().unwrap()
+    }
+
+    fn phased_scenario() -> Scenario<(char, i32)> {
+        Scenario {
+            name: String::from("phased"),
+            description: None,
+            init: Fixture::Docs(Vec::new()),
+            phases: vec![
+                Box::new(|planner| {
+                    planner.client("A").update("/x", |_| Some(('a', 1)));
+                    planner.client("B").remove("/x");
+                }),
+                Box::new(|planner| {
+                    planner.client("C").update("/x", |_| Some(('c', 1)));
+                }),
+            ],
+            expects: Vec::new(),
+            step_checks: Vec::new(),
+            observe_checks: Vec::new(),
+            end_checks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn runs_each_phase_after_all_orderings_of_the_previous_phase() {
+        let scenario = phased_scenario();
+        let hooks = Hooks::default();
+        let runner = RunnerScenario::new(vec![Config::new()], &scenario, &hooks);
+
+        let first = runner.planners[0].orderings().count();
+        let second = runner.planners[1].orderings().count();
+        let plans: Vec<_> = runner.orderings().collect();
+
+        assert_eq!(plans.len(), first * second);
+
+        for plan in plans {
+            let start = plan.iter().position(|act| act.client_id == "C").unwrap();
+            assert!(plan[..start].iter().all(|act| act.client_id != "C"));
+            assert!(plan[start..].iter().all(|act| act.client_id == "C"));
+        }
+    }
+
+    #[test]
+    fn hands_out_orderings_in_chunks() {
+        let sizes: Vec<_> = chunks(0..10, 4).map(|chunk| chunk.len()).collect();
+        assert_eq!(sizes, [4, 4, 2]);
+    }
+
+    #[test]
+    fn collects_clients_from_every_phase() {
+        let scenario = phased_scenario();
+        let hooks = Hooks::default();
+        let runner = RunnerScenario::new(vec![Config::new()], &scenario, &hooks);
+
+        assert_eq!(runner.collect_ids(|p| p.clients()), ["A", "B", "C"]);
+    }
+
+    fn conflict_scenario() -> Scenario<(char, i32)> {
+        Scenario {
+            name: String::from("update/delete conflict"),
+            description: None,
+            init: Fixture::Docs(vec![("/x".into(), ('x', 1))]),
+            phases: vec![Box::new(|planner| {
+                planner.client("A").update("/x", |_| Some(('x', 2)));
+                planner.client("B").remove("/x");
+            })],
+            expects: Vec::new(),
+            step_checks: Vec::new(),
+            observe_checks: Vec::new(),
+            end_checks: Vec::new(),
+        }
+    }
+
+    fn check(config: Config) -> (bool, usize) {
+        let scenario = conflict_scenario();
+        let hooks = Hooks::default();
+        let runner = RunnerScenario::new(vec![config], &scenario, &hooks);
+
+        let (result, _, _) = check_single(&runner);
+        (result.is_pass(), result.repaired())
+    }
+
+    #[test]
+    fn passes_after_repair_when_the_repair_pass_fixes_every_execution() {
+        let config = Config::new().update(Update::GetBeforePut);
+
+        assert_eq!(check(config.clone()), (false, 0));
+
+        let (passed, repaired) = check(config.repair(true));
+        assert!(passed);
+        assert!(repaired > 0);
+    }
+
+    #[test]
+    fn recovers_from_every_broken_state_an_execution_passes_through() {
+        let config = Config::new().update(Update::GetBeforePut);
+
+        assert_eq!(check(config.clone()), (false, 0));
+        assert_eq!(check(config.recovery(true)), (true, 0));
+    }
+
+    struct Mistag;
+
+    impl CustomOp<(char, i32)> for Mistag {
+        fn name(&self) -> &str {
+            "mistag"
+        }
+
+        fn execute(&self, actor: &mut Actor<'_, (char, i32)>, path: &Path) {
+            actor.read_raw(path);
+            let entries = BTreeMap::from([("x".to_string(), Kind::Dir)]);
+            actor.write_raw(path, Some(Db::Dir(entries)));
+        }
+    }
+
+    #[test]
+    fn fails_where_recovery_cannot_restore_a_broken_state() {
+        let mut runner = Runner::new();
+        runner.add("mistag", &[("/x", ('x', 1))], |planner| {
+            planner.client("A").custom("/", Mistag);
+        });
+        let config = Config::new().recovery(true);
+        let scenario = RunnerScenario::new(vec![config], &runner.scenarios[0], &runner.hooks);
+
+        let TestResult::Fail { errors, .. } = check_single(&scenario).0 else {
+            panic!("expected the scenario to fail");
+        };
+        assert_eq!(
+            errors,
+            ["after recovery: dir '/' tags 'x' as a Dir, but '/x' is a Doc"]
+        );
+    }
+
+    #[test]
+    fn blames_the_acts_that_left_the_keys_an_error_names() {
+        let scenario = Scenario {
+            name: String::from("update/delete/update conflict"),
+            description: None,
+            init: Fixture::Docs(Vec::new()),
+            phases: vec![Box::new(|planner| {
+                planner.client("A").update("/x", |_| Some(('x', 1)));
+                planner.client("B").remove("/x");
+                planner.client("C").update("/x", |_| Some(('x', 2)));
+            })],
+            expects: Vec::new(),
+            step_checks: Vec::new(),
+            observe_checks: Vec::new(),
+            end_checks: Vec::new(),
+        };
+        let hooks = Hooks::default();
+        let config = Config::new().server_update(true);
+        let runner = RunnerScenario::new(vec![config], &scenario, &hooks);
+
+        let TestResult::Fail { blame, .. } = check_single(&runner).0 else {
+            panic!("expected the scenario to fail");
+        };
+        assert_eq!(
+            blame,
+            [
+                "'/' was last written by Act<B: unlink('/', 'x')> at t=11, \
+                 which removed 'x' that Act<A: link('/', 'x')> at t=3 added",
+                "'/x' was last written by Act<C: put('/x')> at t=12",
+            ]
+        );
+    }
+
+    #[test]
+    fn prints_the_keys_a_failure_changed() {
+        let scenario = Scenario {
+            name: String::from("update/delete conflict"),
+            description: None,
+            init: Fixture::Docs(vec![
+                ("/w".into(), ('w', 1)),
+                ("/x".into(), ('x', 1)),
+                ("/y".into(), ('y', 1)),
+            ]),
+            phases: vec![
+                Box::new(|planner| {
+                    planner.client("A").update("/z", |_| Some(('z', 1)));
+                    planner.client("B").remove("/y");
+                }),
+                Box::new(|planner| {
+                    planner.client("A").update("/x", |_| Some(('x', 2)));
+                    planner.client("B").remove("/x");
+                }),
+            ],
+            expects: Vec::new(),
+            step_checks: Vec::new(),
+            observe_checks: Vec::new(),
+            end_checks: Vec::new(),
+        };
+        let hooks = Hooks::default();
+        let config = Config::new().server_update(true);
+        let runner = RunnerScenario::new(vec![config.clone()], &scenario, &hooks);
+
+        let initial = runner.create_store(&config);
+        let (result, stats, _) = check_single(&runner);
+        let mut out = String::new();
+        result
+            .print(&mut out, &stats, &initial, &config, &runner.planners, None)
+            .unwrap();
+
+        let changes: Vec<_> = out
+            .lines()
+            .skip_while(|line| *line != "    changes to the store:")
+            .skip(1)
+            .take_while(|line| line.starts_with("        "))
+            .collect();
+
+        assert_eq!(
+            changes,
+            [
+                "        changed '/' => { rev: 5, value: Dir({\"w\": Doc, \"z\": Doc}) }",
+                "            was { rev: 1, value: Dir({\"w\": Doc, \"x\": Doc, \"y\": Doc}) }",
+                "        changed '/x' => { rev: 3, value: Doc(('x', 2), Meta(A at t=15, size 5)) }",
+                "            was { rev: 1, value: Doc(('x', 1), Meta(setup, size 5)) }",
+                "        removed '/y' => { rev: 2, value: <null> }",
+                "            was { rev: 1, value: Doc(('y', 1), Meta(setup, size 5)) }",
+                "        added '/z' => { rev: 1, value: Doc(('z', 1), Meta(A at t=4, size 5)) }",
+                "        (1 key unchanged)",
+            ]
+        );
+    }
+
+    #[test]
+    fn lays_out_the_execution_in_a_column_per_client() {
+        let scenario = conflict_scenario();
+        let hooks = Hooks::default();
+        let config = Config::new().update(Update::GetBeforePut);
+        let runner = RunnerScenario::new(vec![config.clone()], &scenario, &hooks);
+
+        let initial = runner.create_store(&config);
+        let (result, stats, _) = check_single(&runner);
+        let mut out = String::new();
+        result
+            .print(&mut out, &stats, &initial, &config, &runner.planners, None)
+            .unwrap();
+
+        let execution: Vec<_> = out
+            .lines()
+            .skip_while(|line| *line != "    execution:")
+            .skip(1)
+            .take_while(|line| *line != "    steps:")
+            .collect();
+
+        assert_eq!(
+            execution,
+            [
+                "                A                B",
+                "        [t=1]   list('/')",
+                "        [t=2]   link('/', 'x')",
+                "        [t=3]                    list('/')",
+                "        [t=4]                    get('/x')",
+                "        [t=5]                    rm('/x')",
+                "        [t=6]   get('/x')",
+                "        [t=7]   put('/x')",
+                "    ==> [t=8]                    unlink('/', 'x')",
+            ]
+        );
+    }
+
+    #[test]
+    fn tells_what_each_step_read_wrote_and_broke() {
+        let scenario = conflict_scenario();
+        let hooks = Hooks::default();
+        let config = Config::new().update(Update::GetBeforePut);
+        let runner = RunnerScenario::new(vec![config.clone()], &scenario, &hooks);
+
+        let initial = runner.create_store(&config);
+        let (result, stats, _) = check_single(&runner);
+        let mut out = String::new();
+        result
+            .print(&mut out, &stats, &initial, &config, &runner.planners, None)
+            .unwrap();
+
+        let steps: Vec<_> = out
+            .lines()
+            .skip_while(|line| *line != "    steps:")
+            .skip(1)
+            .collect();
+
+        assert_eq!(
+            steps,
+            [
+                "        Act<A: list('/')> at t=1",
+                "            ok, saw '/' at rev 1",
+                "            read '/'",
+                "        Act<A: link('/', 'x')> at t=2",
+                "            ok, saw '/' at rev 1",
+                "            wrote '/'",
+                "        Act<B: list('/')> at t=3",
+                "            ok, saw '/' at rev 2",
+                "            read '/'",
+                "        Act<B: get('/x')> at t=4",
+                "            ok, saw '/x' at rev 1",
+                "            read '/x'",
+                "        Act<B: rm('/x')> at t=5",
+                "            ok, saw '/x' at rev 1",
+                "            deleted '/x'",
+                "        Act<A: get('/x')> at t=6",
+                "            ok, saw '/x' at rev 2",
+                "            read '/x'",
+                "        Act<A: put('/x')> at t=7",
+                "            ok, saw '/x' at rev 2",
+                "            created '/x'",
+                "        Act<B: unlink('/', 'x')> at t=8",
+                "            ok, saw '/' at rev 2",
+                "            wrote '/': removed 'x'",
+                "            broke: dir '/' does not include name 'x', required by doc '/x' \
+                 ('/x' written by Act<A: put('/x')> at t=7)",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_that_each_written_doc_names_a_client_that_put_it() {
+        let mut planner: Planner<(char, i32)> = Planner::new(Config::new());
+        planner.client("A").update("/x", |_| Some(('a', 1)));
+        planner.client("B").remove("/y");
+        let plan: Vec<_> = planner.orderings().next().unwrap();
+
+        let docs = [("/x".into(), ('x', 1)), ("/y".into(), ('y', 1))];
+        let initial = db::build_store(&Config::new(), &docs);
+
+        let mut state = initial.clone();
+        state.write("/x".into(), Some(1), Db::doc_from(('a', 1), 1, "A"));
+        assert_eq!(check_writers(&plan, &initial, &state), Vec::<String>::new());
+
+        state.write("/y".into(), Some(1), Db::doc_from(('b', 1), 2, "B"));
+        assert_eq!(
+            check_writers(&plan, &initial, &state),
+            ["doc '/y' was written by 'B', which has no put to it"]
+        );
+    }
+
+    fn counter(store: &DbStore<(char, i32)>) -> i32 {
+        db::doc_value(store, "/x").map_or(0, |(_, n)| n)
+    }
+
+    fn never_decreases(
+        before: &DbStore<(char, i32)>,
+        after: &DbStore<(char, i32)>,
+    ) -> Result<(), String> {
+        let (old, new) = (counter(before), counter(after));
+        if new < old {
+            return Err(format!("counter went from {} to {}", old, new));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn runs_a_scenarios_own_checks_after_each_step() {
+        let mut runner = Runner::new();
+        runner.add("increments", &[("/x", ('x', 1))], |planner| {
+            planner
+                .client("A")
+                .update("/x", |doc| doc.map(|(k, n)| (k, n + 1)));
+            planner
+                .client("B")
+                .update("/x", |doc| doc.map(|(k, n)| (k, n + 1)));
+        });
+        runner.check_each_step(never_decreases);
+
+        runner.add("increment/reset", &[("/x", ('x', 1))], |planner| {
+            planner
+                .client("A")
+                .update("/x", |doc| doc.map(|(k, n)| (k, n + 1)));
+            planner.client("B").update("/x", |_| Some(('x', 0)));
+        });
+        runner.check_each_step(never_decreases);
+
+        let passing = RunnerScenario::new(vec![Config::new()], &runner.scenarios[0], &runner.hooks);
+        assert!(check_single(&passing).0.is_pass());
+
+        let failing = RunnerScenario::new(vec![Config::new()], &runner.scenarios[1], &runner.hooks);
+        let TestResult::Fail {
+            errors, plan, step, ..
+        } = check_single(&failing).0
+        else {
+            panic!("expected the scenario to fail");
+        };
+        assert_eq!(errors, ["counter went from 2 to 0"]);
+        assert_eq!(plan[step].client_id, "B");
+        assert!(matches!(plan[step].op, Op::Put(_)));
+    }
+
+    #[test]
+    fn runs_a_scenarios_observation_checks_after_each_observe() {
+        let mut runner = Runner::new();
+        runner.add("read during update", &[("/x", ('x', 1))], |planner| {
+            planner
+                .client("A")
+                .update("/x", |doc| doc.map(|(k, n)| (k, n + 1)));
+            planner.client("B").get("/x");
+        });
+        runner.check_observations(|key, value, store| {
+            let stored = db::doc_value(store, key);
+            if value != stored.as_ref() {
+                return Err(format!("saw {:?} in '{}' holding {:?}", value, key, stored));
+            }
+            Ok(())
+        });
+
+        runner.add("stale read", &[("/x", ('x', 1))], |planner| {
+            planner
+                .client("A")
+                .update("/x", |doc| doc.map(|(k, n)| (k, n + 1)));
+            planner.client("B").get("/x");
+        });
+        runner.check_observations(|key, value, _| match value {
+            Some(('x', 1)) => Ok(()),
+            _ => Err(format!("'{}' read as {:?}", key, value)),
+        });
+
+        let passing = RunnerScenario::new(vec![Config::new()], &runner.scenarios[0], &runner.hooks);
+        assert!(check_single(&passing).0.is_pass());
+
+        let failing = RunnerScenario::new(vec![Config::new()], &runner.scenarios[1], &runner.hooks);
+        let TestResult::Fail {
+            errors, plan, step, ..
+        } = check_single(&failing).0
+        else {
+            panic!("expected the scenario to fail");
+        };
+        assert_eq!(errors, ["'/x' read as Some(('x', 2))"]);
+        assert_eq!(plan[step].client_id, "B");
+        assert!(matches!(plan[step].op, Op::Observe));
+    }
+
+    #[test]
+    fn fails_where_a_client_lists_entries_it_did_not_expect() {
+        let mut runner = Runner::new();
+        runner.add(
+            "list during update",
+            &[("/x", ('x', 1)), ("/y", ('y', 1))],
+            |planner| {
+                planner
+                    .client("A")
+                    .update("/x", |doc| doc.map(|(k, n)| (k, n + 1)));
+                planner.client("B").expect_list("/", &["x", "y"]);
+            },
+        );
+
+        runner.add("list during create", &[], |planner| {
+            planner.client("A").update("/x", |_| Some(('a', 1)));
+            planner.client("B").expect_list_with("/", |entries| {
+                if entries.contains("x") {
+                    return Err("listed 'x' before it was created".into());
+                }
+                Ok(())
+            });
+        });
+
+        let passing = RunnerScenario::new(vec![Config::new()], &runner.scenarios[0], &runner.hooks);
+        assert!(check_single(&passing).0.is_pass());
+
+        let failing = RunnerScenario::new(vec![Config::new()], &runner.scenarios[1], &runner.hooks);
+        let TestResult::Fail {
+            errors, plan, step, ..
+        } = check_single(&failing).0
+        else {
+            panic!("expected the scenario to fail");
+        };
+        assert_eq!(errors, ["listed 'x' before it was created"]);
+        assert_eq!(plan[step].client_id, "B");
+        assert!(matches!(plan[step].op, Op::ExpectList(_)));
+    }
+
+    #[test]
+    fn applies_a_transaction_atomically_with_write_batches() {
+        let mut runner = Runner::new();
+        let setup = [("/x", ('x', 1)), ("/y", ('y', 1))];
+        runner.add("transaction/update", &setup, |planner| {
+            planner.client("A").transaction(|tx| {
+                tx.update("/x", |_| Some(('a', 2)));
+                tx.update("/y", |_| Some(('a', 2)));
+            });
+            planner
+                .client("B")
+                .update("/y", |doc| doc.map(|(k, n)| (k, n + 10)));
+        });
+        runner.check_at_end(|store| {
+            let (x, y) = (db::doc_value(store, "/x"), db::doc_value(store, "/y"));
+            let written = |doc: Option<(char, i32)>| doc.is_some_and(|(k, _)| k == 'a');
+            if written(x) != written(y) {
+                return Err(format!(
+                    "transaction half applied: x = {:?}, y = {:?}",
+                    x, y
+                ));
+            }
+            Ok(())
+        });
+        let scenario = &runner.scenarios[0];
+
+        let batched = Config::new().layout(Layout::Flat).write_batch(true);
+        let passing = RunnerScenario::new(vec![batched], scenario, &runner.hooks);
+        assert!(check_single(&passing).0.is_pass());
+
+        let per_key = Config::new().layout(Layout::Flat);
+        let failing = RunnerScenario::new(vec![per_key], scenario, &runner.hooks);
+        let TestResult::Fail { errors, .. } = check_single(&failing).0 else {
+            panic!("expected the scenario to fail");
+        };
+        assert_eq!(
+            errors,
+            ["transaction half applied: x = Some(('a', 2)), y = Some(('y', 11))"]
+        );
+    }
+
+    #[test]
+    fn runs_a_scenarios_own_checks_at_the_end() {
+        let mut runner = Runner::new();
+        runner.add("update/delete conflict", &[("/x", ('x', 1))], |planner| {
+            planner.client("A").update("/x", |_| Some(('x', 2)));
+            planner.client("B").remove("/x");
+        });
+        runner.check_at_end(|store| match db::doc_value(store, "/x") {
+            Some(_) => Ok(()),
+            None => Err(String::from("doc '/x' is gone")),
+        });
+
+        let scenario =
+            RunnerScenario::new(vec![Config::new()], &runner.scenarios[0], &runner.hooks);
+        let TestResult::Fail {
+            errors, step, plan, ..
+        } = check_single(&scenario).0
+        else {
+            panic!("expected the scenario to fail");
+        };
+        assert_eq!(errors, ["doc '/x' is gone"]);
+        assert_eq!(step, plan.len() - 1);
+    }
+
+    #[test]
+    fn prints_what_each_client_had_cached_of_the_keys_an_error_names() {
+        let scenario = conflict_scenario();
+        let hooks = Hooks::default();
+        let config = Config::new().update(Update::GetBeforePut);
+        let runner = RunnerScenario::new(vec![config.clone()], &scenario, &hooks);
+
+        let initial = runner.create_store(&config);
+        let (result, stats, _) = check_single(&runner);
+        let mut out = String::new();
+        result
+            .print(&mut out, &stats, &initial, &config, &runner.planners, None)
+            .unwrap();
+
+        let views: Vec<_> = out
+            .lines()
+            .skip_while(|line| *line != "    client views:")
+            .skip(1)
+            .take_while(|line| line.starts_with("        "))
+            .collect();
+
+        assert_eq!(
+            views,
+            [
+                "        '/':",
+                "            store => { rev: 3, value: Dir({}) }",
+                "            A => { rev: 2, value: Dir({\"x\": Doc}) }",
+                "            B => { rev: 3, value: Dir({}) }",
+                "        '/x':",
+                "            store => { rev: 3, value: Doc(('x', 2), Meta(A at t=7, size 5)) }",
+                "            A => { rev: 3, value: Doc(('x', 2), Meta(A at t=7, size 5)) }",
+                "            B => <null>",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_the_graph_node_of_the_failing_act() {
+        let scenario = conflict_scenario();
+        let hooks = Hooks::default();
+        let config = Config::new().update(Update::GetBeforePut);
+        let runner = RunnerScenario::new(vec![config.clone()], &scenario, &hooks);
+
+        let initial = runner.create_store(&config);
+        let (result, stats, _) = check_single(&runner);
+        let mut out = String::new();
+        result
+            .print(&mut out, &stats, &initial, &config, &runner.planners, None)
+            .unwrap();
+
+        let line = out
+            .lines()
+            .find(|line| line.starts_with("    failing act:"));
+        assert_eq!(
+            line,
+            Some("    failing act: n8 (B: unlink('/', 'x')), after n7")
+        );
+        assert!(out.contains("    plan:\n        A:\n"));
+        assert!(out.contains("\n            n8 unlink('/', 'x'), after rm('/x')\n"));
+    }
+
+    #[test]
+    fn passes_always_when_no_execution_needs_repair() {
+        assert_eq!(check(Config::new().repair(true)), (true, 0));
+    }
+
+    #[test]
+    fn times_each_part_of_checking_a_scenario() {
+        let scenario = conflict_scenario();
+        let hooks = Hooks::default();
+        let runner = RunnerScenario::new(vec![Config::new()], &scenario, &hooks);
+
+        let (_, stats, _) = check_single(&runner);
+        let timings = stats.timings;
+
+        assert!(timings.generate > Duration::ZERO);
+        assert!(timings.clone > Duration::ZERO);
+        assert!(timings.dispatch > Duration::ZERO);
+        assert!(timings.check > Duration::ZERO);
+    }
+
+    #[test]
+    fn counts_the_distinct_states_that_executions_end_in() {
+        let scenario = conflict_scenario();
+        let hooks = Hooks::default();
+        let runner = RunnerScenario::new(vec![Config::new()], &scenario, &hooks);
+
+        let (result, stats, _) = check_single(&runner);
+
+        assert_eq!((result.count(), stats.states), (280, 9));
+    }
+
+    #[test]
+    fn checks_every_ordering_when_deepening_by_preemptions() {
+        let scenario = conflict_scenario();
+        let hooks = Hooks::default();
+        let mut runner = RunnerScenario::new(vec![Config::new()], &scenario, &hooks);
+        runner.deepen = true;
+
+        let (result, stats, _) = check_single(&runner);
+
+        assert!(result.is_pass());
+        assert_eq!((result.count(), stats.states), (280, 9));
+        assert_eq!(stats.preemptions, Some(6));
+    }
+
+    #[test]
+    fn stops_deepening_at_the_most_preemptions_allowed() {
+        let scenario = conflict_scenario();
+        let hooks = Hooks::default();
+        let mut runner = RunnerScenario::new(vec![Config::new()], &scenario, &hooks);
+        runner.deepen = true;
+        runner.max_preemptions = Some(2);
+
+        let (result, stats, _) = check_single(&runner);
+
+        assert!(result.is_pass());
+        assert!(result.count() < 280);
+        assert_eq!((stats.preemptions, stats.bounded), (Some(2), true));
+        assert_eq!(format_preemptions(&stats), ", bounded at 2 preemptions");
+    }
+
+    #[test]
+    fn stops_deepening_at_the_first_failing_bound() {
+        let scenario = conflict_scenario();
+        let hooks = Hooks::default();
+        let config = Config::new().update(Update::GetBeforePut);
+        let mut runner = RunnerScenario::new(vec![config], &scenario, &hooks);
+        runner.deepen = true;
+
+        let (result, stats, _) = check_single(&runner);
+
+        assert!(!result.is_pass());
+        assert_eq!(stats.preemptions, Some(1));
+    }
+
+    #[test]
+    fn reports_the_same_failure_on_every_run() {
+        let scenario = conflict_scenario();
+        let hooks = Hooks::default();
+        let config = Config::new().skip_links(true);
+        let runner = RunnerScenario::new(vec![config.clone()], &scenario, &hooks);
+        let store = runner.create_store(&config);
+
+        let check = || {
+            let (result, stats, _) = check_single(&runner);
+            let mut report = String::new();
+            result
+                .print(&mut report, &stats, &store, &config, &runner.planners, None)
+                .unwrap();
+
+            let lines: Vec<_> = report
+                .lines()
+                .filter(|line| !line.contains("time:"))
+                .map(String::from)
+                .collect();
+            (result.count(), stats.states, lines)
+        };
+
+        let first = check();
+        assert!(passes_first(&runner, first.0 - 1));
+        assert!(!passes_first(&runner, first.0));
+
+        for _ in 0..10 {
+            assert_eq!(check(), first);
+        }
+    }
+
+    // Whether the first `n` orderings all pass under the scenario's only
+    // config.
+    fn passes_first(runner: &RunnerScenario<'_, (char, i32)>, n: usize) -> bool {
+        let store = runner.create_store(&runner.configs[0]);
+        let client_ids = runner.collect_ids(|planner| planner.clients());
+
+        let worker = Worker {
+            config: runner.configs[0].clone(),
+            client_ids: &client_ids,
+            partitioned: &[],
+            timeouts: &[],
+            restarts: &[],
+            stopping: &[],
+            crashed: &[],
+            teardown: None,
+            known_errors: &[],
+            store: &store,
+            final_check: None,
+            expects: &[],
+            step_checks: &[],
+            observe_checks: &[],
+            end_checks: &[],
+            record: false,
+            records: Mutex::new(Vec::new()),
+            timings: Mutex::new(Timings::default()),
+            states: Mutex::new(HashMap::new()),
+            coverage: Mutex::new(HashMap::new()),
+        };
+        let orderings = Box::new(runner.orderings().take(n));
+        let mut generating = Duration::ZERO;
+        let mut checks = runner.check_orderings(&[worker], orderings, 0, &mut generating);
+
+        checks.pop().unwrap().is_ok()
+    }
+
+    #[test]
+    fn splits_phased_orderings_by_their_total_preemptions() {
+        let scenario = phased_scenario();
+        let hooks = Hooks::default();
+        let runner = RunnerScenario::new(vec![Config::new()], &scenario, &hooks);
+
+        let counts: Vec<_> = (0..=6)
+            .map(|bound| runner.orderings_within(bound).count())
+            .collect();
+
+        assert_eq!(counts, [16, 48, 144, 144, 144, 48, 16]);
+        assert_eq!(counts.iter().sum::<usize>(), runner.orderings().count());
+
+        runner.exceeded.store(false, Ordering::Relaxed);
+        assert_eq!(runner.orderings_within(6).count(), 16);
+        assert!(!runner.exceeded.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn samples_scenarios_with_more_orderings_than_the_budget() {
+        let scenario = conflict_scenario();
+        let hooks = Hooks::default();
+        let mut runner = RunnerScenario::new(vec![Config::new()], &scenario, &hooks);
+        runner.budget = Some(50);
+
+        let (result, stats, _) = check_single(&runner);
+
+        assert!(result.is_pass());
+        assert_eq!(result.count(), 50);
+        assert_eq!(stats.sampled, Some(280));
+        assert_eq!(format_count(50, stats.sampled), "50 sampled of 280");
+
+        runner.budget = Some(280);
+        let (result, stats, _) = check_single(&runner);
+
+        assert_eq!((result.count(), stats.sampled), (280, None));
+    }
+
+    #[test]
+    fn samples_the_same_orderings_for_the_same_seed() {
+        let scenario = phased_scenario();
+        let hooks = Hooks::default();
+        let mut runner = RunnerScenario::new(vec![Config::new()], &scenario, &hooks);
+
+        let plans = |runner: &RunnerScenario<_>| -> Vec<String> {
+            runner
+                .sample_orderings(10)
+                .map(|plan| format!("{:?}", plan))
+                .collect()
+        };
+        let first = plans(&runner);
+        assert_eq!(first.len(), 10);
+        assert_eq!(plans(&runner), first);
+
+        runner.seed = 1;
+        assert_ne!(plans(&runner), first);
+    }
+
+    #[test]
+    fn reads_phased_orderings_back_from_the_cache() {
+        let dir = std::env::temp_dir().join(format!("mc2-runner-{}", std::process::id()));
+        let cache = OrderingCache::open(dir.to_str().unwrap()).unwrap();
+
+        let scenario = phased_scenario();
+        let hooks = Hooks::default();
+        let mut runner = RunnerScenario::new(vec![Config::new()], &scenario, &hooks);
+
+        let plans = |runner: &RunnerScenario<_>| -> Vec<String> {
+            runner
+                .orderings()
+                .map(|plan| format!("{:?}", plan))
+                .collect()
+        };
+        let uncached = plans(&runner);
+        runner.cache = Some(&cache);
+
+        assert_eq!(plans(&runner), uncached);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        assert_eq!(plans(&runner), uncached);
+
+        let (result, _, _) = check_single(&runner);
+        assert_eq!(result.count(), uncached.len());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shrinks_to_the_items_that_make_the_test_fail_together() {
+        let minimal = ddmin((0..10).collect(), |items: &[i32]| {
+            items.contains(&3) && items.contains(&7)
+        });
+
+        assert_eq!(minimal, [3, 7]);
+
+        assert_eq!(ddmin(vec![5], |_| true), [5]);
+    }
+
+    #[test]
+    fn formats_timings() {
+        let timings = Timings {
+            generate: Duration::from_millis(1250),
+            clone: Duration::from_millis(30),
+            dispatch: Duration::from_secs(4),
+            check: Duration::ZERO,
+        };
+        assert_eq!(
+            timings.to_string(),
+            "generate 1.25s, clone 0.03s, dispatch 4.00s, check 0.00s"
+        );
+    }
+
+    fn update_remove_runner(config: Config) -> Runner<(char, i32)> {
+        let mut runner = Runner::new();
+        runner.add(
+            "increment/delete conflict",
+            &[("/x", ('x', 1))],
+            |planner| {
+                planner.client("A").remove("/x");
+                planner
+                    .client("B")
+                    .update("/x", |doc| doc.map(|(k, n)| (k, n + 1)));
+            },
+        );
+        runner.expect("/x", |acks| match (acks.acked("A"), acks.acked("B")) {
+            (true, _) => vec![None],
+            (false, true) => vec![Some(('x', 2)), None],
+            (false, false) => vec![Some(('x', 1)), None],
+        });
+        runner.configs(&[config]);
+        runner
+    }
+
+    fn check_values(config: Config) -> Option<Vec<String>> {
+        let runner = update_remove_runner(config.clone());
+        let scenario = RunnerScenario::new(vec![config], &runner.scenarios[0], &runner.hooks);
+
+        match check_single(&scenario).0 {
+            TestResult::Pass { .. } => None,
+            TestResult::Fail { errors, .. } => Some(errors),
+        }
+    }
+
+    #[test]
+    fn passes_when_final_values_match_the_acknowledged_clients() {
+        assert_eq!(check_values(Config::new()), None);
+    }
+
+    #[test]
+    fn does_not_acknowledge_a_client_cut_short() {
+        let mut runner = Runner::new();
+        runner.add("cut-short delete", &[("/x", ('x', 1))], |planner| {
+            planner.client("A").remove("/x");
+            planner
+                .client("B")
+                .update("/x", |doc| doc.map(|(k, n)| (k, n + 1)));
+            planner.crash_after("A", 0);
+        });
+        runner.expect("/x", |acks| match (acks.acked("A"), acks.acked("B")) {
+            (true, _) => vec![None],
+            (false, true) => vec![Some(('x', 2))],
+            (false, false) => vec![Some(('x', 1))],
+        });
+        let scenario =
+            RunnerScenario::new(vec![Config::new()], &runner.scenarios[0], &runner.hooks);
+
+        assert!(check_single(&scenario).0.is_pass());
+    }
+
+    #[test]
+    fn creates_a_doc_only_once_when_two_clients_race() {
+        let mut runner = Runner::new();
+        runner.add("create/create", &[("/y", ('y', 1))], |planner| {
+            planner.client("A").create("/x", ('a', 1));
+            planner.client("B").create("/x", ('b', 1));
+        });
+        runner.expect("/x", |acks| match (acks.acked("A"), acks.acked("B")) {
+            (true, false) => vec![Some(('a', 1))],
+            (false, true) => vec![Some(('b', 1))],
+            (false, false) => vec![None],
+            (true, true) => vec![Some(('a', 1)), Some(('b', 1))],
+        });
+        runner.add("update missing", &[("/y", ('y', 1))], |planner| {
+            let mut client = planner.client("C");
+            client.update_existing("/x", |(k, n)| (k, n + 1));
+            client.update_existing("/y", |(k, n)| (k, n + 1));
+        });
+        runner.expect("/x", |acks| match acks.acked("C") {
+            true => vec![],
+            false => vec![None],
+        });
+
+        for config in [Config::new(), Config::new().server_update(true)] {
+            for scenario in &runner.scenarios {
+                let scenario = RunnerScenario::new(vec![config.clone()], scenario, &runner.hooks);
+                assert!(check_single(&scenario).0.is_pass());
+            }
+        }
+    }
+
+    #[test]
+    fn touches_a_doc_without_changing_one_that_exists() {
+        let mut runner = Runner::new();
+        runner.add("touch", &[("/y", ('y', 1))], |planner| {
+            let mut client = planner.client("A");
+            client.touch("/x");
+            client.touch("/y");
+        });
+        runner.expect("/x", |acks| match acks.acked("A") {
+            true => vec![Some(('\0', 0))],
+            false => vec![None, Some(('\0', 0))],
+        });
+        runner.expect("/y", |_| vec![Some(('y', 1))]);
+
+        let scenario =
+            RunnerScenario::new(vec![Config::new()], &runner.scenarios[0], &runner.hooks);
+        assert!(check_single(&scenario).0.is_pass());
+    }
+
+    #[test]
+    fn acknowledges_clients_that_retry_a_conflicting_update() {
+        let mut runner = Runner::new();
+        runner.add("update/update", &[("/x", ('x', 1))], |planner| {
+            planner
+                .client("A")
+                .update("/x", |doc| doc.map(|(k, n)| (k, n + 1)));
+            planner
+                .client("B")
+                .update("/x", |doc| doc.map(|(k, n)| (k, n + 1)));
+        });
+        runner.expect("/x", |acks| match (acks.acked("A"), acks.acked("B")) {
+            (true, true) => vec![Some(('x', 3))],
+            _ => vec![],
+        });
+
+        let retrying = Config::new().retries(1);
+        let scenario = RunnerScenario::new(vec![retrying], &runner.scenarios[0], &runner.hooks);
+        assert!(check_single(&scenario).0.is_pass());
+
+        let scenario =
+            RunnerScenario::new(vec![Config::new()], &runner.scenarios[0], &runner.hooks);
+        assert!(!check_single(&scenario).0.is_pass());
+    }
+
+    #[test]
+    fn sums_the_stats_of_a_scenarios_phases() {
+        let mut runner = Runner::new();
+        runner.add("update, then remove", &[("/x", ('x', 1))], |planner| {
+            planner.client("A").update("/x", |doc| doc);
+        });
+        runner.then(|planner| {
+            planner.client("A").remove("/x");
+            planner.client("B").update("/y", |doc| doc);
+        });
+        let scenario =
+            RunnerScenario::new(vec![Config::new()], &runner.scenarios[0], &runner.hooks);
+
+        let (first, second) = (scenario.planners[0].stats(), scenario.planners[1].stats());
+        let stats = scenario.stats();
+
+        assert_eq!(stats.acts["A"], first.acts["A"] + second.acts["A"]);
+        assert_eq!(stats.acts["B"], second.acts["B"]);
+        assert_eq!(stats.depth, first.depth + second.depth);
+        assert_eq!(stats.width, first.width.max(second.width));
+        assert_eq!(stats.orderings, scenario.count_orderings());
+    }
+
+    #[test]
+    fn prints_the_description_of_a_failing_scenario() {
+        let config = Config::new().store(Cas::Lax);
+        let mut runner = update_remove_runner(config.clone());
+        runner.describe("A: remove('/x')\nB: update('/x')");
+        let scenario = RunnerScenario::new(vec![config], &runner.scenarios[0], &runner.hooks);
+
+        let mut reports = [String::new()];
+        scenario.run(&mut reports);
+
+        assert!(reports[0]
+            .contains("    scenario:\n        A: remove('/x')\n        B: update('/x')\n"));
+    }
+
+    #[test]
+    fn reports_updates_that_undo_an_acknowledged_remove() {
+        let errors = check_values(Config::new().store(Cas::Lax)).unwrap();
+
+        assert_eq!(
+            errors,
+            [
+                "doc '/x' has value Some(('x', 2)), expected one of [None] with acknowledged clients {\"A\", \"B\"}"
+            ]
+        );
+    }
+    // A set of items kept in the order they were added, which the checks
+    // compare in any order.
+    #[derive(Clone, Debug, Hash, PartialEq)]
+    struct Bag(Vec<char>);
+
+    impl Size for Bag {
+        fn size(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    impl Accept for Bag {
+        fn accepts(&self, actual: &Bag) -> bool {
+            let (mut a, mut b) = (self.0.clone(), actual.0.clone());
+            a.sort();
+            b.sort();
+            a == b
+        }
+    }
+
+    fn add(item: char) -> impl Fn(Option<Bag>) -> Option<Bag> {
+        move |doc| {
+            doc.map(|Bag(mut items)| {
+                items.push(item);
+                Bag(items)
+            })
+        }
+    }
+
+    #[test]
+    fn compares_expected_values_as_their_type_accepts_them() {
+        assert!(Bag(vec!['a', 'b']).accepts(&Bag(vec!['b', 'a'])));
+        assert!(!Bag(vec!['a', 'b']).accepts(&Bag(vec!['a'])));
+
+        let mut runner = Runner::new();
+        runner.add("add/add", &[("/x", Bag(Vec::new()))], |planner| {
+            planner.client("A").update("/x", add('a'));
+            planner.client("B").update("/x", add('b'));
+        });
+        runner.expect("/x", |acks| {
+            let items = [('a', acks.acked("A")), ('b', acks.acked("B"))];
+            let items = items.iter().filter(|(_, acked)| *acked);
+            vec![Some(Bag(items.map(|(item, _)| *item).collect()))]
+        });
+
+        let config = Config::new();
+        let scenario =
+            RunnerScenario::new(vec![config.clone()], &runner.scenarios[0], &runner.hooks);
+        let store = scenario.create_store(&config);
+        let mut checks = scenario.check_execution(&[store], &mut String::new());
+
+        assert!(checks.pop().unwrap().0.is_pass());
+    }
+
+    #[test]
+    fn checks_the_states_a_client_leaves_when_it_stops_early() {
+        let mut runner = Runner::new();
+        runner.add("rename", &[("/x", ('x', 1))], |planner| {
+            planner.client("A").rename("/x", "/y", |_| Some(('x', 1)));
+        });
+        runner.check_at_end(|store| {
+            let docs = ["/x", "/y"].map(|key| db::doc_value(store, key));
+            match docs.iter().flatten().count() {
+                1 => Ok(()),
+                n => Err(format!("{} docs hold the renamed value", n)),
+            }
+        });
+        let mut scenario =
+            RunnerScenario::new(vec![Config::new()], &runner.scenarios[0], &runner.hooks);
+
+        assert!(check_single(&scenario).0.is_pass());
+
+        scenario.stop_early = true;
+        let TestResult::Fail { errors, fault, .. } = check_single(&scenario).0 else {
+            panic!("expected the scenario to fail");
+        };
+        assert_eq!(errors, ["2 docs hold the renamed value"]);
+        assert_eq!(
+            fault.map(|f| f.describe()),
+            Some(String::from("client 'A' stops before its act #7"))
+        );
+    }
+
+    #[test]
+    fn reports_the_fault_points_that_never_struck() {
+        let mut runner = Runner::new();
+        runner.add("put nothing", &[("/x", ('x', 1))], |planner| {
+            planner.client("A").put_unlinked("/y", |_| None);
+            planner.client("B").update("/x", |_| Some(('b', 1)));
+            planner.time_out("A");
+            planner.partition("B");
+        });
+        let scenario =
+            RunnerScenario::new(vec![Config::new()], &runner.scenarios[0], &runner.hooks);
+        let (result, stats, _) = check_single(&scenario);
+
+        assert!(result.is_pass());
+        assert_eq!(stats.faults, 6);
+        assert_eq!(
+            stats.unreachable,
+            [
+                "client 'A' times out on its act #2 (not applied)",
+                "client 'A' times out on its act #2 (applied)",
+            ]
+        );
+    }
+
+    #[test]
+    fn restarts_clients_before_each_of_their_acts() {
+        let mut runner = Runner::new();
+        runner.add("create, then delete", &[("/a/x", ('x', 1))], |planner| {
+            planner.client("A").update("/b/c/y", |_| Some(('y', 1)));
+        });
+        runner.then(|planner| {
+            planner.client("B").remove("/b/c/y");
+            planner.restart("B");
+        });
+
+        let mut scenario =
+            RunnerScenario::new(vec![Config::new()], &runner.scenarios[0], &runner.hooks);
+        let (result, stats, _) = check_single(&scenario);
+        assert!(result.is_pass());
+        assert_eq!(stats.faults, 7);
+
+        scenario.teardown = true;
+        let TestResult::Fail { errors, fault, .. } = check_single(&scenario).0 else {
+            panic!("expected the scenario to fail");
+        };
+        assert_eq!(
+            errors,
+            [
+                "teardown left '/' changed",
+                "teardown left '/b/' behind",
+                "teardown left '/b/c/' behind"
+            ]
+        );
+        assert_eq!(
+            fault.map(|f| f.describe()),
+            Some(String::from("client 'B' restarts before its act #6"))
+        );
+    }
+
+    #[test]
+    fn tears_down_each_execution_back_to_the_initial_state() {
+        let mut runner = Runner::new();
+        runner.add("create, then delete", &[("/a/x", ('x', 1))], |planner| {
+            planner.client("A").update("/b/c/y", |_| Some(('y', 1)));
+        });
+        runner.then(|planner| planner.client("B").remove("/a/x"));
+        runner.add("link only", &[("/a/x", ('x', 1))], |planner| {
+            planner.client("A").link_only("/b/y");
+        });
+
+        let check = |i: usize| {
+            let mut scenario =
+                RunnerScenario::new(vec![Config::new()], &runner.scenarios[i], &runner.hooks);
+            scenario.teardown = true;
+            match check_single(&scenario).0 {
+                TestResult::Pass { .. } => Vec::new(),
+                TestResult::Fail { errors, .. } => errors,
+            }
+        };
+
+        assert_eq!(check(0), Vec::<String>::new());
+        assert_eq!(
+            check(1),
+            ["teardown left '/' changed", "teardown left '/b/' behind"]
+        );
+    }
+
+    #[test]
+    fn does_not_count_leftover_entries_against_a_config_without_unlinks() {
+        let mut runner = Runner::new();
+        runner.add("create, then delete", &[("/a/x", ('x', 1))], |planner| {
+            planner.client("A").update("/b/c/y", |_| Some(('y', 1)));
+        });
+        runner.then(|planner| planner.client("B").remove("/a/x"));
+
+        let config = Config::new().remove(Remove::NoUnlink);
+        let mut scenario = RunnerScenario::new(vec![config], &runner.scenarios[0], &runner.hooks);
+        assert!(check_single(&scenario).0.is_pass());
+
+        scenario.teardown = true;
+        assert!(check_single(&scenario).0.is_pass());
+    }
+
+    #[test]
+    fn bisects_a_failure_to_the_config_fields_responsible() {
+        let baseline = Config::new();
+        let config = Config::new()
+            .store(Cas::Lax)
+            .timeout(Timeout::Retry)
+            .max_value_size(1000);
+        let runner = update_remove_runner(config.clone());
+        let scenario = &runner.scenarios[0];
+
+        assert_eq!(
+            runner.bisect_failure(scenario, &baseline, &config),
+            Some(vec![Field::Store])
+        );
+        assert_eq!(runner.bisect_failure(scenario, &config, &baseline), None);
+    }
+
+    #[test]
+    fn finds_the_dependencies_a_plan_fails_without() {
+        let mut runner = Runner::new();
+        runner.add("create", &[("/a/x", ('x', 1))], |planner| {
+            planner.client("A").update("/b/y", |_| Some(('y', 1)));
+        });
+        let edges = runner.check_necessity(&runner.scenarios[0], &Config::new());
+
+        let needed: Vec<_> = edges
+            .iter()
+            .filter(|(_, need)| *need)
+            .map(|(edge, _)| edge.as_str())
+            .collect();
+        assert_eq!(
+            needed,
+            [
+                "A: link('/', 'b/') -> A: put('/b/y')",
+                "A: link('/b/', 'y') -> A: put('/b/y')",
+            ]
+        );
+        assert_eq!(edges.len(), 8);
+    }
+
+    #[test]
+    fn finds_the_least_constrained_config_that_passes() {
+        let mut runner = update_remove_runner(Config::new().store(Cas::Lax));
+        runner.configs(&[Config::new(), Config::new().store(Cas::MatchRev)]);
+        runner.run();
+
+        assert_eq!(runner.weakest(0), Some(2));
+        assert_eq!(runner.frontier(), [2]);
+        for index in [1, 2] {
+            assert_eq!(
+                runner.failed_relaxations(index),
+                [("store: Lax".to_string(), vec!["increment/delete conflict"])]
+            );
+        }
+    }
+
+    #[test]
+    fn checks_configs_that_plan_alike_in_one_pass() {
+        let configs = vec![Config::new(), Config::new().store(Cas::Lax)];
+        let runner = update_remove_runner(Config::new());
+        let scenario = RunnerScenario::new(configs.clone(), &runner.scenarios[0], &runner.hooks);
+
+        let stores: Vec<_> = configs.iter().map(|c| scenario.create_store(c)).collect();
+        let checks = scenario.check_execution(&stores, &mut String::new());
+        let single = RunnerScenario::new(vec![Config::new()], &runner.scenarios[0], &runner.hooks);
+        let alone = check_single(&single);
+
+        assert!(checks[0].0.is_pass());
+        assert_eq!(checks[0].0.count(), alone.0.count());
+        assert_eq!(checks[0].1.states, alone.1.states);
+        assert!(!checks[1].0.is_pass());
+    }
+
+    #[test]
+    fn groups_configs_by_their_plans() {
+        let mut runner: Runner<(char, i32)> = Runner::new();
+        runner.configs(&[
+            Config::new(),
+            Config::new().update(Update::GetBeforePut),
+            Config::new().store(Cas::Lax),
+        ]);
+
+        let sizes: Vec<_> = runner.config_groups().iter().map(Vec::len).collect();
+        assert_eq!(sizes, [2, 1]);
+
+        runner.deepen();
+
+        let sizes: Vec<_> = runner.config_groups().iter().map(Vec::len).collect();
+        assert_eq!(sizes, [1, 1, 1]);
+    }
+
+    #[test]
+    fn runs_nothing_without_configs() {
+        let mut runner = Runner::new();
+        runner.add("update", &[("/x", ('x', 1))], |planner| {
+            planner.client("A").update("/x", |_| Some(('a', 1)));
+        });
+        runner.run();
+
+        assert!(runner.results.is_empty());
+    }
+
+    #[test]
+    fn finds_the_same_failure_with_reduction_under_any_layout() {
+        for layout in [Layout::DirDocs, Layout::Flat, Layout::Buckets { count: 2 }] {
+            let passes = |reduce: bool| {
+                let mut runner: Runner<(char, i32)> = Runner::new();
+                runner.configs(&[Config::new().layout(layout.clone())]);
+
+                runner.add("list", &[("/d/y", ('y', 1))], move |planner| {
+                    planner.client("A").expect_list_with("/d/", |entries| {
+                        match entries.contains("x") {
+                            true => Err("listed 'x'".to_string()),
+                            false => Ok(()),
+                        }
+                    });
+                    planner.client("B").update("/d/x", |_| Some(('b', 1)));
+                    if reduce {
+                        planner.reduce();
+                    }
+                });
+                runner.run();
+                runner.results[0].1[0].passed
+            };
+            assert!(!passes(false), "{:?}", layout);
+            assert!(!passes(true), "{:?}", layout);
+        }
+    }
+
+    #[test]
+    fn skips_scenarios_over_the_cap_unless_forced_or_sampled() {
+        let mut runner: Runner<(char, i32)> = Runner::new();
+        runner.configs(&[Config::new()]);
+
+        runner.add("small", &[("/x", ('x', 1))], |planner| {
+            planner.client("A").update("/x", |_| Some(('a', 1)));
+        });
+        runner.add("large", &[("/x", ('x', 1))], |planner| {
+            for id in ["A", "B", "C", "D"] {
+                planner.client(id).update("/x", |_| Some(('a', 1)));
+            }
+        });
+        runner.run();
+
+        let skipped: Vec<_> = runner.results[0].1.iter().map(|o| o.skipped).collect();
+        assert_eq!(skipped[0], None);
+        assert!(skipped[1].is_some_and(|count| count > ORDERING_CAP));
+        assert!(runner.results[0].1[0].passed);
+
+        assert!(runner.refuses(ORDERING_CAP + 1));
+        assert!(!runner.refuses(ORDERING_CAP));
+
+        runner.budget(100);
+        assert!(!runner.refuses(ORDERING_CAP + 1));
+
+        runner.budget = None;
+        runner.force();
+        assert!(!runner.refuses(ORDERING_CAP + 1));
     }
 }
@@ -0,0 +1,737 @@
+use crate::blob::{self, Blob};
+use crate::config::{Cas, Config, Layout, Reads, Remove, Timeout, Update};
+use crate::db::{self, DbStore};
+use crate::migration::{self, Schema};
+use crate::planner::Client;
+use crate::runner::Runner;
+
+const PAIR_PATH: &str = "/path/x";
+
+pub fn standard_configs() -> Vec<Config> {
+    vec![
+        Config::new().update(Update::GetBeforePut),
+        Config::new().remove(Remove::UnlinkParallel),
+        Config::new().skip_links(true),
+        Config::new().store(Cas::Lax),
+        Config::new().store(Cas::NoRev),
+        Config::new().store(Cas::MatchRev),
+        Config::new().store(Cas::Strict),
+        Config::new().layout(Layout::Flat),
+        Config::new().layout(Layout::Manifest),
+        Config::new().layout(Layout::Packed { threshold: 8 }),
+        Config::new().layout(Layout::Buckets { count: 2 }),
+        Config::new().server_update(true),
+        Config::new().reads(Reads::Snapshot),
+        Config::new().fresh_reads(true),
+        Config::new().write_back(true),
+    ]
+}
+
+// Every mixture of one CAS mode for docs and another for dirs.
+pub fn cas_configs() -> Vec<Config> {
+    let modes = [Cas::Strict, Cas::MatchRev, Cas::NoRev, Cas::Lax];
+    let mut configs = Vec::new();
+
+    for docs in &modes {
+        for dirs in modes.iter().filter(|dirs| *dirs != docs) {
+            configs.push(Config::new().store(docs.clone()).dir_store(dirs.clone()));
+        }
+    }
+    configs
+}
+
+// The client strategies under a store whose rev check may let through a
+// write one write behind, for docs and dirs alike, or for only one of them.
+pub fn stale_cas_configs() -> Vec<Config> {
+    let stale = Cas::Stale { window: 1 };
+
+    vec![
+        Config::new().store(stale.clone()),
+        Config::new()
+            .store(stale.clone())
+            .update(Update::GetBeforePut),
+        Config::new()
+            .store(stale.clone())
+            .remove(Remove::UnlinkParallel),
+        Config::new().store(stale.clone()).skip_links(true),
+        Config::new().store(stale.clone()).server_update(true),
+        Config::new().store(stale.clone()).reads(Reads::Snapshot),
+        Config::new().store(stale.clone()).layout(Layout::Flat),
+        Config::new().store(stale.clone()).repair(true),
+        Config::new().store(stale.clone()).dir_store(Cas::Strict),
+        Config::new().dir_store(stale),
+    ]
+}
+
+pub fn standard_suite(runner: &mut Runner<(char, i32)>) {
+    runner.add(
+        "update/update conflict",
+        &[("/path/x", ('x', 1))],
+        |planner| {
+            planner.client("A").update("/path/x", |_| Some(('x', 2)));
+            planner.client("B").update("/path/x", |_| Some(('x', 3)));
+        },
+    );
+
+    runner.add(
+        "update/update conflict (missing)",
+        &[("/path/x", ('x', 1))],
+        |planner| {
+            planner.client("A").update("/path/y", |_| Some(('y', 2)));
+            planner.client("B").update("/path/y", |_| Some(('y', 3)));
+        },
+    );
+
+    runner.add(
+        "update/delete conflict",
+        &[("/path/x", ('x', 1))],
+        |planner| {
+            planner.client("A").update("/path/x", |_| Some(('x', 2)));
+            planner.client("B").remove("/path/x");
+        },
+    );
+
+    runner.add(
+        "increment/delete conflict",
+        &[("/path/x", ('x', 1))],
+        |planner| {
+            planner.client("A").remove("/path/x");
+            planner
+                .client("B")
+                .update("/path/x", |doc| doc.map(|(k, n)| (k, n + 1)));
+        },
+    );
+    runner.expect("/path/x", |acks| match (acks.acked("A"), acks.acked("B")) {
+        (true, _) => vec![None],
+        (false, true) => vec![Some(('x', 2)), None],
+        (false, false) => vec![Some(('x', 1)), None],
+    });
+
+    runner.add(
+        "update after syncing a stale cache",
+        &[("/path/x", ('x', 1))],
+        |planner| {
+            planner.client("A").update("/path/x", |_| Some(('x', 2)));
+        },
+    );
+    runner.then(|planner| {
+        planner.client("B").update("/path/y", |_| Some(('y', 1)));
+    });
+    runner.then(|planner| planner.client("A").sync("/"));
+    runner.then(|planner| {
+        planner.client("A").update("/path/z", |_| Some(('z', 1)));
+    });
+    runner.expect("/path/z", |acks| match acks.acked("A") {
+        true => vec![Some(('z', 1))],
+        false => vec![Some(('z', 1)), None],
+    });
+
+    runner.add(
+        "update/delete conflict (missing)",
+        &[("/path/x", ('x', 1))],
+        |planner| {
+            planner.client("A").update("/path/y", |_| Some(('y', 2)));
+            planner.client("B").remove("/path/y");
+        },
+    );
+
+    runner.add(
+        "delete, create sibling",
+        &[("/path/x", ('x', 1))],
+        |planner| {
+            planner.client("A").remove("/path/x");
+            planner.client("B").update("/path/y", |_| Some(('y', 2)));
+        },
+    );
+
+    runner.add(
+        "delete, create sibling, then recreate",
+        &[("/path/x", ('x', 1))],
+        |planner| {
+            planner.client("A").remove("/path/x");
+            planner.client("B").update("/path/y", |_| Some(('y', 2)));
+        },
+    );
+    runner.then(|planner| {
+        planner.client("C").update("/path/x", |_| Some(('x', 3)));
+    });
+
+    runner.add(
+        "delete, create in parent",
+        &[("/path/to/x", ('x', 1))],
+        |planner| {
+            planner.client("A").remove("/path/to/x");
+            planner.client("B").update("/path/y", |_| Some(('y', 2)));
+        },
+    );
+
+    runner.add(
+        "delete, create in grandparent",
+        &[("/path/to/x", ('x', 1))],
+        |planner| {
+            planner.client("A").remove("/path/to/x");
+            planner.client("B").update("/y", |_| Some(('y', 2)));
+        },
+    );
+
+    runner.add(
+        "delete, create in child",
+        &[("/path/x", ('x', 1))],
+        |planner| {
+            planner.client("A").remove("/path/x");
+            planner.client("B").update("/path/to/y", |_| Some(('y', 2)));
+        },
+    );
+
+    runner.add(
+        "delete, create in grandchild",
+        &[("/x", ('x', 1))],
+        |planner| {
+            planner.client("A").remove("/x");
+            planner.client("B").update("/path/to/y", |_| Some(('y', 2)));
+        },
+    );
+
+    runner.add(
+        "delete, update sibling",
+        &[("/path/x", ('x', 1)), ("/path/y", ('y', 1))],
+        |planner| {
+            planner.client("A").remove("/path/x");
+            planner
+                .client("B")
+                .update("/path/y", |doc| doc.map(|(k, n)| (k, n + 1)));
+        },
+    );
+    runner.check_each_step(|before, after| {
+        let count = |store: &DbStore<_>| db::doc_value(store, "/path/y").map(|(_, n)| n);
+
+        match (count(before), count(after)) {
+            (Some(old), Some(new)) if new < old => {
+                Err(format!("y's counter went from {} to {}", old, new))
+            }
+            _ => Ok(()),
+        }
+    });
+
+    runner.add(
+        "delete, update in parent",
+        &[("/path/to/x", ('x', 1)), ("/path/y", ('y', 1))],
+        |planner| {
+            planner.client("A").remove("/path/to/x");
+            planner
+                .client("B")
+                .update("/path/y", |doc| doc.map(|(k, n)| (k, n + 1)));
+        },
+    );
+
+    runner.add(
+        "delete, update in grandparent",
+        &[("/path/to/x", ('x', 1)), ("/y", ('y', 1))],
+        |planner| {
+            planner.client("A").remove("/path/to/x");
+            planner
+                .client("B")
+                .update("/y", |doc| doc.map(|(k, n)| (k, n + 1)));
+        },
+    );
+
+    runner.add(
+        "delete, update in child",
+        &[("/path/x", ('x', 1)), ("/path/to/y", ('y', 1))],
+        |planner| {
+            planner.client("A").remove("/path/x");
+            planner
+                .client("B")
+                .update("/path/to/y", |doc| doc.map(|(k, n)| (k, n + 1)));
+        },
+    );
+
+    runner.add(
+        "delete, update in grandchild",
+        &[("/x", ('x', 1)), ("/path/to/y", ('y', 1))],
+        |planner| {
+            planner.client("A").remove("/x");
+            planner
+                .client("B")
+                .update("/path/to/y", |doc| doc.map(|(k, n)| (k, n + 1)));
+        },
+    );
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PairOp {
+    Update,
+    Remove,
+    Rename,
+    Create,
+}
+
+const PAIR_OPS: [PairOp; 4] = [
+    PairOp::Update,
+    PairOp::Remove,
+    PairOp::Rename,
+    PairOp::Create,
+];
+
+impl PairOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PairOp::Update => "update",
+            PairOp::Remove => "remove",
+            PairOp::Rename => "rename",
+            PairOp::Create => "create",
+        }
+    }
+
+    fn needs_doc(&self) -> bool {
+        *self != PairOp::Create
+    }
+
+    fn plan(&self, mut client: Client<(char, i32)>, key: &str, tag: char) {
+        match self {
+            PairOp::Update => client.update(key, move |doc| doc.map(|(_, n)| (tag, n + 1))),
+            PairOp::Remove => client.remove(key),
+            PairOp::Rename => {
+                let to = format!("{}-moved", key);
+                client.rename(key, &to, move |_| Some((tag, 1)));
+            }
+            PairOp::Create => client.update(key, move |_| Some((tag, 1))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Relation {
+    Same,
+    Sibling,
+    Ancestor,
+    Descendant,
+    Disjoint,
+}
+
+const RELATIONS: [Relation; 5] = [
+    Relation::Same,
+    Relation::Sibling,
+    Relation::Ancestor,
+    Relation::Descendant,
+    Relation::Disjoint,
+];
+
+impl Relation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Relation::Same => "same doc",
+            Relation::Sibling => "sibling",
+            Relation::Ancestor => "ancestor",
+            Relation::Descendant => "descendant",
+            Relation::Disjoint => "disjoint",
+        }
+    }
+
+    // The path of the second op's doc, relative to the first op's doc at
+    // PAIR_PATH.
+    pub fn path(&self) -> &'static str {
+        match self {
+            Relation::Same => PAIR_PATH,
+            Relation::Sibling => "/path/y",
+            Relation::Ancestor => "/y",
+            Relation::Descendant => "/path/to/y",
+            Relation::Disjoint => "/other/y",
+        }
+    }
+}
+
+// Every unordered pair of ops under every relation. Ancestor and descendant
+// are mirror images, so (a, b, ancestor) also covers (b, a, descendant).
+pub fn op_pairs() -> Vec<(PairOp, PairOp, Relation)> {
+    let mut pairs = Vec::new();
+
+    for (i, a) in PAIR_OPS.iter().enumerate() {
+        for b in &PAIR_OPS[i..] {
+            for relation in &RELATIONS {
+                pairs.push((*a, *b, *relation));
+            }
+        }
+    }
+    pairs
+}
+
+pub fn pair_name(a: PairOp, b: PairOp, relation: Relation) -> String {
+    format!("{}/{} ({})", a.name(), b.name(), relation.name())
+}
+
+pub fn pairs_suite(runner: &mut Runner<(char, i32)>) {
+    for (a, b, relation) in op_pairs() {
+        let mut docs = Vec::new();
+
+        if a.needs_doc() {
+            docs.push((PAIR_PATH, ('x', 1)));
+        }
+        if b.needs_doc() && !(a.needs_doc() && relation == Relation::Same) {
+            docs.push((relation.path(), ('y', 1)));
+        }
+
+        runner.add(
+            &pair_name(a, b, relation),
+            docs.as_slice(),
+            move |planner| {
+                a.plan(planner.client("A"), PAIR_PATH, 'a');
+                b.plan(planner.client("B"), relation.path(), 'b');
+            },
+        );
+    }
+}
+
+pub fn three_way_suite(runner: &mut Runner<(char, i32)>) {
+    runner.add(
+        "update/update/update conflict",
+        &[("/x", ('x', 1))],
+        |planner| {
+            planner.client("A").update("/x", |_| Some(('x', 2)));
+            planner.client("B").update("/x", |_| Some(('x', 3)));
+            planner.client("C").update("/x", |_| Some(('x', 4)));
+            planner.symmetric(&["A", "B", "C"]);
+            planner.reduce();
+            planner.conflicts_first();
+        },
+    );
+
+    runner.add(
+        "update/update/delete conflict",
+        &[("/x", ('x', 1))],
+        |planner| {
+            planner.client("A").update("/x", |_| Some(('x', 2)));
+            planner.client("B").update("/x", |_| Some(('x', 3)));
+            planner.client("C").remove("/x");
+            planner.symmetric(&["A", "B"]);
+            planner.reduce();
+            planner.conflicts_first();
+        },
+    );
+}
+
+pub const WIDE_DIR_SIZE: usize = 16;
+
+// The docs of a wide dir sit at the top level so that three clients stay
+// cheap to interleave; the work is in the size of the listing, not the depth.
+fn wide_dir_key(i: usize) -> String {
+    format!("/d{:02}", i)
+}
+
+fn wide_dir_listing(n: usize) -> usize {
+    (0..n).map(|i| wide_dir_key(i).len() - 1).sum()
+}
+
+pub fn wide_dir_configs() -> Vec<Config> {
+    vec![
+        Config::new(),
+        Config::new().layout(Layout::Manifest),
+        Config::new().layout(Layout::Buckets { count: 4 }),
+        Config::new().max_value_size(wide_dir_listing(WIDE_DIR_SIZE) + 1),
+    ]
+}
+
+pub fn wide_dir_suite(runner: &mut Runner<(char, i32)>) {
+    wide_dir_scenarios(runner, WIDE_DIR_SIZE);
+}
+
+pub fn wide_dir_scenarios(runner: &mut Runner<(char, i32)>, n: usize) {
+    let setup = move |mut db: Client<(char, i32)>| {
+        for i in 0..n {
+            db.update(&wide_dir_key(i), |_| Some(('d', 1)));
+        }
+    };
+
+    runner.add(
+        &format!("wide dir ({} docs), create/create/delete", n),
+        setup,
+        |planner| {
+            planner.client("A").update("/a", |_| Some(('a', 1)));
+            planner.client("B").update("/b", |_| Some(('b', 1)));
+            planner.client("C").remove(&wide_dir_key(0));
+        },
+    );
+
+    runner.add(
+        &format!("wide dir ({} docs), delete/delete/update", n),
+        setup,
+        |planner| {
+            planner.client("A").remove(&wide_dir_key(0));
+            planner.client("B").remove(&wide_dir_key(1));
+            planner
+                .client("C")
+                .update(&wide_dir_key(2), |doc| doc.map(|(k, n)| (k, n + 1)));
+        },
+    );
+}
+
+pub fn blob_configs() -> Vec<Config> {
+    vec![
+        Config::new(),
+        Config::new().max_value_size(2048),
+        Config::new().quota(4096),
+    ]
+}
+
+pub fn blob_suite(runner: &mut Runner<Blob>) {
+    runner.add(
+        "large doc update, metadata update",
+        &[
+            ("/data/x.bin", Blob::new(1024, 1)),
+            ("/data/x.meta", Blob::new(16, 2)),
+        ],
+        |planner| {
+            planner
+                .client("A")
+                .update("/data/x.bin", blob::append(3072, 1));
+            planner
+                .client("B")
+                .update("/data/x.meta", blob::fill(32, 3));
+        },
+    );
+
+    runner.add(
+        "large doc create, metadata remove",
+        &[("/data/x.meta", Blob::new(16, 2))],
+        |planner| {
+            planner
+                .client("A")
+                .update("/data/x.bin", blob::fill(3072, 1));
+            planner.client("B").remove("/data/x.meta");
+        },
+    );
+}
+
+pub fn migration_configs() -> Vec<Config> {
+    vec![Config::new(), Config::new().update(Update::GetBeforePut)]
+}
+
+pub fn migration_suite(runner: &mut Runner<Schema<(char, i32)>>) {
+    runner.check_final(migration::check);
+
+    runner.add(
+        "migration, concurrent update",
+        &[
+            ("/version", Schema::Version(1)),
+            ("/path/x", Schema::Doc(1, ('x', 1))),
+            ("/path/y", Schema::Doc(1, ('y', 1))),
+        ],
+        |planner| {
+            planner
+                .client("A")
+                .update_versioned("/version", 1, "/path/x", |doc| doc.map(|(k, n)| (k, n + 1)));
+            planner
+                .client("M")
+                .migrate("/version", 2, &["/path/x", "/path/y"], |(k, n)| {
+                    (k.to_ascii_uppercase(), n * 10)
+                });
+        },
+    );
+
+    runner.add(
+        "migration, concurrent create",
+        &[
+            ("/version", Schema::Version(1)),
+            ("/path/x", Schema::Doc(1, ('x', 1))),
+        ],
+        |planner| {
+            planner
+                .client("A")
+                .update_versioned("/version", 1, "/path/y", |_| Some(('y', 1)));
+            planner
+                .client("M")
+                .migrate("/version", 2, &["/path/x", "/path/y"], |(k, n)| {
+                    (k.to_ascii_uppercase(), n * 10)
+                });
+        },
+    );
+}
+
+pub fn repair_configs() -> Vec<Config> {
+    vec![
+        Config::new(),
+        Config::new().layout(Layout::Manifest),
+        Config::new().layout(Layout::Buckets { count: 2 }),
+    ]
+}
+
+pub fn repair_suite(runner: &mut Runner<(char, i32)>) {
+    runner.add(
+        "repair/update conflict",
+        &[("/path/x", ('x', 1))],
+        |planner| {
+            planner.client("A").update("/path/y", |_| Some(('y', 1)));
+            planner.client("R").repair(&["/path/x", "/path/y"]);
+        },
+    );
+
+    runner.add(
+        "repair/delete conflict",
+        &[("/path/x", ('x', 1)), ("/path/y", ('y', 1))],
+        |planner| {
+            planner.client("A").remove("/path/y");
+            planner.client("R").repair(&["/path/x", "/path/y"]);
+        },
+    );
+
+    runner.add(
+        "repair of an unlinked doc/update conflict",
+        |mut db: Client<_>| {
+            db.update("/path/x", |_| Some(('x', 1)));
+            db.put_unlinked("/path/y", |_| Some(('y', 1)));
+        },
+        |planner| {
+            planner.client("A").update("/path/y", |_| Some(('y', 2)));
+            planner.client("R").repair(&["/path/y"]);
+        },
+    );
+
+    runner.add(
+        "repair of a missing doc/update conflict",
+        |mut db: Client<_>| {
+            db.update("/path/x", |_| Some(('x', 1)));
+            db.link_only("/path/y");
+        },
+        |planner| {
+            planner.client("A").update("/path/y", |_| Some(('y', 2)));
+            planner.client("R").repair(&["/path/y"]);
+        },
+    );
+}
+
+pub fn lease_configs() -> Vec<Config> {
+    vec![Config::new()]
+}
+
+pub fn lease_suite(runner: &mut Runner<(char, i32)>) {
+    runner.add(
+        "short lease/short lease conflict",
+        &[("/y", ('y', 1))],
+        |planner| {
+            planner
+                .client("A")
+                .update_leased("/", 6, "/x", |_| Some(('a', 1)));
+            planner
+                .client("B")
+                .update_leased("/", 6, "/x", |_| Some(('b', 1)));
+        },
+    );
+
+    runner.add(
+        "long lease/long lease conflict",
+        &[("/y", ('y', 1))],
+        |planner| {
+            planner
+                .client("A")
+                .update_leased("/", 20, "/x", |_| Some(('a', 1)));
+            planner
+                .client("B")
+                .update_leased("/", 20, "/x", |_| Some(('b', 1)));
+        },
+    );
+
+    runner.add(
+        "lease holder crash/lease conflict",
+        &[("/y", ('y', 1))],
+        |planner| {
+            planner.client("A").acquire("/", 2);
+            planner
+                .client("B")
+                .update_leased("/", 20, "/x", |_| Some(('b', 1)));
+        },
+    );
+}
+
+// Lazy links only pass once the inconsistent states they go through are
+// tolerated, so they are checked both with and without a repair pass.
+pub fn lazy_configs() -> Vec<Config> {
+    vec![
+        Config::new().update(Update::LazyLinks),
+        Config::new().update(Update::LazyLinks).repair(true),
+    ]
+}
+
+pub fn partition_configs() -> Vec<Config> {
+    vec![
+        Config::new(),
+        Config::new().update(Update::GetBeforePut),
+        Config::new().remove(Remove::UnlinkParallel),
+    ]
+}
+
+pub fn partition_suite(runner: &mut Runner<(char, i32)>) {
+    runner.add(
+        "partitioned update/update conflict",
+        &[("/path/x", ('x', 1))],
+        |planner| {
+            planner.client("A").update("/path/y", |_| Some(('y', 1)));
+            planner.client("B").update("/path/y", |_| Some(('y', 2)));
+            planner.partition("A");
+        },
+    );
+
+    runner.add(
+        "partitioned delete/update conflict",
+        &[("/path/y", ('y', 1))],
+        |planner| {
+            planner.client("A").remove("/path/y");
+            planner.client("B").update("/path/y", |_| Some(('y', 2)));
+            planner.partition("A");
+        },
+    );
+}
+
+pub fn timeout_configs() -> Vec<Config> {
+    vec![
+        Config::new().timeout(Timeout::Abort),
+        Config::new().timeout(Timeout::Continue),
+        Config::new().timeout(Timeout::Retry),
+    ]
+}
+
+pub fn timeout_suite(runner: &mut Runner<(char, i32)>) {
+    runner.add(
+        "timed out update/update conflict",
+        &[("/path/x", ('x', 1))],
+        |planner| {
+            planner.client("A").update("/path/y", |_| Some(('y', 1)));
+            planner.client("B").update("/path/y", |_| Some(('y', 2)));
+            planner.time_out("A");
+        },
+    );
+
+    runner.add(
+        "timed out delete/update conflict",
+        &[("/path/y", ('y', 1))],
+        |planner| {
+            planner.client("A").remove("/path/y");
+            planner.client("B").update("/path/y", |_| Some(('y', 2)));
+            planner.time_out("A");
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn generates_a_uniquely_named_scenario_for_each_op_pair() {
+        let names: BTreeSet<_> = op_pairs()
+            .into_iter()
+            .map(|(a, b, relation)| pair_name(a, b, relation))
+            .collect();
+
+        assert_eq!(names.len(), 50);
+        assert!(names.contains("update/remove (ancestor)"));
+        assert!(names.contains("create/create (same doc)"));
+    }
+
+    #[test]
+    fn limits_wide_dirs_to_one_more_entry() {
+        assert_eq!(wide_dir_listing(16), 48);
+        assert_eq!(wide_dir_configs()[3].max_value_size, Some(49));
+    }
+}
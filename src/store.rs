@@ -1,31 +1,119 @@
 use std::borrow::Borrow;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
 
+use crate::clock::{Clock, Time};
 use crate::config::{Cas, Config};
+use crate::pmap::PMap;
 
 pub type Rev = usize;
 
+pub trait Size {
+    fn size(&self) -> usize;
+}
+
+impl Size for () {
+    fn size(&self) -> usize {
+        0
+    }
+}
+
+impl Size for bool {
+    fn size(&self) -> usize {
+        1
+    }
+}
+
+impl Size for char {
+    fn size(&self) -> usize {
+        self.len_utf8()
+    }
+}
+
+macro_rules! impl_size_for_ints {
+    ($($t:ty),*) => {
+        $(
+            impl Size for $t {
+                fn size(&self) -> usize {
+                    std::mem::size_of::<$t>()
+                }
+            }
+        )*
+    };
+}
+
+impl_size_for_ints!(u8, i32, i64, usize);
+
+impl Size for String {
+    fn size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T: Size> Size for Vec<T> {
+    fn size(&self) -> usize {
+        self.iter().map(Size::size).sum()
+    }
+}
+
+impl<A: Size, B: Size> Size for (A, B) {
+    fn size(&self) -> usize {
+        self.0.size() + self.1.size()
+    }
+}
+
+// Keys that end in a slash hold directories. Besides each dir's own key, that
+// covers the manifest, the buckets and the lock keys named after dirs.
+pub trait Key {
+    fn is_dir(&self) -> bool;
+}
+
+impl Key for str {
+    fn is_dir(&self) -> bool {
+        self.ends_with('/')
+    }
+}
+
+impl Key for String {
+    fn is_dir(&self) -> bool {
+        self.as_str().is_dir()
+    }
+}
+
 #[derive(Clone)]
 pub struct Store<K, V> {
-    data: BTreeMap<K, (Rev, Option<V>)>,
+    data: PMap<K, (Rev, Option<V>)>,
     config: Config,
     pub seq: Rev,
+    pub size: usize,
+    pub conflicts: usize,
+    pub clock: Clock,
+    pub violations: Vec<String>,
 }
 
 impl<K, V> Store<K, V>
 where
-    K: Ord,
-    V: Clone,
+    K: Clone + Ord,
+    V: Clone + Size,
 {
     pub fn new(config: Config) -> Store<K, V> {
         Store {
-            data: BTreeMap::new(),
+            data: PMap::new(),
             config,
             seq: 0,
+            size: 0,
+            conflicts: 0,
+            clock: Clock::new(),
+            violations: Vec::new(),
         }
     }
 
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
     pub fn get<Q>(&self, key: &Q) -> Option<&V>
     where
         K: Borrow<Q>,
@@ -41,9 +129,11 @@ where
     pub fn read<Q>(&self, key: &Q) -> Option<(Rev, Option<V>)>
     where
         K: Borrow<Q>,
-        Q: Ord + ?Sized,
+        Q: Ord + Key + ?Sized,
     {
-        if self.config.store == Cas::Strict || self.config.store == Cas::MatchRev {
+        let cas = self.cas(key);
+
+        if matches!(cas, Cas::Strict | Cas::MatchRev | Cas::Stale { .. }) {
             if let Some((rev, value)) = self.data.get(key) {
                 Some((*rev, value.clone()))
             } else {
@@ -56,69 +146,264 @@ where
         }
     }
 
-    pub fn write(&mut self, key: K, rev: Option<Rev>, value: V) -> Option<Rev> {
+    // The key's latest rev, including the rev of its deletion, whatever the
+    // CAS mode reports to clients. A key never written has rev 0.
+    pub fn rev<Q>(&self, key: &Q) -> Rev
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.data.get(key).map_or(0, |(rev, _)| *rev)
+    }
+
+    // Reads every key in one call, so all of them come from the same state
+    // of the store.
+    pub fn read_many<Q>(&self, keys: &[Q]) -> Vec<Option<(Rev, Option<V>)>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + Key,
+    {
+        keys.iter().map(|key| self.read(key)).collect()
+    }
+
+    pub fn write(&mut self, key: K, rev: Option<Rev>, value: V) -> Option<Rev>
+    where
+        K: Key,
+    {
         self.set_key(key, rev, Some(value))
     }
 
-    pub fn remove(&mut self, key: K, rev: Option<Rev>) -> Option<Rev> {
+    pub fn remove(&mut self, key: K, rev: Option<Rev>) -> Option<Rev>
+    where
+        K: Key,
+    {
         self.set_key(key, rev, None)
     }
 
-    fn set_key(&mut self, key: K, rev: Option<Rev>, value: Option<V>) -> Option<Rev> {
+    // Applies `update` to the key's current value inside the store, as a
+    // backend with server-side transforms would, so no other write can come
+    // between reading the value and replacing it. There is no rev to check,
+    // but size limits still apply. If `update` returns None the value is
+    // left as it is.
+    pub fn update<F>(&mut self, key: K, update: F) -> Option<Rev>
+    where
+        F: FnOnce(Option<&V>) -> Option<V>,
+    {
+        let (rev, value) = match self.data.get(&key) {
+            Some((rev, value)) => (*rev, update(value.as_ref())),
+            None => (0, update(None)),
+        };
+        let Some(value) = value else {
+            return Some(rev);
+        };
+        let entry = self.data.get_or_insert_with(key, || (0, None));
+        let new_rev = replace(
+            entry,
+            Some(value),
+            &self.config,
+            &mut self.size,
+            &mut self.seq,
+        );
+
+        if new_rev.is_none() {
+            self.conflicts += 1;
+        }
+        new_rev
+    }
+
+    // All or nothing: if any write fails its rev check, none are applied.
+    pub fn write_batch(&mut self, writes: Vec<(K, Option<Rev>, Option<V>)>) -> Option<Vec<Rev>>
+    where
+        K: Key,
+    {
+        let saved = (self.data.clone(), self.size, self.seq);
+        let mut revs = Vec::with_capacity(writes.len());
+
+        for (key, rev, value) in writes {
+            if let Some(new_rev) = self.try_set_key(key, rev, value) {
+                revs.push(new_rev);
+            } else {
+                (self.data, self.size, self.seq) = saved;
+                self.conflicts += 1;
+                return None;
+            }
+        }
+        Some(revs)
+    }
+
+    fn set_key(&mut self, key: K, rev: Option<Rev>, value: Option<V>) -> Option<Rev>
+    where
+        K: Key,
+    {
+        let new_rev = self.try_set_key(key, rev, value);
+        if new_rev.is_none() {
+            self.conflicts += 1;
+        }
+        new_rev
+    }
+
+    // The CAS mode for a key: dir keys take the dir mode if the config sets
+    // one, and every other key takes the store's mode.
+    fn cas<Q: Key + ?Sized>(&self, key: &Q) -> &Cas {
+        match &self.config.dir_store {
+            Some(cas) if key.is_dir() => cas,
+            _ => &self.config.store,
+        }
+    }
+
+    fn try_set_key(&mut self, key: K, rev: Option<Rev>, value: Option<V>) -> Option<Rev>
+    where
+        K: Key,
+    {
         let client_rev = rev.unwrap_or(0);
-        let entry = self.data.entry(key).or_insert((0, None));
+        let cas = self.cas(&key).clone();
+        let entry = self.data.get_or_insert_with(key, || (0, None));
 
-        if entry.1.is_some() || self.config.store == Cas::Strict {
-            if client_rev != entry.0 {
+        // A stale check compares the rev with an old snapshot of the key, so
+        // a rev up to `window` writes behind the current one is let through.
+        if let Cas::Stale { window } = cas {
+            if client_rev > entry.0 || client_rev + window < entry.0 {
                 return None;
             }
-        } else if self.config.store == Cas::MatchRev {
-            if client_rev != 0 && client_rev != entry.0 {
+        } else if entry.1.is_some() || cas == Cas::Strict {
+            if client_rev != entry.0 {
                 return None;
             }
-        } else if self.config.store == Cas::NoRev {
-            if client_rev != 0 {
+        } else if cas == Cas::MatchRev {
+            if client_rev != 0 && client_rev != entry.0 {
                 return None;
             }
+        } else if cas == Cas::NoRev && client_rev != 0 {
+            return None;
         }
-
-        *entry = (entry.0 + 1, value);
-        self.seq += 1;
-
-        Some(entry.0)
+        replace(entry, value, &self.config, &mut self.size, &mut self.seq)
     }
 
     pub fn keys(&self) -> impl Iterator<Item = &K> {
         self.data.keys()
     }
+
+    pub fn scan(&self, prefix: &str) -> Vec<K>
+    where
+        K: Borrow<str>,
+    {
+        self.data
+            .iter_from(prefix)
+            .take_while(|(key, _)| (*key).borrow().starts_with(prefix))
+            .filter(|(_, (_, value))| value.is_some())
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    // A hash of every record's key, rev and value. Two stores with the same
+    // fingerprint almost certainly respond the same way to any further act,
+    // whatever their clocks and counters say.
+    pub fn fingerprint(&self) -> u64
+    where
+        K: Hash,
+        V: Hash,
+    {
+        let mut hasher = DefaultHasher::new();
+        for entry in self.data.iter() {
+            entry.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
 }
 
+// Replaces an entry's value, unless the new value breaks the config's size
+// limits. Returns the entry's new rev.
+fn replace<V: Size>(
+    entry: &mut (Rev, Option<V>),
+    value: Option<V>,
+    config: &Config,
+    size: &mut usize,
+    seq: &mut Rev,
+) -> Option<Rev> {
+    let old_size = entry.1.as_ref().map_or(0, Size::size);
+    let new_size = value.as_ref().map_or(0, Size::size);
+
+    if let Some(max) = config.max_value_size {
+        if new_size > max {
+            return None;
+        }
+    }
+    if let Some(quota) = config.quota {
+        if *size - old_size + new_size > quota {
+            return None;
+        }
+    }
+
+    *entry = (entry.0 + 1, value);
+    *seq += 1;
+    *size = *size - old_size + new_size;
+
+    Some(entry.0)
+}
+
+// A client's view of the store. It keeps the record of every key it reads or
+// writes, for the rev to make its next write conditional on, and serves
+// repeated reads from those records unless the config asks for fresh reads.
 pub struct Cache<'a, K, V> {
     store: &'a RefCell<Store<K, V>>,
     data: BTreeMap<K, Option<(Rev, Option<V>)>>,
+    fresh: bool,
+    peeking: bool,
+    reads: Option<Vec<K>>,
+    batch: Option<BTreeMap<K, Option<Rev>>>,
 }
 
 impl<K, V> Cache<'_, K, V>
 where
     K: Clone + Ord,
-    V: Clone,
+    V: Clone + Size,
 {
     pub fn new(store: &RefCell<Store<K, V>>) -> Cache<'_, K, V> {
         Cache {
             store,
             data: BTreeMap::new(),
+            fresh: store.borrow().config.fresh_reads,
+            peeking: false,
+            reads: None,
+            batch: None,
         }
     }
 
+    // While tracing, the cache notes each key it reads from the store, so
+    // that a replay can show what every act read. Reads it serves from its
+    // own records are not noted.
+    pub fn trace_reads(&mut self, on: bool) {
+        self.reads = on.then(Vec::new);
+    }
+
+    pub fn take_reads(&mut self) -> Vec<K> {
+        self.reads.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // While peeking, `read()` always goes to the store, and is traced as
+    // usual, but leaves the cache's records as they were. Writes made after
+    // a peek stay conditional on the revs the cache held before it.
+    pub fn peek(&mut self, on: bool) {
+        self.peeking = on;
+    }
+
     pub fn read<'a, Q>(&mut self, key: &'a Q) -> Option<V>
     where
-        K: Borrow<Q>,
+        K: Borrow<Q> + Key,
         Q: Ord + ?Sized,
         &'a Q: Into<K>,
     {
-        if !self.data.contains_key(key) {
-            let record = self.store.borrow().read(key);
-            self.data.insert(key.into(), record);
+        if self.peeking {
+            let key: K = key.into();
+            let record = self.store.borrow().read::<K>(&key);
+            self.note_reads(std::slice::from_ref(&key));
+            return record.and_then(|(_, value)| value);
+        }
+        if (self.fresh && !self.is_batched(key)) || !self.data.contains_key(key) {
+            let key: K = key.into();
+            let record = self.store.borrow().read::<K>(&key);
+            self.note_reads(std::slice::from_ref(&key));
+            self.data.insert(key, record);
         }
 
         if let Some(Some((_, Some(value)))) = self.data.get(key) {
@@ -128,8 +413,39 @@ where
         }
     }
 
-    pub fn write(&mut self, key: &K, value: V) -> bool {
+    // Reads the keys not already cached with a single call to the store, so
+    // that they are read from the same state of it.
+    pub fn read_many(&mut self, keys: &[K]) -> Vec<Option<V>>
+    where
+        K: Key,
+    {
+        let missing: Vec<_> = keys
+            .iter()
+            .filter(|key| (self.fresh && !self.is_batched(*key)) || !self.data.contains_key(*key))
+            .cloned()
+            .collect();
+
+        let records = self.store.borrow().read_many(&missing);
+        self.note_reads(&missing);
+        self.data.extend(missing.into_iter().zip(records));
+
+        keys.iter()
+            .map(|key| match self.data.get(key) {
+                Some(Some((_, value))) => value.clone(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn write(&mut self, key: &K, value: V) -> bool
+    where
+        K: Key,
+    {
         let old_rev = self.get_rev(key);
+        if self.batch.is_some() {
+            self.hold(key, old_rev, Some(value));
+            return true;
+        }
         let mut store = self.store.borrow_mut();
 
         if let Some(new_rev) = store.write(key.clone(), old_rev, value.clone()) {
@@ -141,8 +457,15 @@ where
         }
     }
 
-    pub fn remove(&mut self, key: &K) -> bool {
+    pub fn remove(&mut self, key: &K) -> bool
+    where
+        K: Key,
+    {
         let old_rev = self.get_rev(key);
+        if self.batch.is_some() {
+            self.hold(key, old_rev, None);
+            return true;
+        }
         let mut store = self.store.borrow_mut();
 
         if store.remove(key.clone(), old_rev).is_some() {
@@ -154,6 +477,157 @@ where
         }
     }
 
+    // Has the store apply `update` to the key's current value. The cache
+    // does not learn what the store wrote, so it forgets the key either way.
+    pub fn update<F>(&mut self, key: &K, update: F) -> bool
+    where
+        F: FnOnce(Option<&V>) -> Option<V>,
+    {
+        let updated = self.store.borrow_mut().update(key.clone(), update);
+        self.data.remove(key);
+        updated.is_some()
+    }
+
+    pub fn begin(&mut self) {
+        self.batch = Some(BTreeMap::new());
+    }
+
+    // A refused batch forgets its keys, as a failed write does.
+    pub fn commit(&mut self) -> Result<Vec<K>, Vec<K>>
+    where
+        K: Key,
+    {
+        let batch = self.batch.take().unwrap_or_default();
+        let writes = batch
+            .iter()
+            .map(|(key, rev)| {
+                let value = match self.data.get(key) {
+                    Some(Some((_, value))) => value.clone(),
+                    _ => None,
+                };
+                (key.clone(), *rev, value)
+            })
+            .collect();
+        let keys: Vec<_> = batch.into_keys().collect();
+
+        let revs = self.store.borrow_mut().write_batch(writes);
+        if let Some(revs) = revs {
+            for (key, rev) in keys.iter().zip(revs) {
+                if let Some(Some(record)) = self.data.get_mut(key) {
+                    record.0 = rev;
+                }
+            }
+            Ok(keys)
+        } else {
+            for key in &keys {
+                self.data.remove(key);
+            }
+            Err(keys)
+        }
+    }
+
+    pub fn is_batching(&self) -> bool {
+        self.batch.is_some()
+    }
+
+    pub fn abort(&mut self) {
+        for (key, _) in self.batch.take().unwrap_or_default() {
+            self.data.remove(&key);
+        }
+    }
+
+    pub fn scan(&self, prefix: &str) -> Vec<K>
+    where
+        K: Borrow<str>,
+    {
+        self.store.borrow().scan(prefix)
+    }
+
+    // Re-reads `keys` and every key under `prefix` that the store or the
+    // cache has a record of, with a single call to the store. The cache then
+    // holds the store's current record of each, and drops any key the store
+    // has never held.
+    pub fn sync(&mut self, prefix: &str, keys: &[K])
+    where
+        K: Borrow<str> + Key,
+    {
+        let mut keys: BTreeSet<K> = keys.iter().cloned().collect();
+        keys.extend(self.scan(prefix));
+        keys.extend(
+            self.data
+                .keys()
+                .filter(|key| (*key).borrow().starts_with(prefix))
+                .cloned(),
+        );
+
+        let keys: Vec<_> = keys.into_iter().collect();
+        let records = self.store.borrow().read_many::<K>(&keys);
+        self.note_reads(&keys);
+
+        for (key, record) in keys.into_iter().zip(records) {
+            match record {
+                Some(record) => self.data.insert(key, Some(record)),
+                None => self.data.remove::<K>(&key),
+            };
+        }
+    }
+
+    // The record the cache holds for a key, if it holds one. A record of
+    // None means the key was read and not found.
+    pub fn cached<Q>(&self, key: &Q) -> Option<&Option<(Rev, Option<V>)>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.data.get(key)
+    }
+
+    pub fn forget(&mut self, key: &K) {
+        self.data.remove(key);
+    }
+
+    pub fn reset(&mut self) {
+        self.data.clear();
+        self.batch = None;
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.store.borrow().clock
+    }
+
+    pub fn tick(&mut self) -> Time {
+        self.store.borrow_mut().clock.tick()
+    }
+
+    pub fn report(&mut self, violation: String) {
+        self.store.borrow_mut().violations.push(violation);
+    }
+
+    fn note_reads(&mut self, keys: &[K]) {
+        if let Some(reads) = &mut self.reads {
+            reads.extend(keys.iter().cloned());
+        }
+    }
+
+    // The batch keeps the rev from before the key's first write.
+    fn hold(&mut self, key: &K, old_rev: Option<Rev>, value: Option<V>) {
+        if let Some(batch) = &mut self.batch {
+            let rev = *batch.entry(key.clone()).or_insert(old_rev);
+            self.data
+                .insert(key.clone(), Some((rev.unwrap_or(0), value)));
+        }
+    }
+
+    fn is_batched<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.batch
+            .as_ref()
+            .is_some_and(|batch| batch.contains_key(key))
+    }
+
     fn get_rev(&self, key: &K) -> Option<Rev> {
         if let Some(Some((rev, _))) = self.data.get(key) {
             Some(*rev)
@@ -182,6 +656,19 @@ mod tests {
         assert_eq!(store.read("x"), Some((1, Some('a'))));
     }
 
+    #[test]
+    fn fingerprints_stores_by_their_records() {
+        let mut a: Store<String, _> = Store::new(Config::new());
+        let mut b: Store<String, _> = Store::new(Config::new());
+
+        a.write("x".into(), None, 'a');
+        b.write("x".into(), None, 'a');
+        assert_eq!(a.fingerprint(), b.fingerprint());
+
+        b.write("x".into(), Some(1), 'a');
+        assert_ne!(a.fingerprint(), b.fingerprint());
+    }
+
     #[test]
     fn does_not_update_a_value_without_a_rev() {
         let mut store: Store<String, _> = Store::new(Config::new());
@@ -221,6 +708,31 @@ mod tests {
         assert_eq!(store.read("x"), Some((2, None)));
     }
 
+    #[test]
+    fn applies_every_write_in_a_batch() {
+        let mut store: Store<String, _> = Store::new(Config::new());
+        let rev = store.write("x".into(), None, 'a');
+
+        let batch = vec![("x".into(), rev, None), ("y".into(), None, Some('b'))];
+        assert_eq!(store.write_batch(batch), Some(vec![2, 1]));
+        assert_eq!(store.read("x"), Some((2, None)));
+        assert_eq!(store.read("y"), Some((1, Some('b'))));
+        assert_eq!(store.seq, 3);
+    }
+
+    #[test]
+    fn applies_no_write_in_a_batch_if_any_fails() {
+        let mut store: Store<String, _> = Store::new(Config::new());
+        let rev = store.write("x".into(), None, 'a');
+        store.write("y".into(), None, 'b');
+
+        let batch = vec![("x".into(), rev, Some('c')), ("y".into(), None, Some('d'))];
+        assert_eq!(store.write_batch(batch), None);
+        assert_eq!(store.read("x"), Some((1, Some('a'))));
+        assert_eq!(store.read("y"), Some((1, Some('b'))));
+        assert_eq!((store.seq, store.conflicts), (2, 1));
+    }
+
     mod strict_mode {
         use super::*;
 
@@ -401,6 +913,70 @@ mod tests {
         }
     }
 
+    mod stale_mode {
+        use super::*;
+
+        const MODE: Cas = Cas::Stale { window: 1 };
+
+        #[test]
+        fn returns_a_rev_after_remove() {
+            let mut store: Store<String, _> = Store::new(Config::new().store(MODE));
+            let rev = store.write("x".into(), None, 'a');
+
+            assert_eq!(store.remove("x".into(), rev), Some(2));
+            assert_eq!(store.read("x"), Some((2, None)));
+        }
+
+        #[test]
+        fn allows_write_with_a_rev_one_write_behind() {
+            let mut store: Store<String, _> = Store::new(Config::new().store(MODE));
+            let rev = store.write("x".into(), None, 'a');
+
+            assert_eq!(store.write("x".into(), rev, 'b'), Some(2));
+            assert_eq!(store.write("x".into(), rev, 'c'), Some(3));
+            assert_eq!(store.read("x"), Some((3, Some('c'))));
+        }
+
+        #[test]
+        fn does_not_allow_write_with_a_rev_outside_the_window() {
+            let mut store: Store<String, _> = Store::new(Config::new().store(MODE));
+            let rev = store.write("x".into(), None, 'a');
+
+            assert_eq!(store.write("x".into(), rev, 'b'), Some(2));
+            assert_eq!(store.write("x".into(), Some(2), 'c'), Some(3));
+            assert_eq!(store.write("x".into(), rev, 'd'), None);
+            assert_eq!(store.write("x".into(), Some(4), 'd'), None);
+            assert_eq!(store.read("x"), Some((3, Some('c'))));
+        }
+
+        #[test]
+        fn allows_write_with_no_rev_over_a_new_key() {
+            let mut store: Store<String, _> = Store::new(Config::new().store(MODE));
+
+            assert_eq!(store.write("x".into(), None, 'a'), Some(1));
+            assert_eq!(store.write("x".into(), None, 'b'), Some(2));
+            assert_eq!(store.write("x".into(), None, 'c'), None);
+        }
+    }
+
+    #[test]
+    fn applies_the_dir_mode_to_dir_keys() {
+        let config = Config::new().store(Cas::Strict).dir_store(Cas::NoRev);
+        let mut store: Store<String, _> = Store::new(config);
+
+        for key in ["x", "d/"] {
+            let rev = store.write(key.into(), None, 'a');
+            store.remove(key.into(), rev);
+        }
+
+        assert_eq!(store.read("x"), Some((2, None)));
+        assert_eq!(store.read("d/"), None);
+
+        assert_eq!(store.write("x".into(), None, 'b'), None);
+        assert_eq!(store.write("d/".into(), Some(2), 'b'), None);
+        assert_eq!(store.write("d/".into(), None, 'b'), Some(3));
+    }
+
     #[test]
     fn updates_a_different_key() {
         let mut store: Store<String, _> = Store::new(Config::new());
@@ -435,6 +1011,144 @@ mod tests {
         assert_eq!(keys, ["/", "/path/", "/z/doc.json"]);
     }
 
+    mod size_limits {
+        use super::*;
+
+        #[test]
+        fn tracks_the_total_size_of_stored_values() {
+            let mut store: Store<String, String> = Store::new(Config::new());
+
+            let rev = store.write("x".into(), None, "abc".into());
+            store.write("y".into(), None, "defgh".into());
+            assert_eq!(store.size, 8);
+
+            let rev = store.write("x".into(), rev, "a".into());
+            assert_eq!(store.size, 6);
+
+            store.remove("x".into(), rev);
+            assert_eq!(store.size, 5);
+        }
+
+        #[test]
+        fn rejects_a_value_larger_than_the_max_size() {
+            let config = Config::new().max_value_size(4);
+            let mut store: Store<String, String> = Store::new(config);
+
+            assert_eq!(store.write("x".into(), None, "abcd".into()), Some(1));
+            assert_eq!(store.write("y".into(), None, "abcde".into()), None);
+            assert_eq!(store.seq, 1);
+            assert_eq!(store.get("y"), None);
+        }
+
+        #[test]
+        fn rejects_a_write_that_exceeds_the_quota() {
+            let mut store: Store<String, String> = Store::new(Config::new().quota(6));
+
+            let rev = store.write("x".into(), None, "abcd".into());
+            assert_eq!(store.write("y".into(), None, "abc".into()), None);
+            assert_eq!(store.write("y".into(), None, "ab".into()), Some(1));
+            assert_eq!(store.size, 6);
+
+            assert_eq!(store.write("x".into(), rev, "abcde".into()), None);
+            assert_eq!(store.write("x".into(), rev, "a".into()), Some(2));
+            assert_eq!(store.size, 3);
+        }
+
+        #[test]
+        fn allows_removal_when_over_quota() {
+            let mut store: Store<String, String> = Store::new(Config::new().quota(4));
+
+            let rev = store.write("x".into(), None, "abcd".into());
+            assert_eq!(store.remove("x".into(), rev), Some(2));
+            assert_eq!(store.size, 0);
+        }
+    }
+
+    #[test]
+    fn updates_a_value_in_place_whatever_its_rev() {
+        let mut store: Store<String, _> = Store::new(Config::new());
+
+        assert_eq!(
+            store.update("x".into(), |v| Some(v.map_or(1, |n| n + 1))),
+            Some(1)
+        );
+        store.write("x".into(), Some(1), 5);
+
+        assert_eq!(
+            store.update("x".into(), |v| Some(v.map_or(1, |n| n + 1))),
+            Some(3)
+        );
+        assert_eq!(store.read("x"), Some((3, Some(6))));
+        assert_eq!(store.seq, 3);
+    }
+
+    #[test]
+    fn leaves_a_value_alone_when_an_update_returns_none() {
+        let mut store: Store<String, _> = Store::new(Config::new());
+        store.write("x".into(), None, 'a');
+
+        assert_eq!(store.update("x".into(), |_| None), Some(1));
+        assert_eq!(store.update("y".into(), |_| None), Some(0));
+        assert_eq!(store.read("x"), Some((1, Some('a'))));
+        assert_eq!(store.read("y"), None);
+        assert_eq!(store.seq, 1);
+    }
+
+    #[test]
+    fn rejects_an_update_over_the_max_size() {
+        let config = Config::new().max_value_size(2);
+        let mut store: Store<String, String> = Store::new(config);
+        store.write("x".into(), None, "ab".into());
+
+        assert_eq!(store.update("x".into(), |v| Some(format!("{}c", v?))), None);
+        assert_eq!(store.read("x"), Some((1, Some("ab".into()))));
+        assert_eq!(store.conflicts, 1);
+    }
+
+    #[test]
+    fn counts_rejected_writes_as_conflicts() {
+        let mut store: Store<String, _> = Store::new(Config::new());
+        let rev = store.write("x".into(), None, 'a');
+
+        store.write("x".into(), None, 'b');
+        store.remove("x".into(), Some(99));
+        store.write("x".into(), rev, 'c');
+
+        assert_eq!(store.conflicts, 2);
+    }
+
+    #[test]
+    fn scans_the_keys_with_a_given_prefix() {
+        let mut store: Store<String, _> = Store::new(Config::new());
+
+        for key in ["/a", "/path/to/x", "/path/y", "/pathz", "/q"] {
+            store.write(key.into(), None, 'a');
+        }
+        let rev = store.write("/path/z".into(), None, 'b');
+        store.remove("/path/z".into(), rev);
+
+        assert_eq!(store.scan("/path/"), ["/path/to/x", "/path/y"]);
+        assert_eq!(
+            store.scan("/"),
+            ["/a", "/path/to/x", "/path/y", "/pathz", "/q"]
+        );
+        assert!(store.scan("/r/").is_empty());
+    }
+
+    #[test]
+    fn reads_many_keys_at_once() {
+        let mut store: Store<String, _> = Store::new(Config::new());
+
+        store.write("a".into(), None, 'a');
+        let rev = store.write("b".into(), None, 'b');
+        store.remove("b".into(), rev);
+
+        assert_eq!(
+            store.read_many(&["a".to_string(), "b".into(), "c".into()]),
+            [Some((1, Some('a'))), Some((2, None)), None]
+        );
+    }
+
     #[test]
     fn returns_none_for_an_unknown_key() {
         let store: RefCell<Store<String, ()>> = RefCell::new(Store::new(Config::new()));
@@ -475,12 +1189,158 @@ mod tests {
         assert_eq!(cache.read("x"), None);
     }
 
+    #[test]
+    fn rereads_every_value_with_fresh_reads() {
+        let store = RefCell::new(Store::new(Config::new().fresh_reads(true)));
+        let mut cache: Cache<String, _> = Cache::new(&store);
+
+        assert_eq!(cache.read("x"), None);
+        assert_eq!(store.borrow_mut().write("x".into(), None, 'a'), Some(1));
+        assert_eq!(cache.read("x"), Some('a'));
+
+        assert_eq!(store.borrow_mut().write("x".into(), Some(1), 'b'), Some(2));
+        assert_eq!(cache.read_many(&["x".to_string()]), [Some('b')]);
+        assert!(cache.write(&"x".into(), 'c'));
+    }
+
+    #[test]
+    fn peeks_at_the_store_without_changing_its_records() {
+        let store = RefCell::new(Store::new(Config::new()));
+        let mut cache: Cache<String, _> = Cache::new(&store);
+
+        store.borrow_mut().write("x".into(), None, 'a');
+        assert_eq!(cache.read("x"), Some('a'));
+        store.borrow_mut().write("x".into(), Some(1), 'b');
+
+        cache.trace_reads(true);
+        cache.peek(true);
+        assert_eq!(cache.read("x"), Some('b'));
+        assert_eq!(cache.take_reads(), ["x"]);
+
+        cache.peek(false);
+        assert_eq!(cache.read("x"), Some('a'));
+        assert!(!cache.write(&"x".into(), 'c'));
+    }
+
+    #[test]
+    fn traces_the_keys_read_from_the_store() {
+        let store = RefCell::new(Store::new(Config::new()));
+        let mut cache: Cache<String, char> = Cache::new(&store);
+
+        cache.read("x");
+        assert_eq!(cache.take_reads(), Vec::<String>::new());
+
+        cache.trace_reads(true);
+        cache.read("x");
+        cache.read("y");
+        cache.read_many(&["y".to_string(), "z".to_string()]);
+        assert_eq!(cache.take_reads(), ["y", "z"]);
+        assert_eq!(cache.take_reads(), Vec::<String>::new());
+
+        cache.trace_reads(false);
+        cache.read_many(&["w".to_string()]);
+        assert_eq!(cache.take_reads(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn syncs_the_keys_under_a_prefix_with_the_store() {
+        let store = RefCell::new(Store::new(Config::new()));
+        let mut cache: Cache<String, _> = Cache::new(&store);
+
+        cache.write(&"/a/x".into(), 'x');
+        cache.write(&"/a/y".into(), 'y');
+        assert_eq!(cache.read("/a/z"), None);
+        assert_eq!(cache.read("/b"), None);
+
+        let mut s = store.borrow_mut();
+        assert_eq!(s.write("/a/x".into(), Some(1), 'X'), Some(2));
+        assert_eq!(s.remove("/a/y".into(), Some(1)), Some(2));
+        assert_eq!(s.write("/a/w".into(), None, 'w'), Some(1));
+        assert_eq!(s.write("/b".into(), None, 'b'), Some(1));
+        drop(s);
+
+        cache.sync("/a/", &[]);
+
+        assert_eq!(cache.cached("/a/w"), Some(&Some((1, Some('w')))));
+        assert_eq!(cache.cached("/a/x"), Some(&Some((2, Some('X')))));
+        assert_eq!(cache.cached("/a/y"), Some(&Some((2, None))));
+        assert_eq!(cache.cached("/a/z"), None);
+        assert_eq!(cache.cached("/b"), Some(&None));
+    }
+
+    #[test]
+    fn rereads_a_forgotten_value() {
+        let store = RefCell::new(Store::new(Config::new()));
+        let mut cache: Cache<String, _> = Cache::new(&store);
+
+        assert_eq!(cache.read("x"), None);
+        assert_eq!(store.borrow_mut().write("x".into(), None, 'a'), Some(1));
+
+        cache.forget(&"x".into());
+        assert_eq!(cache.read("x"), Some('a'));
+    }
+
+    #[test]
+    fn holds_writes_in_a_batch_until_it_commits() {
+        let store = RefCell::new(Store::new(Config::new().fresh_reads(true)));
+        let mut cache: Cache<String, _> = Cache::new(&store);
+
+        assert_eq!(store.borrow_mut().write("x".into(), None, 'a'), Some(1));
+        assert_eq!(cache.read("x"), Some('a'));
+
+        cache.begin();
+        assert!(cache.write(&"x".into(), 'b'));
+        assert!(cache.write(&"y".into(), 'c'));
+        assert_eq!(cache.read("x"), Some('b'));
+        assert_eq!(store.borrow().read("x"), Some((1, Some('a'))));
+        assert_eq!(store.borrow().read("y"), None);
+
+        assert_eq!(cache.commit(), Ok(vec!["x".into(), "y".into()]));
+        assert!(!cache.is_batching());
+        assert_eq!(store.borrow().read("x"), Some((2, Some('b'))));
+        assert_eq!(cache.cached("y"), Some(&Some((1, Some('c')))));
+    }
+
+    #[test]
+    fn forgets_a_batch_the_store_refuses() {
+        let store = RefCell::new(Store::new(Config::new()));
+        let mut cache: Cache<String, _> = Cache::new(&store);
+
+        assert_eq!(cache.read("x"), None);
+        cache.begin();
+        cache.write(&"x".into(), 'a');
+        cache.write(&"y".into(), 'b');
+        assert_eq!(store.borrow_mut().write("x".into(), None, 'c'), Some(1));
+
+        assert_eq!(cache.commit(), Err(vec!["x".into(), "y".into()]));
+        assert_eq!(store.borrow().read("y"), None);
+        assert_eq!(cache.cached("x"), None);
+        assert_eq!(cache.read("x"), Some('c'));
+    }
+
+    #[test]
+    fn reads_many_values_it_has_not_cached() {
+        let store = RefCell::new(Store::new(Config::new()));
+        let mut cache: Cache<String, _> = Cache::new(&store);
+
+        assert_eq!(cache.read("x"), None);
+        store.borrow_mut().write("x".into(), None, 'a');
+        store.borrow_mut().write("y".into(), None, 'b');
+
+        assert_eq!(
+            cache.read_many(&["x".into(), "y".into(), "z".into()]),
+            [None, Some('b'), None]
+        );
+        store.borrow_mut().write("z".into(), None, 'c');
+        assert_eq!(cache.read("z"), None);
+    }
+
     #[test]
     fn writes_a_value_to_the_store() {
         let store = RefCell::new(Store::new(Config::new()));
         let mut cache: Cache<String, _> = Cache::new(&store);
 
-        assert_eq!(cache.write(&"x".into(), 'a'), true);
+        assert!(cache.write(&"x".into(), 'a'));
 
         assert_eq!(store.borrow().read("x"), Some((1, Some('a'))));
         assert_eq!(cache.read("x"), Some('a'));
@@ -491,9 +1351,9 @@ mod tests {
         let store = RefCell::new(Store::new(Config::new()));
         let mut cache: Cache<String, _> = Cache::new(&store);
 
-        assert_eq!(cache.write(&"x".into(), 'a'), true);
-        assert_eq!(cache.write(&"x".into(), 'b'), true);
-        assert_eq!(cache.write(&"x".into(), 'c'), true);
+        assert!(cache.write(&"x".into(), 'a'));
+        assert!(cache.write(&"x".into(), 'b'));
+        assert!(cache.write(&"x".into(), 'c'));
 
         assert_eq!(store.borrow().read("x"), Some((3, Some('c'))));
         assert_eq!(cache.read("x"), Some('c'));
@@ -504,20 +1364,33 @@ mod tests {
         let store = RefCell::new(Store::new(Config::new()));
         let mut cache: Cache<String, _> = Cache::new(&store);
 
-        assert_eq!(cache.write(&"x".into(), 'a'), true);
-        assert_eq!(cache.remove(&"x".into()), true);
+        assert!(cache.write(&"x".into(), 'a'));
+        assert!(cache.remove(&"x".into()));
 
         assert_eq!(store.borrow().read("x"), Some((2, None)));
         assert_eq!(cache.read("x"), None);
     }
 
+    #[test]
+    fn has_the_store_update_a_value_it_did_not_read() {
+        let store = RefCell::new(Store::new(Config::new()));
+        let mut cache: Cache<String, _> = Cache::new(&store);
+
+        assert!(cache.write(&"x".into(), 1));
+        assert_eq!(store.borrow_mut().write("x".into(), Some(1), 5), Some(2));
+
+        assert!(cache.update(&"x".into(), |v| v.map(|n| n + 1)));
+        assert_eq!(store.borrow().read("x"), Some((3, Some(6))));
+        assert_eq!(cache.read("x"), Some(6));
+    }
+
     #[test]
     fn fails_to_update_a_doc_it_did_not_read_first() {
         let store = RefCell::new(Store::new(Config::new()));
         let mut cache: Cache<String, _> = Cache::new(&store);
 
         assert_eq!(store.borrow_mut().write("x".into(), None, 'a'), Some(1));
-        assert_eq!(cache.write(&"x".into(), 'b'), false);
+        assert!(!cache.write(&"x".into(), 'b'));
 
         assert_eq!(store.borrow().read("x"), Some((1, Some('a'))));
         assert_eq!(cache.read("x"), Some('a'));
@@ -528,10 +1401,10 @@ mod tests {
         let store = RefCell::new(Store::new(Config::new()));
         let mut cache: Cache<String, _> = Cache::new(&store);
 
-        assert_eq!(cache.write(&"x".into(), 'a'), true);
+        assert!(cache.write(&"x".into(), 'a'));
 
         assert_eq!(store.borrow_mut().write("x".into(), Some(1), 'c'), Some(2));
-        assert_eq!(cache.write(&"x".into(), 'b'), false);
+        assert!(!cache.write(&"x".into(), 'b'));
 
         assert_eq!(store.borrow().read("x"), Some((2, Some('c'))));
     }
@@ -541,10 +1414,10 @@ mod tests {
         let store = RefCell::new(Store::new(Config::new()));
         let mut cache: Cache<String, _> = Cache::new(&store);
 
-        assert_eq!(cache.write(&"x".into(), 'a'), true);
+        assert!(cache.write(&"x".into(), 'a'));
 
         assert_eq!(store.borrow_mut().write("x".into(), Some(1), 'c'), Some(2));
-        assert_eq!(cache.remove(&"x".into()), false);
+        assert!(!cache.remove(&"x".into()));
 
         assert_eq!(store.borrow().read("x"), Some((2, Some('c'))));
     }
@@ -554,13 +1427,13 @@ mod tests {
         let store = RefCell::new(Store::new(Config::new()));
         let mut cache: Cache<String, _> = Cache::new(&store);
 
-        assert_eq!(cache.write(&"x".into(), 'a'), true);
+        assert!(cache.write(&"x".into(), 'a'));
 
         assert_eq!(store.borrow_mut().write("x".into(), Some(1), 'c'), Some(2));
-        assert_eq!(cache.write(&"x".into(), 'b'), false);
+        assert!(!cache.write(&"x".into(), 'b'));
 
         assert_eq!(cache.read("x"), Some('c'));
-        assert_eq!(cache.write(&"x".into(), 'b'), true);
+        assert!(cache.write(&"x".into(), 'b'));
 
         assert_eq!(store.borrow().read("x"), Some((3, Some('b'))));
         assert_eq!(cache.read("x"), Some('b'));
@@ -572,11 +1445,11 @@ mod tests {
         let mut a: Cache<String, _> = Cache::new(&store);
         let mut b: Cache<String, _> = Cache::new(&store);
 
-        assert_eq!(a.write(&"x".into(), 'a'), true);
-        assert_eq!(b.write(&"y".into(), 'b'), true);
+        assert!(a.write(&"x".into(), 'a'));
+        assert!(b.write(&"y".into(), 'b'));
 
-        assert_eq!(a.write(&"y".into(), 'a'), false);
-        assert_eq!(b.write(&"x".into(), 'b'), false);
+        assert!(!a.write(&"y".into(), 'a'));
+        assert!(!b.write(&"x".into(), 'b'));
 
         assert_eq!(a.read("y"), Some('b'));
         assert_eq!(b.read("x"), Some('a'));
@@ -0,0 +1,38 @@
+use std::cell::RefCell;
+
+use mc2::{Actor, Config, CustomOp, Db, DbStore, Path, Planner};
+
+// Copies the doc at its path to another key, as a program using the crate
+// would prototype a store primitive.
+struct Copy(Path);
+
+impl CustomOp<Vec<char>> for Copy {
+    fn name(&self) -> &str {
+        "copy"
+    }
+
+    fn execute(&self, actor: &mut Actor<'_, Vec<char>>, path: &Path) {
+        let value = actor.read_raw(path);
+        actor.read_raw(&self.0);
+        actor.write_raw(&self.0, value);
+    }
+}
+
+#[test]
+fn runs_a_custom_op_defined_outside_the_crate() {
+    let mut store = DbStore::new(Config::new());
+    store.write("/".into(), None, Db::dir_from(&["x", "y"]));
+    store.write("/x".into(), None, Db::doc(vec!['a']));
+    let store = RefCell::new(store);
+
+    let mut planner = Planner::new(Config::new());
+    planner.client("A").custom("/x", Copy("/y".into()));
+    let plan = planner.orderings().next().unwrap();
+    assert_eq!(plan[0].to_string(), "copy('/x')");
+
+    let mut actor = Actor::new(&store, Config::new());
+    actor.dispatch(plan[0]);
+
+    assert!(actor.is_acknowledged());
+    assert_eq!(actor.get(&"/y".into()), Some(vec!['a']));
+}